@@ -5,7 +5,6 @@
 
 use std::collections::{BTreeMap, HashMap};
 use std::iter;
-use std::mem;
 use std::path::Path;
 
 use anyhow::Result;
@@ -21,9 +20,11 @@ use rust_htslib::bcf;
 use rust_htslib::bcf::record::Numeric;
 use rust_htslib::bcf::Read;
 
-use crate::model::modes::tumor::TumorNormalPairView;
-use crate::model::AlleleFreq;
+use crate::calling::variants::event_tag_name;
+use crate::errors;
+use crate::grammar;
 use crate::utils;
+use crate::variants::model::AlleleFreq;
 
 const MIN_DEPTH: u32 = 10;
 const MAX_GAIN: i32 = 21;
@@ -41,6 +42,42 @@ pub(crate) fn allele_freq_pdf(
     LogProb(binomial_pdf(k, *true_allele_freq, depth).ln())
 }
 
+/// Tumor sample, normal sample and tumor purity as defined by the scenario.
+/// The tumor sample is the only sample that is contaminated, and the normal sample
+/// is the one it is contaminated by.
+pub(crate) fn tumor_normal_samples(scenario: &grammar::Scenario) -> Result<(String, String, f64)> {
+    let contaminated = scenario
+        .samples()
+        .iter()
+        .filter_map(|(name, sample)| {
+            sample
+                .contamination()
+                .as_ref()
+                .map(|contamination| (name, contamination))
+        })
+        .collect_vec();
+    if contaminated.len() != 1 {
+        return Err(errors::Error::InvalidCNVScenario {
+            msg: "exactly one (tumor) sample has to define a contamination by a (normal) sample"
+                .to_owned(),
+        }
+        .into());
+    }
+    let (tumor, contamination) = contaminated[0];
+    if !scenario.samples().contains_key(contamination.by()) {
+        return Err(errors::Error::InvalidContaminationSampleName {
+            name: contamination.by().to_owned(),
+        }
+        .into());
+    }
+
+    Ok((
+        tumor.to_owned(),
+        contamination.by().to_owned(),
+        1.0 - *contamination.fraction(),
+    ))
+}
+
 #[derive(Builder)]
 #[builder(pattern = "owned")]
 pub(crate) struct Caller {
@@ -48,100 +85,138 @@ pub(crate) struct Caller {
     bcf_reader: bcf::Reader,
     #[builder(private)]
     bcf_writer: bcf::Writer,
-    min_bayes_factor: f64,
+    #[builder(private)]
     purity: f64,
+    #[builder(private)]
+    tumor_idx: usize,
+    #[builder(private)]
+    normal_idx: usize,
+    /// INFO tag with the posterior probability of the germline heterozygous event.
+    #[builder(private)]
+    germline_het_tag: String,
+    min_bayes_factor: f64,
     max_dist: u64,
     #[builder(private)]
-    contig_lens: HashMap<Vec<u8>, u32>,
+    contig_lens: HashMap<Vec<u8>, u64>,
 }
 
 impl CallerBuilder {
-    pub(crate) fn bcfs<P: AsRef<Path>>(mut self, in_path: Option<P>, out_path: Option<P>) -> Result<Self> {
-        self = self.bcf_reader(if let Some(path) = in_path {
+    /// Set input and output BCF. The input has to be generated by `varlociraptor call variants`
+    /// with the given scenario, which defines tumor and normal sample as well as tumor purity.
+    /// Germline heterozygous loci are obtained from the posterior probability of the given event.
+    pub(crate) fn bcfs<P: AsRef<Path>>(
+        mut self,
+        scenario: &grammar::Scenario,
+        germline_het_event: &str,
+        in_path: Option<P>,
+        out_path: Option<P>,
+    ) -> Result<Self> {
+        let (tumor, normal, purity) = tumor_normal_samples(scenario)?;
+
+        let bcf_reader = if let Some(path) = in_path {
             bcf::Reader::from_path(path)?
         } else {
             bcf::Reader::from_stdin()?
-        });
+        };
 
-        let bcf_reader = self.bcf_reader.as_ref().unwrap();
+        let sample_idx = |name: &str| {
+            bcf_reader
+                .header()
+                .sample_id(name.as_bytes())
+                .ok_or_else(|| errors::Error::InvalidCNVScenario {
+                    msg: format!("sample {} not found in given calls", name),
+                })
+        };
+        let tumor_idx = sample_idx(&tumor)?;
+        let normal_idx = sample_idx(&normal)?;
+
+        let germline_het_tag = event_tag_name(germline_het_event);
+        if bcf_reader
+            .header()
+            .info_type(germline_het_tag.as_bytes())
+            .is_err()
+        {
+            return Err(errors::Error::MissingBCFTag {
+                name: germline_het_tag,
+            }
+            .into());
+        }
 
+        // METHOD: tumor comes first, such that per-locus values can be written for both samples
+        // by simply concatenating them.
         let mut header = bcf::Header::new();
-        for sample in bcf_reader.header().samples() {
-            header.push_sample(sample);
-        }
+        header.push_sample(tumor.as_bytes());
+        header.push_sample(normal.as_bytes());
 
         header.push_record(
-            "##INFO=<ID=IMPRECISE,Number=0,Type=Flag,Description=\"Imprecise structural variation\">"
-                .as_bytes()
+            b"##INFO=<ID=IMPRECISE,Number=0,Type=Flag,Description=\"Imprecise structural variation\">",
         );
         header.push_record(
-            "##INFO=<ID=CN,Number=1,Type=Integer,Description=\"Copy number in tumor sample\">"
-                .as_bytes(),
+            b"##INFO=<ID=CN,Number=1,Type=Integer,Description=\"Copy number in tumor sample\">",
         );
         header.push_record(
-            "##INFO=<ID=VAF,Number=1,Type=Float,Description=\"Subclone fraction affected by \
-             the CNV.\">"
-                .as_bytes(),
+            b"##INFO=<ID=VAF,Number=1,Type=Float,Description=\"Subclone fraction affected by \
+             the CNV.\">",
         );
         header.push_record(
-            "##INFO=<ID=END,Number=1,Type=Integer,Description=\"End of copy number variation.\">"
-                .as_bytes(),
+            b"##INFO=<ID=END,Number=1,Type=Integer,Description=\"End of copy number variation.\">",
         );
         header.push_record(
-            "##INFO=<ID=CIPOS,Number=2,Type=Integer,Description=\"Confidence interval around POS \
-             for imprecise variants\">"
-                .as_bytes(),
+            b"##INFO=<ID=CIPOS,Number=2,Type=Integer,Description=\"Confidence interval around POS \
+             for imprecise variants\">",
         );
         header.push_record(
-            "##INFO=<ID=CIEND,Number=2,Type=Integer,Description=\"Confidence interval around END \
-             for imprecise variants\">"
-                .as_bytes(),
+            b"##INFO=<ID=CIEND,Number=2,Type=Integer,Description=\"Confidence interval around END \
+             for imprecise variants\">",
         );
+        header.push_record(b"##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"CNV length.\">");
+        header.push_record(b"##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"SV type.\">");
         header.push_record(
-            "##INFO=<ID=SVLEN,Number=1,Type=Integer,Description=\"CNV length.\">".as_bytes(),
+            b"##INFO=<ID=LOCI,Number=1,Type=Integer,Description=\"Number of contained loci.\">",
         );
         header.push_record(
-            "##INFO=<ID=SVTYPE,Number=1,Type=Integer,Description=\"SV type.\">".as_bytes(),
+            b"##INFO=<ID=PROB_CNV,Number=1,Type=Float,Description=\"Posterior probability \
+             for a CNV of the given copy number (PHRED).\">",
         );
         header.push_record(
-            "##INFO=<ID=LOCI,Number=1,Type=Integer,Description=\"Number of contained loci.\">"
-                .as_bytes(),
+            b"##INFO=<ID=PROB_NO_CNV,Number=1,Type=Float,Description=\"Posterior probability \
+             for no CNV (PHRED).\">",
         );
         header.push_record(
-            "##INFO=<ID=OBS,Number=1,Type=String,Description=\"Bayes factors for per-locus \
+            b"##INFO=<ID=OBS,Number=1,Type=String,Description=\"Bayes factors for per-locus \
              support for no CNV, given as Kass Raftery scores: \
-             N: none, B: barely, P: positive, S: strong, V: very strong \">"
-                .as_bytes(),
+             N: none, B: barely, P: positive, S: strong, V: very strong \">",
         );
         header.push_record(
-            "##FORMAT=<ID=LOCI_DP,Number=.,Type=Integer,Description=\"Depths of contained loci.\">"
-                .as_bytes(),
+            b"##FORMAT=<ID=LOCI_DP,Number=.,Type=Integer,Description=\"Depths of contained loci.\">",
         );
         header.push_record(
-            "##FORMAT=<ID=LOCI_VAF,Number=.,Type=Integer,Description=\"VAFs of contained loci.\">"
-                .as_bytes(),
+            b"##FORMAT=<ID=LOCI_VAF,Number=.,Type=Float,Description=\"VAFs of contained loci.\">",
         );
 
         let mut contig_lens = HashMap::new();
         // register sequences
         for rec in bcf_reader.header().header_records() {
-            match rec {
-                bcf::header::HeaderRecord::Contig { values, .. } => {
-                    let name = values.get("ID").unwrap();
-                    let len = values.get("length").unwrap();
-                    contig_lens.insert(name.clone().into_bytes(), len.parse()?);
-                    header.push_record(format!("##contig=<ID={},length={}>", name, len).as_bytes());
-                }
-                _ => (),
+            if let bcf::header::HeaderRecord::Contig { values, .. } = rec {
+                let name = values.get("ID").unwrap();
+                let len = values.get("length").unwrap();
+                contig_lens.insert(name.clone().into_bytes(), len.parse()?);
+                header.push_record(format!("##contig=<ID={},length={}>", name, len).as_bytes());
             }
         }
 
-        self = self.contig_lens(contig_lens);
+        self = self
+            .bcf_reader(bcf_reader)
+            .purity(purity)
+            .tumor_idx(tumor_idx)
+            .normal_idx(normal_idx)
+            .germline_het_tag(germline_het_tag)
+            .contig_lens(contig_lens);
 
         Ok(self.bcf_writer(if let Some(path) = out_path {
-            bcf::Writer::from_path(path, &header, false, bcf::Format::BCF)?
+            bcf::Writer::from_path(path, &header, false, bcf::Format::Bcf)?
         } else {
-            bcf::Writer::from_stdout(&header, false, bcf::Format::BCF)?
+            bcf::Writer::from_stdout(&header, false, bcf::Format::Bcf)?
         }))
     }
 }
@@ -149,18 +224,18 @@ impl CallerBuilder {
 impl Caller {
     pub(crate) fn call(&mut self) -> Result<()> {
         // obtain records
-
         let calls = {
             let mut record = self.bcf_reader.empty_record();
-            let mut last_call: Option<&Call> = None;
-            let mut curr_region = None;
             let mut _calls = Vec::new();
-            loop {
-                if !self.bcf_reader.read(&mut record)? {
-                    break;
-                }
+            while let Some(res) = self.bcf_reader.read(&mut record) {
+                res?;
 
-                if let Some(call) = Call::new(&mut record)? {
+                if let Some(call) = Call::new(
+                    &mut record,
+                    &self.germline_het_tag,
+                    self.tumor_idx,
+                    self.normal_idx,
+                )? {
                     if call.depth_normal >= MIN_DEPTH {
                         _calls.push(call);
                     }
@@ -177,34 +252,31 @@ impl Caller {
             }
 
             let mut calls = HashMap::new();
-
+            let mut last_locus: Option<(u32, u64)> = None;
+            let mut curr_region = None;
             for call in _calls {
-                let region = if let Some(last_call) = last_call {
-                    if call.rid == last_call.rid && (call.start - last_call.start) <= self.max_dist
+                let region = match (last_locus, curr_region) {
+                    (Some((rid, start)), Some(region))
+                        if call.rid == rid && (call.start - start) <= self.max_dist =>
                     {
-                        curr_region.unwrap()
-                    } else {
-                        Region {
-                            rid: call.rid,
-                            start: call.start,
-                        }
+                        region
                     }
-                } else {
-                    Region {
+                    _ => Region {
                         rid: call.rid,
                         start: call.start,
-                    }
+                    },
                 };
                 curr_region = Some(region);
+                last_locus = Some((call.rid, call.start));
                 calls.entry(region).or_insert_with(Vec::new).push(call);
-                last_call = Some(calls.get(&region).unwrap().last().unwrap());
             }
             calls
         };
 
         // normalization
+        let n_loci = calls.values().map(|calls| calls.len()).sum::<usize>();
         let mean_depth = |filter: &dyn Fn(&Call) -> u32| {
-            calls.values().flatten().map(filter).sum::<u32>() as f64 / calls.len() as f64
+            calls.values().flatten().map(filter).sum::<u32>() as f64 / n_loci as f64
         };
         let mean_depth_tumor = mean_depth(&|call: &Call| call.depth_tumor);
         let mean_depth_normal = mean_depth(&|call: &Call| call.depth_normal);
@@ -236,8 +308,7 @@ impl Caller {
                             if group.len() > 1 {
                                 let last_call = group[group.len() - 1];
 
-                                // calculate posterior probability of no CNV
-                                let prob_no_cnv = hmm.prob_no_cnv(&group);
+                                let (prob_cnv, prob_no_cnv) = hmm.posterior(cnv.gain, &group);
                                 let bayes_factors = hmm.bayes_factors(state, &group);
 
                                 Some(CNVCall {
@@ -245,10 +316,11 @@ impl Caller {
                                     next_pos: last_call.next_start,
                                     pos: first_call.start,
                                     end: last_call.start + 1,
-                                    cnv: cnv,
+                                    cnv,
+                                    prob_cnv,
                                     prob_no_cnv,
                                     calls: group,
-                                    bayes_factors: bayes_factors,
+                                    bayes_factors,
                                 })
                             } else {
                                 None
@@ -289,6 +361,7 @@ pub(crate) struct CNVCall<'a> {
     pos: u64,
     end: u64,
     cnv: CNV,
+    prob_cnv: LogProb,
     prob_no_cnv: LogProb,
     calls: Vec<&'a Call>,
     bayes_factors: Vec<BayesFactor>,
@@ -330,10 +403,12 @@ impl<'a> CNVCall<'a> {
                 if let Some(next_pos) = self.next_pos {
                     (next_pos - self.end) as i32
                 } else {
-                    (contig_len - self.end) as i32
+                    contig_len.saturating_sub(self.end) as i32
                 },
             ],
         )?;
+        record.push_info_float(b"PROB_CNV", &[*PHREDProb::from(self.prob_cnv) as f32])?;
+        record.push_info_float(b"PROB_NO_CNV", &[*PHREDProb::from(self.prob_no_cnv) as f32])?;
 
         let mut loci_dp = Vec::new();
         loci_dp.extend(self.calls.iter().map(|call| call.depth_tumor as i32));
@@ -357,7 +432,7 @@ impl<'a> CNVCall<'a> {
         let obs = join(
             self.bayes_factors
                 .iter()
-                .map(|bf| utils::evidence_kass_raftery_to_letter(bf.evidence_kass_raftery())),
+                .map(|bf| utils::bayes_factor_to_letter(*bf)),
             "",
         );
         record.push_info_string(b"OBS", &[obs.as_bytes()])?;
@@ -365,11 +440,12 @@ impl<'a> CNVCall<'a> {
         Ok(())
     }
 
-    pub(crate) fn len(&self) -> u32 {
+    pub(crate) fn len(&self) -> u64 {
         self.end - self.pos + 1
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub(crate) struct HMM {
     states: Vec<CNV>,
     state_by_gain: HashMap<i32, Vec<hmm::State>>,
@@ -388,7 +464,7 @@ impl HMM {
             for gain in -2..MAX_GAIN {
                 if gain != 0 || allele_freq == 1.0 {
                     let cnv = CNV {
-                        gain: gain,
+                        gain,
                         allele_freq: AlleleFreq(allele_freq),
                         purity,
                     };
@@ -423,39 +499,59 @@ impl HMM {
         }
     }
 
-    pub(crate) fn prob_no_cnv(&self, observations: &[&Call]) -> LogProb {
+    /// Posterior probabilities of the given gain and of no CNV over the given loci,
+    /// assuming a uniform prior over all gains.
+    pub(crate) fn posterior(&self, gain: i32, observations: &[&Call]) -> (LogProb, LogProb) {
         let likelihood_no_cnv = likelihood(
             self,
-            iter::repeat(self.state_by_gain.get(&0).unwrap()[0]),
+            iter::repeat(self.null_state()),
             observations.iter().cloned(),
         );
+        let mut likelihood_gain = LogProb::ln_zero();
         let mut likelihoods = vec![likelihood_no_cnv];
-        for gain in -2..MAX_GAIN {
-            if gain != 0 {
-                let af_spectrum = self.state_by_gain.get(&gain).unwrap();
-                likelihoods.push(LogProb::ln_simpsons_integrate_exp(
+        for other_gain in -2..MAX_GAIN {
+            if other_gain != 0 {
+                // METHOD: integrate over the fraction of cells affected by the CNV, with a
+                // uniform prior over the allele frequencies of the states of the gain.
+                let af_spectrum = self.state_by_gain.get(&other_gain).unwrap();
+                let grid = af_spectrum
+                    .iter()
+                    .map(|state| *self.states[**state].allele_freq)
+                    .collect_vec();
+                let prior_density = LogProb(-(grid[grid.len() - 1] - grid[0]).ln());
+                let lh = LogProb::ln_trapezoidal_integrate_grid_exp(
                     |i, _| {
                         let state = af_spectrum[i];
                         likelihood(self, iter::repeat(state), observations.iter().cloned())
                     },
-                    0.0,
-                    1.0,
-                    af_spectrum.len() - 1,
-                ));
+                    &grid,
+                ) + prior_density;
+                if other_gain == gain {
+                    likelihood_gain = lh;
+                }
+                likelihoods.push(lh);
             }
         }
+        let marginal = LogProb::ln_sum_exp(&likelihoods);
 
-        LogProb::ln_sum_exp(&likelihoods)
+        (
+            (likelihood_gain - marginal).cap_numerical_overshoot(utils::NUMERICAL_EPSILON),
+            (likelihood_no_cnv - marginal).cap_numerical_overshoot(utils::NUMERICAL_EPSILON),
+        )
     }
 
     pub(crate) fn null_state(&self) -> hmm::State {
         self.state_by_gain.get(&0).unwrap()[0]
     }
 
-    pub(crate) fn bayes_factors(&self, state: hmm::State, observations: &[&Call]) -> Vec<BayesFactor> {
+    pub(crate) fn bayes_factors(
+        &self,
+        state: hmm::State,
+        observations: &[&Call],
+    ) -> Vec<BayesFactor> {
         let null_state = self.null_state();
         observations
-            .into_iter()
+            .iter()
             .map(|obs| {
                 BayesFactor::new(
                     self.observation_prob(state, obs),
@@ -535,34 +631,6 @@ pub(crate) fn likelihood<'a, O: 'a>(
     p
 }
 
-pub(crate) fn marginal<'a, O: 'a>(
-    hmm: &dyn hmm::Model<O>,
-    observations: impl IntoIterator<Item = &'a O>,
-) -> LogProb {
-    let mut prev = vec![LogProb::ln_zero(); hmm.num_states()];
-    let mut curr = prev.clone();
-
-    for (i, obs) in observations.into_iter().enumerate() {
-        for to in hmm.states() {
-            let prob_obs = hmm.observation_prob(to, obs);
-            curr[*to] = if i == 0 {
-                hmm.initial_prob(to)
-            } else {
-                prob_obs
-                    + LogProb::ln_sum_exp(
-                        &hmm.states()
-                            .map(|from| prev[*from] + hmm.transition_prob(from, to))
-                            .collect_vec(),
-                    )
-            };
-        }
-        mem::swap(&mut prev, &mut curr);
-    }
-
-    LogProb::ln_sum_exp(&prev.into_iter().collect_vec())
-        .cap_numerical_overshoot(utils::NUMERICAL_EPSILON)
-}
-
 #[derive(Debug)]
 pub(crate) struct Call {
     prob_germline_het: LogProb,
@@ -577,9 +645,18 @@ pub(crate) struct Call {
 }
 
 impl Call {
-    pub(crate) fn new(record: &mut bcf::Record) -> Result<Option<Self>> {
+    pub(crate) fn new(
+        record: &mut bcf::Record,
+        germline_het_tag: &str,
+        tumor_idx: usize,
+        normal_idx: usize,
+    ) -> Result<Option<Self>> {
+        // only biallelic records provide a unique VAF per sample
+        if record.allele_count() != 2 {
+            return Ok(None);
+        }
         let pos = record.pos();
-        let prob_germline_het = record.info(b"PROB_GERMLINE_HET").float()?;
+        let prob_germline_het = record.info(germline_het_tag.as_bytes()).float()?;
         if let Some(_prob_germline_het) = prob_germline_het {
             if !_prob_germline_het[0].is_missing() && !_prob_germline_het[0].is_nan() {
                 let prob_germline_het = LogProb::from(PHREDProb(_prob_germline_het[0] as f64));
@@ -589,20 +666,25 @@ impl Call {
                     _prob_germline_het[0],
                     pos
                 );
-                let depths = record
-                    .format(b"DP")
-                    .integer()?
-                    .into_iter()
-                    .map(|d| d[0] as u32)
-                    .collect_vec();
-                let allele_freqs = record.format(b"AF").float()?;
                 if prob_germline_het >= LogProb::from(Prob(0.5)) {
+                    let depths = record.format(b"DP").integer()?;
+                    let allele_freqs = record.format(b"AF").float()?;
+                    let (depth_tumor, depth_normal) = (depths[tumor_idx][0], depths[normal_idx][0]);
+                    let (allele_freq_tumor, allele_freq_normal) =
+                        (allele_freqs[tumor_idx][0], allele_freqs[normal_idx][0]);
+                    if depth_tumor.is_missing()
+                        || depth_normal.is_missing()
+                        || allele_freq_tumor.is_missing()
+                        || allele_freq_normal.is_missing()
+                    {
+                        return Ok(None);
+                    }
                     return Ok(Some(Call {
-                        allele_freq_tumor: AlleleFreq(allele_freqs.tumor()[0] as f64),
-                        allele_freq_normal: AlleleFreq(allele_freqs.normal()[0] as f64),
-                        depth_tumor: *depths.tumor(),
-                        depth_normal: *depths.normal(),
-                        prob_germline_het: prob_germline_het,
+                        allele_freq_tumor: AlleleFreq(allele_freq_tumor as f64),
+                        allele_freq_normal: AlleleFreq(allele_freq_normal as f64),
+                        depth_tumor: depth_tumor as u32,
+                        depth_normal: depth_normal as u32,
+                        prob_germline_het,
                         start: record.pos() as u64,
                         rid: record.rid().unwrap(),
                         prev_start: None,
@@ -619,15 +701,12 @@ impl Call {
         allele_freq_pdf(self.allele_freq_tumor, true_allele_freq, self.depth_tumor)
     }
 
-    pub(crate) fn prob_allele_freq_normal_het(&self) -> LogProb {
-        allele_freq_pdf(self.allele_freq_normal, AlleleFreq(0.5), self.depth_normal)
-    }
-
     pub(crate) fn prob_depth_tumor(&self, true_depth: f64) -> LogProb {
         depth_pmf(self.depth_tumor, true_depth)
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub(crate) struct CNV {
     gain: i32,
@@ -678,23 +757,58 @@ mod tests {
         );
     }
 
-    // #[test]
-    // fn test_call() {
-    //     let call = Call {
-    //         prob_germline_het: LogProb::ln_one(),
-    //         allele_freq_tumor: AlleleFreq(1.0),
-    //         allele_freq_normal: AlleleFreq(0.5),
-    //         depth_tumor: 12,
-    //         depth_normal: 10,
-    //         start: 0,
-    //         rid: 0,
-    //     };
-    //
-    //     let cnv = CNV {
-    //         gain: 1,
-    //         allele_freq: AlleleFreq(1.0),
-    //         purity: 0.15,
-    //     };
-    //     // TODO add assertion
-    // }
+    #[test]
+    fn test_expected_depth_factor() {
+        // clonal loss of one copy at 80% purity
+        let cnv = CNV {
+            gain: -1,
+            allele_freq: AlleleFreq(1.0),
+            purity: 0.8,
+        };
+        assert_relative_eq!(cnv.expected_depth_factor(), 0.6);
+        assert_relative_eq!(*cnv.expected_allele_freq_alt_affected().unwrap(), 0.0);
+    }
+
+    /// Germline heterozygous loci with the given tumor VAF (alternating with its complement)
+    /// and tumor depth, at a normal depth of 40.
+    fn het_calls(allele_freq_tumor: f64, depth_tumor: u32) -> Vec<Call> {
+        (0..20)
+            .map(|i| Call {
+                prob_germline_het: LogProb::from(Prob(0.99)),
+                allele_freq_tumor: if i % 2 == 0 {
+                    AlleleFreq(allele_freq_tumor)
+                } else {
+                    AlleleFreq(1.0 - allele_freq_tumor)
+                },
+                allele_freq_normal: AlleleFreq(0.5),
+                depth_tumor,
+                depth_normal: 40,
+                start: i * 100,
+                rid: 0,
+                prev_start: None,
+                next_start: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_posterior() {
+        let hmm = HMM::new(1.0, 1.5, 0.8);
+
+        // clonal gain of one copy at 80% purity: depth factor 1.4, tumor VAF 2/3
+        let calls = het_calls(2.0 / 3.0, 56);
+        let calls = calls.iter().collect_vec();
+        let (prob_gain, prob_no_cnv) = hmm.posterior(1, &calls);
+        assert!(prob_gain > LogProb::from(Prob(0.5)));
+        assert!(prob_no_cnv < LogProb::from(Prob(0.01)));
+        let (prob_loss, _) = hmm.posterior(-1, &calls);
+        assert!(prob_loss < LogProb::from(Prob(0.01)));
+
+        // no CNV: tumor depth and VAF as in the normal sample
+        let calls = het_calls(0.5, 40);
+        let calls = calls.iter().collect_vec();
+        let (prob_gain, prob_no_cnv) = hmm.posterior(1, &calls);
+        assert!(prob_gain < LogProb::from(Prob(0.05)));
+        assert!(prob_no_cnv > LogProb::from(Prob(0.9)));
+    }
 }
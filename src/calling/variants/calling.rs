@@ -1,10 +1,9 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::path::PathBuf;
-use std::rc::Rc;
 use std::str;
-use std::sync::RwLock;
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
 
 use anyhow::{Context, Result};
 use bio::stats::{bayesian, LogProb, Prob};
@@ -16,17 +15,22 @@ use itertools::{Itertools, MinMaxResult};
 use progress_logger::ProgressLogger;
 use rust_htslib::bcf::{self, Read};
 
+use crate::calling::variants::phasing::{add_phasing_header_entries, Phaser};
+use crate::calling::variants::preprocessing::observation_format::ObservationFormat;
 use crate::calling::variants::preprocessing::{
-    read_observations, remove_observation_header_entries, OBSERVATION_FORMAT_VERSION,
+    read_observations, remove_observation_header_entries,
 };
 use crate::calling::variants::SampleInfo;
 use crate::calling::variants::{
     chrom, event_tag_name, Call, CallBuilder, SampleInfoBuilder, VariantBuilder,
 };
 use crate::errors;
+use crate::estimation::fragment_lengths::FragmentLengthModel;
 use crate::grammar;
 use crate::utils::aux_info::AuxInfoCollector;
+use crate::utils::regions::{RecordReader, RecordSelection, Regions};
 use crate::utils::{self, PathMap};
+use crate::variants::evidence::observations::breakpoint_observation::refined_breakpoints;
 use crate::variants::evidence::observations::pileup::Pileup;
 
 use crate::variants::model::modes::generic::LikelihoodOperands;
@@ -35,7 +39,7 @@ use crate::variants::model::modes::generic::{
 };
 use crate::variants::model::prior::{Inheritance, Prior};
 use crate::variants::model::{self, Event, VariantPrecision};
-use crate::variants::model::{bias::Artifacts, AlleleFreq};
+use crate::variants::model::{bias::Artifacts, bias::Damage, AlleleFreq};
 use crate::variants::model::{Contamination, HaplotypeIdentifier};
 
 use super::preprocessing::haplotype_feature_index::HaplotypeFeatureIndex;
@@ -46,12 +50,15 @@ pub(crate) type AlleleFreqCombination = LikelihoodOperands;
 pub(crate) type Model<Pr> =
     bayesian::Model<GenericLikelihood, Pr, GenericPosterior, generic::Cache>;
 
+/// Combination of biases (read orientation, read position, softclip, homopolymer error, damage)
+/// that a model is configured for.
+type ModelMode = (bool, bool, bool, bool, Option<Damage>);
+
 #[derive(Builder)]
 #[builder(pattern = "owned")]
-pub(crate) struct Caller<Pr, CP, CF>
+pub(crate) struct Caller<Pr, CF>
 where
     Pr: bayesian::model::Prior,
-    CP: CallProcessor,
     CF: CandidateFilter,
 {
     samplenames: grammar::SampleInfo<String>,
@@ -62,24 +69,32 @@ where
     omit_softclip_bias: bool,
     omit_homopolymer_artifact_detection: bool,
     omit_alt_locus_bias: bool,
+    #[builder(default)]
+    omit_damage_bias: bool,
+    #[builder(default)]
+    fragment_length_models: grammar::SampleInfo<Option<FragmentLengthModel>>,
     scenario: grammar::Scenario,
     outbcf: Option<PathBuf>,
     aux_info_fields: Vec<Vec<u8>>,
     contaminations: grammar::SampleInfo<Option<Contamination>>,
     resolutions: grammar::SampleInfo<grammar::Resolution>,
-    prior: Pr,
+    // The prior template is cloned for each model. It is kept behind a mutex because priors
+    // contain an internal cache that may not be shared between threads.
+    prior: Mutex<Pr>,
     haplotype_feature_index: HaplotypeFeatureIndex,
     #[builder(default)]
     haplotype_results: RwLock<HashMap<HaplotypeIdentifier, HaplotypeResult>>,
     log_each_record: bool,
-    call_processor: RefCell<CP>,
     candidate_filter: CF,
+    #[builder(default = "1")]
+    threads: usize,
+    #[builder(default)]
+    record_selection: Option<Arc<RecordSelection>>,
 }
 
-impl<Pr, CP, CF> Caller<Pr, CP, CF>
+impl<Pr, CF> Caller<Pr, CF>
 where
     Pr: bayesian::model::Prior,
-    CP: CallProcessor,
     CF: CandidateFilter,
 {
     pub(crate) fn n_samples(&self) -> usize {
@@ -89,7 +104,7 @@ where
     pub(crate) fn header(&self) -> Result<(bcf::Header, AuxInfoCollector)> {
         let reader = bcf::Reader::from_path(self.observations.first_not_none().as_ref().unwrap())?;
 
-        let aux_info_collector = AuxInfoCollector::new(&self.aux_info_fields, &reader)?;
+        let aux_info_collector = AuxInfoCollector::new(&self.aux_info_fields, reader.header())?;
 
         let mut header = bcf::Header::from_template(reader.header());
 
@@ -115,13 +130,23 @@ where
         header.push_record(
             b"##INFO=<ID=PROB_ARTIFACT,Number=A,Type=Float,\
              Description=\"Posterior probability for any artifact, indicated by strand, read position, \
-             read orientation, softclip bias, or divindel bias (PHRED). See the bias specific records below for \
+             read orientation, softclip bias, divindel bias, or chemical damage (PHRED). See the bias specific records below for \
              an explanation for each type of bias.\">",
         );
         header.push_record(
             b"##INFO=<ID=PROB_ABSENT,Number=A,Type=Float,\
              Description=\"Posterior probability for not having a variant (PHRED)\">",
         );
+        header.push_record(
+            b"##INFO=<ID=REFINED_POS,Number=1,Type=Integer,\
+             Description=\"Most likely position of an imprecise variant, given the evidence \
+             of all samples for the breakpoint candidates within the confidence interval CIPOS\">",
+        );
+        header.push_record(
+            b"##INFO=<ID=REFINED_END,Number=1,Type=Integer,\
+             Description=\"Most likely end of an imprecise variant, given the evidence \
+             of all samples for the breakpoint candidates within the confidence interval CIEND\">",
+        );
 
         // register sample specific tags
         header.push_record(
@@ -230,6 +255,22 @@ where
               Probability for alt locus bias is captured by the ARTIFACT \
               event (PROB_ARTIFACT).\">",
         );
+        header.push_record(
+            b"##INFO=<ID=DAMAGE,Number=A,Type=String,\
+              Description=\"Chemical damage estimate: FFPE indicates that the ALT allele is systematically \
+              associated with cytosine deamination (C>T, or G>A if the reverse strand is damaged) in the read \
+              orientation of the damaged strand, OXOG with guanine oxidation to 8-oxoG (G>T, or C>A if the \
+              reverse strand is damaged). . indicates no damage. \
+              Probability for damage is captured by the ARTIFACT \
+              event (PROB_ARTIFACT).\">",
+        );
+        header.push_record(
+            b"##FORMAT=<ID=TO,Number=A,Type=Float,\
+              Description=\"Posterior probability in PHRED scale that the ALT allele fragments stem \
+              from tumor cells instead of healthy cells (germline variant or clonal hematopoiesis), \
+              given their fragment lengths. Only reported for samples with a fragment length \
+              model (e.g. cfDNA), see 'varlociraptor estimate fragment-lengths --help'.\">",
+        );
         header.push_record(
             b"##FORMAT=<ID=AFD,Number=.,Type=String,\
               Description=\"Sampled posterior probability densities of allele frequencies in PHRED scale \
@@ -242,47 +283,42 @@ where
         Ok((header, aux_info_collector))
     }
 
-    pub(crate) fn writer(&self) -> Result<(bcf::Writer, AuxInfoCollector)> {
-        let (header, aux_info_collector) = self.header()?;
-
-        Ok((
-            if let Some(ref path) = self.outbcf {
-                bcf::Writer::from_path(path, &header, false, bcf::Format::Bcf)
-                    .context(format!("Unable to write BCF to {}.", path.display()))?
-            } else {
-                bcf::Writer::from_stdout(&header, false, bcf::Format::Bcf)
-                    .context("Unable to write BCF to STDOUT.")?
-            },
-            aux_info_collector,
-        ))
+    pub(crate) fn writer(&self, header: &bcf::Header) -> Result<bcf::Writer> {
+        Ok(if let Some(ref path) = self.outbcf {
+            bcf::Writer::from_path(path, header, false, bcf::Format::Bcf)
+                .context(format!("Unable to write BCF to {}.", path.display()))?
+        } else {
+            bcf::Writer::from_stdout(header, false, bcf::Format::Bcf)
+                .context("Unable to write BCF to STDOUT.")?
+        })
     }
 }
 
-impl<Pr, CP, CF> Caller<Pr, CP, CF>
+impl<Pr, CF> Caller<Pr, CF>
 where
     Pr: bayesian::model::Prior<Event = AlleleFreqCombination>
         + model::prior::UpdatablePrior
         + model::prior::CheckablePrior
         + Clone
-        + Default,
-    CP: CallProcessor,
-    CF: CandidateFilter,
+        + Default
+        + Send,
+    CF: CandidateFilter + Sync,
 {
     fn model(&self) -> Model<Pr> {
         GenericModelBuilder::default()
             // TODO allow to define prior in the grammar
-            .prior(self.prior.clone())
+            .prior(self.prior.lock().unwrap().clone())
             .contaminations(self.contaminations.clone())
             .resolutions(self.resolutions.clone())
             .build()
             .unwrap()
     }
 
-    fn observations(&self) -> Result<grammar::SampleInfo<Option<bcf::Reader>>> {
+    fn observations(&self) -> Result<grammar::SampleInfo<Option<RecordReader>>> {
         let mut observations = grammar::SampleInfo::default();
         for path in self.observations.iter() {
             if let Some(path) = path {
-                let mut reader = bcf::Reader::from_path(path)?;
+                let mut reader = RecordReader::from_path(path, self.record_selection.as_ref())?;
                 reader.set_threads(1)?;
                 observations.push(Some(reader));
             } else {
@@ -292,149 +328,354 @@ where
         Ok(observations)
     }
 
-    pub(crate) fn call(&self) -> Result<()> {
+    pub(crate) fn call<CP: CallProcessor>(&self, call_processor: &mut CP) -> Result<()> {
         let mut observations = self.observations()?;
-        let aux_info_collector = self.call_processor.borrow_mut().setup(self)?;
-
-        // Check observation format.
-        for obs_reader in observations.iter_not_none() {
-            let mut valid = false;
-            for record in obs_reader.header().header_records() {
-                if let bcf::HeaderRecord::Generic { key, value } = record {
-                    if key == "varlociraptor_observation_format_version"
-                        && value == OBSERVATION_FORMAT_VERSION
-                    {
-                        valid = true;
-                    }
-                }
-            }
-            if !valid {
-                return Err(errors::Error::InvalidObservationFormat.into());
-            }
-        }
+        let aux_info_collector = call_processor.setup(self)?;
 
-        // data structures
-        // For SNVs and MNVs we need a special model as here read orientation bias and read position bias needs to be considered.
-        let mut models = HashMap::new();
-        let mut events = HashMap::new();
-        let mut last_rids = HashMap::new();
+        // Check observation format. Older formats are decoded with neutral values for
+        // fields that did not exist yet.
+        let formats = grammar::SampleInfo::from(
+            observations
+                .iter()
+                .map(|reader| {
+                    reader
+                        .as_ref()
+                        .map(|reader| ObservationFormat::from_header(reader.header()))
+                        .transpose()
+                })
+                .collect::<Result<Vec<_>>>()?,
+        );
 
         // process calls
-        let mut i = 0;
         let mut progress_logger = ProgressLogger::builder()
             .with_items_name("records")
             .with_frequency(std::time::Duration::from_secs(20))
             .start();
-        loop {
-            let mut records =
-                observations.map(|reader| reader.as_ref().map(|reader| reader.empty_record()));
-            let mut eof = Vec::new();
-            for item in observations.iter_mut().zip(records.iter_mut()) {
-                if let (Some(reader), Some(record)) = item {
-                    eof.push(match reader.read(record) {
-                        None => true,
-                        Some(res) => {
-                            res?;
-                            false
-                        }
-                    });
+
+        if self.threads > 1 {
+            self.call_parallel(
+                &mut observations,
+                &formats,
+                &aux_info_collector,
+                call_processor,
+                &mut progress_logger,
+            )?;
+        } else {
+            // For SNVs and MNVs we need a special model as here read orientation bias and read position bias needs to be considered.
+            let mut model_cache = ModelCache::default();
+            let mut known_haplotypes = HashSet::new();
+            let mut i = 0;
+            while let Some(mut records) = self.next_records(&mut observations)? {
+                let mut work_item = self.preprocess_record(
+                    &mut records,
+                    i,
+                    &observations,
+                    &formats,
+                    &aux_info_collector,
+                    &known_haplotypes,
+                )?;
+
+                if self.candidate_filter.filter(&work_item, &self.samplenames) {
+                    self.register_haplotype(&work_item, &mut known_haplotypes);
+                    self.process_work_item(&mut work_item, &mut model_cache)?;
+
+                    call_processor.process_call(work_item.call, &self.samplenames)?;
                 }
+                progress_logger.update(1u64);
+
+                i += 1;
             }
+        }
 
-            if eof.iter().all(|v| *v) {
-                self.call_processor.borrow_mut().finalize()?;
-                progress_logger.stop();
-                return Ok(());
-            } else if !eof.iter().all(|v| !v) {
-                // only some are EOF, this is an error
-                return Err(errors::Error::InconsistentObservations.into());
+        call_processor.finalize()?;
+        progress_logger.stop();
+        Ok(())
+    }
+
+    /// Evaluate the model for work items in a pool of worker threads, each holding its own models.
+    /// Records are read and calls are passed to the call processor by the current thread,
+    /// in the order of the input records.
+    fn call_parallel<CP: CallProcessor>(
+        &self,
+        observations: &mut grammar::SampleInfo<Option<RecordReader>>,
+        formats: &grammar::SampleInfo<Option<ObservationFormat>>,
+        aux_info_collector: &Option<AuxInfoCollector>,
+        call_processor: &mut CP,
+        progress_logger: &mut ProgressLogger,
+    ) -> Result<()> {
+        let (work_sender, work_receiver) = mpsc::sync_channel(self.threads * 2);
+        let work_receiver = Mutex::new(work_receiver);
+        let (result_sender, result_receiver) = mpsc::channel();
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads {
+                let work_receiver = &work_receiver;
+                let result_sender = result_sender.clone();
+                scope.spawn(move || {
+                    let mut model_cache = ModelCache::default();
+                    loop {
+                        let received = work_receiver.lock().unwrap().recv();
+                        if let Ok((seq, mut work_item)) = received {
+                            let result = self
+                                .process_work_item(&mut work_item, &mut model_cache)
+                                .map(|_| (seq, work_item));
+                            if result_sender.send(result).is_err() {
+                                // The receiving side has stopped because of an error.
+                                break;
+                            }
+                        } else {
+                            // All work items have been processed.
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(result_sender);
+
+            let mut pending_calls = PendingCalls::default();
+            self.dispatch_work_items(
+                observations,
+                formats,
+                aux_info_collector,
+                call_processor,
+                progress_logger,
+                work_sender,
+                &result_receiver,
+                &mut pending_calls,
+            )?;
+
+            for result in result_receiver {
+                let (seq, work_item) = result?;
+                pending_calls.insert(seq, PendingCall::Called(work_item));
             }
+            self.process_pending_calls(&mut pending_calls, call_processor)?;
 
-            // ensure that all observation BCFs contain exactly the same calls
-            let first_record = records.first_not_none()?;
-            let current_rid = first_record.rid();
-            let current_pos = first_record.pos();
-            let current_alleles = first_record.alleles();
-            for record in records[1..].iter().flatten() {
-                if record.rid() != current_rid
-                    || record.pos() != current_pos
-                    || record.alleles() != current_alleles
-                {
-                    return Err(errors::Error::InconsistentObservations.into());
+            Ok(())
+        })
+    }
+
+    /// Read records, send work items to the workers and pass finished calls to the call processor.
+    /// The work sender is consumed such that workers terminate once all items have been sent,
+    /// regardless of whether an error occurred.
+    #[allow(clippy::too_many_arguments)]
+    fn dispatch_work_items<CP: CallProcessor>(
+        &self,
+        observations: &mut grammar::SampleInfo<Option<RecordReader>>,
+        formats: &grammar::SampleInfo<Option<ObservationFormat>>,
+        aux_info_collector: &Option<AuxInfoCollector>,
+        call_processor: &mut CP,
+        progress_logger: &mut ProgressLogger,
+        work_sender: mpsc::SyncSender<(usize, WorkItem)>,
+        result_receiver: &mpsc::Receiver<Result<(usize, WorkItem)>>,
+        pending_calls: &mut PendingCalls,
+    ) -> Result<()> {
+        // METHOD: bound the number of calls waiting for being processed in order,
+        // such that a single expensive record does not lead to unbounded memory usage.
+        let max_pending = self.threads * 16;
+        let mut known_haplotypes = HashSet::new();
+        let mut seq = 0;
+        let mut i = 0;
+        while let Some(mut records) = self.next_records(observations)? {
+            let work_item = self.preprocess_record(
+                &mut records,
+                i,
+                observations,
+                formats,
+                aux_info_collector,
+                &known_haplotypes,
+            )?;
+
+            if self.candidate_filter.filter(&work_item, &self.samplenames) {
+                let is_known_haplotype = work_item
+                    .haplotype
+                    .as_ref()
+                    .is_some_and(|haplotype| known_haplotypes.contains(haplotype));
+                self.register_haplotype(&work_item, &mut known_haplotypes);
+                if is_known_haplotype {
+                    // METHOD: results will be copied from the first record of the haplotype,
+                    // which is processed before because calls are handled in order.
+                    pending_calls.insert(seq, PendingCall::Copy(work_item));
+                } else if work_sender.send((seq, work_item)).is_err() {
+                    // All workers are gone, which can only happen if they panicked.
+                    panic!("bug: calling worker threads terminated unexpectedly");
                 }
+                seq += 1;
             }
+            progress_logger.update(1u64);
+            i += 1;
 
-            if self.log_each_record {
-                info!(
-                    "Processing record at {}:{}",
-                    first_record.contig(),
-                    first_record.pos() + 1
-                );
+            while let Ok(result) = result_receiver.try_recv() {
+                let (seq, work_item) = result?;
+                pending_calls.insert(seq, PendingCall::Called(work_item));
             }
+            self.process_pending_calls(pending_calls, call_processor)?;
+
+            while seq - pending_calls.next_seq > max_pending {
+                let (seq, work_item) = result_receiver
+                    .recv()
+                    .expect("bug: calling worker threads terminated unexpectedly")?;
+                pending_calls.insert(seq, PendingCall::Called(work_item));
+                self.process_pending_calls(pending_calls, call_processor)?;
+            }
+        }
 
-            // obtain variant type
-            let variant_type = utils::collect_variants(records.first_not_none_mut()?, false, None)?
-                [0]
-            .variant()
-            .to_type();
-
-            let mut work_item =
-                self.preprocess_record(&mut records, i, &observations, &aux_info_collector)?;
+        Ok(())
+    }
 
-            if self.candidate_filter.filter(&work_item, &self.samplenames) {
-                // process work item
-                let contig = str::from_utf8(work_item.call.chrom()).unwrap();
-                let _last_rid;
-
-                let model_mode = (
-                    work_item.check_read_orientation_bias,
-                    work_item.check_read_position_bias,
-                    work_item.check_softclip_bias,
-                    work_item.check_homopolymer_artifact_detection,
-                );
-                let _model = models.entry(model_mode).or_insert_with(|| self.model());
-                let _events = events.entry(model_mode).or_insert_with(Vec::new);
-                {
-                    let entry = last_rids.entry(model_mode).or_insert(None);
-                    _last_rid = *entry;
-                    *entry = Some(work_item.rid);
+    /// Pass all calls that are next in the order of input records to the call processor.
+    fn process_pending_calls<CP: CallProcessor>(
+        &self,
+        pending_calls: &mut PendingCalls,
+        call_processor: &mut CP,
+    ) -> Result<()> {
+        while let Some(pending_call) = pending_calls.calls.remove(&pending_calls.next_seq) {
+            let work_item = match pending_call {
+                PendingCall::Called(work_item) => work_item,
+                PendingCall::Copy(mut work_item) => {
+                    let copied = self.copy_haplotype_result(&mut work_item);
+                    assert!(
+                        copied,
+                        "bug: no result for previous record of the same haplotype"
+                    );
+                    work_item
                 }
+            };
+            call_processor.process_call(work_item.call, &self.samplenames)?;
+            pending_calls.next_seq += 1;
+        }
+        Ok(())
+    }
 
-                self.configure_model(
-                    work_item.rid,
-                    _last_rid,
-                    _model,
-                    _events,
-                    contig,
-                    variant_type,
-                    work_item.check_read_orientation_bias,
-                    work_item.check_strand_bias,
-                    work_item.check_read_position_bias,
-                    work_item.check_softclip_bias,
-                    work_item.check_homopolymer_artifact_detection,
-                    work_item.check_alt_locus_bias,
-                )?;
+    /// Read the next record from each observation BCF. Returns None at the end of the files.
+    fn next_records(
+        &self,
+        observations: &mut grammar::SampleInfo<Option<RecordReader>>,
+    ) -> Result<Option<grammar::SampleInfo<Option<bcf::Record>>>> {
+        let mut records =
+            observations.map(|reader| reader.as_ref().map(|reader| reader.empty_record()));
+        let mut eof = Vec::new();
+        for item in observations.iter_mut().zip(records.iter_mut()) {
+            if let (Some(reader), Some(record)) = item {
+                eof.push(match reader.read(record) {
+                    None => true,
+                    Some(res) => {
+                        res?;
+                        false
+                    }
+                });
+            }
+        }
 
-                self.call_record(&mut work_item, _model, _events);
+        if eof.iter().all(|v| *v) {
+            return Ok(None);
+        } else if !eof.iter().all(|v| !v) {
+            // only some are EOF, this is an error
+            return Err(errors::Error::InconsistentObservations.into());
+        }
 
-                self.call_processor
-                    .borrow_mut()
-                    .process_call(work_item.call, &self.samplenames)?;
+        // ensure that all observation BCFs contain exactly the same calls
+        let first_record = records.first_not_none()?;
+        let current_rid = first_record.rid();
+        let current_pos = first_record.pos();
+        let current_alleles = first_record.alleles();
+        for record in records[1..].iter().flatten() {
+            if record.rid() != current_rid
+                || record.pos() != current_pos
+                || record.alleles() != current_alleles
+            {
+                return Err(errors::Error::InconsistentObservations.into());
             }
-            progress_logger.update(1u64);
+        }
 
-            i += 1;
+        if self.log_each_record {
+            info!(
+                "Processing record at {}:{}",
+                first_record.contig(),
+                first_record.pos() + 1
+            );
         }
+
+        Ok(Some(records))
+    }
+
+    /// Remember that a record of the haplotype of the given work item will be called,
+    /// such that remaining records of the haplotype can reuse the results.
+    fn register_haplotype(
+        &self,
+        work_item: &WorkItem,
+        known_haplotypes: &mut HashSet<HaplotypeIdentifier>,
+    ) {
+        if let Some(ref haplotype) = work_item.haplotype {
+            if self.haplotype_feature_index.last_record_index(haplotype) == Some(work_item.index) {
+                known_haplotypes.remove(haplotype);
+            } else {
+                known_haplotypes.insert(haplotype.to_owned());
+            }
+        }
+    }
+
+    fn process_work_item(
+        &self,
+        work_item: &mut WorkItem,
+        model_cache: &mut ModelCache<Pr>,
+    ) -> Result<()> {
+        let contig = str::from_utf8(work_item.call.chrom()).unwrap();
+
+        let model_mode = (
+            work_item.check_read_orientation_bias,
+            work_item.check_read_position_bias,
+            work_item.check_softclip_bias,
+            work_item.check_homopolymer_artifact_detection,
+            work_item.check_damage_bias,
+        );
+        let model = model_cache
+            .models
+            .entry(model_mode)
+            .or_insert_with(|| self.model());
+        let events = model_cache.events.entry(model_mode).or_default();
+        let last_rid = {
+            let entry = model_cache.last_rids.entry(model_mode).or_insert(None);
+            let last_rid = *entry;
+            *entry = Some(work_item.rid);
+            last_rid
+        };
+
+        self.configure_model(
+            work_item.rid,
+            last_rid,
+            model,
+            events,
+            contig,
+            work_item.variant_type.clone(),
+            work_item.check_read_orientation_bias,
+            work_item.check_strand_bias,
+            work_item.check_read_position_bias,
+            work_item.check_softclip_bias,
+            work_item.check_homopolymer_artifact_detection,
+            work_item.check_alt_locus_bias,
+            work_item.check_damage_bias,
+        )?;
+
+        self.call_record(work_item, model, events);
+
+        Ok(())
     }
 
     fn preprocess_record(
         &self,
         records: &mut grammar::SampleInfo<Option<bcf::Record>>,
         index: usize,
-        observations: &grammar::SampleInfo<Option<bcf::Reader>>,
+        observations: &grammar::SampleInfo<Option<RecordReader>>,
+        formats: &grammar::SampleInfo<Option<ObservationFormat>>,
         aux_info_collector: &Option<AuxInfoCollector>,
+        known_haplotypes: &HashSet<HaplotypeIdentifier>,
     ) -> Result<WorkItem> {
-        let (call, snv, haplotype, rid, is_snv_or_mnv) = {
+        // obtain variant type
+        let variant_type = utils::collect_variants(records.first_not_none_mut()?, false, None)?[0]
+            .variant()
+            .to_type();
+
+        let (call, snv, damage, haplotype, rid, is_snv_or_mnv) = {
             let first_record = records.first_not_none_mut()?;
             let start = first_record.pos() as u64;
             let chrom = chrom(observations.first_not_none()?, first_record);
@@ -462,6 +703,7 @@ where
 
             // store information about SNV for special handling in posterior computation (variant selection operations)
             let snv;
+            let damage;
             let is_snv_or_mnv;
             {
                 let alleles = first_record.alleles();
@@ -471,12 +713,19 @@ where
                         alleles[0][0],
                         alleles[1][0],
                     ));
+                    damage = Damage::from_substitution(alleles[0][0], alleles[1][0]);
                 } else if alleles[0].len() == alleles[1].len() {
+                    // METHOD: damage is only considered for single-base SNV records. Damage
+                    // affects each base of a fragment independently, hence it is very unlikely
+                    // to produce all substitutions of an MNV in the same fragment, and
+                    // preprocessing does not record a damage signature for MNVs.
                     is_snv_or_mnv = true;
                     snv = None;
+                    damage = None;
                 } else {
                     is_snv_or_mnv = false;
                     snv = None;
+                    damage = None;
                 }
             };
             let haplotype = HaplotypeIdentifier::from(first_record)?;
@@ -485,7 +734,7 @@ where
                 .rid()
                 .ok_or(errors::Error::RecordMissingChrom { i: index + 1 })?;
 
-            (call, snv, haplotype, rid, is_snv_or_mnv)
+            (call, snv, damage, haplotype, rid, is_snv_or_mnv)
         };
 
         let mut variant_builder = VariantBuilder::default();
@@ -496,6 +745,7 @@ where
         // METHOD: for imprecise variants, we can skip various meaninless biases below
         let mut work_item = WorkItem {
             rid,
+            variant_type,
             call,
             pileups: None,
             snv,
@@ -510,15 +760,11 @@ where
             check_softclip_bias: is_snv_or_mnv && !self.omit_softclip_bias && is_precise,
             check_homopolymer_artifact_detection: false,
             check_alt_locus_bias: !self.omit_alt_locus_bias,
+            check_damage_bias: damage.filter(|_| !self.omit_damage_bias && is_precise),
         };
 
         if let Some(ref haplotype) = work_item.haplotype {
-            if self
-                .haplotype_results
-                .read()
-                .unwrap()
-                .contains_key(haplotype)
-            {
+            if known_haplotypes.contains(haplotype) {
                 // METHOD: Another breakend in the same event was already processed, hence, we will just copy the
                 // results (no pileup needed).
                 return Ok(work_item);
@@ -527,12 +773,12 @@ where
 
         // obtain pileups
         let mut pileups = Vec::new();
-        for record in records.iter_mut() {
-            let pileup = if let Some(record) = record {
+        for (record, format) in records.iter_mut().zip(formats.iter()) {
+            let pileup = if let (Some(record), Some(format)) = (record, format) {
                 let Observations {
                     mut pileup,
                     is_homopolymer_indel,
-                } = read_observations(record)?;
+                } = read_observations(record, *format)?;
                 if is_homopolymer_indel && !self.omit_homopolymer_artifact_detection {
                     // METHOD: check for homopolymer artifacts if at least one pileup contains the corresponding information.
                     work_item.check_homopolymer_artifact_detection |= true;
@@ -552,6 +798,14 @@ where
             pileups.push(pileup);
         }
 
+        work_item
+            .variant_builder
+            .refined_breakpoints(refined_breakpoints(
+                pileups
+                    .iter()
+                    .map(|pileup| &pileup.breakpoint_observations()[..]),
+            ));
+
         work_item.pileups = Some(pileups);
 
         Ok(work_item)
@@ -571,6 +825,7 @@ where
         consider_softclip_bias: bool,
         consider_homopolymer_error: bool,
         consider_alt_locus_bias: bool,
+        consider_damage: Option<Damage>,
     ) -> Result<()> {
         if !rid.map_or(false, |rid: u32| current_rid == rid) || events.is_empty() {
             // rid is not the same as before or the model mode has changed to something new, obtain event universe
@@ -599,6 +854,7 @@ where
                     consider_softclip_bias,
                     consider_homopolymer_error,
                     consider_alt_locus_bias,
+                    consider_damage,
                 )
                 .collect();
 
@@ -640,21 +896,8 @@ where
         model: &Model<Pr>,
         event_universe: &[model::Event],
     ) {
-        if let Some(ref haplotype) = work_item.haplotype {
-            if let Some(result) = self.haplotype_results.read().unwrap().get(haplotype) {
-                // Take sample info and event probs from previous item.
-                work_item
-                    .variant_builder
-                    .event_probs(Some(result.event_probs.clone()));
-                work_item
-                    .variant_builder
-                    .sample_info(result.sample_info.clone());
-
-                let variant = work_item.variant_builder.build().unwrap();
-                work_item.call.variant = Some(variant);
-
-                return;
-            }
+        if self.copy_haplotype_result(work_item) {
+            return;
         }
 
         if work_item.pileups.is_some() {
@@ -758,6 +1001,34 @@ where
         work_item.call.variant = Some(variant);
     }
 
+    /// Take sample info and event probs from a previously called record of the same haplotype.
+    /// Returns false if there is no such record.
+    fn copy_haplotype_result(&self, work_item: &mut WorkItem) -> bool {
+        if let Some(ref haplotype) = work_item.haplotype {
+            if let Some(result) = self.haplotype_results.read().unwrap().get(haplotype) {
+                work_item
+                    .variant_builder
+                    .event_probs(Some(result.event_probs.clone()));
+                work_item
+                    .variant_builder
+                    .sample_info(result.sample_info.clone());
+
+                let variant = work_item.variant_builder.build().unwrap();
+                work_item.call.variant = Some(variant);
+            } else {
+                return false;
+            }
+
+            if self.haplotype_feature_index.last_record_index(haplotype) == Some(work_item.index) {
+                // METHOD: last index, hence clear result
+                self.haplotype_results.write().unwrap().remove(haplotype);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
     fn sample_infos(
         &self,
         model_instance: &bayesian::model::ModelInstance<AlleleFreqCombination, model::Event>,
@@ -788,7 +1059,8 @@ where
                 .enumerate()
                 .map(|(sample, (pileup, estimate))| {
                     let mut sample_builder = SampleInfoBuilder::default();
-                    sample_builder.pileup(Rc::new(pileup));
+                    let pileup = Arc::new(pileup);
+                    sample_builder.pileup(Arc::clone(&pileup));
                     match estimate {
                         model::likelihood::Event {
                             artifacts: biases, ..
@@ -844,6 +1116,17 @@ where
                         None
                     });
 
+                    if let Some(Some(fragment_length_model)) =
+                        self.fragment_length_models.get(sample)
+                    {
+                        if !estimate.is_artifact() {
+                            sample_builder.prob_tumor_origin(
+                                fragment_length_model
+                                    .prob_tumor_origin(&pileup, estimate.allele_freq),
+                            );
+                        }
+                    }
+
                     Some(sample_builder.build().unwrap())
                 })
                 .collect_vec();
@@ -854,6 +1137,42 @@ where
     }
 }
 
+/// Models and event universes of a single thread, one for each model mode.
+struct ModelCache<Pr: bayesian::model::Prior> {
+    models: HashMap<ModelMode, Model<Pr>>,
+    events: HashMap<ModelMode, Vec<model::Event>>,
+    last_rids: HashMap<ModelMode, Option<u32>>,
+}
+
+impl<Pr: bayesian::model::Prior> Default for ModelCache<Pr> {
+    fn default() -> Self {
+        ModelCache {
+            models: HashMap::new(),
+            events: HashMap::new(),
+            last_rids: HashMap::new(),
+        }
+    }
+}
+
+enum PendingCall {
+    Called(WorkItem),
+    /// Record whose results are copied from a previous record of the same haplotype.
+    Copy(WorkItem),
+}
+
+/// Calls that wait for being passed to the call processor in the order of input records.
+#[derive(Default)]
+struct PendingCalls {
+    calls: BTreeMap<usize, PendingCall>,
+    next_seq: usize,
+}
+
+impl PendingCalls {
+    fn insert(&mut self, seq: usize, call: PendingCall) {
+        self.calls.insert(seq, call);
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct HaplotypeResult {
     event_probs: HashMap<String, LogProb>,
@@ -864,6 +1183,7 @@ pub(crate) struct HaplotypeResult {
 #[getset(get = "pub(crate)")]
 pub(crate) struct WorkItem {
     rid: u32,
+    variant_type: model::VariantType,
     call: Call,
     variant_builder: VariantBuilder,
     pileups: Option<Vec<Pileup>>,
@@ -876,12 +1196,14 @@ pub(crate) struct WorkItem {
     check_softclip_bias: bool,
     check_homopolymer_artifact_detection: bool,
     check_alt_locus_bias: bool,
+    /// Damage that can cause the variant (if it is a substitution of a damage type).
+    check_damage_bias: Option<Damage>,
 }
 
 pub(crate) trait CallProcessor: Sized {
     fn setup<Pr: bayesian::model::Prior, CF: CandidateFilter>(
         &mut self,
-        caller: &Caller<Pr, Self, CF>,
+        caller: &Caller<Pr, CF>,
     ) -> Result<Option<AuxInfoCollector>>;
 
     fn process_call(
@@ -896,24 +1218,32 @@ pub(crate) trait CallProcessor: Sized {
 pub(crate) struct CallWriter {
     #[new(default)]
     bcf_writer: Option<bcf::Writer>,
+    /// Phase calls via the fragments they share.
+    phaser: Option<Phaser>,
 }
 
 impl CallProcessor for CallWriter {
     fn setup<Pr: bayesian::model::Prior, CF: CandidateFilter>(
         &mut self,
-        caller: &Caller<Pr, Self, CF>,
+        caller: &Caller<Pr, CF>,
     ) -> Result<Option<AuxInfoCollector>> {
-        let (writer, aux_info_collector) = caller.writer()?;
-        self.bcf_writer = Some(writer);
+        let (mut header, aux_info_collector) = caller.header()?;
+        if self.phaser.is_some() {
+            add_phasing_header_entries(&mut header);
+        }
+        self.bcf_writer = Some(caller.writer(&header)?);
 
         Ok(Some(aux_info_collector))
     }
 
     fn process_call(
         &mut self,
-        call: Call,
+        mut call: Call,
         _sample_names: &grammar::SampleInfo<String>,
     ) -> Result<()> {
+        if let Some(ref mut phaser) = self.phaser {
+            phaser.phase(&mut call);
+        }
         call.write_final_record(self.bcf_writer.as_mut().unwrap())
     }
 
@@ -945,16 +1275,20 @@ pub(crate) fn call_generic<CP, CF>(
     omit_softclip_bias: bool,
     omit_homopolymer_artifact_detection: bool,
     omit_alt_locus_bias: bool,
+    omit_damage_bias: bool,
+    fragment_length_models: PathMap,
     output: Option<PathBuf>,
     log_each_record: bool,
-    call_processor: CP,
+    mut call_processor: CP,
     candidate_filter: CF,
     propagate_info_fields: Vec<String>,
     full_prior: bool,
+    threads: usize,
+    regions: Option<Regions>,
 ) -> Result<()>
 where
     CP: CallProcessor,
-    CF: CandidateFilter,
+    CF: CandidateFilter + Sync,
 {
     let sample_infos = SampleInfos::try_from(&scenario)?;
 
@@ -978,13 +1312,42 @@ where
         }
     }
 
+    for model_sample_name in fragment_length_models.keys() {
+        if !sample_infos.names.as_slice().contains(model_sample_name) {
+            return Err(errors::Error::InvalidFragmentLengthModelSampleName {
+                name: model_sample_name.to_owned(),
+            }
+            .into());
+        }
+    }
+
+    // load fragment length models
+    let mut sample_fragment_length_models = scenario.sample_info();
+    for (sample_name, _) in scenario.samples().iter() {
+        let model = fragment_length_models
+            .get(sample_name)
+            .map(FragmentLengthModel::from_path)
+            .transpose()?;
+        sample_fragment_length_models = sample_fragment_length_models.push(sample_name, model);
+    }
+    let sample_fragment_length_models = sample_fragment_length_models.build();
+
     let propagate_info_fields = propagate_info_fields
         .iter()
         .map(|s| s.as_bytes().to_owned())
         .collect();
 
-    let haplotype_feature_index =
-        HaplotypeFeatureIndex::new(sample_observations.first_not_none()?)?;
+    // METHOD: all observation BCFs contain the same records, hence it suffices to determine
+    // the records to process from the first one.
+    let record_selection = regions
+        .map(|regions| RecordSelection::new(sample_observations.first_not_none()?, regions))
+        .transpose()?
+        .map(Arc::new);
+
+    let haplotype_feature_index = HaplotypeFeatureIndex::new(
+        sample_observations.first_not_none()?,
+        record_selection.as_ref(),
+    )?;
 
     let prior = Prior::builder()
         .ploidies(None)
@@ -1013,21 +1376,24 @@ where
         .omit_softclip_bias(omit_softclip_bias)
         .omit_homopolymer_artifact_detection(omit_homopolymer_artifact_detection)
         .omit_alt_locus_bias(omit_alt_locus_bias)
+        .omit_damage_bias(omit_damage_bias)
+        .fragment_length_models(sample_fragment_length_models)
         .scenario(scenario)
-        .prior(prior)
+        .prior(Mutex::new(prior))
         .contaminations(sample_infos.contaminations)
         .resolutions(sample_infos.resolutions)
         .haplotype_feature_index(haplotype_feature_index)
         .outbcf(output)
         .aux_info_fields(propagate_info_fields)
         .log_each_record(log_each_record)
-        .call_processor(RefCell::new(call_processor))
         .candidate_filter(candidate_filter)
+        .threads(threads)
+        .record_selection(record_selection)
         .build()
         .unwrap();
 
     // call
-    caller.call()?;
+    caller.call(&mut call_processor)?;
 
     Ok(())
 }
@@ -1128,3 +1494,141 @@ impl<'a> TryFrom<&'a grammar::Scenario> for SampleInfos {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::calling::variants::preprocessing::{
+        add_observation_header_entries, write_observations, OBSERVATION_FORMAT_VERSION,
+    };
+    use crate::variants::model::tests::observation;
+
+    /// Write observations for records on two contigs, with a haplotype block of three records
+    /// (of which the latter two obtain a copy of the results of the first one).
+    fn write_test_observations(path: &Path) {
+        let mut header = bcf::Header::new();
+        for info in [
+            "##INFO=<ID=SVLEN,Number=.,Type=Integer,Description=\"SVLEN\">",
+            "##INFO=<ID=END,Number=1,Type=Integer,Description=\"END\">",
+            "##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"SVTYPE\">",
+            "##INFO=<ID=EVENT,Number=1,Type=String,Description=\"EVENT\">",
+            "##INFO=<ID=MATEID,Number=.,Type=String,Description=\"MATEID\">",
+            "##INFO=<ID=IMPRECISE,Number=0,Type=Flag,Description=\"IMPRECISE\">",
+            "##INFO=<ID=CIPOS,Number=2,Type=Integer,Description=\"CIPOS\">",
+            "##INFO=<ID=CIEND,Number=2,Type=Integer,Description=\"CIEND\">",
+            "##contig=<ID=chr1,length=1000>",
+            "##contig=<ID=chr2,length=1000>",
+        ]
+        .iter()
+        {
+            header.push_record(info.as_bytes());
+        }
+        add_observation_header_entries(&mut header);
+        header.push_record(
+            format!(
+                "##varlociraptor_observation_format_version={}",
+                OBSERVATION_FORMAT_VERSION
+            )
+            .as_bytes(),
+        );
+
+        let mut writer = bcf::Writer::from_path(path, &header, true, bcf::Format::Bcf).unwrap();
+        for i in 0..40 {
+            let rid = if i < 20 { 0 } else { 1 };
+            let mut record = writer.empty_record();
+            record.set_rid(Some(rid));
+            record.set_pos(10 + 10 * (i % 20));
+            let alleles: [&[u8]; 2] = match i % 3 {
+                0 => [b"A", b"T"],
+                1 => [b"C", b"G"],
+                _ => [b"AC", b"A"],
+            };
+            record.set_alleles(&alleles).unwrap();
+            if (5..8).contains(&i) {
+                record.push_info_string(b"EVENT", &[b"haplotype"]).unwrap();
+            }
+
+            let n_alt = i % 7;
+            let n_ref = 10 - n_alt;
+            let read_observations = (0..n_alt)
+                .map(|_| observation(LogProb::ln_one(), LogProb::ln_one(), LogProb(0.01f64.ln())))
+                .chain((0..n_ref).map(|_| {
+                    observation(LogProb::ln_one(), LogProb(0.01f64.ln()), LogProb::ln_one())
+                }))
+                .collect();
+            write_observations(&Pileup::new(read_observations, Vec::new()), &mut record).unwrap();
+            writer.write(&record).unwrap();
+        }
+    }
+
+    fn call(observations: &Path, output: &Path, threads: usize) {
+        let scenario: grammar::Scenario = serde_yaml::from_str(
+            r#"samples:
+  sample:
+    universe: "[0.0,1.0]"
+events:
+  present: "sample:]0.0,1.0]"
+  absent: "sample:0.0""#,
+        )
+        .unwrap();
+        let mut sample_observations = PathMap::default();
+        sample_observations.insert("sample".to_owned(), observations.to_owned());
+
+        call_generic(
+            scenario,
+            sample_observations,
+            false,
+            false,
+            false,
+            false,
+            false,
+            false,
+            false,
+            PathMap::default(),
+            Some(output.to_owned()),
+            false,
+            CallWriter::new(None),
+            DefaultCandidateFilter::new(),
+            Vec::new(),
+            false,
+            threads,
+            None,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_call_parallel() {
+        let tmp = tempfile::tempdir().unwrap();
+        let observations = tmp.path().join("observations.bcf");
+        write_test_observations(&observations);
+
+        let serial = tmp.path().join("serial.bcf");
+        let parallel = tmp.path().join("parallel.bcf");
+        call(&observations, &serial, 1);
+        call(&observations, &parallel, 4);
+
+        let mut reader = bcf::Reader::from_path(&parallel).unwrap();
+        let records = reader.records().map(|record| record.unwrap()).collect_vec();
+        assert_eq!(records.len(), 40);
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| (record.rid().unwrap(), record.pos()))
+                .collect_vec(),
+            (0..40)
+                .map(|i| (if i < 20 { 0 } else { 1 }, 10 + 10 * (i % 20)))
+                .collect_vec()
+        );
+        // the records of the haplotype share their results
+        let prob_absent =
+            |record: &bcf::Record| record.info(b"PROB_ABSENT").float().unwrap().unwrap()[0];
+        assert_eq!(prob_absent(&records[5]), prob_absent(&records[6]));
+        assert_eq!(prob_absent(&records[5]), prob_absent(&records[7]));
+
+        assert_eq!(fs::read(&serial).unwrap(), fs::read(&parallel).unwrap());
+    }
+}
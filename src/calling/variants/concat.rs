@@ -0,0 +1,222 @@
+// Copyright 2024 Johannes Köster.
+// Licensed under the GNU GPLv3 license (https://opensource.org/licenses/GPL-3.0)
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Concatenation of observation or call BCFs that have been obtained from disjoint
+//! regions (see `--regions`) of the same candidate variants.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rust_htslib::bcf::{self, header::HeaderView, Read};
+
+use crate::calling::variants::preprocessing::{
+    read_observation_format_version, read_preprocess_options,
+};
+use crate::cli;
+use crate::errors;
+
+/// Concatenate the given observation BCFs, ensuring that they stem from the same
+/// preprocessing setup.
+pub(crate) fn concat_observations<P: AsRef<Path>>(
+    inputs: &[P],
+    output: Option<&Path>,
+) -> Result<()> {
+    let reference = &inputs[0];
+    let format_version = read_observation_format_version(reference)?;
+    let options = shard_independent_preprocess_options(reference)?;
+    for input in &inputs[1..] {
+        if read_observation_format_version(input)? != format_version {
+            return Err(incompatible(input, "observation format version differs"));
+        }
+        if shard_independent_preprocess_options(input)? != options {
+            return Err(incompatible(input, "preprocessing options differ"));
+        }
+    }
+
+    concat(inputs, output)
+}
+
+/// Concatenate the given call BCFs.
+pub(crate) fn concat_calls<P: AsRef<Path>>(inputs: &[P], output: Option<&Path>) -> Result<()> {
+    concat(inputs, output)
+}
+
+/// Preprocessing options as JSON, without those that naturally differ between runs
+/// on different regions.
+fn shard_independent_preprocess_options<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut options = read_preprocess_options(path)?;
+    if let cli::Varlociraptor::Preprocess {
+        kind:
+            cli::PreprocessKind::Variants {
+                ref mut output,
+                ref mut output_raw_observations,
+                ..
+            },
+    } = options
+    {
+        *output = None;
+        *output_raw_observations = None;
+    }
+    Ok(serde_json::to_string(&options)?)
+}
+
+fn incompatible<P: AsRef<Path>>(path: P, msg: &str) -> anyhow::Error {
+    errors::Error::IncompatibleConcatInput {
+        path: path.as_ref().to_owned(),
+        msg: msg.to_owned(),
+    }
+    .into()
+}
+
+/// Check that the given header has the same samples, contigs and fields as the reference header.
+fn check_header<P: AsRef<Path>>(
+    path: P,
+    header: &HeaderView,
+    reference: &HeaderView,
+) -> Result<()> {
+    if header.samples() != reference.samples() {
+        return Err(incompatible(path, "samples differ"));
+    }
+
+    let contigs = |header: &HeaderView| {
+        (0..header.contig_count())
+            .map(|rid| Ok(header.rid2name(rid)?.to_owned()))
+            .collect::<Result<Vec<_>>>()
+    };
+    if contigs(header)? != contigs(reference)? {
+        return Err(incompatible(path, "contigs differ"));
+    }
+
+    // METHOD: records are translated to the header of the first input, which requires
+    // the INFO and FORMAT fields to be defined in the same order.
+    let fields = |header: &HeaderView| {
+        header
+            .header_records()
+            .into_iter()
+            .filter_map(|record| match record {
+                bcf::header::HeaderRecord::Info { values, .. } => {
+                    Some((b'I', values.get("ID").cloned()))
+                }
+                bcf::header::HeaderRecord::Format { values, .. } => {
+                    Some((b'F', values.get("ID").cloned()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    if fields(header) != fields(reference) {
+        return Err(incompatible(path, "INFO or FORMAT fields differ"));
+    }
+
+    Ok(())
+}
+
+/// Merge the records of the given BCFs by contig and position, while keeping the order of
+/// records within each input. Since each input is in the order of the candidate variants,
+/// this repairs the order at region boundaries, e.g. in case a region has processed a
+/// haplotype whose last record is located in a subsequent region.
+fn concat<P: AsRef<Path>>(inputs: &[P], output: Option<&Path>) -> Result<()> {
+    let mut readers = inputs
+        .iter()
+        .map(|path| {
+            bcf::Reader::from_path(path)
+                .with_context(|| format!("Unable to read BCF {}.", path.as_ref().display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for (path, reader) in inputs.iter().zip(readers.iter()).skip(1) {
+        check_header(path, reader.header(), readers[0].header())?;
+    }
+
+    let header = bcf::Header::from_template(readers[0].header());
+    let mut writer = if let Some(path) = output {
+        bcf::Writer::from_path(path, &header, false, bcf::Format::Bcf)
+            .context(format!("Unable to write BCF to {}.", path.display()))?
+    } else {
+        bcf::Writer::from_stdout(&header, false, bcf::Format::Bcf)
+            .context("Unable to write BCF to STDOUT.")?
+    };
+
+    let mut current_records: Vec<Option<bcf::Record>> = Vec::with_capacity(readers.len());
+    let mut heap = BinaryHeap::new();
+    for (i, reader) in readers.iter_mut().enumerate() {
+        let record = read_next(reader, i, &mut heap)?;
+        current_records.push(record);
+    }
+
+    while let Some(Reverse((_, _, i))) = heap.pop() {
+        let mut record = current_records[i].take().unwrap();
+        writer.translate(&mut record);
+        writer.write(&record)?;
+        current_records[i] = read_next(&mut readers[i], i, &mut heap)?;
+    }
+
+    Ok(())
+}
+
+type MergeKey = Reverse<(u32, i64, usize)>;
+
+fn read_next(
+    reader: &mut bcf::Reader,
+    input_idx: usize,
+    heap: &mut BinaryHeap<MergeKey>,
+) -> Result<Option<bcf::Record>> {
+    let mut record = reader.empty_record();
+    match reader.read(&mut record) {
+        None => Ok(None),
+        Some(res) => {
+            res?;
+            let rid = record.rid().context("BCF record without contig")?;
+            heap.push(Reverse((rid, record.pos(), input_idx)));
+            Ok(Some(record))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_bcf(path: &Path, positions: &[(&[u8], i64)]) {
+        let mut header = bcf::Header::new();
+        header.push_record(b"##contig=<ID=chr1,length=1000>");
+        header.push_record(b"##contig=<ID=chr2,length=1000>");
+        let mut writer = bcf::Writer::from_path(path, &header, true, bcf::Format::Bcf).unwrap();
+        for (contig, pos) in positions {
+            let mut record = writer.empty_record();
+            record.set_rid(Some(writer.header().name2rid(contig).unwrap()));
+            record.set_pos(*pos);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            writer.write(&record).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_concat_repairs_order_at_region_boundaries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let first = tmp.path().join("first.bcf");
+        let second = tmp.path().join("second.bcf");
+        let output = tmp.path().join("output.bcf");
+        // The first region owns a haplotype whose last record is located in the second region.
+        write_bcf(&first, &[(b"chr1", 10), (b"chr1", 20), (b"chr1", 150)]);
+        write_bcf(&second, &[(b"chr1", 100), (b"chr1", 200), (b"chr2", 5)]);
+
+        concat_calls(&[&first, &second], Some(&output)).unwrap();
+
+        let mut reader = bcf::Reader::from_path(&output).unwrap();
+        let loci: Vec<_> = reader
+            .records()
+            .map(|record| {
+                let record = record.unwrap();
+                (record.rid().unwrap(), record.pos())
+            })
+            .collect();
+        assert_eq!(
+            loci,
+            vec![(0, 10), (0, 20), (0, 100), (0, 150), (0, 200), (1, 5)]
+        );
+    }
+}
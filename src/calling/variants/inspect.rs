@@ -0,0 +1,262 @@
+// Copyright 2024 Johannes Köster.
+// Licensed under the GNU GPLv3 license (https://opensource.org/licenses/GPL-3.0)
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Human readable output of the observations stored in preprocessed observation BCFs.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use bio::stats::{LogProb, Prob};
+use bio_types::genome::AbstractLocus;
+use bio_types::sequence::SequenceReadPairOrientation;
+
+use crate::calling::variants::preprocessing::observation_format::ObservationFormat;
+use crate::calling::variants::preprocessing::read_observations;
+use crate::utils::regions::{RecordReader, RecordSelection, Regions};
+use crate::variants::evidence::observations::read_observation::{
+    AltLocus, ProcessedReadObservation, ReadPosition, Strand,
+};
+
+/// Output format of inspected observations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InspectionFormat {
+    /// Tab separated values with a header line.
+    Tsv,
+    /// One JSON object per line.
+    Json,
+}
+
+/// A read observation together with the record it stems from.
+/// Probabilities are given in linear space.
+#[derive(Debug, Serialize)]
+struct InspectedObservation<'a> {
+    chrom: &'a str,
+    /// 1-based position of the record.
+    pos: u64,
+    id: &'a str,
+    alleles: &'a str,
+    fragment_id: Option<u64>,
+    prob_mapping: f64,
+    prob_mismapping: f64,
+    prob_alt: f64,
+    prob_ref: f64,
+    prob_missed_allele: f64,
+    prob_sample_alt: f64,
+    prob_double_overlap: f64,
+    prob_single_overlap: f64,
+    prob_hit_base: f64,
+    strand: Strand,
+    read_orientation: SequenceReadPairOrientation,
+    softclipped: bool,
+    paired: bool,
+    read_position: ReadPosition,
+    prob_observable_at_homopolymer_artifact: Option<f64>,
+    prob_observable_at_homopolymer_variant: Option<f64>,
+    homopolymer_indel_len: Option<i8>,
+    is_max_mapq: bool,
+    alt_locus: AltLocus,
+    third_allele_evidence: Option<u32>,
+    family_size: Option<u32>,
+    fragment_len: Option<u32>,
+}
+
+impl<'a> InspectedObservation<'a> {
+    fn new(
+        chrom: &'a str,
+        pos: u64,
+        id: &'a str,
+        alleles: &'a str,
+        obs: &ProcessedReadObservation,
+    ) -> Self {
+        let prob = |p: LogProb| *Prob::from(p);
+        InspectedObservation {
+            chrom,
+            pos,
+            id,
+            alleles,
+            fragment_id: obs.fragment_id,
+            prob_mapping: prob(obs.prob_mapping()),
+            prob_mismapping: prob(obs.prob_mismapping()),
+            prob_alt: prob(obs.prob_alt),
+            prob_ref: prob(obs.prob_ref),
+            prob_missed_allele: prob(obs.prob_missed_allele),
+            prob_sample_alt: prob(obs.prob_sample_alt),
+            prob_double_overlap: prob(obs.prob_double_overlap),
+            prob_single_overlap: prob(obs.prob_single_overlap),
+            prob_hit_base: prob(obs.prob_hit_base),
+            strand: obs.strand,
+            read_orientation: obs.read_orientation,
+            softclipped: obs.softclipped,
+            paired: obs.paired,
+            read_position: obs.read_position,
+            prob_observable_at_homopolymer_artifact: obs
+                .prob_observable_at_homopolymer_artifact
+                .map(prob),
+            prob_observable_at_homopolymer_variant: obs
+                .prob_observable_at_homopolymer_variant
+                .map(prob),
+            homopolymer_indel_len: obs.homopolymer_indel_len,
+            is_max_mapq: obs.is_max_mapq,
+            alt_locus: obs.alt_locus,
+            third_allele_evidence: obs.third_allele_evidence,
+            family_size: obs.family_size,
+            fragment_len: obs.fragment_len,
+        }
+    }
+}
+
+/// Decode the observations of the given observation BCF and print them per read,
+/// optionally restricted to the given regions. Depth and breakpoint observations as well
+/// as the damage information of the records are not printed.
+pub(crate) fn inspect_observations<P: AsRef<Path>>(
+    observations: P,
+    regions: Option<Regions>,
+    output_format: InspectionFormat,
+    output: Option<&Path>,
+) -> Result<()> {
+    let observations = observations.as_ref();
+    let selection = regions
+        .map(|regions| RecordSelection::new(observations, regions).map(Arc::new))
+        .transpose()?;
+    let mut reader = RecordReader::from_path(observations, selection.as_ref())?;
+    let format = ObservationFormat::from_header(reader.header())?;
+
+    let out: Box<dyn Write> = if let Some(path) = output {
+        Box::new(
+            File::create(path)
+                .with_context(|| format!("Unable to write to {}.", path.display()))?,
+        )
+    } else {
+        Box::new(io::stdout())
+    };
+    let mut writer = match output_format {
+        InspectionFormat::Tsv => ObservationWriter::Tsv(Box::new(
+            csv::WriterBuilder::new().delimiter(b'\t').from_writer(out),
+        )),
+        InspectionFormat::Json => ObservationWriter::Json(io::BufWriter::new(out)),
+    };
+
+    let mut record = reader.empty_record();
+    while let Some(res) = reader.read(&mut record) {
+        res?;
+        let pileup = read_observations(&mut record, format)?.pileup;
+
+        let chrom = record.contig().to_owned();
+        let pos = record.pos() as u64 + 1;
+        let id = String::from_utf8_lossy(&record.id()).into_owned();
+        let alleles = record
+            .alleles()
+            .iter()
+            .map(|allele| String::from_utf8_lossy(allele))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        for obs in pileup.read_observations() {
+            writer.write(&InspectedObservation::new(&chrom, pos, &id, &alleles, obs))?;
+        }
+    }
+    writer.flush()
+}
+
+enum ObservationWriter {
+    Tsv(Box<csv::Writer<Box<dyn Write>>>),
+    Json(io::BufWriter<Box<dyn Write>>),
+}
+
+impl ObservationWriter {
+    fn write(&mut self, obs: &InspectedObservation) -> Result<()> {
+        match self {
+            ObservationWriter::Tsv(writer) => writer.serialize(obs)?,
+            ObservationWriter::Json(writer) => {
+                serde_json::to_writer(&mut *writer, obs)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            ObservationWriter::Tsv(writer) => writer.flush()?,
+            ObservationWriter::Json(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calling::variants::preprocessing::{
+        add_observation_header_entries, write_observations, OBSERVATION_FORMAT_VERSION,
+    };
+    use crate::variants::evidence::observations::pileup::Pileup;
+    use crate::variants::model::tests::observation;
+    use rust_htslib::bcf;
+
+    #[test]
+    fn test_inspect_observations() {
+        let tmp = tempfile::tempdir().unwrap();
+        let observations = tmp.path().join("observations.bcf");
+        let output = tmp.path().join("observations.tsv");
+
+        let mut header = bcf::Header::new();
+        header.push_record(b"##contig=<ID=chr1,length=1000>");
+        header.push_record(
+            format!(
+                "##varlociraptor_observation_format_version={}",
+                OBSERVATION_FORMAT_VERSION
+            )
+            .as_bytes(),
+        );
+        add_observation_header_entries(&mut header);
+        let observation = |prob_alt: LogProb, strand| {
+            let mut obs = observation(LogProb::ln_one(), prob_alt, prob_alt.ln_one_minus_exp());
+            obs.strand = strand;
+            obs
+        };
+        {
+            let mut writer =
+                bcf::Writer::from_path(&observations, &header, true, bcf::Format::Bcf).unwrap();
+            for pos in [10, 500] {
+                let mut record = writer.empty_record();
+                record.set_rid(Some(0));
+                record.set_pos(pos);
+                record.set_alleles(&[b"A", b"T"]).unwrap();
+                let pileup = Pileup::new(
+                    vec![
+                        observation(LogProb::ln_one(), Strand::Forward),
+                        observation(LogProb::ln_zero(), Strand::Reverse),
+                    ],
+                    Vec::new(),
+                );
+                write_observations(&pileup, &mut record).unwrap();
+                writer.write(&record).unwrap();
+            }
+        }
+
+        let regions = Regions::from_args(&["chr1:1-100".to_owned()], None).unwrap();
+        inspect_observations(&observations, regions, InspectionFormat::Tsv, Some(&output)).unwrap();
+
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_path(&output)
+            .unwrap();
+        let headers = reader.headers().unwrap().clone();
+        let column = |name: &str| headers.iter().position(|h| h == name).unwrap();
+        let rows: Vec<csv::StringRecord> = reader.records().map(|row| row.unwrap()).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(&rows[0][column("pos")], "11");
+        assert_eq!(&rows[0][column("alleles")], "A,T");
+        assert_eq!(&rows[0][column("strand")], "Forward");
+        assert_eq!(&rows[0][column("read_orientation")], "None");
+        assert_eq!(&rows[1][column("strand")], "Reverse");
+        assert_eq!(rows[0][column("prob_alt")].parse::<f64>().unwrap(), 1.0);
+        assert_eq!(rows[1][column("prob_alt")].parse::<f64>().unwrap(), 0.0);
+    }
+}
@@ -4,14 +4,17 @@
 // except according to those terms.
 
 pub(crate) mod calling;
+pub(crate) mod concat;
+pub(crate) mod inspect;
+pub(crate) mod phasing;
 pub mod preprocessing;
 
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::convert::TryFrom;
 use std::ops::RangeInclusive;
-use std::rc::Rc;
 use std::str;
+use std::sync::Arc;
 
 use anyhow::Result;
 use bio::stats::{LogProb, PHREDProb};
@@ -19,13 +22,14 @@ use bio_types::sequence::SequenceReadPairOrientation;
 use derive_builder::Builder;
 use itertools::Itertools;
 use ordered_float::OrderedFloat;
-use rust_htslib::bcf::{self, record::Numeric, Read};
+use rust_htslib::bcf::{self, record::Numeric};
 use vec_map::VecMap;
 
 use crate::calling::variants::preprocessing::write_observations;
 use crate::utils;
 use crate::utils::aux_info::AuxInfo;
 use crate::utils::bayes_factor_to_letter;
+use crate::utils::regions::RecordReader;
 use crate::variants::evidence::observations::pileup::Pileup;
 use crate::variants::evidence::observations::read_observation::expected_depth;
 use crate::variants::evidence::observations::read_observation::AltLocus;
@@ -63,6 +67,9 @@ pub(crate) struct Call {
     //aux_fields: HashSet<Vec<u8>>,
     #[builder(default)]
     variant: Option<Variant>,
+    /// Phase of the call in each sample, empty if calls are not phased.
+    #[builder(default)]
+    phases: Vec<Option<phasing::Phase>>,
 }
 
 impl CallBuilder {
@@ -122,13 +129,19 @@ impl Call {
     }
 
     fn write_record_aux_info(&self, variant: &Variant, record: &mut bcf::Record) -> Result<()> {
+        if let Some(ref unit) = variant.repeat_unit {
+            record.push_info_string(b"RU", &[unit])?;
+        }
+        if let Some(site_alts) = variant.site_alts {
+            record.push_info_integer(b"SITEALTS", &[site_alts as i32])?;
+        }
         if let VariantPrecision::Imprecise {
             ref cistart,
             ref ciend,
         } = variant.precision
         {
             record.push_info_flag(b"IMPRECISE")?;
-            let get_data = |ci: &RangeInclusive<u64>| [*ci.start() as i32, *ci.end() as i32];
+            let get_data = |ci: &RangeInclusive<i64>| [*ci.start() as i32, *ci.end() as i32];
             record.push_info_integer(b"CIPOS", &get_data(cistart))?;
             if let Some(ciend) = ciend {
                 record.push_info_integer(b"CIEND", &get_data(ciend))?;
@@ -382,6 +395,10 @@ impl Call {
             record.push_info_integer(b"END", &ends)?;
         }
         self.write_record_aux_info(variant, &mut record)?;
+        if let Some((pos, end)) = variant.refined_breakpoints {
+            record.push_info_integer(b"REFINED_POS", &[pos as i32 + 1])?;
+            record.push_info_integer(b"REFINED_END", &[end as i32])?;
+        }
 
         if let Some(ref mateid) = self.mateid {
             record.push_info_string(b"MATEID", &[mateid])?;
@@ -422,6 +439,15 @@ impl Call {
 
         // set sample info
         if !no_obs {
+            // Artifacts are shared between samples, hence the damage estimate is the same for all of them.
+            let damage = variant
+                .sample_info
+                .iter()
+                .flatten()
+                .find_map(|sample_info| sample_info.artifacts.damage_bias().damage())
+                .map_or(".", |damage| damage.label());
+            record.push_info_string(b"DAMAGE", &[damage.as_bytes()])?;
+
             let dp = obs_counts.values().cloned().collect_vec();
             record.push_format_integer(b"DP", &dp)?;
 
@@ -512,6 +538,20 @@ impl Call {
                 })
                 .collect_vec();
             record.push_format_string(b"AFD", &vaf_densities)?;
+
+            let tumor_origin = variant
+                .sample_info
+                .iter()
+                .map(|sample_info| {
+                    sample_info
+                        .as_ref()
+                        .and_then(|sample_info| sample_info.prob_tumor_origin)
+                        .map_or(f32::missing(), |prob| *PHREDProb::from(prob) as f32)
+                })
+                .collect_vec();
+            if tumor_origin.iter().any(|prob| !prob.is_missing()) {
+                record.push_format_float(b"TO", &tumor_origin)?;
+            }
         } else {
             record.push_format_integer(b"DP", &vec![i32::missing(); variant.sample_info.len()])?;
             record.push_format_float(b"AF", &vec![f32::missing(); variant.sample_info.len()])?;
@@ -525,6 +565,21 @@ impl Call {
             record.push_format_string(b"AFD", &vec![b".".to_vec(); variant.sample_info.len()])?;
         }
 
+        if !self.phases.is_empty() {
+            let phase_sets = self
+                .phases
+                .iter()
+                .map(|phase| phase.map_or(i32::missing(), |phase| phase.phase_set() as i32))
+                .collect_vec();
+            record.push_format_integer(b"PS", &phase_sets)?;
+            let haplotypes = self
+                .phases
+                .iter()
+                .map(|phase| phase.map_or(i32::missing(), |phase| phase.haplotype() as i32))
+                .collect_vec();
+            record.push_format_integer(b"PH", &haplotypes)?;
+        }
+
         bcf_writer.write(&record)?;
         Ok(())
     }
@@ -546,11 +601,20 @@ pub(crate) struct Variant {
     end: Option<u64>,
     #[builder(private, default)]
     precision: VariantPrecision,
+    /// Repeat unit of a short tandem repeat.
+    #[builder(private, default = "None")]
+    repeat_unit: Option<Vec<u8>>,
+    /// Number of ALT alleles at the site of a variant with competing ALT alleles.
+    #[builder(private, default = "None")]
+    site_alts: Option<u32>,
+    /// Most likely pos (0-based) and end (1-based) of an imprecise variant.
+    #[builder(private, default)]
+    refined_breakpoints: Option<(u64, u64)>,
     #[builder(private, default = "None")]
     #[getset(get = "pub(crate)")]
     event_probs: Option<HashMap<String, LogProb>>,
     #[builder(default = "None")]
-    pileup: Option<Rc<Pileup>>,
+    pileup: Option<Arc<Pileup>>,
     #[builder(default)]
     #[getset(get = "pub(crate)")]
     sample_info: Vec<Option<SampleInfo>>,
@@ -567,6 +631,8 @@ impl VariantBuilder {
             .event(utils::info_tag_event(record)?.map(|e| e.to_vec()))
             .svtype(utils::info_tag_svtype(record)?.map(|s| s.to_vec()))
             .end(record.info(b"END").integer()?.map(|v| v[0] as u64))
+            .repeat_unit(utils::info_tag_repeat_unit(record)?)
+            .site_alts(utils::info_tag_site_alts(record)?)
             .precision(VariantPrecision::try_from(&*record)?))
     }
 
@@ -609,6 +675,15 @@ impl VariantBuilder {
                     .svlen(Some(svlen))
                     .svtype(Some(b"INS".to_vec()))
             }
+            model::Variant::MobileElementInsertion { element, len, .. } => {
+                let mut alt_allele = b"<INS:ME:".to_vec();
+                alt_allele.extend(element);
+                alt_allele.push(b'>');
+                self.ref_allele(chrom_seq.unwrap()[start..start + 1].to_ascii_uppercase())
+                    .alt_allele(alt_allele)
+                    .svlen(Some(*len as i32))
+                    .svtype(Some(b"INS".to_vec()))
+            }
             model::Variant::Snv(base) => self
                 .ref_allele(chrom_seq.unwrap()[start..start + 1].to_ascii_uppercase())
                 .alt_allele([*base].to_ascii_uppercase()),
@@ -640,6 +715,15 @@ impl VariantBuilder {
             } => self
                 .ref_allele(ref_allele.to_owned())
                 .alt_allele(alt_allele.to_owned()),
+            model::Variant::ShortTandemRepeat {
+                unit,
+                ref_len,
+                count,
+            } => self
+                .ref_allele(chrom_seq.unwrap()[start..start + 1].to_ascii_uppercase())
+                .alt_allele(format!("<STR{}>", count).into_bytes())
+                .repeat_unit(Some(unit.to_owned()))
+                .end(Some(start as u64 + ref_len)), // start points to the base before the repeat
             model::Variant::None => self
                 .ref_allele(chrom_seq.unwrap()[start..start + 1].to_ascii_uppercase())
                 .alt_allele(b"<REF>".to_ascii_uppercase()),
@@ -651,13 +735,17 @@ impl VariantBuilder {
 pub(crate) struct SampleInfo {
     #[getset(get_copy = "pub(crate)")]
     allelefreq_estimate: AlleleFreq,
-    pileup: Rc<Pileup>,
+    pileup: Arc<Pileup>,
     artifacts: Artifacts,
     #[getset(get = "pub(crate)")]
     vaf_dist: Option<HashMap<AlleleFreq, LogProb>>,
+    /// Posterior probability that the ALT allele fragments stem from tumor cells, given
+    /// their fragment lengths (if a fragment length model is given for the sample).
+    #[builder(default)]
+    prob_tumor_origin: Option<LogProb>,
 }
 
-pub(crate) fn chrom<'a>(inbcf: &'a bcf::Reader, record: &bcf::Record) -> &'a [u8] {
+pub(crate) fn chrom<'a>(inbcf: &'a RecordReader, record: &bcf::Record) -> &'a [u8] {
     inbcf.header().rid2name(record.rid().unwrap()).unwrap()
 }
 
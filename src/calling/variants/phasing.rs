@@ -0,0 +1,236 @@
+// Copyright 2021 Johannes Köster.
+// Licensed under the GNU GPLv3 license (https://opensource.org/licenses/GPL-3.0)
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::{HashMap, VecDeque};
+
+use bio::stats::LogProb;
+use rust_htslib::bcf;
+
+use crate::calling::variants::Call;
+use crate::variants::evidence::observations::pileup::Pileup;
+
+/// Maximum distance between two calls that shall be phased via shared fragments.
+/// This is large enough to cover the fragments of long reads.
+const MAX_PHASING_DISTANCE: u64 = 100_000;
+
+/// Phase of the ALT allele of a call in a sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters, new)]
+#[getset(get_copy = "pub(crate)")]
+pub(crate) struct Phase {
+    /// Position (1-based) of the first call of the phase set.
+    phase_set: u64,
+    /// Haplotype (0 or 1) of the phase set that carries the ALT allele.
+    haplotype: u8,
+}
+
+#[derive(Debug)]
+struct PhasedCall {
+    pos: u64,
+    phase: Phase,
+    /// Fragments that strongly support one of the alleles (true for ALT, false for REF).
+    fragments: HashMap<u64, bool>,
+}
+
+/// Read-backed phasing of calls, linking them via fragments they share.
+/// Calls have to be passed in the order of their position.
+#[derive(Debug, new)]
+pub(crate) struct Phaser {
+    /// Maximum posterior probability of the variant being absent (or an artifact) for a
+    /// call to be phased.
+    max_prob_absent: LogProb,
+    #[new(default)]
+    chrom: Vec<u8>,
+    /// Recent phased calls of each sample.
+    #[new(default)]
+    calls: Vec<VecDeque<PhasedCall>>,
+}
+
+impl Phaser {
+    /// Determine the phase of the given call in each sample.
+    pub(crate) fn phase(&mut self, call: &mut Call) {
+        if call.chrom != self.chrom {
+            self.calls.clear();
+            self.chrom = call.chrom.clone();
+        }
+        let pos = call.pos;
+        let variant = call.variant.as_ref().unwrap();
+        // METHOD: only called variants are phased. Otherwise, absent variants and artifacts
+        // would receive a phase and, since phasing is propagated from call to call, also
+        // confuse the phasing of subsequent (called) variants.
+        if !is_called(variant.event_probs().as_ref(), self.max_prob_absent) {
+            return;
+        }
+        let sample_info = &variant.sample_info;
+        self.calls.resize_with(sample_info.len(), VecDeque::new);
+
+        let phases = sample_info
+            .iter()
+            .zip(self.calls.iter_mut())
+            .map(|(sample_info, calls)| {
+                while calls
+                    .front()
+                    .is_some_and(|prev| pos.saturating_sub(prev.pos) > MAX_PHASING_DISTANCE)
+                {
+                    calls.pop_front();
+                }
+
+                let fragments = sample_info
+                    .as_ref()
+                    .filter(|sample_info| *sample_info.allelefreq_estimate() > 0.0)
+                    .map(|sample_info| fragment_alleles(&sample_info.pileup))
+                    .unwrap_or_default();
+                if !fragments.values().any(|is_alt| *is_alt) {
+                    // variant not present in the sample or no evidence for the ALT allele,
+                    // nothing to phase
+                    return None;
+                }
+
+                // METHOD: calls that cannot be linked to a previous one open a new phase set.
+                let phase = link(&fragments, calls).unwrap_or(Phase::new(pos + 1, 0));
+                calls.push_back(PhasedCall {
+                    pos,
+                    phase,
+                    fragments,
+                });
+                Some(phase)
+            })
+            .collect();
+
+        call.phases = phases;
+    }
+}
+
+/// Whether the variant with the given event posteriors is called, i.e., its probability to
+/// be absent or an artifact does not exceed the given maximum.
+fn is_called(event_probs: Option<&HashMap<String, LogProb>>, max_prob_absent: LogProb) -> bool {
+    event_probs.is_some_and(|event_probs| {
+        let probs_absent = ["absent", "artifact"]
+            .iter()
+            .filter_map(|event| event_probs.get(*event).cloned())
+            .collect::<Vec<_>>();
+        LogProb::ln_sum_exp(&probs_absent) <= max_prob_absent
+    })
+}
+
+/// Obtain the alleles that are strongly supported by each fragment.
+fn fragment_alleles(pileup: &Pileup) -> HashMap<u64, bool> {
+    pileup
+        .read_observations()
+        .iter()
+        .filter_map(|obs| {
+            obs.fragment_id.and_then(|fragment_id| {
+                if obs.is_strong_alt_support() {
+                    Some((fragment_id, true))
+                } else if obs.is_strong_ref_support() {
+                    Some((fragment_id, false))
+                } else {
+                    None
+                }
+            })
+        })
+        .collect()
+}
+
+/// Link a call with the given fragments to the previous call that shares the most
+/// informative fragments with it and return the resulting phase.
+fn link(fragments: &HashMap<u64, bool>, calls: &VecDeque<PhasedCall>) -> Option<Phase> {
+    // METHOD: fragments carrying both ALT alleles indicate that the calls are in cis,
+    // fragments carrying only one of them indicate that they are in trans. Fragments
+    // carrying both REF alleles are uninformative. The previous call with the clearest
+    // majority is chosen, preferring the closest one in case of ties.
+    calls
+        .iter()
+        .filter_map(|prev| {
+            let (mut cis, mut trans) = (0_usize, 0_usize);
+            for (fragment_id, is_alt) in fragments {
+                match (is_alt, prev.fragments.get(fragment_id)) {
+                    (true, Some(true)) => cis += 1,
+                    (true, Some(false)) | (false, Some(true)) => trans += 1,
+                    _ => (),
+                }
+            }
+            if cis == trans {
+                None
+            } else {
+                let haplotype = if cis > trans {
+                    prev.phase.haplotype
+                } else {
+                    1 - prev.phase.haplotype
+                };
+                Some((
+                    cis.abs_diff(trans),
+                    Phase::new(prev.phase.phase_set, haplotype),
+                ))
+            }
+        })
+        .max_by_key(|(margin, _)| *margin)
+        .map(|(_, phase)| phase)
+}
+
+pub(crate) fn add_phasing_header_entries(header: &mut bcf::Header) {
+    header.push_record(
+        b"##FORMAT=<ID=PS,Number=1,Type=Integer,\
+          Description=\"Phase set, given by the position of its first call. Calls of the \
+          same phase set are linked via fragments they share.\">",
+    );
+    header.push_record(
+        b"##FORMAT=<ID=PH,Number=1,Type=Integer,\
+          Description=\"Haplotype (0 or 1) of the phase set (PS) that carries the ALT allele. \
+          ALT alleles with the same PS and PH are in cis, ALT alleles with the same PS but \
+          different PH are in trans.\">",
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phased_call(pos: u64, phase: Phase, fragments: &[(u64, bool)]) -> PhasedCall {
+        PhasedCall {
+            pos,
+            phase,
+            fragments: fragments.iter().cloned().collect(),
+        }
+    }
+
+    #[test]
+    fn test_is_called() {
+        let max_prob_absent = LogProb(0.05_f64.ln());
+        let event_probs = |absent: f64, artifact: f64| {
+            HashMap::from([
+                ("absent".to_owned(), LogProb(absent.ln())),
+                ("artifact".to_owned(), LogProb(artifact.ln())),
+                (
+                    "present".to_owned(),
+                    LogProb((1.0 - absent - artifact).ln()),
+                ),
+            ])
+        };
+        assert!(is_called(Some(&event_probs(0.01, 0.01)), max_prob_absent));
+        assert!(!is_called(Some(&event_probs(0.9, 0.0)), max_prob_absent));
+        assert!(!is_called(Some(&event_probs(0.01, 0.5)), max_prob_absent));
+        assert!(!is_called(None, max_prob_absent));
+    }
+
+    #[test]
+    fn test_link() {
+        let calls = VecDeque::from(vec![
+            phased_call(100, Phase::new(101, 0), &[(1, true), (2, true), (3, false)]),
+            phased_call(200, Phase::new(101, 1), &[(4, true), (5, false)]),
+        ]);
+
+        // shares ALT fragments with the first call
+        let fragments = HashMap::from([(1, true), (2, true), (3, false)]);
+        assert_eq!(link(&fragments, &calls), Some(Phase::new(101, 0)));
+
+        // ALT fragment carries the REF allele of the second call
+        let fragments = HashMap::from([(5, true), (6, true)]);
+        assert_eq!(link(&fragments, &calls), Some(Phase::new(101, 0)));
+
+        // only REF fragments are shared, which is uninformative
+        let fragments = HashMap::from([(3, false), (7, true)]);
+        assert_eq!(link(&fragments, &calls), None);
+    }
+}
@@ -1,34 +1,67 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
 
 use anyhow::Result;
-use rust_htslib::bcf::{self, Read};
 
 use crate::utils;
+use crate::utils::regions::{RecordReader, RecordSelection};
 use crate::variants::model::HaplotypeIdentifier;
 
 #[derive(Default, Debug)]
 pub(crate) struct HaplotypeFeatureIndex {
     last_records: HashMap<HaplotypeIdentifier, usize>,
+    /// Haplotypes that contain both breakends and other variants.
+    mixed: HashSet<HaplotypeIdentifier>,
 }
 
 impl HaplotypeFeatureIndex {
-    pub(crate) fn new<P: AsRef<Path>>(inbcf: P) -> Result<Self> {
-        let mut bcf_reader = bcf::Reader::from_path(inbcf)?;
-        if !utils::is_haplotype_bcf(&bcf_reader) {
+    /// Index the last record of each haplotype, and whether it mixes breakends with other
+    /// variants. Record indices only count the records of the given selection, if any.
+    pub(crate) fn new<P: AsRef<Path>>(
+        inbcf: P,
+        record_selection: Option<&Arc<RecordSelection>>,
+    ) -> Result<Self> {
+        let mut bcf_reader = RecordReader::from_path(inbcf, record_selection)?;
+        if !utils::is_haplotype_bcf(bcf_reader.header()) {
             return Ok(HaplotypeFeatureIndex::default());
         }
 
         let mut last_records = HashMap::new();
+        let mut with_breakends = HashSet::new();
+        let mut with_other_variants = HashSet::new();
 
         let mut i = 0;
         loop {
             let mut record = bcf_reader.empty_record();
             match bcf_reader.read(&mut record) {
-                None => return Ok(HaplotypeFeatureIndex { last_records }),
+                None => {
+                    let mixed = with_breakends
+                        .intersection(&with_other_variants)
+                        .cloned()
+                        .collect();
+                    return Ok(HaplotypeFeatureIndex {
+                        last_records,
+                        mixed,
+                    });
+                }
                 Some(res) => res?,
             }
 
             if let Some(identifier) = HaplotypeIdentifier::from(&mut record)? {
+                let is_breakend = record
+                    .info(b"SVTYPE")
+                    .string()
+                    .ok()
+                    .flatten()
+                    .is_some_and(|svtype| svtype[0] == b"BND");
+                if is_breakend {
+                    with_breakends.insert(identifier.clone());
+                } else {
+                    with_other_variants.insert(identifier.clone());
+                }
                 last_records.insert(identifier, i);
             }
 
@@ -42,4 +75,59 @@ impl HaplotypeFeatureIndex {
     ) -> Option<usize> {
         self.last_records.get(haplotype_identifier).cloned()
     }
+
+    /// Whether the given haplotype contains both breakends and other variants.
+    /// Then, all of them are evaluated together in a haplotype block.
+    pub(crate) fn is_mixed(&self, haplotype_identifier: &HaplotypeIdentifier) -> bool {
+        self.mixed.contains(haplotype_identifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf;
+
+    #[test]
+    fn test_mixed_haplotypes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("candidates.bcf");
+        let mut header = bcf::Header::new();
+        header.push_record(b"##contig=<ID=chr1,length=1000>");
+        header.push_record(b"##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"\">");
+        header.push_record(b"##INFO=<ID=EVENT,Number=1,Type=String,Description=\"\">");
+        {
+            let mut writer =
+                bcf::Writer::from_path(&path, &header, true, bcf::Format::Bcf).unwrap();
+            for (pos, alleles, event) in [
+                (
+                    10,
+                    [b"A".as_slice(), b"A[chr1:500[".as_slice()],
+                    b"complex".as_slice(),
+                ),
+                (12, [b"C", b"CT"], b"complex".as_slice()),
+                (499, [b"G", b"]chr1:11]G"], b"complex".as_slice()),
+                (600, [b"A", b"T"], b"phased"),
+                (610, [b"A", b"G"], b"phased"),
+            ] {
+                let mut record = writer.empty_record();
+                record.set_rid(Some(0));
+                record.set_pos(pos);
+                record.set_alleles(&alleles).unwrap();
+                if alleles[1].contains(&b'[') || alleles[1].contains(&b']') {
+                    record.push_info_string(b"SVTYPE", &[b"BND"]).unwrap();
+                }
+                record.push_info_string(b"EVENT", &[event]).unwrap();
+                writer.write(&record).unwrap();
+            }
+        }
+
+        let index = HaplotypeFeatureIndex::new(&path, None).unwrap();
+        let complex = HaplotypeIdentifier::Event(b"complex".to_vec());
+        let phased = HaplotypeIdentifier::Event(b"phased".to_vec());
+        assert!(index.is_mixed(&complex));
+        assert!(!index.is_mixed(&phased));
+        assert_eq!(index.last_record_index(&complex), Some(2));
+        assert_eq!(index.last_record_index(&phased), Some(4));
+    }
 }
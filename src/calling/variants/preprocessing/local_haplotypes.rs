@@ -0,0 +1,446 @@
+// Copyright 2021 Johannes Köster.
+// Licensed under the GNU GPLv3 license (https://opensource.org/licenses/GPL-3.0)
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use rust_htslib::bam::{self, record::Cigar, Read as BAMRead};
+use rust_htslib::bcf::{self, Read as BCFRead};
+
+use crate::reference;
+use crate::variants::model::HaplotypeIdentifier;
+use crate::variants::sample::is_valid_record;
+
+/// Minimum fraction of the fragments covering two nearby candidates that has to carry both
+/// ALT alleles in order to consider them to be located on the same haplotype.
+const MIN_LINKING_FRACTION: f64 = 0.05;
+
+/// Candidate with explicit REF and ALT sequence that may be part of a local haplotype.
+#[derive(Debug)]
+struct Candidate {
+    record: bcf::Record,
+    pos: u64,
+    ref_allele: Vec<u8>,
+    alt_allele: Vec<u8>,
+}
+
+impl Candidate {
+    /// Obtain a candidate from the given record, if it is a biallelic SNV, MNV or small
+    /// indel that is not already part of a haplotype.
+    fn new(mut record: bcf::Record) -> Result<std::result::Result<Self, bcf::Record>> {
+        let is_sv = record.info(b"SVTYPE").string().ok().flatten().is_some();
+        if is_sv || record.allele_count() != 2 || HaplotypeIdentifier::from(&mut record)?.is_some()
+        {
+            return Ok(Err(record));
+        }
+        let alleles = record.alleles();
+        let (ref_allele, alt_allele) = (
+            alleles[0].to_ascii_uppercase(),
+            alleles[1].to_ascii_uppercase(),
+        );
+        let is_sequence = |allele: &[u8]| {
+            allele
+                .iter()
+                .all(|base| matches!(base, b'A' | b'C' | b'G' | b'T' | b'N'))
+        };
+        let is_simple = ref_allele.len() == alt_allele.len()
+            || ((ref_allele.len() == 1 || alt_allele.len() == 1) && ref_allele[0] == alt_allele[0]);
+        if !(is_sequence(&ref_allele) && is_sequence(&alt_allele) && is_simple) {
+            return Ok(Err(record));
+        }
+
+        Ok(Ok(Candidate {
+            pos: record.pos() as u64,
+            record,
+            ref_allele,
+            alt_allele,
+        }))
+    }
+
+    fn end(&self) -> u64 {
+        self.pos + self.ref_allele.len() as u64
+    }
+
+    /// Whether the alignment of the given read spans the entire candidate.
+    fn is_covered_by(&self, read: &bam::Record) -> bool {
+        read.pos() as u64 <= self.pos && read.cigar().end_pos() as u64 >= self.end()
+    }
+
+    /// Whether the alignment of the given read carries the ALT allele.
+    fn is_carried_by(&self, read: &bam::Record) -> bool {
+        let seq = read.seq();
+        let substitutions: Vec<_> = if self.ref_allele.len() == self.alt_allele.len() {
+            self.ref_allele
+                .iter()
+                .zip(self.alt_allele.iter())
+                .enumerate()
+                .filter(|(_, (ref_base, alt_base))| ref_base != alt_base)
+                .map(|(i, (_, alt_base))| (self.pos + i as u64, *alt_base))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let mut carried_substitutions = 0;
+
+        let mut ref_pos = read.pos() as u64;
+        let mut read_pos = 0;
+        for op in read.cigar().iter() {
+            match *op {
+                Cigar::Match(l) | Cigar::Equal(l) | Cigar::Diff(l) => {
+                    let block = ref_pos..ref_pos + l as u64;
+                    carried_substitutions += substitutions
+                        .iter()
+                        .filter(|(pos, base)| {
+                            block.contains(pos) && seq[read_pos + (pos - ref_pos) as usize] == *base
+                        })
+                        .count();
+                    ref_pos += l as u64;
+                    read_pos += l as usize;
+                }
+                Cigar::Del(l) => {
+                    if self.ref_allele.len() > self.alt_allele.len()
+                        && ref_pos == self.pos + 1
+                        && l as usize == self.ref_allele.len() - 1
+                    {
+                        return true;
+                    }
+                    ref_pos += l as u64;
+                }
+                Cigar::RefSkip(l) => ref_pos += l as u64,
+                Cigar::Ins(l) => {
+                    if self.alt_allele.len() > self.ref_allele.len()
+                        && ref_pos == self.pos + 1
+                        && l as usize == self.alt_allele.len() - 1
+                        && (0..l as usize).all(|i| seq[read_pos + i] == self.alt_allele[i + 1])
+                    {
+                        return true;
+                    }
+                    read_pos += l as usize;
+                }
+                Cigar::SoftClip(l) => read_pos += l as usize,
+                Cigar::HardClip(_) | Cigar::Pad(_) => (),
+            }
+        }
+
+        !substitutions.is_empty() && carried_substitutions == substitutions.len()
+    }
+}
+
+/// Construct local haplotypes from nearby candidate variants whose ALT alleles co-occur in
+/// the fragments of the given BAM files, and write the candidates to the given BCF file,
+/// which is indexed afterwards. Candidates of the same local haplotype obtain a shared EVENT
+/// tag, such that they are evaluated jointly in a haplotype block. In addition, a merged MNV
+/// or complex allele is proposed for each local haplotype.
+///
+/// The BAM files of all samples have to be given, such that all samples are preprocessed
+/// with the same candidate records.
+pub(crate) fn construct_local_haplotypes<P: AsRef<Path>>(
+    inbcf: P,
+    outbcf: P,
+    inbams: &[PathBuf],
+    reference_buffer: &reference::Buffer,
+    max_distance: u64,
+) -> Result<()> {
+    let mut bcf_reader = bcf::Reader::from_path(&inbcf)
+        .with_context(|| format!("Unable to read candidate BCF {}.", inbcf.as_ref().display()))?;
+    let mut header = bcf::Header::from_template(bcf_reader.header());
+    if bcf_reader.header().info_type(b"EVENT").is_err() {
+        header.push_record(
+            b"##INFO=<ID=EVENT,Number=1,Type=String,\
+              Description=\"ID of event associated to breakend\">",
+        );
+    }
+    let mut bcf_writer = bcf::Writer::from_path(&outbcf, &header, false, bcf::Format::Bcf)
+        .with_context(|| format!("Unable to write BCF to {}.", outbcf.as_ref().display()))?;
+
+    let mut bam_readers = inbams
+        .iter()
+        .map(|path| {
+            let mut bam_reader = bam::IndexedReader::from_path(path)
+                .with_context(|| format!("Unable to read BAM/CRAM file {}.", path.display()))?;
+            if let Some(reference_path) = reference_buffer.reference_path() {
+                bam_reader
+                    .set_reference(reference_path)
+                    .context("Unable to read reference FASTA")?;
+            }
+            Ok(bam_reader)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut cluster: Vec<Candidate> = Vec::new();
+    for record in bcf_reader.records() {
+        match Candidate::new(record?)? {
+            Ok(candidate) => {
+                if cluster.last().is_some_and(|last| {
+                    last.record.rid() != candidate.record.rid()
+                        || candidate.pos.saturating_sub(last.end()) > max_distance
+                }) {
+                    write_cluster(
+                        &mut cluster,
+                        &mut bcf_writer,
+                        &mut bam_readers,
+                        reference_buffer,
+                    )?;
+                }
+                cluster.push(candidate);
+            }
+            Err(mut record) => {
+                write_cluster(
+                    &mut cluster,
+                    &mut bcf_writer,
+                    &mut bam_readers,
+                    reference_buffer,
+                )?;
+                bcf_writer.translate(&mut record);
+                bcf_writer.write(&record)?;
+            }
+        }
+    }
+    write_cluster(
+        &mut cluster,
+        &mut bcf_writer,
+        &mut bam_readers,
+        reference_buffer,
+    )?;
+    drop(bcf_writer);
+
+    bcf::index::build(&outbcf, None, 1, bcf::index::Type::Csi(14))
+        .with_context(|| format!("Unable to index BCF {}.", outbcf.as_ref().display()))?;
+
+    Ok(())
+}
+
+/// Determine the local haplotypes of the given cluster of nearby candidates and write
+/// the candidates, together with the merged alleles of the local haplotypes.
+fn write_cluster(
+    cluster: &mut Vec<Candidate>,
+    bcf_writer: &mut bcf::Writer,
+    bam_readers: &mut [bam::IndexedReader],
+    reference_buffer: &reference::Buffer,
+) -> Result<()> {
+    if cluster.is_empty() {
+        return Ok(());
+    }
+    let contig = String::from_utf8(
+        bcf_writer
+            .header()
+            .rid2name(cluster[0].record.rid().unwrap())?
+            .to_owned(),
+    )?;
+
+    let haplotypes = if cluster.len() > 1 {
+        let start = cluster[0].pos;
+        let end = cluster
+            .iter()
+            .map(|candidate| candidate.end())
+            .max()
+            .unwrap();
+
+        // METHOD: mates are considered together, such that candidates can be linked
+        // via the fragment even if they are covered by different reads. For each fragment,
+        // we record the candidates it covers and the candidates whose ALT allele it carries.
+        let mut fragments: HashMap<Vec<u8>, (BTreeSet<usize>, BTreeSet<usize>)> = HashMap::new();
+        for bam_reader in bam_readers.iter_mut() {
+            bam_reader.fetch((contig.as_str(), start as i64, end as i64))?;
+            for read in bam_reader.records() {
+                let read = read?;
+                if !is_valid_record(&read, false) {
+                    continue;
+                }
+                let (covered, carried) = fragments.entry(read.qname().to_owned()).or_default();
+                for (i, candidate) in cluster.iter().enumerate() {
+                    if candidate.is_covered_by(&read) {
+                        covered.insert(i);
+                    }
+                    if candidate.is_carried_by(&read) {
+                        carried.insert(i);
+                    }
+                }
+            }
+        }
+
+        let mut links: HashMap<(usize, usize), Link> = HashMap::new();
+        for (_, carried) in fragments.values() {
+            for (i, j) in carried.iter().zip(carried.iter().skip(1)) {
+                links.entry((*i, *j)).or_default().linking += 1;
+            }
+        }
+        for ((i, j), link) in links.iter_mut() {
+            link.covering = fragments
+                .values()
+                .filter(|(covered, carried)| {
+                    (covered.contains(i) || carried.contains(i))
+                        && (covered.contains(j) || carried.contains(j))
+                })
+                .count();
+        }
+        local_haplotypes(cluster.len(), &links)
+    } else {
+        vec![0]
+    };
+
+    let ref_seq = reference_buffer.seq(&contig)?;
+    let merged: HashMap<_, _> = haplotypes
+        .iter()
+        .filter(|haplotype| haplotypes.iter().filter(|h| h == haplotype).count() > 1)
+        .map(|haplotype| {
+            let members: Vec<_> = cluster
+                .iter()
+                .zip(haplotypes.iter())
+                .filter(|(_, h)| *h == haplotype)
+                .map(|(candidate, _)| candidate)
+                .collect();
+            (*haplotype, merged_alleles(&members, &ref_seq))
+        })
+        .collect();
+
+    let positions: Vec<_> = cluster.iter().map(|candidate| candidate.pos).collect();
+    for (i, candidate) in cluster.iter_mut().enumerate() {
+        let haplotype = haplotypes[i];
+        bcf_writer.translate(&mut candidate.record);
+        if merged.contains_key(&haplotype) {
+            let event = format!("local_haplotype_{}_{}", contig, positions[haplotype] + 1);
+            candidate
+                .record
+                .push_info_string(b"EVENT", &[event.as_bytes()])?;
+        }
+        bcf_writer.write(&candidate.record)?;
+
+        // write merged allele after the first member of the local haplotype
+        if haplotype == i {
+            if let Some(Some((ref_allele, alt_allele))) = merged.get(&haplotype) {
+                let mut record = bcf_writer.empty_record();
+                record.set_rid(candidate.record.rid());
+                record.set_pos(candidate.pos as i64);
+                record.set_alleles(&[ref_allele, alt_allele])?;
+                bcf_writer.write(&record)?;
+            }
+        }
+    }
+    cluster.clear();
+
+    Ok(())
+}
+
+/// Fragment counts for a pair of candidates.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Link {
+    /// Number of fragments carrying both ALT alleles (and none of the candidates in between).
+    linking: usize,
+    /// Number of fragments covering both candidates.
+    covering: usize,
+}
+
+impl Link {
+    fn is_significant(&self) -> bool {
+        self.linking > 0 && self.linking as f64 >= MIN_LINKING_FRACTION * self.covering as f64
+    }
+}
+
+/// Group candidates into local haplotypes, given the fragment counts of two consecutive
+/// ALT alleles carried by the same fragment. Returns the index of the local haplotype
+/// (given by its first member) of each candidate.
+fn local_haplotypes(n: usize, links: &HashMap<(usize, usize), Link>) -> Vec<usize> {
+    let mut haplotypes: Vec<usize> = (0..n).collect();
+    let mut links: Vec<_> = links
+        .iter()
+        .filter(|(_, link)| link.is_significant())
+        .map(|(link, _)| *link)
+        .collect();
+    links.sort();
+    for (i, j) in links {
+        let (a, b) = (haplotypes[i], haplotypes[j]);
+        let (keep, replace) = (a.min(b), a.max(b));
+        for haplotype in haplotypes.iter_mut() {
+            if *haplotype == replace {
+                *haplotype = keep;
+            }
+        }
+    }
+    haplotypes
+}
+
+/// Merge the alleles of the given members of a local haplotype into a single allele,
+/// filling the gaps between them with the reference sequence. Returns None if the
+/// members overlap.
+fn merged_alleles(members: &[&Candidate], ref_seq: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let start = members[0].pos;
+    let end = members.iter().map(|member| member.end()).max().unwrap();
+    if end as usize > ref_seq.len() {
+        return None;
+    }
+
+    let mut alt_allele = Vec::new();
+    let mut cursor = start;
+    for member in members {
+        if member.pos < cursor {
+            return None;
+        }
+        alt_allele.extend(&ref_seq[cursor as usize..member.pos as usize]);
+        alt_allele.extend(&member.alt_allele);
+        cursor = member.end();
+    }
+    alt_allele.extend(&ref_seq[cursor as usize..end as usize]);
+
+    Some((ref_seq[start as usize..end as usize].to_vec(), alt_allele))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(writer: &bcf::Writer, pos: u64, alleles: &[&[u8]]) -> Candidate {
+        let mut record = writer.empty_record();
+        record.set_rid(Some(0));
+        record.set_pos(pos as i64);
+        record.set_alleles(alleles).unwrap();
+        Candidate::new(record).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_local_haplotypes() {
+        let link = |linking, covering| Link { linking, covering };
+        let links = HashMap::from([
+            ((0, 1), link(5, 10)),
+            ((1, 2), link(1, 40)),
+            ((2, 3), link(3, 50)),
+        ]);
+        assert_eq!(local_haplotypes(4, &links), vec![0, 0, 2, 2]);
+        // the same number of linking fragments is not enough at higher coverage
+        let links = HashMap::from([((0, 1), link(5, 10)), ((2, 3), link(3, 100))]);
+        assert_eq!(local_haplotypes(4, &links), vec![0, 0, 2, 3]);
+    }
+
+    #[test]
+    fn test_merged_alleles() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut header = bcf::Header::new();
+        header.push_record(b"##contig=<ID=chr1,length=20>");
+        let writer = bcf::Writer::from_path(
+            tmp.path().join("candidates.bcf"),
+            &header,
+            true,
+            bcf::Format::Bcf,
+        )
+        .unwrap();
+
+        let ref_seq = b"ACGTACGTACGTACGTACGT";
+        let snv = candidate(&writer, 2, &[b"G", b"T"]);
+        let mnv = candidate(&writer, 4, &[b"AC", b"TT"]);
+        let deletion = candidate(&writer, 8, &[b"ACG", b"A"]);
+
+        assert_eq!(
+            merged_alleles(&[&snv, &mnv], ref_seq),
+            Some((b"GTAC".to_vec(), b"TTTT".to_vec()))
+        );
+        assert_eq!(
+            merged_alleles(&[&snv, &deletion], ref_seq),
+            Some((b"GTACGTACG".to_vec(), b"TTACGTA".to_vec()))
+        );
+        let overlapping = candidate(&writer, 5, &[b"C", b"A"]);
+        assert_eq!(merged_alleles(&[&mnv, &overlapping], ref_seq), None);
+    }
+}
@@ -6,24 +6,24 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
 use std::str;
 use std::sync::{Arc, Mutex, RwLock};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use bio_types::genome::{self, AbstractLocus};
 use bio_types::sequence::SequenceReadPairOrientation;
 use bv::BitVec;
 use byteorder::{ByteOrder, LittleEndian};
 use itertools::Itertools;
 use progress_logger::ProgressLogger;
+use rayon::prelude::*;
 use rust_htslib::bam::{self, Read as BAMRead};
 use rust_htslib::bcf::{self, Read as BCFRead};
 
 use crate::calling::variants::{Call, CallBuilder, VariantBuilder};
 use crate::cli;
 use crate::errors;
-use crate::estimation::alignment_properties::AlignmentProperties;
+use crate::estimation::alignment_properties::{AlignmentProperties, DamageStats};
 use crate::reference;
 use crate::utils;
 use crate::utils::aux_info::AuxInfoCollector;
@@ -31,30 +31,41 @@ use crate::utils::collect_variants::VariantInfo;
 use crate::utils::variant_buffer::{VariantBuffer, Variants};
 use crate::utils::MiniLogProb;
 use crate::variants;
+use crate::variants::evidence::observations::alt_hits::AltHitFormat;
+use crate::variants::evidence::observations::breakpoint_observation::BreakpointObservation;
+use crate::variants::evidence::observations::depth_observation::{DepthCounter, DepthObservation};
 use crate::variants::evidence::observations::pileup::Pileup;
 use crate::variants::evidence::observations::read_observation::{
     AltLocus, ReadObservationBuilder, ReadPosition, Strand,
 };
 use crate::variants::evidence::realignment::{self, Realignable};
-use crate::variants::model::{self, HaplotypeIdentifier};
+use crate::variants::model::{self, bias::Damage, HaplotypeIdentifier, VariantPrecision};
 use crate::variants::sample::Sample;
 use crate::variants::sample::SampleBuilder;
 use crate::variants::types::haplotype_block::HaplotypeBlock;
-use crate::variants::types::{breakends::Breakend, Loci};
+use crate::variants::types::{breakends::Breakend, Loci, Variant};
 
 pub(crate) mod haplotype_feature_index;
+pub(crate) mod local_haplotypes;
+pub(crate) mod observation_format;
+pub(crate) mod shards;
 
 use crate::calling::variants::preprocessing::haplotype_feature_index::HaplotypeFeatureIndex;
+use crate::calling::variants::preprocessing::observation_format::ObservationFormat;
+use crate::calling::variants::preprocessing::shards::Shard;
+use crate::utils::regions::{RecordReader, RecordSelection};
 
 #[derive(TypedBuilder)]
 pub(crate) struct ObservationProcessor<R: realignment::Realigner + Clone + 'static> {
-    alignment_properties: AlignmentProperties,
+    /// Alignment properties of each BAM file, in the same order as `inbams`.
+    alignment_properties: Vec<AlignmentProperties>,
     max_depth: usize,
     reference_buffer: Arc<reference::Buffer>,
     realigner: R,
     inbcf: PathBuf,
     outbcf: Option<PathBuf>,
-    inbam: PathBuf,
+    /// BAM files of the sample. Reads are fetched from all of them.
+    inbams: Vec<PathBuf>,
     aux_info_fields: Vec<Vec<u8>>,
     min_bam_refetch_distance: u64,
     options: cli::Varlociraptor,
@@ -72,14 +83,34 @@ pub(crate) struct ObservationProcessor<R: realignment::Realigner + Clone + 'stat
     log_each_record: bool,
     raw_observation_output: Option<PathBuf>,
     report_fragment_ids: bool,
+    #[builder(default)]
+    report_fragment_lengths: bool,
     adjust_prob_mapping: bool,
+    #[builder(default)]
+    umi_families: bool,
+    #[builder(default)]
+    alt_hit_format: Option<AltHitFormat>,
     atomic_candidate_variants: bool,
+    #[builder(default = 1)]
+    threads: usize,
+    /// Number of records after which a shard is cut within a contig (see `Shard::plan`).
+    #[builder(default = shards::MAX_SHARD_RECORDS)]
+    max_shard_records: usize,
+    #[builder(default)]
+    record_selection: Option<Arc<RecordSelection>>,
+    #[builder(default)]
+    mobile_element_library: Option<Arc<reference::MobileElementLibrary>>,
 }
 
 impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
     ObservationProcessor<R>
 {
-    fn writer(&self, aux_info_collector: &AuxInfoCollector) -> Result<bcf::Writer> {
+    fn writer(
+        &self,
+        aux_info_collector: &AuxInfoCollector,
+        path: Option<&Path>,
+        uncompressed: bool,
+    ) -> Result<bcf::Writer> {
         let mut header = bcf::Header::new();
 
         // register tags
@@ -103,6 +134,19 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
             b"##INFO=<ID=MATEID,Number=.,Type=String,\
               Description=\"ID of mate breakends\">",
         );
+        header.push_record(
+            b"##INFO=<ID=RU,Number=1,Type=String,\
+              Description=\"Repeat unit of short tandem repeat. An ALT allele <STRn> denotes n \
+              repeat units. Reads are evaluated against all repeat lengths of the site jointly, \
+              and the AFD of each record is the marginal posterior of the frequency of its \
+              repeat length in each sample.\">",
+        );
+        header.push_record(
+            b"##INFO=<ID=SITEALTS,Number=1,Type=Integer,\
+              Description=\"Number of ALT alleles at the site of the record (if more than one). \
+              Reads are evaluated against all ALT alleles of the site, and the AFD of each \
+              record is marginalized over the allele frequencies of the other ALT alleles.\">",
+        );
         header.push_record(
             b"##INFO=<ID=IMPRECISE,Number=0,Type=Flag,Description=\"Imprecise structural variation\">"
         );
@@ -121,32 +165,7 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
         }
 
         // store observations
-        for name in &vec![
-            "FRAGMENT_ID",
-            "PROB_MAPPING",
-            "PROB_ALT",
-            "PROB_REF",
-            "PROB_MISSED_ALLELE",
-            "PROB_SAMPLE_ALT",
-            "PROB_DOUBLE_OVERLAP",
-            "PROB_HIT_BASE",
-            "STRAND",
-            "READ_ORIENTATION",
-            "READ_POSITION",
-            "SOFTCLIPPED",
-            "ALT_INDEL_OPERATIONS",
-            "PAIRED",
-            "PROB_HOMOPOLYMER_ARTIFACT_OBSERVABLE",
-            "PROB_HOMOPOLYMER_VARIANT_OBSERVABLE",
-            "HOMOPOLYMER_INDEL_LEN",
-            "IS_MAX_MAPQ",
-            "ALT_LOCUS",
-            "THIRD_ALLELE_EVIDENCE",
-        ] {
-            header.push_record(
-                format!("##INFO=<ID={},Number=.,Type=Integer,Description=\"Varlociraptor observations (binary encoded, meant for internal use only).\">", name).as_bytes()
-            );
-        }
+        add_observation_header_entries(&mut header);
 
         // store options
         header.push_record(
@@ -168,17 +187,98 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
 
         aux_info_collector.write_header_info(&mut header);
 
-        Ok(if let Some(ref path) = self.outbcf {
-            bcf::Writer::from_path(path, &header, false, bcf::Format::Bcf)
+        Ok(if let Some(path) = path {
+            bcf::Writer::from_path(path, &header, uncompressed, bcf::Format::Bcf)
                 .context(format!("Unable to write BCF to {}.", path.display()))?
         } else {
-            bcf::Writer::from_stdout(&header, false, bcf::Format::Bcf)
+            bcf::Writer::from_stdout(&header, uncompressed, bcf::Format::Bcf)
                 .context("Unable to write BCF to STDOUT.")?
         })
     }
 
     pub(crate) fn process(&mut self) -> Result<()> {
-        let mut bcf_reader = bcf::Reader::from_path(&self.inbcf)?;
+        if self.threads > 1 {
+            if bcf::IndexedReader::from_path(&self.inbcf).is_err() {
+                warn!(
+                    "Candidate variants are not indexed (run e.g. bcftools index), \
+                     preprocessing them with a single thread."
+                );
+            } else {
+                // METHOD: fragment IDs are assigned per contig. In order to obtain the same IDs
+                // as in a single-threaded run, contigs are not split if fragment IDs are reported.
+                let max_records = if self.report_fragment_ids {
+                    None
+                } else {
+                    Some(self.max_shard_records)
+                };
+                let shards = Shard::plan(&self.inbcf, self.record_selection.as_ref(), max_records)?;
+                if shards.len() > 1 {
+                    return self.process_shards(&shards);
+                }
+            }
+        }
+
+        self.process_records(None, self.outbcf.as_deref(), false)
+    }
+
+    /// Process the given shards in parallel, writing each of them into a temporary BCF.
+    /// Afterwards, the temporary BCFs are concatenated in the order of the input records.
+    fn process_shards(&self, shards: &[Shard]) -> Result<()> {
+        info!(
+            "Preprocessing {} shards with {} threads.",
+            shards.len(),
+            self.threads
+        );
+        let tmp_dir = tempfile::tempdir()?;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()?;
+
+        let shard_paths = pool.install(|| {
+            shards
+                .par_iter()
+                .enumerate()
+                .map(|(i, shard)| {
+                    let path = tmp_dir.path().join(format!("shard-{}.bcf", i));
+                    self.process_records(Some(shard), Some(&path), true)?;
+                    Ok(path)
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+
+        let bcf_reader = bcf::Reader::from_path(&self.inbcf)?;
+        let aux_info_collector = AuxInfoCollector::new(&self.aux_info_fields, bcf_reader.header())?;
+        let mut bcf_writer = self.writer(&aux_info_collector, self.outbcf.as_deref(), false)?;
+
+        for path in shard_paths {
+            let mut shard_reader = bcf::Reader::from_path(&path)?;
+            for record in shard_reader.records() {
+                let mut record = record?;
+                bcf_writer.translate(&mut record);
+                bcf_writer.write(&record)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Process all candidate records (or those of the given shard, fetched via the index)
+    /// and write the obtained observations to the given path (or STDOUT).
+    fn process_records(
+        &self,
+        shard: Option<&Shard>,
+        outbcf: Option<&Path>,
+        uncompressed: bool,
+    ) -> Result<()> {
+        let mut bcf_reader = if let Some(shard) = shard {
+            RecordReader::from_path_intervals(
+                &self.inbcf,
+                self.record_selection.as_ref(),
+                shard.intervals().clone(),
+            )?
+        } else {
+            RecordReader::from_path(&self.inbcf, self.record_selection.as_ref())?
+        };
         bcf_reader.set_threads(1)?;
 
         let progress_logger = ProgressLogger::builder()
@@ -186,9 +286,9 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
             .with_frequency(std::time::Duration::from_secs(20))
             .start();
 
-        let aux_info_collector = AuxInfoCollector::new(&self.aux_info_fields, &bcf_reader)?;
+        let aux_info_collector = AuxInfoCollector::new(&self.aux_info_fields, bcf_reader.header())?;
 
-        let mut bcf_writer = self.writer(&aux_info_collector)?;
+        let mut bcf_writer = self.writer(&aux_info_collector, outbcf, uncompressed)?;
         bcf_writer.set_threads(1)?;
 
         let mut variant_buffer = VariantBuffer::new(
@@ -197,27 +297,44 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
             self.log_each_record,
             aux_info_collector,
         );
+        if let Some(shard) = shard {
+            variant_buffer = variant_buffer.with_record_range(shard.records().clone());
+        }
 
-        let mut bam_reader =
-            bam::IndexedReader::from_path(&self.inbam).context("Unable to read BAM/CRAM file.")?;
-        bam_reader.set_threads(1)?;
-        bam_reader
-            .set_reference(
-                self.reference_buffer.reference_path().expect(
+        let bam_reader = |path: &PathBuf| -> Result<bam::IndexedReader> {
+            let mut bam_reader = bam::IndexedReader::from_path(path)
+                .with_context(|| format!("Unable to read BAM/CRAM file {}.", path.display()))?;
+            bam_reader.set_threads(1)?;
+            bam_reader
+                .set_reference(self.reference_buffer.reference_path().expect(
                     "bug: reference buffer seemingly has not been created from reference file",
-                ),
-            )
-            .context("Unable to read reference FASTA")?;
+                ))
+                .context("Unable to read reference FASTA")?;
+            Ok(bam_reader)
+        };
 
         let mut sample = SampleBuilder::default()
             .max_depth(self.max_depth)
             .report_fragment_ids(self.report_fragment_ids)
+            .report_fragment_lengths(self.report_fragment_lengths)
             .adjust_prob_mapping(self.adjust_prob_mapping)
             .alignments(
-                bam_reader,
-                self.alignment_properties.clone(),
+                self.inbams
+                    .iter()
+                    .zip(self.alignment_properties.iter())
+                    .map(|(path, alignment_properties)| {
+                        Ok((bam_reader(path)?, alignment_properties.clone()))
+                    })
+                    .collect::<Result<_>>()?,
                 self.min_bam_refetch_distance,
+                self.umi_families,
+                self.alt_hit_format,
             )
+            // METHOD: depth is counted with a separate reader, such that the potentially
+            // large event intervals do not have to be kept in the record buffer.
+            .depth_counter(Some(DepthCounter::new(
+                self.inbams.iter().map(bam_reader).collect::<Result<_>>()?,
+            )))
             .build()
             .unwrap();
 
@@ -262,6 +379,16 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
         Ok(())
     }
 
+    /// Probability for substitutions of the given damage type to occur in the read orientation
+    /// in which the damage manifests, estimated over all BAM files of the sample.
+    fn prob_damage_orientation(&self, damage: &Damage) -> Option<f64> {
+        let mut damage_stats = DamageStats::default();
+        for alignment_properties in &self.alignment_properties {
+            damage_stats += alignment_properties.damage_stats.clone();
+        }
+        damage_stats.prob_damage_orientation(damage)
+    }
+
     fn process_variant(&self, variants: Variants, sample: &mut Sample) -> Result<Vec<Call>> {
         let call_builder = |chrom, start, id| {
             let mut builder = CallBuilder::default();
@@ -279,6 +406,7 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
             VariantInfo {
                 variant,
                 haplotype: None,
+                precision,
             } => {
                 let mut call = call_builder(
                     variants.locus().contig().as_bytes().to_owned(),
@@ -290,10 +418,35 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                 .unwrap();
 
                 let chrom_seq = self.reference_buffer.seq(variants.locus().contig())?;
-                let pileup = self.process_pileup(&variants, sample)?.unwrap(); // only breakends can lead to None, and they are handled below
+                let mut pileup = if let Some(pileup) = self.process_pileup(&variants, sample)? {
+                    pileup
+                } else {
+                    // the variant could not be evaluated (e.g. unsupported single breakend)
+                    return Ok(Vec::new());
+                };
+
+                if let model::Variant::Snv(altbase) = variant {
+                    // METHOD: record how strongly the sample shows a damage signature for
+                    // the substitution type, such that the calling step can consider damage.
+                    if let Some(damage) = Damage::from_substitution(
+                        chrom_seq[variants.locus().pos() as usize],
+                        *altbase,
+                    ) {
+                        pileup.set_prob_damage_orientation(self.prob_damage_orientation(&damage));
+                    }
+                }
 
                 self.write_observations(&pileup, &variants)?;
 
+                // METHOD: other variants at the same locus are competing ALT alleles of the
+                // site. This is recorded, such that the calling step can account for them.
+                let site_alts = 1 + variants
+                    .alt_variants()
+                    .filter(|variant_info| {
+                        !variant_info.variant().is_breakend() && !variant_info.variant().is_none()
+                    })
+                    .count() as u32;
+
                 // add variant information
                 call.variant = Some(
                     VariantBuilder::default()
@@ -303,7 +456,9 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                             variants.locus().pos() as usize,
                             Some(chrom_seq.as_ref()),
                         )
-                        .pileup(Some(Rc::new(pileup)))
+                        .precision(precision.clone())
+                        .site_alts(Some(site_alts).filter(|site_alts| *site_alts > 1))
+                        .pileup(Some(Arc::new(pileup)))
                         .build()
                         .unwrap(),
                 );
@@ -313,16 +468,48 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                 haplotype: Some(haplotype),
                 ..
             } => {
+                let breakend_call =
+                    |breakend: &Breakend, haplotype: &HaplotypeIdentifier, pileup: &Arc<Pileup>| {
+                        let mut call_builder = call_builder(
+                            breakend.locus().contig().as_bytes().to_owned(),
+                            breakend.locus().pos(),
+                            breakend.id().to_owned(),
+                        );
+                        call_builder.mateid(breakend.mateid().to_owned());
+                        if let Some(ref aux_info) = breakend.aux_info() {
+                            call_builder.aux_info(aux_info.clone());
+                        }
+                        let mut call = call_builder.build().unwrap();
+
+                        // add variant information
+                        call.variant = Some(
+                            VariantBuilder::default()
+                                .variant(
+                                    &breakend.to_variant(),
+                                    &Some(haplotype.to_owned()),
+                                    breakend.locus().pos() as usize,
+                                    None,
+                                )
+                                .precision(breakend.precision().to_owned())
+                                .pileup(Some(Arc::clone(pileup)))
+                                .build()
+                                .unwrap(),
+                        );
+                        call
+                    };
+
                 let mut calls = Vec::new();
                 // process breakend
                 match variants.variant_of_interest().variant() {
-                    model::Variant::Breakend { .. } => {
+                    model::Variant::Breakend { .. }
+                        if !self.haplotype_feature_index.is_mixed(haplotype) =>
+                    {
                         match haplotype {
                             HaplotypeIdentifier::Event(event) => {
                                 if let Some(pileup) = self.process_pileup(&variants, sample)? {
                                     self.write_observations(&pileup, &variants)?;
 
-                                    let pileup = Rc::new(pileup);
+                                    let pileup = Arc::new(pileup);
                                     for breakend in self
                                         .breakend_groups
                                         .read()
@@ -334,32 +521,7 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                                         .unwrap()
                                         .breakends()
                                     {
-                                        let mut call_builder = call_builder(
-                                            breakend.locus().contig().as_bytes().to_owned(),
-                                            breakend.locus().pos(),
-                                            breakend.id().to_owned(),
-                                        );
-                                        call_builder.mateid(breakend.mateid().to_owned());
-                                        if let Some(ref aux_info) = breakend.aux_info() {
-                                            call_builder.aux_info(aux_info.clone());
-                                        }
-                                        let mut call = call_builder.build().unwrap();
-
-                                        // add variant information
-                                        call.variant = Some(
-                                            VariantBuilder::default()
-                                                .variant(
-                                                    &breakend.to_variant(),
-                                                    &Some(haplotype.to_owned()),
-                                                    breakend.locus().pos() as usize,
-                                                    None,
-                                                )
-                                                .precision(breakend.precision().to_owned())
-                                                .pileup(Some(Rc::clone(&pileup)))
-                                                .build()
-                                                .unwrap(),
-                                        );
-                                        calls.push(call);
+                                        calls.push(breakend_call(breakend, haplotype, &pileup));
                                     }
                                     // As all records a written, the breakend group can be discarded.
                                     self.breakend_groups.write().unwrap().remove(event);
@@ -368,12 +530,12 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                         }
                     }
                     _ => {
-                        // process haplotype block
+                        // process haplotype block (possibly including breakends)
                         // if this is the last variant in the block
                         if let Some(pileup) = self.process_pileup(&variants, sample)? {
                             self.write_observations(&pileup, &variants)?;
 
-                            let pileup = Rc::new(pileup);
+                            let pileup = Arc::new(pileup);
                             {
                                 let haplotype_blocks = self.haplotype_blocks.read().unwrap();
                                 let haplotype_block = haplotype_blocks
@@ -388,7 +550,7 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                                     variant: model::Variant,
                                     reference_buffer: Arc<reference::Buffer>,
                                     haplotype: &HaplotypeIdentifier,
-                                    pileup: Rc<Pileup>,
+                                    pileup: Arc<Pileup>,
                                 ) -> Result<Call> {
                                     if let Some(contig) = loci.contig() {
                                         let mut call = CallBuilder::default()
@@ -408,13 +570,13 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                                                     loci.first_pos() as usize,
                                                     Some(chrom_seq.as_ref()),
                                                 )
-                                                .pileup(Some(Rc::clone(&pileup)))
+                                                .pileup(Some(Arc::clone(&pileup)))
                                                 .build()
                                                 .unwrap(),
                                         );
                                         Ok(call)
                                     } else {
-                                        unreachable!("bug: unexpected multi-contig variant (must be breakend, which is unsupported for now) in haplotype block");
+                                        unreachable!("bug: unexpected multi-contig variant in haplotype block (breakends are reported separately)");
                                     }
                                 }
 
@@ -424,9 +586,12 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                                         variant.to_variant_representation(),
                                         Arc::clone(&self.reference_buffer),
                                         haplotype,
-                                        Rc::clone(&pileup),
+                                        Arc::clone(&pileup),
                                     )?);
                                 }
+                                for breakend in haplotype_block.breakends() {
+                                    calls.push(breakend_call(breakend, haplotype, &pileup));
+                                }
                             }
                             // As all records a written, the haplotype block can be discarded.
                             self.haplotype_blocks.write().unwrap().remove(haplotype);
@@ -438,13 +603,64 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
         }
     }
 
+    /// Evaluate a haplotype consisting of breakends and other variants, once its last
+    /// record has been reached. Breakends have been collected in a breakend group builder,
+    /// all other variants in a haplotype block.
+    fn process_mixed_haplotype(
+        &self,
+        haplotype: &HaplotypeIdentifier,
+        variants: &Variants,
+        sample: &mut Sample,
+    ) -> Result<Option<Pileup>> {
+        if self
+            .haplotype_feature_index
+            .last_record_index(haplotype)
+            .unwrap()
+            != variants.record_info().index()
+        {
+            return Ok(None);
+        }
+        let HaplotypeIdentifier::Event(event) = haplotype;
+
+        let mut haplotype_block = self
+            .haplotype_blocks
+            .write()
+            .unwrap()
+            .remove(haplotype)
+            .map(|block| block.into_inner().unwrap())
+            .unwrap_or_default();
+        let group = self
+            .breakend_group_builders
+            .write()
+            .unwrap()
+            .remove(event)
+            .and_then(|builder| builder.into_inner().unwrap())
+            .and_then(|mut builder| builder.build());
+
+        if let Some(group) = group {
+            haplotype_block.push_breakend_group(group);
+            let pileup = sample.extract_observations(&haplotype_block, &Vec::new())?;
+            // keep the block until its records have been written
+            self.haplotype_blocks
+                .write()
+                .unwrap()
+                .insert(haplotype.to_owned(), Mutex::new(haplotype_block));
+            Ok(Some(pileup))
+        } else {
+            // METHOD: if any breakend is invalid, the haplotype cannot be evaluated as a whole.
+            info!(
+                "Skipping haplotype {} because its breakends cannot be evaluated.",
+                String::from_utf8_lossy(event)
+            );
+            Ok(None)
+        }
+    }
+
     fn process_pileup(&self, variants: &Variants, sample: &mut Sample) -> Result<Option<Pileup>> {
-        let interval = |len: u64| {
-            genome::Interval::new(
-                variants.locus().contig().to_owned(),
-                variants.locus().pos()..variants.locus().pos() + len,
-            )
+        let interval_at = |pos: u64, len: u64| {
+            genome::Interval::new(variants.locus().contig().to_owned(), pos..pos + len)
         };
+        let interval = |len: u64| interval_at(variants.locus().pos(), len);
 
         let ref_base = || {
             self.reference_buffer
@@ -503,8 +719,10 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
             ))
         };
 
-        let parse_deletion =
-            |len| variants::types::Deletion::new(interval(len), self.realigner.clone());
+        let parse_deletion_at = |pos, len| {
+            variants::types::Deletion::new(interval_at(pos, len), self.realigner.clone())
+        };
+        let parse_deletion = |len| parse_deletion_at(variants.locus().pos(), len);
 
         let parse_insertion = |seq: &Vec<u8>| {
             variants::types::Insertion::new(
@@ -514,25 +732,46 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
             )
         };
 
-        let parse_inversion = |len| -> Result<variants::types::Inversion<R>> {
+        let parse_mobile_element_insertion =
+            |element: &Vec<u8>, len, strand| -> Result<variants::types::Insertion<R>> {
+                let consensus = self
+                    .mobile_element_library
+                    .as_ref()
+                    .and_then(|library| library.consensus(element))
+                    .ok_or_else(|| errors::Error::UnknownMobileElement {
+                        element: String::from_utf8_lossy(element).into_owned(),
+                    })?;
+                variants::types::Insertion::mobile_element(
+                    variants.locus().clone(),
+                    element.to_owned(),
+                    consensus,
+                    len,
+                    strand,
+                    self.realigner.clone(),
+                )
+            };
+
+        let parse_inversion_at = |pos, len| -> Result<variants::types::Inversion<R>> {
             Ok(variants::types::Inversion::new(
-                interval(len),
+                interval_at(pos, len),
                 self.realigner.clone(),
                 self.reference_buffer
                     .seq(variants.locus().contig())?
                     .as_ref(),
             ))
         };
+        let parse_inversion = |len| parse_inversion_at(variants.locus().pos(), len);
 
-        let parse_duplication = |len| -> Result<variants::types::Duplication<R>> {
+        let parse_duplication_at = |pos, len| -> Result<variants::types::Duplication<R>> {
             Ok(variants::types::Duplication::new(
-                interval(len),
+                interval_at(pos, len),
                 self.realigner.clone(),
                 self.reference_buffer
                     .seq(variants.locus().contig())?
                     .as_ref(),
             ))
         };
+        let parse_duplication = |len| parse_duplication_at(variants.locus().pos(), len);
 
         let parse_replacement = |ref_allele: &Vec<u8>,
                                  alt_allele: &Vec<u8>|
@@ -544,23 +783,70 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
             )
         };
 
+        let parse_short_tandem_repeat = |unit: &Vec<u8>,
+                                         ref_len: u64,
+                                         count: u32,
+                                         site_counts: Vec<Option<u32>>|
+         -> Result<variants::types::ShortTandemRepeat<R>> {
+            // the locus spans the base before the repeat and the repeat tract
+            variants::types::ShortTandemRepeat::new(
+                interval(ref_len + 1),
+                unit.to_owned(),
+                count,
+                site_counts,
+                self.realigner.clone(),
+            )
+        };
+
+        let is_realignable_alt_variant = |variant_info: &&VariantInfo| {
+            !variant_info.variant().is_breakend() && !variant_info.variant().is_none()
+        };
+        // Numbers of repeat units of the alt variants that are alleles of the given repeat.
+        let site_repeat_counts = |unit: &Vec<u8>, ref_len: u64| {
+            variants
+                .alt_variants()
+                .filter(is_realignable_alt_variant)
+                .map(|variant_info| match variant_info.variant() {
+                    model::Variant::ShortTandemRepeat {
+                        unit: other_unit,
+                        ref_len: other_ref_len,
+                        count,
+                    } if other_unit == unit && *other_ref_len == ref_len => Some(*count),
+                    _ => None,
+                })
+                .collect_vec()
+        };
+
         let alt_variants = variants
             .alt_variants()
-            .filter(|variant_info| {
-                !variant_info.variant().is_breakend() && !variant_info.variant().is_none()
-            })
-            .map(|variant_info| -> Result<Box<dyn Realignable>> {
+            .filter(is_realignable_alt_variant)
+            .map(|variant_info| -> Result<Box<dyn Realignable + Send>> {
                 Ok(match variant_info.variant() {
                     model::Variant::Snv(alt) => Box::new(parse_snv(*alt)?),
                     model::Variant::Mnv(alt) => Box::new(parse_mnv(alt)?),
                     model::Variant::Deletion(l) => Box::new(parse_deletion(*l)?),
                     model::Variant::Insertion(seq) => Box::new(parse_insertion(seq)?),
+                    model::Variant::MobileElementInsertion {
+                        element,
+                        len,
+                        strand,
+                    } => Box::new(parse_mobile_element_insertion(element, *len, *strand)?),
                     model::Variant::Inversion(len) => Box::new(parse_inversion(*len)?),
                     model::Variant::Duplication(len) => Box::new(parse_duplication(*len)?),
                     model::Variant::Replacement {
                         ref_allele,
                         alt_allele,
                     } => Box::new(parse_replacement(ref_allele, alt_allele)?),
+                    model::Variant::ShortTandemRepeat {
+                        unit,
+                        ref_len,
+                        count,
+                    } => Box::new(parse_short_tandem_repeat(
+                        unit,
+                        *ref_len,
+                        *count,
+                        Vec::new(),
+                    )?),
                     model::Variant::Breakend { .. } => {
                         unreachable!();
                     }
@@ -596,6 +882,32 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                                     .insert(event.to_owned(), Mutex::new(Some(builder)));
                             }
                         }
+                        if self.haplotype_feature_index.is_mixed(haplotype) {
+                            // METHOD: the event also contains other variants, hence it is
+                            // evaluated as a whole in a haplotype block.
+                            {
+                                let group_builders = self.breakend_group_builders.read().unwrap();
+                                let mut group = group_builders.get(event).unwrap().lock().unwrap();
+                                if group.is_some() {
+                                    if let Some(breakend) = Breakend::new(
+                                        variants.locus().clone(),
+                                        ref_allele,
+                                        spec,
+                                        variants.record_info().id(),
+                                        variants.record_info().mateid().clone(),
+                                        precision.clone(),
+                                        variants.record_info().aux_info().clone(),
+                                    )? {
+                                        group.as_mut().unwrap().push_breakend(breakend);
+                                    } else {
+                                        // Breakend type not supported, invalidate the group.
+                                        *group = None;
+                                    }
+                                }
+                            }
+                            return self.process_mixed_haplotype(haplotype, variants, sample);
+                        }
+
                         let group_builders = self.breakend_group_builders.read().unwrap();
 
                         let mut group = group_builders.get(event).unwrap().lock().unwrap();
@@ -693,6 +1005,13 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                             model::Variant::Insertion(seq) => {
                                 haplotype_block.push_variant(Box::new(parse_insertion(seq)?))
                             }
+                            model::Variant::MobileElementInsertion {
+                                element,
+                                len,
+                                strand,
+                            } => haplotype_block.push_variant(Box::new(
+                                parse_mobile_element_insertion(element, *len, *strand)?,
+                            )),
                             model::Variant::Inversion(len) => {
                                 haplotype_block.push_variant(Box::new(parse_inversion(*len)?))
                             }
@@ -704,11 +1023,27 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                                 alt_allele,
                             } => haplotype_block
                                 .push_variant(Box::new(parse_replacement(ref_allele, alt_allele)?)),
+                            model::Variant::ShortTandemRepeat {
+                                unit,
+                                ref_len,
+                                count,
+                            } => haplotype_block.push_variant(Box::new(parse_short_tandem_repeat(
+                                unit,
+                                *ref_len,
+                                *count,
+                                Vec::new(),
+                            )?)),
                             model::Variant::Breakend { .. } => {
-                                bail!(errors::Error::HaplotypeBlockWithBreakend);
+                                unreachable!("bug: breakends are handled above")
                             }
                         }
 
+                        if self.haplotype_feature_index.is_mixed(haplotype) {
+                            drop(haplotype_block);
+                            drop(haplotype_blocks);
+                            return self.process_mixed_haplotype(haplotype, variants, sample);
+                        }
+
                         // If this is the last variant in the group, process!
                         if self
                             .haplotype_feature_index
@@ -722,6 +1057,73 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                         }
                     }
                 }
+            } else if let VariantPrecision::Imprecise { cistart, ciend } =
+                variants.variant_of_interest().precision()
+            {
+                // imprecise structural variants
+                let pos = variants.locus().pos();
+                let ciend = ciend.as_ref();
+                let pileup = match variants.variant_of_interest().variant() {
+                    model::Variant::Deletion(l) => {
+                        // pos is pointing to the base before the deletion
+                        let variant = variants::types::Imprecise::new(
+                            pos,
+                            pos + 1 + l,
+                            cistart,
+                            ciend,
+                            |pos, end| {
+                                if end > pos + 1 {
+                                    parse_deletion_at(pos, end - (pos + 1)).map(Some)
+                                } else {
+                                    Ok(None)
+                                }
+                            },
+                        )?;
+                        extract_imprecise_observations(&variant, sample, &alt_variants)?
+                    }
+                    model::Variant::Inversion(len) => {
+                        let variant = variants::types::Imprecise::new(
+                            pos,
+                            pos + len,
+                            cistart,
+                            ciend,
+                            |pos, end| {
+                                if pos > 0 && end > pos {
+                                    parse_inversion_at(pos, end - pos).map(Some)
+                                } else {
+                                    Ok(None)
+                                }
+                            },
+                        )?;
+                        extract_imprecise_observations(&variant, sample, &alt_variants)?
+                    }
+                    model::Variant::Duplication(len) => {
+                        let variant = variants::types::Imprecise::new(
+                            pos,
+                            pos + len,
+                            cistart,
+                            ciend,
+                            |pos, end| {
+                                if pos > 0 && end > pos {
+                                    parse_duplication_at(pos, end - pos).map(Some)
+                                } else {
+                                    Ok(None)
+                                }
+                            },
+                        )?;
+                        extract_imprecise_observations(&variant, sample, &alt_variants)?
+                    }
+                    variant => unreachable!(
+                        "bug: only deletions, inversions and duplications can be imprecise, found {}",
+                        variant
+                    ),
+                };
+                if let Some(pileup) = pileup {
+                    pileup
+                } else {
+                    // no feasible placement of the breakpoints
+                    return Ok(None);
+                }
             } else {
                 // single variants
                 match variants.variant_of_interest().variant() {
@@ -740,6 +1142,14 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                     model::Variant::Insertion(seq) => {
                         sample.extract_observations(&parse_insertion(seq)?, &alt_variants)?
                     }
+                    model::Variant::MobileElementInsertion {
+                        element,
+                        len,
+                        strand,
+                    } => sample.extract_observations(
+                        &parse_mobile_element_insertion(element, *len, *strand)?,
+                        &alt_variants,
+                    )?,
                     model::Variant::Inversion(len) => {
                         sample.extract_observations(&parse_inversion(*len)?, &alt_variants)?
                     }
@@ -753,24 +1163,84 @@ impl<R: realignment::Realigner + Clone + std::marker::Send + std::marker::Sync>
                         &parse_replacement(ref_allele, alt_allele)?,
                         &alt_variants,
                     )?,
-                    model::Variant::Breakend { .. } => unimplemented!(
-                        "bug: breakends without haplotype events should be ignored for now"
-                    ),
+                    model::Variant::ShortTandemRepeat {
+                        unit,
+                        ref_len,
+                        count,
+                    } => sample.extract_observations(
+                        &parse_short_tandem_repeat(
+                            unit,
+                            *ref_len,
+                            *count,
+                            site_repeat_counts(unit, *ref_len),
+                        )?,
+                        &alt_variants,
+                    )?,
+                    model::Variant::Breakend {
+                        ref_allele,
+                        spec,
+                        precision,
+                    } => {
+                        // single breakend, not linked to any other record
+                        if let Some(breakend) = Breakend::new(
+                            variants.locus().clone(),
+                            ref_allele,
+                            spec,
+                            variants.record_info().id(),
+                            None,
+                            precision.clone(),
+                            variants.record_info().aux_info().clone(),
+                        )? {
+                            let mut builder =
+                                variants::types::breakends::BreakendGroupBuilder::new();
+                            builder.realigner(self.realigner.clone());
+                            builder.push_breakend(breakend);
+                            if let Some(group) = builder.build() {
+                                sample.extract_observations(&group, &Vec::new())?
+                            } else {
+                                // skipped with message in the builder
+                                return Ok(None);
+                            }
+                        } else {
+                            return Ok(None);
+                        }
+                    }
                 }
             },
         ))
     }
 }
 
-pub(crate) static OBSERVATION_FORMAT_VERSION: &str = "15";
+/// Extract observations for an imprecise variant, recording the likelihoods of the
+/// candidate breakpoints in the pileup.
+fn extract_imprecise_observations<V>(
+    variant: &variants::types::Imprecise<V>,
+    sample: &mut Sample,
+    alt_variants: &[Box<dyn Realignable + Send>],
+) -> Result<Option<Pileup>>
+where
+    V: Variant,
+{
+    if variant.is_empty() {
+        return Ok(None);
+    }
+    let mut pileup = sample.extract_observations(variant, alt_variants)?;
+    *pileup.breakpoint_observations_mut() = variant.breakpoint_observations();
+    Ok(Some(pileup))
+}
+
+pub(crate) static OBSERVATION_FORMAT_VERSION: &str = "21";
 
 pub struct Observations {
     pub pileup: Pileup,
     pub is_homopolymer_indel: bool,
 }
 
-/// Read observations from BCF record.
-pub fn read_observations(record: &mut bcf::Record) -> Result<Observations> {
+/// Read observations of the given format from BCF record.
+pub(crate) fn read_observations(
+    record: &mut bcf::Record,
+    format: ObservationFormat,
+) -> Result<Observations> {
     fn read_values<T>(record: &mut bcf::Record, tag: &[u8], allow_missing: bool) -> Result<T>
     where
         T: serde::de::DeserializeOwned + Debug + Default,
@@ -823,9 +1293,43 @@ pub fn read_observations(record: &mut bcf::Record) -> Result<Observations> {
         read_values(record, b"HOMOPOLYMER_INDEL_LEN", true)?;
     let is_homopolymer_indel = !prob_observable_at_homopolymer_artifact.is_empty();
     let is_max_mapq: BitVec<u8> = read_values(record, b"IS_MAX_MAPQ", false)?;
-    let alt_locus: Vec<AltLocus> = read_values(record, b"ALT_LOCUS", false)?;
-    let third_allele_evidence: Vec<Option<u32>> =
-        read_values(record, b"THIRD_ALLELE_EVIDENCE", false)?;
+    // fields that are missing in older formats are filled with neutral values
+    let alt_locus: Vec<AltLocus> = if format.has_alt_locus() {
+        read_values(record, b"ALT_LOCUS", false)?
+    } else {
+        vec![AltLocus::None; prob_mapping.len()]
+    };
+    let third_allele_evidence: Vec<Option<u32>> = if format.has_third_allele_evidence() {
+        read_values(record, b"THIRD_ALLELE_EVIDENCE", false)?
+    } else {
+        vec![None; prob_mapping.len()]
+    };
+
+    let mut family_size: Vec<Option<u32>> = if format.has_family_size() {
+        read_values(record, b"FAMILY_SIZE", true)?
+    } else {
+        Vec::new()
+    };
+    if family_size.is_empty() {
+        family_size = vec![None; prob_mapping.len()];
+    }
+    let mut fragment_len: Vec<Option<u32>> = if format.has_fragment_len() {
+        read_values(record, b"FRAGMENT_LEN", true)?
+    } else {
+        Vec::new()
+    };
+    if fragment_len.is_empty() {
+        fragment_len = vec![None; prob_mapping.len()];
+    }
+
+    let mut prob_competing_alleles: Vec<Vec<MiniLogProb>> = if format.has_competing_alleles() {
+        read_values(record, b"PROB_COMPETING_ALLELES", true)?
+    } else {
+        Vec::new()
+    };
+    if prob_competing_alleles.is_empty() {
+        prob_competing_alleles = vec![Vec::new(); prob_mapping.len()];
+    }
 
     let read_obs = (0..prob_mapping.len())
         .map(|i| {
@@ -835,6 +1339,12 @@ pub fn read_observations(record: &mut bcf::Record) -> Result<Observations> {
                 .prob_mapping_mismapping(prob_mapping[i].to_logprob())
                 .prob_alt(prob_alt[i].to_logprob())
                 .prob_ref(prob_ref[i].to_logprob())
+                .prob_competing_alleles(
+                    prob_competing_alleles[i]
+                        .iter()
+                        .map(|prob| prob.to_logprob())
+                        .collect(),
+                )
                 .prob_missed_allele(prob_missed_allele[i].to_logprob())
                 .prob_sample_alt(prob_sample_alt[i].to_logprob())
                 .prob_overlap(prob_double_overlap[i].to_logprob())
@@ -846,7 +1356,9 @@ pub fn read_observations(record: &mut bcf::Record) -> Result<Observations> {
                 .paired(paired[i as u64])
                 .is_max_mapq(is_max_mapq[i as u64])
                 .alt_locus(alt_locus[i])
-                .third_allele_evidence(third_allele_evidence[i]);
+                .third_allele_evidence(third_allele_evidence[i])
+                .family_size(family_size[i])
+                .fragment_len(fragment_len[i]);
 
             if is_homopolymer_indel {
                 obs.homopolymer_indel_len(homopolymer_indel_len[i])
@@ -865,16 +1377,36 @@ pub fn read_observations(record: &mut bcf::Record) -> Result<Observations> {
         })
         .collect_vec();
 
-    let depth_obs = Vec::new(); // TODO: read depth observations!
+    let depth_obs: Vec<DepthObservation> = if format.has_depth_observations() {
+        read_values(record, b"DEPTH_OBSERVATIONS", true)?
+    } else {
+        Vec::new()
+    };
+
+    let breakpoint_obs: Vec<BreakpointObservation> = if format.has_breakpoint_observations() {
+        read_values(record, b"BREAKPOINT_OBSERVATIONS", true)?
+    } else {
+        Vec::new()
+    };
+
+    let mut pileup = Pileup::new(read_obs, depth_obs);
+    *pileup.breakpoint_observations_mut() = breakpoint_obs;
+    if format.has_damage() {
+        pileup.set_prob_damage_orientation(
+            record
+                .info(b"PROB_DAMAGE_ORIENTATION")
+                .float()?
+                .map(|prob| prob[0] as f64),
+        );
+    }
 
     Ok(Observations {
-        pileup: Pileup::new(read_obs, depth_obs),
+        pileup,
         is_homopolymer_indel,
     })
 }
 
 pub(crate) fn write_observations(pileup: &Pileup, record: &mut bcf::Record) -> Result<()> {
-    // TODO: write depth observations
     let read_observations = pileup.read_observations();
 
     let vec = || Vec::with_capacity(read_observations.len());
@@ -882,6 +1414,8 @@ pub(crate) fn write_observations(pileup: &Pileup, record: &mut bcf::Record) -> R
     let mut prob_mapping = vec();
     let mut prob_ref = vec();
     let mut prob_alt = vec();
+    let mut prob_competing_alleles: Vec<Vec<MiniLogProb>> =
+        Vec::with_capacity(read_observations.len());
     let mut prob_missed_allele = vec();
     let mut prob_sample_alt = vec();
     let mut prob_double_overlap = vec();
@@ -899,6 +1433,8 @@ pub(crate) fn write_observations(pileup: &Pileup, record: &mut bcf::Record) -> R
     let mut is_max_mapq: BitVec<u8> = BitVec::with_capacity(read_observations.len() as u64);
     let mut alt_locus = Vec::with_capacity(read_observations.len());
     let mut third_allele_evidence = Vec::with_capacity(read_observations.len());
+    let mut family_size: Vec<Option<u32>> = Vec::with_capacity(read_observations.len());
+    let mut fragment_len: Vec<Option<u32>> = Vec::with_capacity(read_observations.len());
 
     let encode_logprob = utils::MiniLogProb::new;
     for obs in read_observations {
@@ -906,6 +1442,13 @@ pub(crate) fn write_observations(pileup: &Pileup, record: &mut bcf::Record) -> R
         prob_mapping.push(encode_logprob(obs.prob_mapping()));
         prob_ref.push(encode_logprob(obs.prob_ref));
         prob_alt.push(encode_logprob(obs.prob_alt));
+        prob_competing_alleles.push(
+            obs.prob_competing_alleles
+                .iter()
+                .cloned()
+                .map(encode_logprob)
+                .collect(),
+        );
         prob_missed_allele.push(encode_logprob(obs.prob_missed_allele));
         prob_sample_alt.push(encode_logprob(obs.prob_sample_alt));
         prob_double_overlap.push(encode_logprob(obs.prob_double_overlap));
@@ -918,6 +1461,8 @@ pub(crate) fn write_observations(pileup: &Pileup, record: &mut bcf::Record) -> R
         is_max_mapq.push(obs.is_max_mapq);
         alt_locus.push(obs.alt_locus);
         third_allele_evidence.push(obs.third_allele_evidence);
+        family_size.push(obs.family_size);
+        fragment_len.push(obs.fragment_len);
 
         prob_observable_at_homopolymer_artifact.push(
             obs.prob_observable_at_homopolymer_artifact
@@ -989,9 +1534,79 @@ pub(crate) fn write_observations(pileup: &Pileup, record: &mut bcf::Record) -> R
         push_values(record, b"HOMOPOLYMER_INDEL_LEN", &homopolymer_indel_len)?;
     }
 
+    if prob_competing_alleles.iter().any(|probs| !probs.is_empty()) {
+        // only record values if there are competing ALT alleles at the site
+        push_values(record, b"PROB_COMPETING_ALLELES", &prob_competing_alleles)?;
+    }
+
+    if !pileup.depth_observations().is_empty() {
+        push_values(record, b"DEPTH_OBSERVATIONS", pileup.depth_observations())?;
+    }
+
+    if family_size.iter().any(|size| size.is_some()) {
+        // only record values if reads have been grouped into UMI families
+        push_values(record, b"FAMILY_SIZE", &family_size)?;
+    }
+
+    if fragment_len.iter().any(|len| len.is_some()) {
+        // only record values if fragment lengths shall be reported
+        push_values(record, b"FRAGMENT_LEN", &fragment_len)?;
+    }
+
+    if !pileup.breakpoint_observations().is_empty() {
+        push_values(
+            record,
+            b"BREAKPOINT_OBSERVATIONS",
+            pileup.breakpoint_observations(),
+        )?;
+    }
+
+    if let Some(prob) = pileup.prob_damage_orientation() {
+        record.push_info_float(b"PROB_DAMAGE_ORIENTATION", &[prob as f32])?;
+    }
+
     Ok(())
 }
 
+pub(crate) fn add_observation_header_entries(header: &mut bcf::Header) {
+    for name in &vec![
+        "FRAGMENT_ID",
+        "PROB_MAPPING",
+        "PROB_ALT",
+        "PROB_REF",
+        "PROB_MISSED_ALLELE",
+        "PROB_SAMPLE_ALT",
+        "PROB_DOUBLE_OVERLAP",
+        "PROB_HIT_BASE",
+        "STRAND",
+        "READ_ORIENTATION",
+        "READ_POSITION",
+        "SOFTCLIPPED",
+        "ALT_INDEL_OPERATIONS",
+        "PAIRED",
+        "PROB_HOMOPOLYMER_ARTIFACT_OBSERVABLE",
+        "PROB_HOMOPOLYMER_VARIANT_OBSERVABLE",
+        "HOMOPOLYMER_INDEL_LEN",
+        "IS_MAX_MAPQ",
+        "ALT_LOCUS",
+        "THIRD_ALLELE_EVIDENCE",
+        "DEPTH_OBSERVATIONS",
+        "FAMILY_SIZE",
+        "FRAGMENT_LEN",
+        "BREAKPOINT_OBSERVATIONS",
+        "PROB_COMPETING_ALLELES",
+    ] {
+        header.push_record(
+            format!("##INFO=<ID={},Number=.,Type=Integer,Description=\"Varlociraptor observations (binary encoded, meant for internal use only).\">", name).as_bytes()
+        );
+    }
+    header.push_record(
+        b"##INFO=<ID=PROB_DAMAGE_ORIENTATION,Number=1,Type=Float,Description=\"Probability for \
+          substitutions of the variant's damage type to occur in the read orientation in which the \
+          damage manifests, estimated for the sample (meant for internal use only).\">",
+    );
+}
+
 pub(crate) fn remove_observation_header_entries(header: &mut bcf::Header) {
     header.remove_info(b"FRAGMENT_ID");
     header.remove_info(b"PROB_MAPPING");
@@ -1012,6 +1627,27 @@ pub(crate) fn remove_observation_header_entries(header: &mut bcf::Header) {
     header.remove_info(b"IS_MAX_MAPQ");
     header.remove_info(b"ALT_LOCUS");
     header.remove_info(b"THIRD_ALLELE_EVIDENCE");
+    header.remove_info(b"DEPTH_OBSERVATIONS");
+    header.remove_info(b"FAMILY_SIZE");
+    header.remove_info(b"FRAGMENT_LEN");
+    header.remove_info(b"BREAKPOINT_OBSERVATIONS");
+    header.remove_info(b"PROB_DAMAGE_ORIENTATION");
+    header.remove_info(b"PROB_COMPETING_ALLELES");
+}
+
+pub(crate) fn read_observation_format_version<P: AsRef<Path>>(bcfpath: P) -> Result<String> {
+    let reader = bcf::Reader::from_path(&bcfpath)?;
+    for rec in reader.header().header_records() {
+        if let bcf::header::HeaderRecord::Generic { key, value } = rec {
+            if key == "varlociraptor_observation_format_version" {
+                return Ok(value);
+            }
+        }
+    }
+    Err(errors::Error::InvalidObservations {
+        path: bcfpath.as_ref().to_owned(),
+    }
+    .into())
 }
 
 pub(crate) fn read_preprocess_options<P: AsRef<Path>>(bcfpath: P) -> Result<cli::Varlociraptor> {
@@ -1028,3 +1664,128 @@ pub(crate) fn read_preprocess_options<P: AsRef<Path>>(bcfpath: P) -> Result<cli:
     }
     .into())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use structopt::StructOpt;
+
+    use super::*;
+    use crate::estimation::alignment_properties::{ReadType, NUM_FRAGMENTS};
+    use crate::variants::evidence::realignment::PathHMMRealigner;
+    use crate::variants::sample::estimate_alignment_properties;
+
+    const BAM: &str = "tests/resources/tumor-first30000.bam";
+    const REFERENCE: &str = "tests/resources/chr10.fa";
+    const MAX_SHARD_RECORDS: usize = 7;
+
+    /// Write an indexed candidate BCF with SNVs along the covered part of chr10 and an
+    /// additional deletion at every fifth SNV position. Returns the number of records.
+    fn write_candidates(path: &Path) -> usize {
+        let seq = reference::Buffer::from_path(REFERENCE, 1)
+            .unwrap()
+            .seq("chr10")
+            .unwrap();
+        let mut header = bcf::Header::new();
+        header.push_record(b"##contig=<ID=chr10,length=129681>");
+        let mut n_records = 0;
+        {
+            let mut writer =
+                bcf::Writer::from_path(path, &header, false, bcf::Format::Bcf).unwrap();
+            for i in 0..60 {
+                let pos = 60000 + 1000 * i;
+                let altbase = [if seq[pos] == b'A' { b'C' } else { b'A' }];
+                let mut alleles: Vec<[&[u8]; 2]> = vec![[&seq[pos..pos + 1], &altbase]];
+                if i % 5 == 0 {
+                    alleles.push([&seq[pos..pos + 4], &seq[pos..pos + 1]]);
+                }
+                for alleles in alleles {
+                    let mut record = writer.empty_record();
+                    record.set_rid(Some(0));
+                    record.set_pos(pos as i64);
+                    record.set_alleles(&alleles).unwrap();
+                    writer.write(&record).unwrap();
+                    n_records += 1;
+                }
+            }
+        }
+        bcf::index::build(path, None, 1, bcf::index::Type::Csi(14)).unwrap();
+        n_records
+    }
+
+    fn preprocess(candidates: &Path, output: &Path, threads: usize) {
+        let mut reference_buffer = reference::Buffer::from_path(REFERENCE, 1).unwrap();
+        let alignment_properties = estimate_alignment_properties(
+            BAM,
+            false,
+            ReadType::Short,
+            &mut reference_buffer,
+            Some(NUM_FRAGMENTS),
+        )
+        .unwrap();
+        let reference_buffer = Arc::new(reference_buffer);
+        let options = cli::Varlociraptor::from_iter(vec![
+            "varlociraptor",
+            "preprocess",
+            "variants",
+            REFERENCE,
+            "--candidates",
+            candidates.to_str().unwrap(),
+            "--bam",
+            BAM,
+        ]);
+
+        let mut processor = ObservationProcessor::builder()
+            .report_fragment_ids(false)
+            .adjust_prob_mapping(true)
+            .alignment_properties(vec![alignment_properties.clone()])
+            .max_depth(200)
+            .inbams(vec![PathBuf::from(BAM)])
+            .min_bam_refetch_distance(1)
+            .reference_buffer(Arc::clone(&reference_buffer))
+            .haplotype_feature_index(HaplotypeFeatureIndex::new(candidates, None).unwrap())
+            .inbcf(candidates.to_owned())
+            .aux_info_fields(Vec::new())
+            .options(options)
+            .outbcf(Some(output.to_owned()))
+            .raw_observation_output(None)
+            .log_each_record(false)
+            .realigner(PathHMMRealigner::new(
+                alignment_properties.gap_params.clone(),
+                64,
+                reference_buffer,
+                Vec::new(),
+            ))
+            .atomic_candidate_variants(false)
+            .threads(threads)
+            .max_shard_records(MAX_SHARD_RECORDS)
+            .build();
+        processor.process().unwrap();
+    }
+
+    #[test]
+    fn test_process_shards() {
+        let tmp = tempfile::tempdir().unwrap();
+        let candidates = tmp.path().join("candidates.bcf");
+        let n_records = write_candidates(&candidates);
+
+        // a single contig is split into multiple shards, never between records of a locus
+        let shards = Shard::plan(&candidates, None, Some(MAX_SHARD_RECORDS)).unwrap();
+        assert!(shards.len() > 1);
+        assert_eq!(shards[0].records(), &(0..8));
+        assert_eq!(shards[0].intervals(), &vec![(0, 60000..65001)]);
+        assert_eq!(shards.last().unwrap().records().end, n_records);
+        // without cuts within contigs, there is only one shard
+        assert_eq!(Shard::plan(&candidates, None, None).unwrap().len(), 1);
+
+        let serial = tmp.path().join("serial.bcf");
+        let parallel = tmp.path().join("parallel.bcf");
+        preprocess(&candidates, &serial, 1);
+        preprocess(&candidates, &parallel, 4);
+
+        let n_observations = bcf::Reader::from_path(&serial).unwrap().records().count();
+        assert_eq!(n_observations, n_records);
+        assert_eq!(fs::read(&serial).unwrap(), fs::read(&parallel).unwrap());
+    }
+}
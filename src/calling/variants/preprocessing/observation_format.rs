@@ -0,0 +1,246 @@
+// Copyright 2024 Johannes Köster.
+// Licensed under the GNU GPLv3 license (https://opensource.org/licenses/GPL-3.0)
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Versions of the format in which observations are stored in preprocessed BCFs.
+//!
+//! Newer formats only add fields to older ones. When reading an older format, fields that are
+//! missing from it are filled with neutral values, i.e. values that do not indicate any bias.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rust_htslib::bcf::{self, header::HeaderView, Read};
+
+use crate::calling::variants::preprocessing::{
+    add_observation_header_entries, read_observations, remove_observation_header_entries,
+    write_observations, OBSERVATION_FORMAT_VERSION,
+};
+use crate::errors;
+
+/// Oldest observation format version that can still be read.
+const MIN_SUPPORTED_VERSION: u32 = 13;
+/// First version that records whether reads also map to alternative loci (ALT_LOCUS).
+const ALT_LOCUS_VERSION: u32 = 14;
+/// First version that records the evidence of reads for a third allele (THIRD_ALLELE_EVIDENCE).
+const THIRD_ALLELE_EVIDENCE_VERSION: u32 = 15;
+/// First version that records depth observations (DEPTH_OBSERVATIONS).
+const DEPTH_OBSERVATIONS_VERSION: u32 = 16;
+/// First version that records the size of UMI families (FAMILY_SIZE).
+const FAMILY_SIZE_VERSION: u32 = 17;
+/// First version that records the length of sequenced fragments (FRAGMENT_LEN).
+const FRAGMENT_LEN_VERSION: u32 = 18;
+/// First version that records the likelihoods of candidate breakpoints of imprecise
+/// structural variants (BREAKPOINT_OBSERVATIONS).
+const BREAKPOINT_OBSERVATIONS_VERSION: u32 = 19;
+/// First version that records the probability for damage induced substitutions to occur in
+/// the damaged read orientation (PROB_DAMAGE_ORIENTATION).
+const DAMAGE_VERSION: u32 = 20;
+/// First version that records the probabilities of reads for the competing ALT alleles at
+/// sites with multiple ALT alleles (PROB_COMPETING_ALLELES).
+const COMPETING_ALLELES_VERSION: u32 = 21;
+
+const VERSION_HEADER_KEY: &str = "varlociraptor_observation_format_version";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct ObservationFormat {
+    version: u32,
+}
+
+impl ObservationFormat {
+    /// The format written by this version of Varlociraptor.
+    pub(crate) fn current() -> Self {
+        ObservationFormat {
+            version: OBSERVATION_FORMAT_VERSION
+                .parse()
+                .expect("bug: invalid observation format version"),
+        }
+    }
+
+    pub(crate) fn new(version: &str) -> Result<Self> {
+        let unsupported = || errors::Error::UnsupportedObservationFormat {
+            version: version.to_owned(),
+        };
+        let version: u32 = version.parse().map_err(|_| unsupported())?;
+        let format = ObservationFormat { version };
+        if version < MIN_SUPPORTED_VERSION || format > Self::current() {
+            return Err(unsupported().into());
+        }
+        Ok(format)
+    }
+
+    /// Obtain the format of a BCF with observations from its header.
+    pub(crate) fn from_header(header: &HeaderView) -> Result<Self> {
+        for record in header.header_records() {
+            if let bcf::HeaderRecord::Generic { key, value } = record {
+                if key == VERSION_HEADER_KEY {
+                    return Self::new(&value);
+                }
+            }
+        }
+        Err(errors::Error::InvalidObservationFormat.into())
+    }
+
+    pub(crate) fn is_current(&self) -> bool {
+        *self == Self::current()
+    }
+
+    pub(crate) fn has_alt_locus(&self) -> bool {
+        self.version >= ALT_LOCUS_VERSION
+    }
+
+    pub(crate) fn has_third_allele_evidence(&self) -> bool {
+        self.version >= THIRD_ALLELE_EVIDENCE_VERSION
+    }
+
+    pub(crate) fn has_depth_observations(&self) -> bool {
+        self.version >= DEPTH_OBSERVATIONS_VERSION
+    }
+
+    pub(crate) fn has_family_size(&self) -> bool {
+        self.version >= FAMILY_SIZE_VERSION
+    }
+
+    pub(crate) fn has_fragment_len(&self) -> bool {
+        self.version >= FRAGMENT_LEN_VERSION
+    }
+
+    pub(crate) fn has_breakpoint_observations(&self) -> bool {
+        self.version >= BREAKPOINT_OBSERVATIONS_VERSION
+    }
+
+    pub(crate) fn has_damage(&self) -> bool {
+        self.version >= DAMAGE_VERSION
+    }
+
+    pub(crate) fn has_competing_alleles(&self) -> bool {
+        self.version >= COMPETING_ALLELES_VERSION
+    }
+}
+
+/// Rewrite a BCF with observations of an older format into the current format.
+pub(crate) fn upgrade_observations<P: AsRef<Path>>(input: P, output: Option<&Path>) -> Result<()> {
+    let input = input.as_ref();
+    let mut reader = bcf::Reader::from_path(input)
+        .with_context(|| format!("Unable to read BCF {}.", input.display()))?;
+    let format = ObservationFormat::from_header(reader.header())?;
+    if format.is_current() {
+        warn!(
+            "{} already has the current observation format, it will be copied unchanged.",
+            input.display()
+        );
+    }
+
+    let mut header = bcf::Header::from_template(reader.header());
+    remove_observation_header_entries(&mut header);
+    header.remove_info(b"ALT_INDEL_OPERATIONS");
+    header.remove_generic(VERSION_HEADER_KEY.as_bytes());
+    add_observation_header_entries(&mut header);
+    header
+        .push_record(format!("##{}={}", VERSION_HEADER_KEY, OBSERVATION_FORMAT_VERSION).as_bytes());
+
+    let mut writer = if let Some(path) = output {
+        bcf::Writer::from_path(path, &header, false, bcf::Format::Bcf)
+            .context(format!("Unable to write BCF to {}.", path.display()))?
+    } else {
+        bcf::Writer::from_stdout(&header, false, bcf::Format::Bcf)
+            .context("Unable to write BCF to STDOUT.")?
+    };
+
+    let mut record = reader.empty_record();
+    while let Some(res) = reader.read(&mut record) {
+        res?;
+        let observations = read_observations(&mut record, format)?;
+        writer.translate(&mut record);
+        write_observations(&observations.pileup, &mut record)?;
+        writer.write(&record)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variants::evidence::observations::pileup::Pileup;
+    use crate::variants::model::tests::observation;
+    use bio::stats::LogProb;
+
+    #[test]
+    fn test_observation_format_versions() {
+        let current = ObservationFormat::current();
+        assert!(current.has_alt_locus());
+        assert!(current.has_third_allele_evidence());
+        assert!(current.has_depth_observations());
+        assert!(current.has_family_size());
+        assert!(current.has_fragment_len());
+        assert!(current.has_breakpoint_observations());
+        assert!(current.has_damage());
+        assert!(current.has_competing_alleles());
+
+        let old = ObservationFormat::new("14").unwrap();
+        assert!(old.has_alt_locus());
+        assert!(!old.has_third_allele_evidence());
+        assert!(!old.has_depth_observations());
+        assert!(!old.has_family_size());
+        assert!(!old.has_fragment_len());
+        assert!(!old.has_breakpoint_observations());
+        assert!(!old.has_damage());
+        assert!(!old.has_competing_alleles());
+
+        assert!(ObservationFormat::new("12").is_err());
+        assert!(ObservationFormat::new("foo").is_err());
+        assert!(ObservationFormat::new(&(current.version + 1).to_string()).is_err());
+    }
+
+    #[test]
+    fn test_upgrade_observations() {
+        let tmp = tempfile::tempdir().unwrap();
+        let old = tmp.path().join("old.bcf");
+        let upgraded = tmp.path().join("upgraded.bcf");
+
+        let mut header = bcf::Header::new();
+        header.push_record(b"##contig=<ID=chr1,length=1000>");
+        header.push_record(format!("##{}=14", VERSION_HEADER_KEY).as_bytes());
+        add_observation_header_entries(&mut header);
+        {
+            let mut writer = bcf::Writer::from_path(&old, &header, true, bcf::Format::Bcf).unwrap();
+            let mut record = writer.empty_record();
+            record.set_rid(Some(0));
+            record.set_pos(10);
+            record.set_alleles(&[b"A", b"T"]).unwrap();
+            let pileup = Pileup::new(
+                vec![observation(
+                    LogProb::ln_one(),
+                    LogProb::ln_one(),
+                    LogProb::ln_zero(),
+                )],
+                Vec::new(),
+            );
+            write_observations(&pileup, &mut record).unwrap();
+            // format 14 did not record third allele evidence
+            record.clear_info_integer(b"THIRD_ALLELE_EVIDENCE").unwrap();
+            writer.write(&record).unwrap();
+        }
+
+        upgrade_observations(&old, Some(&upgraded)).unwrap();
+
+        let mut reader = bcf::Reader::from_path(&upgraded).unwrap();
+        assert!(ObservationFormat::from_header(reader.header())
+            .unwrap()
+            .is_current());
+        let mut records = reader.records();
+        let mut record = records.next().unwrap().unwrap();
+        assert!(record
+            .info(b"THIRD_ALLELE_EVIDENCE")
+            .integer()
+            .unwrap()
+            .is_some());
+        let observations = read_observations(&mut record, ObservationFormat::current()).unwrap();
+        let read_observations = observations.pileup.read_observations();
+        assert_eq!(read_observations.len(), 1);
+        assert_eq!(read_observations[0].third_allele_evidence, None);
+        assert!(records.next().is_none());
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2024 Johannes Köster.
+// Licensed under the GNU GPLv3 license (https://opensource.org/licenses/GPL-3.0)
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use itertools::Itertools;
+
+use crate::utils;
+use crate::utils::regions::{RecordReader, RecordSelection};
+use crate::variants::model::HaplotypeIdentifier;
+
+/// Default number of records after which a shard is cut within a contig.
+pub(crate) const MAX_SHARD_RECORDS: usize = 10000;
+
+/// A contiguous range of candidate records that can be preprocessed independently
+/// of all other shards.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[getset(get = "pub(crate)")]
+pub(crate) struct Shard {
+    records: Range<usize>,
+    /// Genomic intervals (contig ID and 0-based half-open positions) containing the start
+    /// positions of the records of the shard, in the order of the records. They allow
+    /// fetching the records of the shard via the index of the candidate BCF.
+    intervals: Vec<(u32, Range<u64>)>,
+}
+
+impl Shard {
+    /// Split the records of the given (indexed) candidate BCF into shards.
+    ///
+    /// Shards are cut at contig boundaries and, if `max_records` is given, within contigs
+    /// whenever a shard has reached the given number of records. Cuts within contigs are only
+    /// made between different positions, such that all records of a locus end up in the same
+    /// shard. A cut is not made if a haplotype (e.g. a breakend event spanning multiple
+    /// contigs) has records on both sides of it. Record indices only count the records of
+    /// the given selection, if any.
+    pub(crate) fn plan<P: AsRef<Path>>(
+        inbcf: P,
+        record_selection: Option<&Arc<RecordSelection>>,
+        max_records: Option<usize>,
+    ) -> Result<Vec<Shard>> {
+        let mut bcf_reader = RecordReader::from_path(inbcf, record_selection)?;
+        let is_haplotype_bcf = utils::is_haplotype_bcf(bcf_reader.header());
+
+        let mut cuts = Vec::new();
+        let mut haplotype_spans: HashMap<HaplotypeIdentifier, Range<usize>> = HashMap::new();
+        // Contig IDs in the order of the records, with the index of their first record.
+        let mut contigs = Vec::new();
+        // Positions of the first and last record and of the records around each cut.
+        let mut positions = HashMap::new();
+        let mut last_locus: Option<(u32, u64)> = None;
+        let mut last_cut = 0;
+        let mut n_records = 0;
+
+        loop {
+            let mut record = bcf_reader.empty_record();
+            match bcf_reader.read(&mut record) {
+                None => break,
+                Some(res) => res?,
+            }
+
+            let locus = (record.rid().unwrap(), record.pos() as u64);
+            let is_cut = match last_locus {
+                None => {
+                    contigs.push((n_records, locus.0));
+                    positions.insert(n_records, locus.1);
+                    false
+                }
+                Some((last_rid, _)) if last_rid != locus.0 => {
+                    contigs.push((n_records, locus.0));
+                    true
+                }
+                Some((_, last_pos)) => {
+                    last_pos != locus.1
+                        && max_records.is_some_and(|max| n_records - last_cut >= max)
+                }
+            };
+            if is_cut {
+                cuts.push(n_records);
+                last_cut = n_records;
+                positions.insert(n_records - 1, last_locus.unwrap().1);
+                positions.insert(n_records, locus.1);
+            }
+            last_locus = Some(locus);
+
+            if is_haplotype_bcf {
+                if let Some(identifier) = HaplotypeIdentifier::from(&mut record)? {
+                    haplotype_spans
+                        .entry(identifier)
+                        .or_insert(n_records..n_records)
+                        .end = n_records;
+                }
+            }
+
+            n_records += 1;
+        }
+        if let Some((_, last_pos)) = last_locus {
+            positions.insert(n_records - 1, last_pos);
+        }
+
+        Ok(
+            Self::from_cuts(cuts, haplotype_spans.into_values().collect(), n_records)
+                .into_iter()
+                .map(|records| Shard {
+                    intervals: Self::record_intervals(&records, &contigs, &positions, n_records),
+                    records,
+                })
+                .collect(),
+        )
+    }
+
+    /// Build record ranges from the given candidate cuts, skipping all cuts that would split
+    /// one of the given haplotype spans (first and last record index).
+    fn from_cuts(
+        cuts: Vec<usize>,
+        haplotype_spans: Vec<Range<usize>>,
+        n_records: usize,
+    ) -> Vec<Range<usize>> {
+        let haplotype_spans = haplotype_spans
+            .into_iter()
+            .sorted_unstable_by_key(|span| span.start)
+            .collect_vec();
+
+        let mut shards = Vec::new();
+        let mut start = 0;
+        let mut span_idx = 0;
+        // The largest last record index of all haplotypes starting before the current cut.
+        let mut max_open_end = None;
+        for cut in cuts {
+            while span_idx < haplotype_spans.len() && haplotype_spans[span_idx].start < cut {
+                max_open_end = max_open_end.max(Some(haplotype_spans[span_idx].end));
+                span_idx += 1;
+            }
+            if max_open_end.is_none_or(|end| end < cut) {
+                shards.push(start..cut);
+                start = cut;
+            }
+        }
+        if start < n_records || shards.is_empty() {
+            shards.push(start..n_records);
+        }
+
+        shards
+    }
+
+    /// Genomic intervals of the given record range, given the contigs in the order of the
+    /// records (with the index of their first record) and the positions of the records
+    /// at the boundaries of the range.
+    fn record_intervals(
+        records: &Range<usize>,
+        contigs: &[(usize, u32)],
+        positions: &HashMap<usize, u64>,
+        n_records: usize,
+    ) -> Vec<(u32, Range<u64>)> {
+        if records.is_empty() {
+            return Vec::new();
+        }
+        let last = records.end - 1;
+        contigs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &(first, rid))| {
+                let contig_records = first..contigs.get(i + 1).map_or(n_records, |c| c.0);
+                if contig_records.end <= records.start || contig_records.start > last {
+                    return None;
+                }
+                let start = if contig_records.contains(&records.start) {
+                    positions[&records.start]
+                } else {
+                    0
+                };
+                let end = if contig_records.contains(&last) {
+                    positions[&last] + 1
+                } else {
+                    u64::MAX
+                };
+                Some((rid, start..end))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shards_from_cuts() {
+        let shards = Shard::from_cuts(vec![3, 5, 9], vec![0..1, 4..6], 12);
+        assert_eq!(shards, vec![0..3, 3..9, 9..12]);
+    }
+
+    #[test]
+    fn test_shards_without_cuts() {
+        let shards = Shard::from_cuts(vec![], vec![], 0);
+        assert_eq!(shards, vec![0..0]);
+    }
+
+    #[test]
+    fn test_shard_intervals() {
+        // records 0..4 on contig 2, 4..6 on contig 0 and 6..9 on contig 1
+        let contigs = [(0, 2), (4, 0), (6, 1)];
+        let positions: HashMap<usize, u64> =
+            vec![(0, 10), (2, 30), (3, 40), (5, 7), (6, 3), (8, 90)]
+                .into_iter()
+                .collect();
+        assert_eq!(
+            Shard::record_intervals(&(0..3), &contigs, &positions, 9),
+            vec![(2, 10..31)]
+        );
+        assert_eq!(
+            Shard::record_intervals(&(3..9), &contigs, &positions, 9),
+            vec![(2, 40..u64::MAX), (0, 0..u64::MAX), (1, 0..91)]
+        );
+        assert_eq!(
+            Shard::record_intervals(&(0..6), &contigs, &positions, 9),
+            vec![(2, 10..u64::MAX), (0, 0..8)]
+        );
+        assert!(Shard::record_intervals(&(0..0), &contigs, &positions, 9).is_empty());
+    }
+}
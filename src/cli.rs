@@ -20,19 +20,24 @@ use crate::calling;
 use crate::calling::variants::calling::{
     call_generic, CallWriter, DefaultCandidateFilter, SampleInfos,
 };
+use crate::calling::variants::inspect::InspectionFormat;
+use crate::calling::variants::phasing::Phaser;
 use crate::calling::variants::preprocessing::haplotype_feature_index::HaplotypeFeatureIndex;
+use crate::calling::variants::preprocessing::local_haplotypes::construct_local_haplotypes;
 use crate::candidates;
 use crate::conversion;
 use crate::errors;
 use crate::estimation;
-use crate::estimation::alignment_properties::AlignmentProperties;
+use crate::estimation::alignment_properties::{AlignmentProperties, ReadType};
 //use crate::estimation::sample_variants;
 //use crate::estimation::tumor_mutational_burden;
 use crate::filtration;
 use crate::grammar;
 use crate::reference;
 use crate::testcase;
+use crate::utils::regions::{RecordSelection, Regions};
 use crate::utils::PathMap;
+use crate::variants::evidence::observations::alt_hits::AltHitFormat;
 use crate::variants::evidence::realignment;
 
 use crate::variants::model::prior::CheckablePrior;
@@ -125,11 +130,87 @@ pub enum Varlociraptor {
         #[structopt(name = "output", parse(from_os_str), help = "Output BCF File")]
         output: Option<PathBuf>,
     },
+    #[structopt(
+        name = "inspect",
+        about = "Inspect internal data of Varlociraptor in a human readable way.",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    Inspect {
+        #[structopt(subcommand)]
+        kind: InspectKind,
+    },
+    #[structopt(
+        name = "upgrade-observations",
+        about = "Rewrite a BCF with observations obtained by an older version of \
+        'varlociraptor preprocess variants' into the current observation format. \
+        Observations that older formats do not record are filled with neutral values. \
+        Note that 'varlociraptor call' can also read older formats directly.",
+        usage = "varlociraptor upgrade-observations old.bcf > observations.bcf",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    UpgradeObservations {
+        #[structopt(
+            parse(from_os_str),
+            required = true,
+            help = "BCF with observations of an older format."
+        )]
+        input: PathBuf,
+        #[structopt(
+            parse(from_os_str),
+            long,
+            help = "BCF file that shall contain the results (if omitted, write to STDOUT)."
+        )]
+        output: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "concat-observations",
+        about = "Concatenate observation BCFs obtained by preprocessing disjoint regions \
+        (see 'varlociraptor preprocess variants --regions') of the same candidate variants. \
+        In contrast to e.g. 'bcftools concat', this checks that all files stem from the same \
+        preprocessing setup and restores the record order expected by 'varlociraptor call'.",
+        usage = "varlociraptor concat-observations region1.bcf region2.bcf ... > observations.bcf",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    ConcatObservations {
+        #[structopt(
+            parse(from_os_str),
+            required = true,
+            help = "Observation BCFs, in the order of the regions they have been obtained from."
+        )]
+        inputs: Vec<PathBuf>,
+        #[structopt(
+            parse(from_os_str),
+            long,
+            help = "BCF file that shall contain the results (if omitted, write to STDOUT)."
+        )]
+        output: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "concat-calls",
+        about = "Concatenate call BCFs obtained by calling disjoint regions \
+        (see 'varlociraptor call variants --regions') of the same observations.",
+        usage = "varlociraptor concat-calls region1.bcf region2.bcf ... > calls.bcf",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    ConcatCalls {
+        #[structopt(
+            parse(from_os_str),
+            required = true,
+            help = "Call BCFs, in the order of the regions they have been obtained from."
+        )]
+        inputs: Vec<PathBuf>,
+        #[structopt(
+            parse(from_os_str),
+            long,
+            help = "BCF file that shall contain the results (if omitted, write to STDOUT)."
+        )]
+        output: Option<PathBuf>,
+    },
 }
 
 pub struct PreprocessInput {
     reference: PathBuf,
-    bam: PathBuf,
+    bam: Vec<PathBuf>,
 }
 
 impl Varlociraptor {
@@ -169,6 +250,51 @@ fn default_min_bam_refetch_distance() -> u64 {
     1
 }
 
+fn default_threads() -> usize {
+    1
+}
+
+/// Deserialize paths given as a single path, or as a list of paths. Older versions of
+/// varlociraptor stored only a single BAM file and alignment properties file per sample in the
+/// preprocessing options.
+fn deserialize_paths<'de, D>(d: D) -> std::result::Result<Vec<PathBuf>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Paths {
+        Single(Option<PathBuf>),
+        Multiple(Vec<PathBuf>),
+    }
+
+    Ok(match <Paths as serde::Deserialize>::deserialize(d)? {
+        Paths::Single(path) => path.into_iter().collect(),
+        Paths::Multiple(paths) => paths,
+    })
+}
+
+fn default_phasing_max_prob_absent() -> f64 {
+    0.05
+}
+
+fn default_germline_het_event() -> String {
+    "germline_het".to_owned()
+}
+
+fn default_min_bayes_factor() -> f64 {
+    1.01
+}
+
+fn default_max_dist() -> u64 {
+    1000
+}
+
+fn default_inspection_format() -> String {
+    "tsv".to_owned()
+}
+
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, StructOpt, Serialize, Deserialize, Clone)]
 pub enum PreprocessKind {
     #[structopt(
@@ -198,15 +324,28 @@ pub enum PreprocessKind {
         #[structopt(
             long,
             required = true,
-            help = "BAM file with aligned reads from a single sample."
+            help = "BAM files with aligned reads from a single sample. Multiple files (e.g. from \
+                    different sequencing runs) are combined as if they were merged into one, \
+                    while keeping separate alignment properties per file. Note that the gap \
+                    and homopolymer parameters of the realignment are taken from the \
+                    alignment properties of the first file."
         )]
-        bam: PathBuf,
+        #[serde(deserialize_with = "deserialize_paths")]
+        bam: Vec<PathBuf>,
         #[structopt(
             long,
             help = "Report fragment IDs in output BCF. This information can be used for phasing."
         )]
         #[serde(default)]
         report_fragment_ids: bool,
+        #[structopt(
+            long,
+            help = "Report fragment lengths (e.g. insert sizes of read pairs) in output BCF. \
+                    This information is needed to learn fragment length distributions of \
+                    cfDNA samples, see 'varlociraptor estimate fragment-lengths --help'."
+        )]
+        #[serde(default)]
+        report_fragment_lengths: bool,
         #[structopt(
             long,
             help = "Assume that candidate variants are given in atomic form (unlike e.g. \
@@ -227,6 +366,53 @@ pub enum PreprocessKind {
         )]
         #[serde(default)]
         omit_mapq_adjustment: bool,
+        #[structopt(
+            long = "umi-families",
+            help = "Combine reads that share a UMI family (given by the MI tag, or, if missing, \
+                    by the RX tag together with the fragment start) into a single observation. \
+                    Duplicate marked reads are then considered as members of their family instead \
+                    of being ignored. Use this for UMI-tagged libraries, e.g. for low-VAF ctDNA \
+                    analyses."
+        )]
+        #[serde(default)]
+        umi_families: bool,
+        #[structopt(
+            long = "alt-hit-format",
+            possible_values = &AltHitFormat::iter().map(|v| v.into()).collect_vec(),
+            help = "Format of the alternative hits reported by the read mapper (bwa: XA tag, \
+                    minimap2: tp, cm, s1 and s2 tags, bowtie2: AS and XS tags, dragen: XA tag). \
+                    Alternative hits are used to detect variants that stem from a distant \
+                    homologous locus. If omitted, the format is inferred from the @PG lines of \
+                    the BAM header, falling back to bwa."
+        )]
+        #[serde(default)]
+        alt_hit_format: Option<AltHitFormat>,
+        #[structopt(
+            parse(from_os_str),
+            long = "mobile-element-library",
+            help = "FASTA file with consensus sequences of mobile elements (e.g. ALU, L1, SVA). \
+                    Candidate mobile element insertions (e.g. <INS:ME:ALU>) are evaluated by \
+                    realigning reads against the consensus sequence of the element with the \
+                    same name, and by considering reads with a discordant mate that points \
+                    towards the insertion site. The inserted length is taken from SVLEN or \
+                    MEINFO, such that 5' truncated insertions are supported, and the \
+                    orientation from the MEINFO polarity (if missing, both orientations are \
+                    considered)."
+        )]
+        #[serde(default)]
+        mobile_element_library: Option<PathBuf>,
+        #[structopt(
+            parse(from_os_str),
+            long = "germline-haplotypes",
+            help = "VCF/BCF file with phased germline variants of the sample (the first sample \
+                    in the file is used). Reads are realigned against both germline haplotypes \
+                    (obtained by applying the phased SNVs and MNVs to the reference), and allele \
+                    support is marginalized over them. This avoids penalizing reads that carry \
+                    nearby heterozygous germline variants, e.g. in highly polymorphic regions \
+                    like HLA."
+        )]
+        #[serde(default)]
+        germline_haplotypes: Option<PathBuf>,
         #[structopt(
             long = "reference-buffer-size",
             short = "b",
@@ -250,11 +436,13 @@ pub enum PreprocessKind {
         min_bam_refetch_distance: u64,
         #[structopt(
             long = "alignment-properties",
-            help = "Alignment properties JSON file for sample. If not provided, properties \
-                    will be estimated from the given BAM file. It is recommended to estimate alignment \
-                    properties separately, see 'varlociraptor estimate alignment-properties --help'."
+            help = "Alignment properties JSON files for sample, one for each BAM file, in the same order. \
+                    If not provided, properties will be estimated from the given BAM files. It is \
+                    recommended to estimate alignment properties separately, see \
+                    'varlociraptor estimate alignment-properties --help'."
         )]
-        alignment_properties: Option<PathBuf>,
+        #[serde(default, deserialize_with = "deserialize_paths")]
+        alignment_properties: Vec<PathBuf>,
         #[structopt(
             parse(from_os_str),
             long,
@@ -279,8 +467,9 @@ pub enum PreprocessKind {
         #[structopt(
             long = "max-depth",
             default_value = "200",
-            help = "Maximum number of observations to use for calling. If locus is exceeding this \
-                    number, downsampling is performed."
+            help = "Maximum number of observations to use for calling, per sample (i.e., over \
+                    all its BAM files). If locus is exceeding this number, downsampling is \
+                    performed."
         )]
         max_depth: usize,
         #[structopt(
@@ -290,6 +479,28 @@ pub enum PreprocessKind {
         )]
         #[serde(default)]
         omit_insert_size: bool,
+        #[structopt(
+            long = "rna",
+            help = "Process reads from RNA sequencing. Spliced reads are realigned across splice junctions \
+                    (N CIGAR operations) by concatenating their exons, insert sizes are not considered since \
+                    they are meaningless for spliced fragments, and softclips starting at splice junctions \
+                    close to the variant are not considered, since they typically stem from unaligned \
+                    exon overhangs."
+        )]
+        #[serde(default)]
+        rna: bool,
+        #[structopt(
+            long = "read-type",
+            possible_values = &ReadType::iter().map(|v| v.into()).collect_vec(),
+            help = "Type of the sequencing reads (short, ont or hifi). For long reads (ont, hifi), \
+                    technology specific error rates are assumed if they cannot be estimated, \
+                    realignment is anchored at the variant, and the read position and softclip \
+                    biases are disabled, since they are meaningless for single long reads. \
+                    If omitted, the read type recorded in the given alignment properties is \
+                    used, and short reads are assumed if alignment properties are estimated."
+        )]
+        #[serde(default)]
+        read_type: Option<ReadType>,
         #[structopt(
             long = "pairhmm-mode",
             possible_values = &["fast", "exact", "homopolymer"],
@@ -325,6 +536,145 @@ pub enum PreprocessKind {
         )]
         #[serde(default)]
         output_raw_observations: Option<PathBuf>,
+        #[structopt(
+            long = "threads",
+            default_value = "1",
+            help = "Number of threads to use for preprocessing. Candidate variants are split into shards \
+            at contig boundaries and within contigs which are processed in parallel. Contigs are not split \
+            if --report-fragment-ids is given. Requires the candidate BCF to be indexed (e.g. via \
+            bcftools index). The output is identical to a single-threaded run."
+        )]
+        #[serde(skip, default = "default_threads")]
+        threads: usize,
+        #[structopt(
+            long = "regions",
+            help = "Only preprocess candidate variants in the given regions (CHROM, CHROM:START or \
+            CHROM:START-END, 1-based and inclusive). Breakend events and haplotype blocks are \
+            preprocessed entirely by the run whose regions contain their first record. Hence, \
+            scattering over disjoint regions processes every candidate exactly once."
+        )]
+        #[serde(skip)]
+        regions: Vec<String>,
+        #[structopt(
+            parse(from_os_str),
+            long = "regions-bed",
+            help = "Only preprocess candidate variants in the regions of the given BED file \
+            (see --regions)."
+        )]
+        #[serde(skip)]
+        regions_bed: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "local-haplotypes",
+        about = "Construct local haplotypes from candidate variants (SNVs, MNVs, small indels) \
+                 that are at most one read length apart and whose ALT alleles co-occur in the same \
+                 fragments. Candidates of the same local haplotype are evaluated jointly, and a merged \
+                 MNV or complex allele of each local haplotype is added as additional candidate. \
+                 Candidates that already carry an EVENT or MATEID tag are left unchanged. \
+                 Run this once with the BAM files of all samples, and use the resulting (indexed) BCF \
+                 as candidates when preprocessing each sample, such that all samples share the same \
+                 candidate records.",
+        usage = "varlociraptor preprocess local-haplotypes reference.fasta --candidates candidates.bcf \
+                 --bam tumor.bam normal.bam --output candidates.local-haplotypes.bcf",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    LocalHaplotypes {
+        #[structopt(
+            parse(from_os_str),
+            help = "FASTA file with reference genome. Has to be indexed with samtools faidx."
+        )]
+        reference: PathBuf,
+        #[structopt(
+            parse(from_os_str),
+            long,
+            required = true,
+            help = "Sorted VCF/BCF file with candidate variants."
+        )]
+        candidates: PathBuf,
+        #[structopt(
+            long,
+            required = true,
+            help = "BAM files with aligned reads of all samples that shall be preprocessed with \
+                    the resulting candidates."
+        )]
+        #[serde(deserialize_with = "deserialize_paths")]
+        bam: Vec<PathBuf>,
+        #[structopt(
+            long,
+            help = "Alignment properties JSON files, one for each BAM file, in the same order. \
+                    They are used to determine the maximum read length, which is the maximum \
+                    distance between candidates of a local haplotype. If not provided, properties \
+                    will be estimated from the given BAM files."
+        )]
+        #[serde(default, deserialize_with = "deserialize_paths")]
+        alignment_properties: Vec<PathBuf>,
+        #[structopt(
+            parse(from_os_str),
+            long,
+            required = true,
+            help = "BCF file that shall contain the candidates with local haplotypes. \
+                    It is indexed afterwards."
+        )]
+        output: PathBuf,
+        #[structopt(
+            long = "reference-buffer-size",
+            short = "b",
+            default_value = "10",
+            help = "Number of reference sequences to keep in buffer."
+        )]
+        #[serde(default = "default_reference_buffer_size")]
+        reference_buffer_size: usize,
+    },
+}
+
+#[derive(Debug, StructOpt, Serialize, Deserialize, Clone)]
+pub enum InspectKind {
+    #[structopt(
+        name = "observations",
+        about = "Print the observations of a BCF obtained with 'varlociraptor preprocess variants', \
+                 with one line per read (or read pair) and record. Probabilities are given in linear space. \
+                 Only read observations are shown, not the depth and breakpoint observations or the damage \
+                 information stored along with them.",
+        usage = "varlociraptor inspect observations observations.bcf --regions chr1:1000-2000 > observations.tsv",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    Observations {
+        #[structopt(
+            parse(from_os_str),
+            required = true,
+            help = "BCF with observations obtained with 'varlociraptor preprocess variants'."
+        )]
+        observations: PathBuf,
+        #[structopt(
+            long = "regions",
+            alias = "region",
+            help = "Only inspect records in the given regions (CHROM, CHROM:START or CHROM:START-END, \
+            1-based and inclusive)."
+        )]
+        #[serde(default)]
+        regions: Vec<String>,
+        #[structopt(
+            parse(from_os_str),
+            long = "regions-bed",
+            help = "Only inspect records in the regions of the given BED file (see --regions)."
+        )]
+        #[serde(default)]
+        regions_bed: Option<PathBuf>,
+        #[structopt(
+            long = "format",
+            possible_values = &["tsv", "json"],
+            default_value = "tsv",
+            help = "Output format: tab separated values with a header line, or one JSON object per line."
+        )]
+        #[serde(default = "default_inspection_format")]
+        format: String,
+        #[structopt(
+            parse(from_os_str),
+            long,
+            short,
+            help = "File that shall contain the results (if omitted, write to STDOUT)."
+        )]
+        output: Option<PathBuf>,
     },
 }
 
@@ -398,6 +748,15 @@ pub enum EstimateKind {
 
         #[structopt(long, help = "Number of records to sample from the BAM file")]
         num_records: Option<usize>,
+
+        #[structopt(
+            long = "read-type",
+            default_value = "short",
+            possible_values = &ReadType::iter().map(|v| v.into()).collect_vec(),
+            help = "Type of the sequencing reads (short, ont or hifi)."
+        )]
+        #[serde(default)]
+        read_type: ReadType,
     },
     #[structopt(
         name = "contamination",
@@ -442,6 +801,47 @@ pub enum EstimateKind {
         )]
         output_max_vaf_variants: Option<PathBuf>,
     },
+    #[structopt(
+        name = "fragment-lengths",
+        about = "Estimate the fragment length distributions of tumor derived and healthy \
+                 fragments (e.g. for cfDNA, where tumor derived fragments are shorter). The former \
+                 is learned from the variant supporting fragments of high confidence somatic \
+                 calls, the latter from reference supporting fragments. The resulting model is \
+                 printed in JSON format to STDOUT and can be passed to \
+                 'varlociraptor call variants --fragment-length-models'.",
+        usage = "varlociraptor estimate fragment-lengths --observations sample.observations.bcf \
+                 --calls calls.bcf --events SOMATIC_TUMOR > sample.fragment-lengths.json",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    FragmentLengths {
+        #[structopt(
+            parse(from_os_str),
+            long,
+            help = "Observations of the sample, preprocessed with --report-fragment-lengths."
+        )]
+        observations: PathBuf,
+        #[structopt(
+            parse(from_os_str),
+            long,
+            help = "Variant calls obtained from the given observations."
+        )]
+        calls: PathBuf,
+        #[structopt(
+            long = "events",
+            required = true,
+            help = "Somatic events to consider as tumor variant calls (e.g. SOMATIC_TUMOR). \
+                    Do not include germline events, since their variant supporting fragments \
+                    stem from healthy cells."
+        )]
+        events: Vec<String>,
+        #[structopt(
+            long = "min-prob",
+            default_value = "0.95",
+            help = "Minimum posterior probability of the given events for a call to be \
+                    considered as high confidence."
+        )]
+        min_prob: f64,
+    },
     #[structopt(
         name = "mutational-burden",
         about = "Estimate mutational burden. Takes Varlociraptor calls (must be annotated \
@@ -541,6 +941,46 @@ pub enum CallKind {
         )]
         #[serde(default)]
         omit_alt_locus_bias: bool,
+        #[structopt(
+            long = "omit-damage-bias",
+            help = "Do not consider chemical damage (FFPE cytosine deamination and 8-oxoG guanine \
+                   oxidation) when calculating the probability of an artifact. Damage is only \
+                   considered for samples that show a damage signature, i.e. for which the \
+                   damage type substitutions are enriched in one read orientation (estimated \
+                   along with the alignment properties)."
+        )]
+        #[serde(default)]
+        omit_damage_bias: bool,
+        #[structopt(
+            long = "fragment-length-models",
+            help = "Fragment length models of samples, given as SAMPLE=PATH (e.g. for cfDNA), \
+                    see 'varlociraptor estimate fragment-lengths --help'. The lengths of the ALT \
+                    allele fragments are then used to assess whether they stem from tumor cells \
+                    or from healthy cells (germline variant or clonal hematopoiesis), reported \
+                    per sample in the FORMAT field TO. Requires observations preprocessed with \
+                    --report-fragment-lengths."
+        )]
+        #[serde(default)]
+        fragment_length_models: Vec<String>,
+        #[structopt(
+            long = "phase",
+            help = "Phase calls via the fragments they share (read-backed phasing). Calls are \
+                    linked if fragments support both ALT alleles (cis) or only one of them \
+                    (trans). The phase set and the haplotype carrying the ALT allele are reported \
+                    per sample in the FORMAT fields PS and PH. Requires observations preprocessed \
+                    with --report-fragment-ids."
+        )]
+        #[serde(default)]
+        phase: bool,
+        #[structopt(
+            long = "phasing-max-prob-absent",
+            default_value = "0.05",
+            help = "Only phase calls whose posterior probability of the variant being absent or \
+                    an artifact (PROB_ABSENT and PROB_ARTIFACT) is at most the given value \
+                    (when using --phase)."
+        )]
+        #[serde(default = "default_phasing_max_prob_absent")]
+        phasing_max_prob_absent: f64,
         #[structopt(
             long = "full-prior",
             help = "Compute the full prior distribution for any allele frequency combination. \
@@ -589,47 +1029,97 @@ pub enum CallKind {
         )]
         #[serde(default = "default_log_mode")]
         log_mode: String,
+        #[structopt(
+            long = "threads",
+            default_value = "1",
+            help = "Number of threads to use for evaluating the model. Records are still read and written \
+            by the main thread, and calls are written in the order of the input records."
+        )]
+        #[serde(default = "default_threads")]
+        threads: usize,
+        #[structopt(
+            long = "regions",
+            help = "Only call variants in the given regions (CHROM, CHROM:START or CHROM:START-END, \
+            1-based and inclusive). Breakend events and haplotype blocks are called entirely by the \
+            run whose regions contain their first record (see 'varlociraptor preprocess variants --help')."
+        )]
+        #[serde(default)]
+        regions: Vec<String>,
+        #[structopt(
+            parse(from_os_str),
+            long = "regions-bed",
+            help = "Only call variants in the regions of the given BED file (see --regions)."
+        )]
+        #[serde(default)]
+        regions_bed: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "cnvs",
+        about = "Call CNVs in tumor-normal sample pairs, based on germline heterozygous variants \
+                 called with 'varlociraptor call variants generic'. This is experimental.",
+        usage = "varlociraptor call cnvs --scenario scenario.yaml --calls calls.bcf > cnvs.bcf",
+        setting = structopt::clap::AppSettings::ColoredHelp,
+    )]
+    CNVs {
+        #[structopt(
+            parse(from_os_str),
+            long,
+            required = true,
+            help = "Scenario that has been used for calling variants. The tumor sample is \
+                    the sample that is contaminated by another (the normal) sample, and \
+                    the tumor purity is derived from the contamination fraction."
+        )]
+        scenario: PathBuf,
+        #[structopt(
+            parse(from_os_str),
+            long,
+            help = "VCF/BCF file (generated by varlociraptor call variants generic) to process \
+                    (if omitted, read from STDIN)."
+        )]
+        calls: Option<PathBuf>,
+        #[structopt(
+            parse(from_os_str),
+            long,
+            short,
+            help = "BCF file that shall contain the results (if omitted, write to STDOUT)."
+        )]
+        output: Option<PathBuf>,
+        #[structopt(
+            long = "germline-het-event",
+            default_value = "germline_het",
+            help = "Event of the scenario that denotes heterozygous germline variants. \
+                    Its posterior probability (INFO field PROB_<EVENT>) is used to select \
+                    the loci that are informative about copy number changes."
+        )]
+        #[serde(default = "default_germline_het_event")]
+        germline_het_event: String,
+        #[structopt(
+            long = "min-bayes-factor",
+            default_value = "1.01",
+            help = "Minimum bayes factor (> 1.0) between likelihoods of CNV and no CNV to consider. \
+                    The higher this value, the fewer candidate CNVs will be investigated. \
+                    Note that this can be usually left unchanged, because every CNV is provided \
+                    with a posterior probability that can be used for filtering, e.g., via \
+                    'varlociraptor filter-calls control-fdr'."
+        )]
+        #[serde(default = "default_min_bayes_factor")]
+        min_bayes_factor: f64,
+        #[structopt(
+            long,
+            default_value = "1000",
+            help = "Maximum distance between supporting loci in a CNV."
+        )]
+        #[serde(default = "default_max_dist")]
+        max_dist: u64,
+        #[structopt(
+            long,
+            short = "t",
+            default_value = "1",
+            help = "Number of threads to use."
+        )]
+        #[serde(default = "default_threads")]
+        threads: usize,
     },
-    // #[structopt(
-    //     name = "cnvs",
-    //     about = "Call CNVs in tumor-normal sample pairs. This is experimental (do not use it yet).",
-    //     setting = structopt::clap::AppSettings::ColoredHelp,
-    // )]
-    // CNVs {
-    //     #[structopt(
-    //         parse(from_os_str),
-    //         long,
-    //         help = "VCF/BCF file (generated by varlociraptor call-tumor-normal) to process \
-    //                 (if omitted, read from STDIN)."
-    //     )]
-    //     calls: Option<PathBuf>,
-    //     #[structopt(
-    //         parse(from_os_str),
-    //         long,
-    //         help = "BCF file that shall contain the results (if omitted, write to STDOUT)."
-    //     )]
-    //     output: Option<PathBuf>,
-    //     #[structopt(long, short = "p", help = "Tumor purity.")]
-    //     purity: f64,
-    //     #[structopt(
-    //         long = "min-bayes-factor",
-    //         default_value = "1.01",
-    //         help = "Minimum bayes factor (> 1.0) between likelihoods of CNV and no CNV to consider. \
-    //                 The higher this value, the fewer candidate CNVs will be investigated. \
-    //                 Note that this can be usually left unchanged, because every CNV is provided \
-    //                 with a posterior probability that can be used for filtering, e.g., via \
-    //                 'varlociraptor filter-calls control-fdr'."
-    //     )]
-    //     min_bayes_factor: f64,
-    //     #[structopt(
-    //         long,
-    //         default_value = "1000",
-    //         help = "Maximum distance between supporting loci in a CNV."
-    //     )]
-    //     max_dist: u32,
-    //     #[structopt(long, short = "t", help = "Number of threads to use.")]
-    //     threads: usize,
-    // },
 }
 
 #[derive(Debug, StructOpt, Serialize, Deserialize, Clone)]
@@ -798,19 +1288,29 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                     candidates,
                     bam,
                     report_fragment_ids,
+                    report_fragment_lengths,
                     atomic_candidate_variants,
                     omit_mapq_adjustment,
+                    umi_families,
+                    alt_hit_format,
+                    mobile_element_library,
+                    germline_haplotypes,
                     alignment_properties,
                     output,
                     propagate_info_fields,
                     realignment_window,
                     max_depth,
                     omit_insert_size,
+                    rna,
+                    read_type,
                     pairhmm_mode,
                     reference_buffer_size,
                     min_bam_refetch_distance,
                     log_mode,
                     output_raw_observations,
+                    threads,
+                    regions,
+                    regions_bed,
                 } => {
                     // TODO: handle testcases
                     if realignment_window > (128 / 2) {
@@ -827,15 +1327,48 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                             .context("Unable to read genome reference.")?,
                     );
 
-                    let alignment_properties = est_or_load_alignment_properties(
-                        &alignment_properties,
-                        &bam,
-                        omit_insert_size,
-                        Arc::get_mut(&mut reference_buffer).unwrap(),
-                        Some(crate::estimation::alignment_properties::NUM_FRAGMENTS),
-                    )?;
+                    if !alignment_properties.is_empty() && alignment_properties.len() != bam.len() {
+                        return Err(errors::Error::InvalidAlignmentPropertiesCount {
+                            bams: bam.len(),
+                            alignment_properties: alignment_properties.len(),
+                        }
+                        .into());
+                    }
+                    let alignment_properties = bam
+                        .iter()
+                        .enumerate()
+                        .map(|(i, bam)| {
+                            let mut alignment_properties = est_or_load_alignment_properties(
+                                &alignment_properties.get(i),
+                                bam,
+                                omit_insert_size || rna,
+                                read_type,
+                                Arc::get_mut(&mut reference_buffer).unwrap(),
+                                Some(crate::estimation::alignment_properties::NUM_FRAGMENTS),
+                            )?;
+                            if rna {
+                                alignment_properties.set_spliced();
+                            }
+                            Ok(alignment_properties)
+                        })
+                        .collect::<Result<Vec<_>>>()?;
 
-                    let gap_params = alignment_properties.gap_params.clone();
+                    if pairhmm_mode != "homopolymer"
+                        && alignment_properties
+                            .iter()
+                            .any(|properties| properties.read_type == ReadType::Ont)
+                    {
+                        warn!(
+                            "Processing ONT reads with pairhmm mode {}. Consider using \
+                            --pairhmm-mode homopolymer, which models homopolymer errors \
+                            separately from other gaps.",
+                            pairhmm_mode
+                        );
+                    }
+
+                    // METHOD: realignment is performed with the gap and hop parameters of the
+                    // first BAM file.
+                    let gap_params = alignment_properties[0].gap_params.clone();
 
                     let log_each_record = log_mode == "each-record";
 
@@ -844,20 +1377,57 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                         .map(|s| s.as_bytes().to_owned())
                         .collect();
 
+                    let mobile_element_library = mobile_element_library
+                        .map(|path| {
+                            reference::MobileElementLibrary::from_path(&path)
+                                .context("Unable to read mobile element library.")
+                        })
+                        .transpose()?
+                        .map(Arc::new);
+
+                    let germline_haplotype_buffers = if let Some(path) = germline_haplotypes {
+                        let germline_haplotypes = Arc::new(
+                            reference::GermlineHaplotypes::from_path(&path)
+                                .context("Unable to read phased germline variants.")?,
+                        );
+                        (0..2)
+                            .map(|haplotype| {
+                                reference::Buffer::germline_haplotype(
+                                    &reference,
+                                    reference_buffer_size,
+                                    Arc::clone(&germline_haplotypes),
+                                    haplotype,
+                                )
+                                .map(Arc::new)
+                            })
+                            .collect::<Result<Vec<_>>>()?
+                    } else {
+                        Vec::new()
+                    };
+
+                    let record_selection = Regions::from_args(&regions, regions_bed.as_deref())?
+                        .map(|regions| RecordSelection::new(&candidates, regions))
+                        .transpose()?
+                        .map(Arc::new);
+
                     match pairhmm_mode.as_ref() {
                         "homopolymer" => {
-                            let hop_params = alignment_properties.hop_params.clone();
+                            let hop_params = alignment_properties[0].hop_params.clone();
                             let mut processor =
                                 calling::variants::preprocessing::ObservationProcessor::builder()
                                     .report_fragment_ids(report_fragment_ids)
+                                    .report_fragment_lengths(report_fragment_lengths)
                                     .adjust_prob_mapping(!omit_mapq_adjustment)
+                                    .umi_families(umi_families)
+                                    .alt_hit_format(alt_hit_format)
                                     .alignment_properties(alignment_properties)
                                     .max_depth(max_depth)
-                                    .inbam(bam)
+                                    .inbams(bam)
                                     .min_bam_refetch_distance(min_bam_refetch_distance)
                                     .reference_buffer(Arc::clone(&reference_buffer))
                                     .haplotype_feature_index(HaplotypeFeatureIndex::new(
                                         &candidates,
+                                        record_selection.as_ref(),
                                     )?)
                                     .inbcf(candidates)
                                     .aux_info_fields(propagate_info_fields)
@@ -867,11 +1437,15 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                                     .log_each_record(log_each_record)
                                     .realigner(realignment::HomopolyPairHMMRealigner::new(
                                         reference_buffer,
+                                        germline_haplotype_buffers,
                                         gap_params,
                                         hop_params,
                                         realignment_window,
                                     ))
                                     .atomic_candidate_variants(atomic_candidate_variants)
+                                    .threads(threads)
+                                    .record_selection(record_selection)
+                                    .mobile_element_library(mobile_element_library)
                                     .build();
                             processor.process()?;
                         }
@@ -879,14 +1453,18 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                             let mut processor =
                                 calling::variants::preprocessing::ObservationProcessor::builder()
                                     .report_fragment_ids(report_fragment_ids)
+                                    .report_fragment_lengths(report_fragment_lengths)
                                     .adjust_prob_mapping(!omit_mapq_adjustment)
+                                    .umi_families(umi_families)
+                                    .alt_hit_format(alt_hit_format)
                                     .alignment_properties(alignment_properties)
                                     .max_depth(max_depth)
-                                    .inbam(bam)
+                                    .inbams(bam)
                                     .min_bam_refetch_distance(min_bam_refetch_distance)
                                     .reference_buffer(Arc::clone(&reference_buffer))
                                     .haplotype_feature_index(HaplotypeFeatureIndex::new(
                                         &candidates,
+                                        record_selection.as_ref(),
                                     )?)
                                     .inbcf(candidates)
                                     .aux_info_fields(propagate_info_fields)
@@ -898,8 +1476,12 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                                         gap_params,
                                         realignment_window,
                                         reference_buffer,
+                                        germline_haplotype_buffers,
                                     ))
                                     .atomic_candidate_variants(atomic_candidate_variants)
+                                    .threads(threads)
+                                    .record_selection(record_selection)
+                                    .mobile_element_library(mobile_element_library)
                                     .build();
                             processor.process()?;
                         }
@@ -907,14 +1489,18 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                             let mut processor =
                                 calling::variants::preprocessing::ObservationProcessor::builder()
                                     .report_fragment_ids(report_fragment_ids)
+                                    .report_fragment_lengths(report_fragment_lengths)
                                     .adjust_prob_mapping(!omit_mapq_adjustment)
+                                    .umi_families(umi_families)
+                                    .alt_hit_format(alt_hit_format)
                                     .alignment_properties(alignment_properties)
                                     .max_depth(max_depth)
-                                    .inbam(bam)
+                                    .inbams(bam)
                                     .min_bam_refetch_distance(min_bam_refetch_distance)
                                     .reference_buffer(Arc::clone(&reference_buffer))
                                     .haplotype_feature_index(HaplotypeFeatureIndex::new(
                                         &candidates,
+                                        record_selection.as_ref(),
                                     )?)
                                     .inbcf(candidates)
                                     .aux_info_fields(propagate_info_fields)
@@ -924,16 +1510,61 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                                     .log_each_record(log_each_record)
                                     .realigner(realignment::PairHMMRealigner::new(
                                         reference_buffer,
+                                        germline_haplotype_buffers,
                                         gap_params,
                                         realignment_window,
                                     ))
                                     .atomic_candidate_variants(atomic_candidate_variants)
+                                    .threads(threads)
+                                    .record_selection(record_selection)
+                                    .mobile_element_library(mobile_element_library)
                                     .build();
                             processor.process()?;
                         }
                         _ => panic!("Unknown pairhmm mode '{}'", pairhmm_mode),
                     };
                 }
+                PreprocessKind::LocalHaplotypes {
+                    reference,
+                    candidates,
+                    bam,
+                    alignment_properties,
+                    output,
+                    reference_buffer_size,
+                } => {
+                    let mut reference_buffer =
+                        reference::Buffer::from_path(&reference, reference_buffer_size)
+                            .context("Unable to read genome reference.")?;
+
+                    if !alignment_properties.is_empty() && alignment_properties.len() != bam.len() {
+                        return Err(errors::Error::InvalidAlignmentPropertiesCount {
+                            bams: bam.len(),
+                            alignment_properties: alignment_properties.len(),
+                        }
+                        .into());
+                    }
+                    let mut max_read_len = 0;
+                    for (i, bam) in bam.iter().enumerate() {
+                        let properties = est_or_load_alignment_properties(
+                            &alignment_properties.get(i),
+                            bam,
+                            false,
+                            None,
+                            &mut reference_buffer,
+                            Some(crate::estimation::alignment_properties::NUM_FRAGMENTS),
+                        )?;
+                        max_read_len = max_read_len.max(properties.max_read_len as u64);
+                    }
+
+                    construct_local_haplotypes(
+                        &candidates,
+                        &output,
+                        &bam,
+                        &reference_buffer,
+                        max_read_len,
+                    )
+                    .context("Unable to construct local haplotypes.")?;
+                }
             }
         }
         Varlociraptor::Call { kind } => {
@@ -946,6 +1577,10 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                     omit_softclip_bias,
                     omit_homopolymer_artifact_detection,
                     omit_alt_locus_bias,
+                    omit_damage_bias,
+                    fragment_length_models,
+                    phase,
+                    phasing_max_prob_absent,
                     testcase_locus,
                     testcase_prefix,
                     testcase_anonymous,
@@ -953,7 +1588,20 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                     log_mode,
                     propagate_info_fields,
                     full_prior,
+                    threads,
+                    regions,
+                    regions_bed,
                 } => {
+                    let regions = Regions::from_args(&regions, regions_bed.as_deref())?;
+                    let fragment_length_models = parse_key_values(&fragment_length_models)
+                        .ok_or(errors::Error::InvalidFragmentLengthModelsSpec)?;
+                    let phaser = if phase {
+                        Some(Phaser::new(LogProb::from(Prob::checked(
+                            phasing_max_prob_absent,
+                        )?)))
+                    } else {
+                        None
+                    };
                     let testcase_builder = if let Some(testcase_locus) = testcase_locus {
                         if let Some(testcase_prefix) = testcase_prefix {
                             // TODO obtain sample information from input bcfs?
@@ -988,7 +1636,7 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                                         let preprocess_input = options.preprocess_input();
                                         testcase_builder = testcase_builder.register_sample(
                                             sample_name,
-                                            preprocess_input.bam,
+                                            &preprocess_input.bam,
                                             options,
                                         )?;
                                         if i == 0 {
@@ -1020,12 +1668,16 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                                     omit_softclip_bias,
                                     omit_homopolymer_artifact_detection,
                                     omit_alt_locus_bias,
+                                    omit_damage_bias,
+                                    fragment_length_models,
                                     output,
                                     log_each_record,
-                                    CallWriter::new(),
+                                    CallWriter::new(phaser),
                                     DefaultCandidateFilter::new(),
                                     propagate_info_fields,
                                     full_prior,
+                                    threads,
+                                    regions,
                                 )?;
                             } else {
                                 return Err(errors::Error::InvalidObservationsSpec.into());
@@ -1050,12 +1702,12 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                                     .reference(tumor_options.preprocess_input().reference)?
                                     .register_sample(
                                         "tumor",
-                                        tumor_options.preprocess_input().bam,
+                                        &tumor_options.preprocess_input().bam,
                                         tumor_options,
                                     )?
                                     .register_sample(
                                         "normal",
-                                        normal_options.preprocess_input().bam,
+                                        &normal_options.preprocess_input().bam,
                                         normal_options,
                                     )?
                                     .scenario(None)
@@ -1105,40 +1757,51 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                                 omit_softclip_bias,
                                 omit_homopolymer_artifact_detection,
                                 omit_alt_locus_bias,
+                                omit_damage_bias,
+                                fragment_length_models,
                                 output,
                                 log_each_record,
-                                CallWriter::new(),
+                                CallWriter::new(phaser),
                                 DefaultCandidateFilter::new(),
                                 propagate_info_fields,
                                 full_prior,
+                                threads,
+                                regions,
                             )?;
                         }
                     }
-                } // CallKind::CNVs {
-                  //     calls,
-                  //     output,
-                  //     min_bayes_factor,
-                  //     threads,
-                  //     purity,
-                  //     max_dist,
-                  // } => {
-                  //     rayon::ThreadPoolBuilder::new()
-                  //         .num_threads(threads)
-                  //         .build_global()?;
-
-                  //     if min_bayes_factor <= 1.0 {
-                  //         Err(errors::Error::InvalidMinBayesFactor)?
-                  //     }
-
-                  //     let mut caller = calling::cnvs::CallerBuilder::default()
-                  //         .bcfs(calls.as_ref(), output.as_ref())?
-                  //         .min_bayes_factor(min_bayes_factor)
-                  //         .purity(purity)
-                  //         .max_dist(max_dist)
-                  //         .build()
-                  //         .unwrap();
-                  //     caller.call()?;
-                  // }
+                }
+                CallKind::CNVs {
+                    scenario,
+                    calls,
+                    output,
+                    germline_het_event,
+                    min_bayes_factor,
+                    max_dist,
+                    threads,
+                } => {
+                    rayon::ThreadPoolBuilder::new()
+                        .num_threads(threads)
+                        .build_global()?;
+
+                    if min_bayes_factor <= 1.0 {
+                        Err(errors::Error::InvalidMinBayesFactor)?
+                    }
+
+                    let scenario = grammar::Scenario::from_path(scenario)?;
+                    let mut caller = calling::cnvs::CallerBuilder::default()
+                        .bcfs(
+                            &scenario,
+                            &germline_het_event,
+                            calls.as_ref(),
+                            output.as_ref(),
+                        )?
+                        .min_bayes_factor(min_bayes_factor)
+                        .max_dist(max_dist)
+                        .build()
+                        .unwrap();
+                    caller.call()?;
+                }
             }
         }
         Varlociraptor::FilterCalls { method } => match method {
@@ -1236,14 +1899,31 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
                 mode,
                 cutoff,
             )?,
+            EstimateKind::FragmentLengths {
+                observations,
+                calls,
+                events,
+                min_prob,
+            } => estimation::fragment_lengths::estimate_fragment_lengths(
+                observations,
+                calls,
+                &events,
+                Prob::checked(min_prob)?,
+            )?,
             EstimateKind::AlignmentProperties {
                 reference,
                 bam,
                 num_records,
+                read_type,
             } => {
                 let mut reference_buffer = reference::Buffer::from_path(&reference, 1)?;
-                let alignment_properties =
-                    estimate_alignment_properties(bam, false, &mut reference_buffer, num_records)?;
+                let alignment_properties = estimate_alignment_properties(
+                    bam,
+                    false,
+                    read_type,
+                    &mut reference_buffer,
+                    num_records,
+                )?;
                 println!("{}", serde_json::to_string_pretty(&alignment_properties)?);
             }
         },
@@ -1298,6 +1978,39 @@ pub fn run(opt: Varlociraptor) -> Result<()> {
         Varlociraptor::MethylationCandidates { input, output } => {
             candidates::methylation::find_candidates(input, output)?;
         }
+        Varlociraptor::Inspect { kind } => match kind {
+            InspectKind::Observations {
+                observations,
+                regions,
+                regions_bed,
+                format,
+                output,
+            } => {
+                let regions = Regions::from_args(&regions, regions_bed.as_deref())?;
+                let format = match format.as_str() {
+                    "json" => InspectionFormat::Json,
+                    _ => InspectionFormat::Tsv,
+                };
+                calling::variants::inspect::inspect_observations(
+                    observations,
+                    regions,
+                    format,
+                    output.as_deref(),
+                )?;
+            }
+        },
+        Varlociraptor::UpgradeObservations { input, output } => {
+            calling::variants::preprocessing::observation_format::upgrade_observations(
+                input,
+                output.as_deref(),
+            )?;
+        }
+        Varlociraptor::ConcatObservations { inputs, output } => {
+            calling::variants::concat::concat_observations(&inputs, output.as_deref())?;
+        }
+        Varlociraptor::ConcatCalls { inputs, output } => {
+            calling::variants::concat::concat_calls(&inputs, output.as_deref())?;
+        }
     }
     Ok(())
 }
@@ -1306,14 +2019,80 @@ pub(crate) fn est_or_load_alignment_properties(
     alignment_properties_file: &Option<impl AsRef<Path>>,
     bam_file: impl AsRef<Path>,
     omit_insert_size: bool,
+    read_type: Option<ReadType>,
     reference_buffer: &mut reference::Buffer,
     num_records: Option<usize>,
 ) -> Result<AlignmentProperties> {
     if let Some(alignment_properties_file) = alignment_properties_file {
-        Ok(serde_json::from_reader(File::open(
-            alignment_properties_file,
-        )?)?)
+        let mut alignment_properties: AlignmentProperties =
+            serde_json::from_reader(File::open(alignment_properties_file)?)?;
+        if let Some(read_type) = read_type {
+            alignment_properties.set_read_type(read_type);
+        }
+        if omit_insert_size {
+            alignment_properties.omit_insert_size();
+        }
+        if alignment_properties.damage_stats.is_empty() {
+            warn!(
+                "Alignment properties in {} do not contain damage statistics (e.g. because they \
+                have been estimated with an older version of Varlociraptor). Chemical damage \
+                (FFPE, 8-oxoG) will not be considered for this sample. Re-estimate the alignment \
+                properties in order to enable it.",
+                alignment_properties_file.as_ref().display()
+            );
+        }
+        Ok(alignment_properties)
     } else {
-        estimate_alignment_properties(bam_file, omit_insert_size, reference_buffer, num_records)
+        estimate_alignment_properties(
+            bam_file,
+            omit_insert_size,
+            read_type.unwrap_or_default(),
+            reference_buffer,
+            num_records,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_alignment_properties_omit_insert_size() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("alignment-properties.json");
+        let mut props: AlignmentProperties = serde_json::from_str(
+            r#"{
+                "insert_size": {"mean": 300.0, "sd": 10.0},
+                "max_del_cigar_len": 30,
+                "max_ins_cigar_len": 12,
+                "frac_max_softclip": 0.6,
+                "max_read_len": 100
+            }"#,
+        )
+        .unwrap();
+        props.read_groups.insert("lib1".to_owned(), props.clone());
+        serde_json::to_writer(File::create(&path).unwrap(), &props).unwrap();
+
+        let mut reference_buffer =
+            reference::Buffer::from_path("tests/resources/chr10.fa", 1).unwrap();
+        let mut load = |omit_insert_size| {
+            est_or_load_alignment_properties(
+                &Some(&path),
+                "tests/resources/tumor-first30000.bam",
+                omit_insert_size,
+                None,
+                &mut reference_buffer,
+                None,
+            )
+            .unwrap()
+        };
+
+        assert!(load(false)
+            .iter_read_groups()
+            .all(|props| props.insert_size.is_some()));
+        assert!(load(true)
+            .iter_read_groups()
+            .all(|props| props.insert_size.is_none()));
     }
 }
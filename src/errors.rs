@@ -15,12 +15,18 @@ pub(crate) enum Error {
     InvalidInheritanceSampleName { name: String },
     #[error("observation files must be provided as samplename=path")]
     InvalidObservationsSpec,
+    #[error("fragment length models must be provided as samplename=path")]
+    InvalidFragmentLengthModelsSpec,
+    #[error("sample {name} (given by --fragment-length-models) cannot be found in the scenario")]
+    InvalidFragmentLengthModelSampleName { name: String },
     #[error(
         "invalid variant index given, must be not higher than the number of variants at the locus"
     )]
     InvalidIndex,
     #[error("invalid locus for --testcase-locus. Use CHROM:POS syntax")]
     InvalidLocus,
+    #[error("invalid region {region}: use CHROM, CHROM:START or CHROM:START-END syntax (1-based, inclusive) or a valid BED file")]
+    InvalidRegion { region: String },
     #[error("no candidate variant at the given locus")]
     NoCandidateFound,
     #[error("testcase prefix must be given with --testcase-prefix")]
@@ -49,8 +55,17 @@ pub(crate) enum Error {
     InvalidObservations { path: PathBuf },
     #[error("invalid observations: varlociraptor cannot read given observations; either the file has not been preprocessed with varlociraptor or with a too old version")]
     InvalidObservationFormat,
+    #[error("unsupported observation format version {version}: observations have been preprocessed with a too old or too new version of varlociraptor; please preprocess them again")]
+    UnsupportedObservationFormat { version: String },
     #[error("invalid BND record: ALT {spec} does not follow BND spec")]
     InvalidBNDRecordAlt { spec: String },
+    #[error("unable to concatenate {path}: {msg} compared to the first input file")]
+    IncompatibleConcatInput { path: PathBuf, msg: String },
+    #[error("--alignment-properties must be given once for each BAM file passed to --bam ({bams} BAM files, {alignment_properties} alignment properties files)")]
+    InvalidAlignmentPropertiesCount {
+        bams: usize,
+        alignment_properties: usize,
+    },
     #[error("at least one BCF with observations must be provided")]
     EmptyObservations,
     #[error(
@@ -59,6 +74,10 @@ pub(crate) enum Error {
     UndefinedExpression { identifier: String },
     #[error("invalid prior configuration: {msg}")]
     InvalidPriorConfiguration { msg: String },
+    #[error("invalid scenario for CNV calling: {msg}")]
+    InvalidCNVScenario { msg: String },
+    #[error("invalid minimum bayes factor: must be greater than 1.0")]
+    InvalidMinBayesFactor,
     #[error("read position determined from cigar string exceeds record length")]
     ReadPosOutOfBounds,
     #[error("invalid strand information '{value}', must be '+', '-', '*' or '.'")]
@@ -74,14 +93,14 @@ pub(crate) enum Error {
     },
     // #[error("invalid phase set, PS tag only supported for single sample VCF/BCF, may only contain a single value, and records may only contain a single ALT allele")]
     // InvalidPhaseSet,
-    #[error("haplotype block consisting of normal variants in combination with breakends: this is currently unsupported")]
-    HaplotypeBlockWithBreakend,
     #[error("invalid prior contamination estimate. Both --prior-estiate and --prior-considered-cells have to be specified. The latter has to be >0.")]
     InvalidPriorContaminationEstimate,
     #[error("breakend with MATEID found that does not have its own ID set: this is currently unsupported, as there is no way to uniquely identify the pair")]
     BreakendMateidWithoutRecid,
     #[error("invalid FDR control events, no events provided or none of the given events matches the events found in the callset")]
     InvalidFDRControlEvents,
+    #[error("no consensus sequence found for mobile element {element}; provide a FASTA file with mobile element consensus sequences via --mobile-element-library")]
+    UnknownMobileElement { element: String },
     #[error("unrealistic insert size distribution: the standard deviation is 0.0, consider sampling more reads for estimating alignment properties")]
     UnrealisticIsizeSd,
 }
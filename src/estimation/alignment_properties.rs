@@ -14,11 +14,12 @@ use std::str;
 
 use anyhow::{anyhow, Result};
 use bio::stats::{LogProb, Prob};
+use bio_types::sequence::SequenceReadPairOrientation;
 use counter::Counter;
 use itertools::Itertools;
 use num_traits::Zero;
 use ordered_float::NotNan;
-use rust_htslib::bam::{self, record::Cigar};
+use rust_htslib::bam::{self, record::Aux, record::Cigar};
 use statrs::distribution::ContinuousCDF;
 use statrs::statistics::{Data, Distribution, OrderStatistics};
 
@@ -27,6 +28,7 @@ use crate::utils::aux_tag_is_entire_fragment;
 use crate::utils::homopolymers::is_homopolymer_seq;
 use crate::utils::homopolymers::{extend_homopolymer_stretch, is_homopolymer_iter};
 use crate::utils::SimpleCounter;
+use crate::variants::model::bias::Damage;
 
 pub(crate) const MIN_HOMOPOLYMER_LEN: usize = 2;
 
@@ -76,26 +78,177 @@ pub(crate) struct AlignmentProperties {
     pub(crate) wildtype_homopolymer_error_model: HashMap<i16, f64>,
     #[serde(default)]
     initial: bool,
+    /// Properties estimated separately for each read group (RG tag) of the BAM file.
+    /// Empty if there is at most one read group or if the properties stem from a file
+    /// written before read groups were distinguished. Then, the properties above apply
+    /// to all records.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) read_groups: HashMap<String, AlignmentProperties>,
+    /// Sequencing technology the reads stem from.
+    #[serde(default)]
+    pub(crate) read_type: ReadType,
+    /// Whether the reads stem from RNA and may hence be spliced (N CIGAR operations).
+    #[serde(default)]
+    pub(crate) spliced: bool,
+    /// Counts of substitutions that can be caused by chemical damage (e.g. FFPE or 8-oxoG).
+    #[serde(default)]
+    pub(crate) damage_stats: DamageStats,
+}
+
+/// Type of sequencing reads, determining which technology specific assumptions are made
+/// during estimation, realignment and bias detection.
+#[derive(
+    Display,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    Serialize,
+    Deserialize,
+    EnumString,
+    EnumIter,
+    IntoStaticStr,
+)]
+#[strum(serialize_all = "kebab_case")]
+#[serde(rename_all = "kebab-case")]
+pub enum ReadType {
+    /// Short (paired-end) reads, e.g. from Illumina sequencers.
+    #[default]
+    Short,
+    /// Long reads from Oxford Nanopore sequencers.
+    Ont,
+    /// Long high fidelity (circular consensus) reads from PacBio sequencers.
+    Hifi,
+}
+
+impl ReadType {
+    pub(crate) fn is_long(&self) -> bool {
+        !matches!(self, ReadType::Short)
+    }
+
+    /// Typical read length of the technology, used before any reads have been inspected.
+    fn typical_read_len(&self) -> u32 {
+        match self {
+            ReadType::Short => 100,
+            ReadType::Ont => 10_000,
+            ReadType::Hifi => 15_000,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
 struct EstimationParams {
     num_alignments: Option<u64>,
+    read_len: u32,
     precision: f64,
     precision_is_relative: bool,
     confidence_level: f64,
 }
 
+/// Minimum number of records of a read group for estimating its properties separately.
+const MIN_READ_GROUP_RECORDS: usize = 1000;
+/// Minimum number of fragments of a read group for estimating its insert size separately.
+const MIN_READ_GROUP_FRAGMENTS: usize = 1000;
+
+fn read_group(record: &bam::Record) -> Option<&str> {
+    match record.aux(b"RG") {
+        Ok(Aux::String(read_group)) => Some(read_group),
+        _ => None,
+    }
+}
+
 impl AlignmentProperties {
+    /// Return the properties of the read group of the given record.
+    /// Falls back to the properties of the entire BAM file if the record has no read group
+    /// or if its read group has not been estimated separately.
+    pub(crate) fn read_group_properties(&self, record: &bam::Record) -> &AlignmentProperties {
+        if self.read_groups.is_empty() {
+            return self;
+        }
+        read_group(record)
+            .and_then(|read_group| self.read_groups.get(read_group))
+            .unwrap_or(self)
+    }
+
+    /// Iterate over the properties of the entire BAM file and of all its read groups.
+    pub(crate) fn iter_read_groups(&self) -> impl Iterator<Item = &AlignmentProperties> {
+        std::iter::once(self).chain(self.read_groups.values())
+    }
+
+    /// Set the read type, including the one of all read groups.
+    pub(crate) fn set_read_type(&mut self, read_type: ReadType) {
+        self.read_type = read_type;
+        for properties in self.read_groups.values_mut() {
+            properties.read_type = read_type;
+        }
+    }
+
+    /// Discard the insert size, including the one of all read groups.
+    pub(crate) fn omit_insert_size(&mut self) {
+        self.insert_size = None;
+        for properties in self.read_groups.values_mut() {
+            properties.insert_size = None;
+        }
+    }
+
+    /// Mark the reads as spliced RNA reads, including the ones of all read groups.
+    /// Since the insert size of spliced fragments does not reflect their length on the
+    /// transcript, it is discarded.
+    pub(crate) fn set_spliced(&mut self) {
+        self.spliced = true;
+        self.insert_size = None;
+        for properties in self.read_groups.values_mut() {
+            properties.set_spliced();
+        }
+    }
+
+    /// Combine the properties of multiple BAM files whose records are merged into a single
+    /// BAM file, with the records of each file being assigned to the given read group.
+    /// The properties of each file are retained as the properties of its read group.
+    /// The properties of the merged file are the ones of the first file, extended to the
+    /// maximum lengths and MAPQ observed over all files.
+    pub(crate) fn merge_files(files: Vec<(String, AlignmentProperties)>) -> AlignmentProperties {
+        let mut merged = files[0].1.clone();
+        merged.read_groups = HashMap::new();
+        for (read_group, mut properties) in files {
+            merged.max_read_len = cmp::max(merged.max_read_len, properties.max_read_len);
+            merged.max_mapq = cmp::max(merged.max_mapq, properties.max_mapq);
+            merged.max_del_cigar_len =
+                cmp::max(merged.max_del_cigar_len, properties.max_del_cigar_len);
+            merged.max_ins_cigar_len =
+                cmp::max(merged.max_ins_cigar_len, properties.max_ins_cigar_len);
+            merged.frac_max_softclip =
+                match (merged.frac_max_softclip, properties.frac_max_softclip) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                };
+            // the original read groups of the file cannot be distinguished after merging
+            properties.read_groups.clear();
+            merged.read_groups.insert(read_group, properties);
+        }
+        merged
+    }
+
     /// Update maximum observed cigar operation lengths. Return whether any D, I, S, or H operation
     /// was found in the cigar string.
     /// The argument `update_unknown` denotes whether unknown properties shall be updated as well.
     /// This is only desired during initial estimation.
+    /// The properties of the read group of the record are updated as well.
     pub(crate) fn update_max_cigar_ops_len(
         &mut self,
         record: &bam::Record,
         allow_hardclips: bool,
     ) -> (bool, bool) {
+        if !self.read_groups.is_empty() {
+            if let Some(properties) =
+                read_group(record).and_then(|read_group| self.read_groups.get_mut(read_group))
+            {
+                properties.update_max_cigar_ops_len(record, allow_hardclips);
+            }
+        }
+
         let norm = |j| NotNan::new(j as f64 / record.seq().len() as f64).unwrap();
 
         let mut is_regular = true;
@@ -148,26 +301,14 @@ impl AlignmentProperties {
     pub(crate) fn estimate(
         path: impl AsRef<Path>,
         omit_insert_size: bool,
+        read_type: ReadType,
         reference_buffer: &mut reference::Buffer,
         num_records: Option<usize>,
     ) -> Result<Self> {
         // If we do not consider insert size, it is safe to also process hardclipped reads.
-        let allow_hardclips = omit_insert_size;
-
-        let mut properties = AlignmentProperties {
-            insert_size: None,
-            max_del_cigar_len: None,
-            max_ins_cigar_len: None,
-            frac_max_softclip: None,
-            max_read_len: 0,
-            max_mapq: 0,
-            cigar_counts: Default::default(),
-            transition_counts: Default::default(),
-            wildtype_homopolymer_error_model: HashMap::new(),
-            initial: true,
-            gap_params: Default::default(),
-            hop_params: Default::default(),
-        };
+        // METHOD: long reads are not paired, and supplementary alignments of split long reads
+        // are usually hardclipped. Hence, they are always processed.
+        let allow_hardclips = omit_insert_size || read_type.is_long();
 
         #[derive(Debug)]
         struct RecordFlagStats {
@@ -191,11 +332,13 @@ impl AlignmentProperties {
             }
         }
 
+        #[derive(Clone)]
         struct RecordStats {
             mapq: u8,
             read_len: u32,
             cigar_counts: CigarStats,
             transition_counts: TransitionCounts,
+            damage_stats: DamageStats,
             insert_size: Option<f64>,
         }
 
@@ -211,9 +354,61 @@ impl AlignmentProperties {
             max_ins: Option<u32>,
             cigar_counts: CigarStats,
             transition_counts: TransitionCounts,
+            damage_stats: DamageStats,
             tlens: Vec<f64>,
         }
 
+        impl AlignmentStats {
+            fn update(&mut self, rs: RecordStats) {
+                self.n_reads += 1;
+                self.max_mapq = self.max_mapq.max(rs.mapq);
+                self.max_read_len = self.max_read_len.max(rs.read_len);
+                self.n_softclips += rs.cigar_counts.has_soft_clip as u32;
+                self.n_not_usable += rs.cigar_counts.is_not_regular as u32;
+                self.frac_max_softclip = self
+                    .frac_max_softclip
+                    .max(rs.cigar_counts.frac_max_softclip);
+                self.max_ins = OptionMax::max(self.max_ins, rs.cigar_counts.max_ins);
+                self.max_del = OptionMax::max(self.max_del, rs.cigar_counts.max_del);
+                self.cigar_counts += rs.cigar_counts;
+                self.transition_counts += rs.transition_counts;
+                self.damage_stats += rs.damage_stats;
+                if let Some(insert_size) = rs.insert_size {
+                    self.tlens.push(insert_size);
+                }
+            }
+
+            fn properties(&self, read_type: ReadType) -> AlignmentProperties {
+                let mut properties = AlignmentProperties {
+                    insert_size: estimate_insert_size(&self.tlens),
+                    max_del_cigar_len: self.max_del,
+                    max_ins_cigar_len: self.max_ins,
+                    frac_max_softclip: self.frac_max_softclip,
+                    max_read_len: self.max_read_len,
+                    max_mapq: self.max_mapq,
+                    cigar_counts: Some(self.cigar_counts.clone()),
+                    transition_counts: Some(self.transition_counts.clone()),
+                    wildtype_homopolymer_error_model: HashMap::new(),
+                    initial: false,
+                    gap_params: Default::default(),
+                    hop_params: Default::default(),
+                    read_groups: HashMap::new(),
+                    read_type,
+                    spliced: false,
+                    damage_stats: self.damage_stats.clone(),
+                };
+                properties.wildtype_homopolymer_error_model =
+                    properties.wildtype_homopolymer_error_model();
+                properties.gap_params = properties
+                    .estimate_gap_params()
+                    .unwrap_or_else(|_| GapParams::default_for(read_type));
+                properties.hop_params = properties
+                    .estimate_hop_params()
+                    .unwrap_or_else(|_| HopParams::default_for(read_type));
+                properties
+            }
+        }
+
         let mut bam = bam::IndexedReader::from_path(path.as_ref())?;
         // Retrieve number of alignments in the bam file.
         // Use this to estimate the number of alignments needed to estimate the
@@ -236,6 +431,7 @@ impl AlignmentProperties {
         // These are currently hardcoded, but could be made configurable.
         let est_params = EstimationParams {
             num_alignments: (num_alignments > 0).then_some(num_alignments),
+            read_len: read_type.typical_read_len(),
             precision: 1e-5,
             precision_is_relative: true,
             confidence_level: 0.1,
@@ -296,7 +492,7 @@ impl AlignmentProperties {
             .step_by(step)
             .take(num_records);
 
-        let all_stats = records
+        let (all_stats, read_group_stats) = records
             .map(|mut record| {
                 record_flag_stats.update(&record);
                 n_records_analysed += 1;
@@ -306,11 +502,10 @@ impl AlignmentProperties {
                 }
 
                 let chrom = str::from_utf8(header.tid2name(record.tid() as u32)).unwrap();
-                let (cigar_counts, transition_counts) = cigar_stats(
-                    &record,
-                    &reference_buffer.seq(chrom).unwrap(),
-                    allow_hardclips,
-                );
+                let refseq = reference_buffer.seq(chrom).unwrap();
+                let (cigar_counts, transition_counts) =
+                    cigar_stats(&record, &refseq, allow_hardclips);
+                let damage_stats = DamageStats::from_record(&record, &refseq);
 
                 let insert_size = {
                     if !cigar_counts.is_not_regular && !omit_insert_size {
@@ -332,45 +527,56 @@ impl AlignmentProperties {
                     }
                 };
 
-                RecordStats {
-                    mapq: record.mapq(),
-                    read_len: record.seq().len() as u32,
-                    cigar_counts,
-                    transition_counts,
-                    insert_size,
-                }
+                (
+                    read_group(&record).map(|read_group| read_group.to_owned()),
+                    RecordStats {
+                        mapq: record.mapq(),
+                        read_len: record.seq().len() as u32,
+                        cigar_counts,
+                        transition_counts,
+                        damage_stats,
+                        insert_size,
+                    },
+                )
             })
-            .fold(AlignmentStats::default(), |mut acc, rs| {
-                acc.n_reads += 1;
-                acc.max_mapq = acc.max_mapq.max(rs.mapq);
-                acc.max_read_len = acc.max_read_len.max(rs.read_len);
-                acc.n_softclips += rs.cigar_counts.has_soft_clip as u32;
-                acc.n_not_usable += rs.cigar_counts.is_not_regular as u32;
-                acc.frac_max_softclip =
-                    acc.frac_max_softclip.max(rs.cigar_counts.frac_max_softclip);
-                acc.max_ins = OptionMax::max(acc.max_ins, rs.cigar_counts.max_ins);
-                acc.max_del = OptionMax::max(acc.max_del, rs.cigar_counts.max_del);
-                acc.cigar_counts += rs.cigar_counts;
-                acc.transition_counts += rs.transition_counts;
-                if let Some(insert_size) = rs.insert_size {
-                    acc.tlens.push(insert_size);
-                }
-                acc
-            });
-
-        properties.cigar_counts = Some(all_stats.cigar_counts.clone());
-        properties.transition_counts = Some(all_stats.transition_counts.clone());
-
-        properties.wildtype_homopolymer_error_model = properties.wildtype_homopolymer_error_model();
+            .fold(
+                (AlignmentStats::default(), HashMap::new()),
+                |(mut acc, mut read_group_acc): (_, HashMap<String, AlignmentStats>),
+                 (read_group, rs)| {
+                    if let Some(read_group) = read_group {
+                        read_group_acc
+                            .entry(read_group)
+                            .or_default()
+                            .update(rs.clone());
+                    }
+                    acc.update(rs);
+                    (acc, read_group_acc)
+                },
+            );
 
-        properties.gap_params = properties.estimate_gap_params().unwrap_or_default();
-        properties.hop_params = properties.estimate_hop_params().unwrap_or_default();
-        properties.max_read_len = all_stats.max_read_len;
-        properties.max_del_cigar_len = all_stats.max_del;
-        properties.max_ins_cigar_len = all_stats.max_ins;
-        properties.frac_max_softclip = all_stats.frac_max_softclip;
-        properties.max_mapq = all_stats.max_mapq;
-        properties.max_read_len = all_stats.max_read_len;
+        let mut properties = all_stats.properties(read_type);
+        // METHOD: libraries with different fragment sizes or error profiles may be merged
+        // into one BAM file. Hence, if there are multiple read groups, we estimate the
+        // properties for each of them separately. Read groups with too few records to
+        // yield a reliable estimate fall back to the properties of the entire BAM file,
+        // and so does the insert size of read groups with too few fragments.
+        if read_group_stats.len() > 1 {
+            properties.read_groups = read_group_stats
+                .iter()
+                .filter(|(_, stats)| stats.n_reads >= MIN_READ_GROUP_RECORDS)
+                .map(|(read_group, stats)| {
+                    let mut read_group_properties = stats.properties(read_type);
+                    if stats.tlens.len() < MIN_READ_GROUP_FRAGMENTS
+                        || !read_group_properties
+                            .insert_size
+                            .is_some_and(|insert_size| insert_size.sd > 0.0)
+                    {
+                        read_group_properties.insert_size = properties.insert_size;
+                    }
+                    (read_group.clone(), read_group_properties)
+                })
+                .collect();
+        }
 
         let s = format!(
             "in {} alignments (out of {})",
@@ -399,9 +605,6 @@ impl AlignmentProperties {
             )
         }
 
-        // Mark initial estimation as done.
-        properties.initial = false;
-
         if all_stats.tlens.is_empty() {
             warn!(
                 "\nFound no records to use for estimating the insert size. Will assume\n\
@@ -437,34 +640,18 @@ impl AlignmentProperties {
                 mate_unmapped = record_flag_stats.inner.get(&("is_mate_unmapped", true)).unwrap_or(&0),
                 record_unmapped = record_flag_stats.inner.get(&("is_unmapped", true)).unwrap_or(&0),
             );
-            properties.insert_size = None;
-            Ok(properties)
-        } else {
-            let mut tlens = Data::new(all_stats.tlens);
-            let upper = tlens.percentile(95);
-            let lower = tlens.percentile(5);
-            let valid = Data::new(
-                tlens
-                    .iter()
-                    .cloned()
-                    .filter(|l| *l <= upper && *l >= lower)
-                    .collect_vec(),
-            );
-
-            properties.insert_size = Some(InsertSize {
-                mean: valid.iter().sum::<f64>() / valid.len() as f64,
-                sd: valid.std_dev().unwrap(),
-            });
-            Ok(properties)
         }
+        Ok(properties)
     }
 
     fn estimate_number_of_alignments_for_hphmm_mle_param_estimation(
         params: EstimationParams,
     ) -> usize {
-        // TODO: Get rough estimate of read length, assume 100 for illumina for now
-        // Most reads in the wild are at least 100bp long, so this is a conservative estimate.
-        let transitions_per_alignment = 100;
+        // METHOD: the number of transitions per alignment is roughly the read length.
+        // Since no reads have been inspected yet, we use the typical read length of the
+        // sequencing technology. For short reads, most reads in the wild are at least 100bp long,
+        // so this is a conservative estimate.
+        let transitions_per_alignment = params.read_len;
         // The target precision for the transition probabilities.
         let precision = params.precision; // 5e-1;
 
@@ -664,6 +851,172 @@ impl AddAssign for CigarStats {
     }
 }
 
+/// Number of substitutions and of substitutable reference bases.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SubstitutionCounts {
+    substitutions: u64,
+    bases: u64,
+}
+
+impl SubstitutionCounts {
+    fn rate(&self) -> Option<f64> {
+        (self.bases > 0).then(|| self.substitutions as f64 / self.bases as f64)
+    }
+}
+
+impl AddAssign for SubstitutionCounts {
+    fn add_assign(&mut self, rhs: Self) {
+        self.substitutions += rhs.substitutions;
+        self.bases += rhs.bases;
+    }
+}
+
+/// Counts of substitutions that can be caused by chemical damage, separately for the read
+/// orientation in which the damage manifests and the opposite one.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct DamageStats {
+    c_to_t: [SubstitutionCounts; 2],
+    g_to_a: [SubstitutionCounts; 2],
+    g_to_t: [SubstitutionCounts; 2],
+    c_to_a: [SubstitutionCounts; 2],
+}
+
+impl AddAssign for DamageStats {
+    fn add_assign(&mut self, rhs: Self) {
+        for i in 0..2 {
+            self.c_to_t[i] += rhs.c_to_t[i];
+            self.g_to_a[i] += rhs.g_to_a[i];
+            self.g_to_t[i] += rhs.g_to_t[i];
+            self.c_to_a[i] += rhs.c_to_a[i];
+        }
+    }
+}
+
+impl DamageStats {
+    /// Minimum number of substitutions needed to estimate the orientation of a damage type.
+    const MIN_SUBSTITUTIONS: u64 = 10;
+
+    /// Return true if no bases have been counted, e.g. because the properties have been
+    /// estimated by a version that did not record damage statistics yet.
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == DamageStats::default()
+    }
+
+    /// Count the damage type substitutions in the given record.
+    fn from_record(record: &bam::Record, refseq: &[u8]) -> Self {
+        let mut stats = DamageStats::default();
+        // METHOD: the read orientation is only defined for pairs mapped to the same contig.
+        if !record.is_paired() || record.is_mate_unmapped() || record.tid() != record.mtid() {
+            return stats;
+        }
+        let read_orientation = if record.is_first_in_template() != record.is_reverse() {
+            SequenceReadPairOrientation::F1R2
+        } else {
+            SequenceReadPairOrientation::F2R1
+        };
+
+        let qseq = record.seq();
+        let mut qpos = 0usize;
+        let mut rpos = record.pos() as usize;
+        let iter = if let Some(cigar) = record.cigar_cached() {
+            Box::new(cigar.iter().copied()) as Box<dyn Iterator<Item = Cigar>>
+        } else {
+            Box::new(iter_cigar(record))
+        };
+        for c in iter {
+            match c {
+                Cigar::Match(l) | Cigar::Equal(l) | Cigar::Diff(l) => {
+                    for i in 0..l as usize {
+                        if let Some(refbase) = refseq.get(rpos + i) {
+                            stats.count(
+                                refbase.to_ascii_uppercase(),
+                                qseq[qpos + i],
+                                read_orientation,
+                            );
+                        }
+                    }
+                    qpos += l as usize;
+                    rpos += l as usize;
+                }
+                Cigar::Ins(l) | Cigar::SoftClip(l) => qpos += l as usize,
+                Cigar::Del(l) | Cigar::RefSkip(l) => rpos += l as usize,
+                Cigar::HardClip(_) | Cigar::Pad(_) => (),
+            }
+        }
+
+        stats
+    }
+
+    fn count(&mut self, refbase: u8, readbase: u8, read_orientation: SequenceReadPairOrientation) {
+        let damages = match refbase {
+            b'C' => [
+                Damage::Deamination {
+                    reverse_strand: false,
+                },
+                Damage::Oxidation {
+                    reverse_strand: true,
+                },
+            ],
+            b'G' => [
+                Damage::Deamination {
+                    reverse_strand: true,
+                },
+                Damage::Oxidation {
+                    reverse_strand: false,
+                },
+            ],
+            _ => return,
+        };
+        for damage in damages {
+            let i = if read_orientation == damage.read_orientation() {
+                0
+            } else {
+                1
+            };
+            let counts = &mut self.counts_mut(&damage)[i];
+            counts.bases += 1;
+            if readbase == damage.substitution().1 {
+                counts.substitutions += 1;
+            }
+        }
+    }
+
+    fn counts(&self, damage: &Damage) -> &[SubstitutionCounts; 2] {
+        match damage.substitution() {
+            (b'C', b'T') => &self.c_to_t,
+            (b'G', b'A') => &self.g_to_a,
+            (b'G', b'T') => &self.g_to_t,
+            _ => &self.c_to_a,
+        }
+    }
+
+    fn counts_mut(&mut self, damage: &Damage) -> &mut [SubstitutionCounts; 2] {
+        match damage.substitution() {
+            (b'C', b'T') => &mut self.c_to_t,
+            (b'G', b'A') => &mut self.g_to_a,
+            (b'G', b'T') => &mut self.g_to_t,
+            _ => &mut self.c_to_a,
+        }
+    }
+
+    /// Probability for a substitution of the given damage type to occur in the read orientation
+    /// in which the damage manifests. This is 0.5 for an undamaged sample, and approaches 1.0
+    /// the more substitutions are caused by the damage. Returns None if there are not enough
+    /// substitutions for an estimate.
+    pub(crate) fn prob_damage_orientation(&self, damage: &Damage) -> Option<f64> {
+        let counts = self.counts(damage);
+        if counts[0].substitutions + counts[1].substitutions < Self::MIN_SUBSTITUTIONS {
+            return None;
+        }
+        // METHOD: substitutions in the opposite orientation are caused by sequencing errors or
+        // real variants, which affect both orientations equally. Comparing the rates in both
+        // orientations thereby isolates the damage.
+        let rate_damaged = counts[0].rate()?;
+        let rate_opposite = counts[1].rate()?;
+        Some(rate_damaged / (rate_damaged + rate_opposite))
+    }
+}
+
 fn iter_cigar(record: &bam::Record) -> impl Iterator<Item = Cigar> + '_ {
     record.raw_cigar().iter().map(|&c| {
         let len = c >> 4;
@@ -1003,6 +1356,29 @@ impl AlignmentProperties {
     }
 }
 
+/// Estimate the insert size distribution from the given observed insert sizes,
+/// ignoring those below the 5% and above the 95% percentile.
+fn estimate_insert_size(tlens: &[f64]) -> Option<InsertSize> {
+    if tlens.is_empty() {
+        return None;
+    }
+    let mut tlens = Data::new(tlens.to_vec());
+    let upper = tlens.percentile(95);
+    let lower = tlens.percentile(5);
+    let valid = Data::new(
+        tlens
+            .iter()
+            .cloned()
+            .filter(|l| *l <= upper && *l >= lower)
+            .collect_vec(),
+    );
+
+    Some(InsertSize {
+        mean: valid.iter().sum::<f64>() / valid.len() as f64,
+        sd: valid.std_dev().unwrap(),
+    })
+}
+
 /// Expected insert size in terms of mean and standard deviation.
 /// This should be estimated from unsorted(!) bam files to avoid positional biases.
 #[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
@@ -1055,9 +1431,14 @@ mod tests {
         let path = "tests/resources/tumor-first30000.bam";
         let mut reference_buffer = reference_buffer();
 
-        let props =
-            AlignmentProperties::estimate(path, false, &mut reference_buffer, Some(NUM_FRAGMENTS))
-                .unwrap();
+        let props = AlignmentProperties::estimate(
+            path,
+            false,
+            ReadType::Short,
+            &mut reference_buffer,
+            Some(NUM_FRAGMENTS),
+        )
+        .unwrap();
         println!("{:?}", props);
 
         if let Some(isize) = props.insert_size {
@@ -1076,9 +1457,14 @@ mod tests {
         let path = "tests/resources/tumor-first30000.reads_with_soft_clips.bam";
         let mut reference_buffer = reference_buffer();
 
-        let props =
-            AlignmentProperties::estimate(path, false, &mut reference_buffer, Some(NUM_FRAGMENTS))
-                .unwrap();
+        let props = AlignmentProperties::estimate(
+            path,
+            false,
+            ReadType::Short,
+            &mut reference_buffer,
+            Some(NUM_FRAGMENTS),
+        )
+        .unwrap();
         println!("{:?}", props);
 
         assert!(props.insert_size.is_none());
@@ -1087,15 +1473,142 @@ mod tests {
         assert_eq!(props.frac_max_softclip, Some(0.63));
     }
 
+    #[test]
+    fn test_read_group_properties() {
+        // properties written before read groups were distinguished
+        let json = r#"{
+            "insert_size": {"mean": 300.0, "sd": 10.0},
+            "max_del_cigar_len": 30,
+            "max_ins_cigar_len": 12,
+            "frac_max_softclip": 0.6,
+            "max_read_len": 100
+        }"#;
+        let mut props: AlignmentProperties = serde_json::from_str(json).unwrap();
+        assert!(props.read_groups.is_empty());
+
+        let mut lib2 = props.clone();
+        lib2.insert_size = Some(InsertSize {
+            mean: 500.0,
+            sd: 20.0,
+        });
+        props.read_groups.insert("lib1".to_owned(), props.clone());
+        props.read_groups.insert("lib2".to_owned(), lib2);
+        let props: AlignmentProperties =
+            serde_json::from_str(&serde_json::to_string(&props).unwrap()).unwrap();
+
+        let mut record = bam::Record::new();
+        assert_eq!(
+            props
+                .read_group_properties(&record)
+                .insert_size
+                .unwrap()
+                .mean,
+            300.0
+        );
+        record.push_aux(b"RG", Aux::String("lib2")).unwrap();
+        assert_eq!(
+            props
+                .read_group_properties(&record)
+                .insert_size
+                .unwrap()
+                .mean,
+            500.0
+        );
+    }
+
+    #[test]
+    fn test_merge_files() {
+        let json = r#"{
+            "insert_size": {"mean": 300.0, "sd": 10.0},
+            "max_del_cigar_len": 30,
+            "max_ins_cigar_len": 12,
+            "frac_max_softclip": 0.6,
+            "max_read_len": 100
+        }"#;
+        let file1: AlignmentProperties = serde_json::from_str(json).unwrap();
+        let mut file2 = file1.clone();
+        file2.insert_size = Some(InsertSize {
+            mean: 500.0,
+            sd: 20.0,
+        });
+        file2.max_read_len = 150;
+        file2.max_del_cigar_len = Some(40);
+
+        let props = AlignmentProperties::merge_files(vec![
+            ("a.bam".to_owned(), file1),
+            ("b.bam".to_owned(), file2),
+        ]);
+        assert_eq!(props.max_read_len, 150);
+        assert_eq!(props.max_del_cigar_len, Some(40));
+        assert_eq!(props.max_ins_cigar_len, Some(12));
+
+        let mut record = bam::Record::new();
+        record.push_aux(b"RG", Aux::String("b.bam")).unwrap();
+        assert_eq!(
+            props
+                .read_group_properties(&record)
+                .insert_size
+                .unwrap()
+                .mean,
+            500.0
+        );
+    }
+
+    #[test]
+    fn test_read_type() {
+        // properties written before read types were distinguished
+        let json = r#"{
+            "insert_size": null,
+            "max_del_cigar_len": 30,
+            "max_ins_cigar_len": 12,
+            "frac_max_softclip": 0.6,
+            "max_read_len": 100
+        }"#;
+        let mut props: AlignmentProperties = serde_json::from_str(json).unwrap();
+        assert_eq!(props.read_type, ReadType::Short);
+        assert!(!props.read_type.is_long());
+
+        props.read_groups.insert("lib1".to_owned(), props.clone());
+        props.set_read_type("ont".parse().unwrap());
+        let props: AlignmentProperties =
+            serde_json::from_str(&serde_json::to_string(&props).unwrap()).unwrap();
+        assert_eq!(props.read_type, ReadType::Ont);
+        assert!(props
+            .iter_read_groups()
+            .all(|props| props.read_type.is_long()));
+    }
+
+    #[test]
+    fn test_default_hop_params_for_read_type() {
+        let short = HopParams::default_for(ReadType::Short);
+        assert!(short
+            .prob_seq_homopolymer
+            .iter()
+            .all(|prob| *prob == LogProb::zero()));
+
+        let ont = HopParams::default_for(ReadType::Ont);
+        let hifi = HopParams::default_for(ReadType::Hifi);
+        for base in 0..4 {
+            assert!(ont.prob_seq_homopolymer[base] > hifi.prob_seq_homopolymer[base]);
+            assert!(hifi.prob_ref_homopolymer[base] > LogProb::zero());
+            assert!(hifi.prob_ref_extend_homopolymer[base] > LogProb::zero());
+        }
+    }
+
     #[test]
     fn test_estimate_all_reads_single_end() {
         // this file contains only single-ended reads (artificially made single-ended with awk)
         let path = "tests/resources/tumor-first30000.bunch_of_reads_made_single_ended.bam";
         let mut reference_buffer = reference_buffer();
 
-        let props =
-            AlignmentProperties::estimate(path, false, &mut reference_buffer, Some(NUM_FRAGMENTS))
-                .unwrap();
+        let props = AlignmentProperties::estimate(
+            path,
+            false,
+            ReadType::Short,
+            &mut reference_buffer,
+            Some(NUM_FRAGMENTS),
+        )
+        .unwrap();
         println!("{:?}", props);
 
         assert!(props.insert_size.is_none());
@@ -1103,4 +1616,163 @@ mod tests {
         assert_eq!(props.max_ins_cigar_len, None);
         assert_eq!(props.frac_max_softclip, Some(0.03));
     }
+
+    fn damage_record(seq: &[u8], first_in_template: bool, reverse: bool) -> bam::Record {
+        let mut record = bam::Record::new();
+        record.set(
+            b"read",
+            Some(&bam::record::CigarString(vec![Cigar::Match(
+                seq.len() as u32
+            )])),
+            seq,
+            &vec![40; seq.len()],
+        );
+        record.set_pos(0);
+        record.set_tid(0);
+        record.set_mtid(0);
+        record.set_paired();
+        if first_in_template {
+            record.set_first_in_template();
+        } else {
+            record.set_last_in_template();
+        }
+        if reverse {
+            record.set_reverse();
+        }
+        record
+    }
+
+    #[test]
+    fn test_damage_stats_from_record() {
+        let refseq = b"CCCCGGGG";
+        let deamination = Damage::Deamination {
+            reverse_strand: false,
+        };
+        let oxidation = Damage::Oxidation {
+            reverse_strand: false,
+        };
+
+        // first read in forward orientation, hence F1R2
+        let stats = DamageStats::from_record(&damage_record(b"TTCCGGTG", true, false), refseq);
+        assert_eq!(
+            stats.c_to_t,
+            [
+                SubstitutionCounts {
+                    substitutions: 2,
+                    bases: 4
+                },
+                SubstitutionCounts::default()
+            ]
+        );
+        assert_eq!(
+            stats.g_to_t,
+            [
+                SubstitutionCounts {
+                    substitutions: 1,
+                    bases: 4
+                },
+                SubstitutionCounts::default()
+            ]
+        );
+        // C>A and G>A damage manifests in F2R1 pairs
+        assert_eq!(
+            stats.c_to_a,
+            [
+                SubstitutionCounts::default(),
+                SubstitutionCounts {
+                    substitutions: 0,
+                    bases: 4
+                }
+            ]
+        );
+        assert_eq!(stats.counts(&deamination)[0].bases, 4);
+        assert_eq!(stats.counts(&oxidation)[0].substitutions, 1);
+
+        // second read in forward orientation and first read in reverse orientation, hence F2R1
+        for (first_in_template, reverse) in [(false, false), (true, true)] {
+            let stats = DamageStats::from_record(
+                &damage_record(b"TTCCGGTG", first_in_template, reverse),
+                refseq,
+            );
+            assert_eq!(stats.c_to_t[0], SubstitutionCounts::default());
+            assert_eq!(
+                stats.c_to_t[1],
+                SubstitutionCounts {
+                    substitutions: 2,
+                    bases: 4
+                }
+            );
+        }
+
+        // the read orientation is undefined for unpaired reads and mates on other contigs
+        let mut record = damage_record(b"TTCCGGTG", true, false);
+        record.set_mtid(1);
+        assert!(DamageStats::from_record(&record, refseq).is_empty());
+        let mut record = damage_record(b"TTCCGGTG", true, false);
+        record.unset_paired();
+        assert!(DamageStats::from_record(&record, refseq).is_empty());
+    }
+
+    #[test]
+    fn test_prob_damage_orientation() {
+        let deamination = Damage::Deamination {
+            reverse_strand: false,
+        };
+        let mut stats = DamageStats::default();
+        assert!(stats.is_empty());
+        assert_eq!(stats.prob_damage_orientation(&deamination), None);
+
+        // not enough substitutions
+        stats.c_to_t = [
+            SubstitutionCounts {
+                substitutions: 5,
+                bases: 1000,
+            },
+            SubstitutionCounts {
+                substitutions: 4,
+                bases: 1000,
+            },
+        ];
+        assert_eq!(stats.prob_damage_orientation(&deamination), None);
+
+        // damaged sample
+        stats.c_to_t = [
+            SubstitutionCounts {
+                substitutions: 30,
+                bases: 1000,
+            },
+            SubstitutionCounts {
+                substitutions: 10,
+                bases: 1000,
+            },
+        ];
+        assert_relative_eq!(stats.prob_damage_orientation(&deamination).unwrap(), 0.75);
+
+        // undamaged sample, rates are normalized by the number of bases
+        stats.c_to_t = [
+            SubstitutionCounts {
+                substitutions: 10,
+                bases: 1000,
+            },
+            SubstitutionCounts {
+                substitutions: 20,
+                bases: 2000,
+            },
+        ];
+        assert_relative_eq!(stats.prob_damage_orientation(&deamination).unwrap(), 0.5);
+        // other damage types are not affected
+        assert_eq!(
+            stats.prob_damage_orientation(&Damage::Oxidation {
+                reverse_strand: false
+            }),
+            None
+        );
+    }
+
+    #[test]
+    fn test_damage_stats_backwards_compatibility() {
+        // properties written before damage statistics were recorded
+        let props: AlignmentProperties = serde_json::from_str(r#"{"max_read_len": 100}"#).unwrap();
+        assert!(props.damage_stats.is_empty());
+    }
 }
@@ -373,7 +373,7 @@ impl ContaminationEstimator {
 impl CallProcessor for ContaminationEstimator {
     fn setup<Pr: bayesian::model::Prior, CF: CandidateFilter>(
         &mut self,
-        _caller: &Caller<Pr, Self, CF>,
+        _caller: &Caller<Pr, CF>,
     ) -> Result<Option<AuxInfoCollector>> {
         Ok(None)
     }
@@ -463,11 +463,15 @@ pub(crate) fn estimate_contamination(
         false,
         false,
         false,
+        false,
+        PathMap::default(),
         None,
         false,
         ContaminationEstimator::new(output, output_plot, output_max_vaf_variants, prior_estimate),
         ContaminationCandidateFilter::new(),
         Vec::new(),
         false,
+        1,
+        None,
     )
 }
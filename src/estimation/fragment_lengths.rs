@@ -0,0 +1,302 @@
+// Copyright 2024 Johannes Köster.
+// Licensed under the GNU GPLv3 license (https://opensource.org/licenses/GPL-3.0)
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Fragment length distributions of tumor derived and healthy fragments.
+//! In cfDNA, tumor derived fragments are shorter than those stemming from healthy
+//! cells. Learning both distributions from high confidence somatic calls allows to
+//! assess whether the variant supporting fragments of a call stem from tumor cells,
+//! or rather from healthy cells (i.e., the variant is germline or stems from clonal
+//! hematopoiesis).
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use bio::stats::{LogProb, Prob};
+use rust_htslib::bcf::{self, Read};
+
+use crate::calling::variants::preprocessing::{
+    observation_format::ObservationFormat, read_observations,
+};
+use crate::utils;
+use crate::variants::evidence::observations::pileup::Pileup;
+use crate::variants::evidence::observations::read_observation::ProcessedReadObservation;
+use crate::variants::model::AlleleFreq;
+use crate::SimpleEvent;
+
+/// Width of the fragment length bins.
+const BIN_WIDTH: u32 = 5;
+/// Fragments longer than this are collected in the last bin.
+const MAX_FRAGMENT_LEN: u32 = 1000;
+
+/// Binned empirical distribution of fragment lengths.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct FragmentLengthDistribution {
+    bin_width: u32,
+    counts: Vec<u64>,
+}
+
+impl Default for FragmentLengthDistribution {
+    fn default() -> Self {
+        FragmentLengthDistribution {
+            bin_width: BIN_WIDTH,
+            counts: vec![0; (MAX_FRAGMENT_LEN / BIN_WIDTH) as usize + 1],
+        }
+    }
+}
+
+impl FragmentLengthDistribution {
+    fn bin(&self, fragment_len: u32) -> usize {
+        ((fragment_len / self.bin_width) as usize).min(self.counts.len() - 1)
+    }
+
+    pub(crate) fn insert(&mut self, fragment_len: u32) {
+        let bin = self.bin(fragment_len);
+        self.counts[bin] += 1;
+    }
+
+    pub(crate) fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Probability to observe a fragment of the given length.
+    pub(crate) fn prob(&self, fragment_len: u32) -> LogProb {
+        // METHOD: add a pseudocount of one to each bin, such that fragment lengths that
+        // have not been seen during training do not get a probability of zero.
+        let total = self.total() + self.counts.len() as u64;
+        let count = self.counts[self.bin(fragment_len)] + 1;
+        LogProb::from(Prob(count as f64 / total as f64))
+    }
+}
+
+/// Fragment length distributions of tumor derived (variant supporting fragments of somatic
+/// calls) and healthy (reference supporting) fragments of a sample.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub(crate) struct FragmentLengthModel {
+    alt: FragmentLengthDistribution,
+    #[serde(rename = "ref")]
+    reference: FragmentLengthDistribution,
+}
+
+impl FragmentLengthModel {
+    pub(crate) fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let reader = File::open(path).with_context(|| {
+            format!(
+                "Unable to read fragment length model from {}.",
+                path.display()
+            )
+        })?;
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /// Record the fragment length of the given observation as tumor derived, if it strongly
+    /// supports the ALT allele of a somatic call.
+    fn register_alt(&mut self, obs: &ProcessedReadObservation) {
+        if let Some(fragment_len) = obs.fragment_len {
+            if obs.is_strong_alt_support() {
+                self.alt.insert(fragment_len);
+            }
+        }
+    }
+
+    /// Record the fragment length of the given observation as healthy, if it strongly
+    /// supports the reference allele.
+    fn register_ref(&mut self, obs: &ProcessedReadObservation) {
+        if let Some(fragment_len) = obs.fragment_len {
+            if obs.is_strong_ref_support() {
+                self.reference.insert(fragment_len);
+            }
+        }
+    }
+
+    /// Posterior probability that the ALT allele fragments of the given pileup stem from
+    /// tumor cells instead of healthy cells (germline or clonal hematopoiesis), given their
+    /// fragment lengths and the allele frequency estimate of the sample. None if there
+    /// are no fragment lengths or no ALT allele fragments.
+    pub(crate) fn prob_tumor_origin(
+        &self,
+        pileup: &Pileup,
+        allele_freq: AlleleFreq,
+    ) -> Option<LogProb> {
+        if *allele_freq == 0.0 {
+            return None;
+        }
+        let prob_alt_allele = LogProb(allele_freq.ln());
+        let prob_ref_allele = prob_alt_allele.ln_one_minus_exp();
+
+        // METHOD: if the variant stems from tumor cells, its ALT allele fragments follow the
+        // tumor fragment length distribution, otherwise (germline or clonal hematopoiesis)
+        // they follow the one of healthy fragments, like the reference allele fragments.
+        // Since the allele of a fragment is uncertain, the tumor case is a mixture of both
+        // distributions, weighted by the posterior probability of the fragment to stem from
+        // the ALT allele (given the allele frequency estimate). With a uniform prior over
+        // both origins, the posterior follows from the likelihood ratio. This is
+        // independent of the allele evidence, which is hence not affected by the
+        // fragment length.
+        let mut log_likelihood_ratio = LogProb::ln_one();
+        let mut has_fragment_lens = false;
+        for obs in pileup.read_observations() {
+            if let Some(fragment_len) = obs.fragment_len {
+                has_fragment_lens = true;
+                let prob_alt = prob_alt_allele + obs.prob_alt;
+                let prob_ref = prob_ref_allele + obs.prob_ref;
+                let prob_from_alt = prob_alt - prob_alt.ln_add_exp(prob_ref);
+                if prob_from_alt.is_nan() {
+                    // the fragment is impossible under both alleles
+                    continue;
+                }
+                let prob_healthy = self.reference.prob(fragment_len);
+                let prob_tumor = (prob_from_alt + self.alt.prob(fragment_len))
+                    .ln_add_exp(prob_from_alt.ln_one_minus_exp() + prob_healthy);
+                log_likelihood_ratio += prob_tumor - prob_healthy;
+            }
+        }
+        if !has_fragment_lens {
+            return None;
+        }
+
+        Some(log_likelihood_ratio - log_likelihood_ratio.ln_add_exp(LogProb::ln_one()))
+    }
+}
+
+/// Learn the fragment length model of a sample from its preprocessed observations and
+/// high confidence somatic calls.
+///
+/// # Arguments
+/// * `observations` - observation BCF of the sample, preprocessed with
+///   `--report-fragment-lengths`
+/// * `calls` - calls of Varlociraptor on the given observations
+/// * `events` - somatic events that shall be considered as high confidence tumor variants
+/// * `min_prob` - minimum posterior probability of the given events
+pub(crate) fn estimate_fragment_lengths<P: AsRef<Path>>(
+    observations: P,
+    calls: P,
+    events: &[String],
+    min_prob: Prob,
+) -> Result<()> {
+    let events: Vec<_> = events.iter().map(|event| SimpleEvent::new(event)).collect();
+    let tags = utils::events_to_tags(&events);
+    let min_prob = LogProb::from(min_prob);
+
+    let mut calls = bcf::Reader::from_path(calls)?;
+    let mut confident_sites = HashSet::new();
+    for record in calls.records() {
+        let mut record = record?;
+        let prob = utils::tags_prob_sum(&mut record, &tags, None)?;
+        if prob
+            .first()
+            .copied()
+            .flatten()
+            .is_some_and(|p| p >= min_prob)
+        {
+            confident_sites.insert(site(&record)?);
+        }
+    }
+
+    let mut observations = bcf::Reader::from_path(observations)?;
+    let format = ObservationFormat::from_header(observations.header())?;
+    let mut model = FragmentLengthModel::default();
+    for record in observations.records() {
+        let mut record = record?;
+        // METHOD: only ALT allele fragments of somatic calls are known to stem from tumor
+        // cells, while ALT allele fragments of germline or clonal hematopoiesis variants
+        // stem from healthy cells. Reference allele fragments mostly stem from healthy
+        // cells at any site.
+        let is_somatic = confident_sites.contains(&site(&record)?);
+        let observations = read_observations(&mut record, format)?;
+        for obs in observations.pileup.read_observations() {
+            if is_somatic {
+                model.register_alt(obs);
+            }
+            model.register_ref(obs);
+        }
+    }
+
+    if model.alt.total() == 0 || model.reference.total() == 0 {
+        warn!(
+            "No fragment lengths found for variant supporting fragments of somatic calls or \
+             for reference supporting fragments. Did you preprocess with \
+             --report-fragment-lengths?"
+        );
+    }
+
+    serde_json::to_writer_pretty(io::stdout(), &model)?;
+    Ok(())
+}
+
+/// Identify a record by contig, position and alleles.
+fn site(record: &bcf::Record) -> Result<(Vec<u8>, i64, Vec<Vec<u8>>)> {
+    let contig = record
+        .header()
+        .rid2name(record.rid().context("Record without contig.")?)?;
+    Ok((
+        contig.to_owned(),
+        record.pos(),
+        record
+            .alleles()
+            .into_iter()
+            .map(|allele| allele.to_owned())
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variants::model::tests::observation;
+
+    #[test]
+    fn test_fragment_length_distribution() {
+        let mut dist = FragmentLengthDistribution::default();
+        for _ in 0..10 {
+            dist.insert(142);
+        }
+        dist.insert(5000);
+        assert_eq!(dist.total(), 11);
+        assert!(dist.prob(141) > dist.prob(167));
+        // unseen lengths and lengths beyond the maximum do not get zero probability
+        assert!(dist.prob(300) > LogProb::ln_zero());
+        assert_eq!(dist.prob(2000), dist.prob(5000));
+    }
+
+    #[test]
+    fn test_prob_tumor_origin() {
+        let mut model = FragmentLengthModel::default();
+        for _ in 0..100 {
+            model.alt.insert(140);
+            model.reference.insert(170);
+        }
+        let pileup = |fragment_len| {
+            let mut pileup = Pileup::default();
+            for i in 0..10 {
+                // half of the fragments support the ALT allele
+                let (prob_alt, prob_ref) = if i % 2 == 0 {
+                    (LogProb::ln_one(), LogProb(0.01f64.ln()))
+                } else {
+                    (LogProb(0.01f64.ln()), LogProb::ln_one())
+                };
+                let mut obs = observation(LogProb::ln_one(), prob_alt, prob_ref);
+                obs.fragment_len = Some(if i % 2 == 0 { fragment_len } else { 170 });
+                pileup.read_observations_mut().push(obs);
+            }
+            pileup
+        };
+        let allele_freq = AlleleFreq(0.5);
+
+        // short ALT fragments indicate tumor origin
+        let prob = model.prob_tumor_origin(&pileup(140), allele_freq).unwrap();
+        assert!(prob.exp() > 0.99);
+        // ALT fragments of healthy length indicate germline or clonal hematopoiesis origin
+        let prob = model.prob_tumor_origin(&pileup(170), allele_freq).unwrap();
+        assert!(prob.exp() < 0.01);
+        // without ALT allele, there is nothing to assess
+        assert!(model
+            .prob_tumor_origin(&pileup(140), AlleleFreq(0.0))
+            .is_none());
+    }
+}
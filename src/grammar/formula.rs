@@ -1529,6 +1529,20 @@ where
                 predicate,
             })
         }
+        Rule::rna_editing => {
+            // METHOD: A-to-I editing (by ADAR enzymes) is by far the most common type of
+            // RNA editing. Inosine is read as guanine, which appears as T>C on the reverse strand.
+            let variant = |refbase: u8, altbase: u8| {
+                Formula::Terminal(FormulaTerminal::Variant {
+                    refbase: Iupac(refbase),
+                    altbase: Iupac(altbase),
+                    positive: true,
+                })
+            };
+            Formula::Disjunction {
+                operands: vec![variant(b'A', b'G'), variant(b'T', b'C')],
+            }
+        }
         Rule::false_literal => Formula::Terminal(FormulaTerminal::False),
         Rule::true_literal => Formula::Terminal(FormulaTerminal::True),
         Rule::cmp_ops => unreachable!(),
@@ -1702,4 +1716,28 @@ events:
         let full = full.normalize(&scenario, "all").unwrap();
         assert_eq!(full, expected.normalize(&scenario, "all").unwrap());
     }
+
+    #[test]
+    fn test_rna_editing() {
+        let scenario: Scenario = serde_yaml::from_str(
+            r#"samples:
+  tumor:
+    resolution: 0.01
+    universe: "[0.0,1.0]"
+  tumor_rna:
+    resolution: 0.01
+    molecule: rna
+events:
+  editing: "tumor:0.0 & tumor_rna:]0.0,1.0] & rna_editing"
+  expected: "tumor:0.0 & tumor_rna:]0.0,1.0] & (A>G | T>C)""#,
+        )
+        .unwrap();
+        assert!(scenario.samples()["tumor_rna"].has_uniform_prior());
+        let expected = scenario.events["expected"].clone();
+        let editing = scenario.events["editing"].clone();
+        assert_eq!(
+            editing.normalize(&scenario, "all").unwrap(),
+            expected.normalize(&scenario, "all").unwrap()
+        );
+    }
 }
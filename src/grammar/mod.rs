@@ -404,9 +404,10 @@ pub(crate) struct VariantTypeFraction {
 impl VariantTypeFraction {
     pub(crate) fn get(&self, variant_type: &VariantType) -> f64 {
         match variant_type {
-            VariantType::Insertion(_) | VariantType::Deletion(_) | VariantType::Replacement => {
-                self.indel
-            }
+            VariantType::Insertion(_)
+            | VariantType::Deletion(_)
+            | VariantType::Replacement
+            | VariantType::ShortTandemRepeat => self.indel,
             VariantType::Mnv => self.mnv,
             VariantType::Inversion | VariantType::Breakend | VariantType::Duplication => self.sv,
             _ => 1.0,
@@ -493,11 +494,15 @@ pub(crate) struct Sample {
     inheritance: Option<Inheritance>,
     #[serde(default)]
     sex: Option<Sex>,
+    /// molecule the sample has been sequenced from (DNA or RNA)
+    #[serde(default)]
+    #[get = "pub(crate)"]
+    molecule: Molecule,
 }
 
 impl Sample {
     pub(crate) fn has_uniform_prior(&self) -> bool {
-        self.universe.is_some()
+        self.universe.is_some() || self.molecule == Molecule::Rna
     }
 
     pub(crate) fn contig_universe(
@@ -518,6 +523,18 @@ impl Sample {
                         .clone(),
                 },
             })
+        } else if self.molecule == Molecule::Rna {
+            // METHOD: due to allele specific expression, allele frequencies in RNA are not
+            // determined by the ploidy. Hence, any frequency is possible.
+            let mut universe = VAFUniverse::default();
+            universe.insert(VAFSpectrum::Range(
+                VAFRange::builder()
+                    .inner(AlleleFreq(0.0)..AlleleFreq(1.0))
+                    .left_exclusive(false)
+                    .right_exclusive(false)
+                    .build(),
+            ));
+            Ok(universe)
         } else {
             let ploidy_derived_spectrum = |ploidy| -> BTreeSet<AlleleFreq> {
                 (0..=ploidy)
@@ -664,6 +681,14 @@ pub(crate) enum Sex {
     Other(String),
 }
 
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Molecule {
+    #[default]
+    Dna,
+    Rna,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(untagged)]
 pub(crate) enum UniverseDefinition {
@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::fs;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::str;
 use std::sync::Arc;
@@ -6,13 +8,212 @@ use std::sync::{Mutex, RwLock};
 
 use anyhow::Result;
 use bio::io::fasta;
+use bio_types::genome::{self, AbstractInterval};
 use lru_time_cache::LruCache;
+use rust_htslib::bcf::{self, record::GenotypeAllele, Read};
+
+/// Consensus sequences of mobile elements (e.g. ALU, L1, SVA), used to reconstruct the
+/// inserted sequence of mobile element insertions given as symbolic alleles.
+#[derive(Debug, Default)]
+pub(crate) struct MobileElementLibrary {
+    consensus: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MobileElementLibrary {
+    pub(crate) fn from_path<P: AsRef<Path> + std::fmt::Debug>(path: P) -> Result<Self> {
+        let mut consensus = HashMap::new();
+        for record in fasta::Reader::from_file(path)?.records() {
+            let record = record?;
+            consensus.insert(
+                record.id().as_bytes().to_ascii_uppercase(),
+                record.seq().to_ascii_uppercase(),
+            );
+        }
+        Ok(MobileElementLibrary { consensus })
+    }
+
+    /// Return the consensus sequence for the given mobile element (e.g. ALU for
+    /// `<INS:ME:ALU>`). Subtypes (e.g. L1:HS) fall back to their top level element if
+    /// they are not contained in the library.
+    pub(crate) fn consensus(&self, element: &[u8]) -> Option<&[u8]> {
+        let element = element.to_ascii_uppercase();
+        self.consensus
+            .get(&element)
+            .or_else(|| {
+                element
+                    .split(|c| *c == b':')
+                    .next()
+                    .and_then(|family| self.consensus.get(family))
+            })
+            .map(|seq| seq.as_slice())
+    }
+}
+
+/// Phased germline substitutions (SNVs and MNVs) of a sample, used to reconstruct its two
+/// germline haplotypes from the reference genome.
+#[derive(Debug, Default)]
+pub(crate) struct GermlineHaplotypes {
+    substitutions: HashMap<String, Vec<GermlineSubstitution>>,
+    max_len: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GermlineSubstitution {
+    pos: u64,
+    ref_allele: Vec<u8>,
+    alt: Vec<u8>,
+    haplotype: usize,
+}
+
+impl GermlineSubstitution {
+    fn overlaps(&self, interval: &Range<u64>) -> bool {
+        self.pos < interval.end && self.pos + self.alt.len() as u64 > interval.start
+    }
+
+    fn is_masked(&self, contig: &str, mask: &[genome::Interval]) -> bool {
+        mask.iter()
+            .any(|locus| locus.contig() == contig && self.overlaps(&locus.range()))
+    }
+}
+
+impl GermlineHaplotypes {
+    /// Read germline variants from the first sample of the given VCF/BCF file.
+    pub(crate) fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut reader = bcf::Reader::from_path(path)?;
+        let mut substitutions: HashMap<String, Vec<GermlineSubstitution>> = HashMap::new();
+        let mut max_len = 0;
+        for record in reader.records() {
+            let record = record?;
+            let genotypes = record.genotypes()?;
+            let genotype = genotypes.get(0);
+            if genotype.len() != 2 {
+                continue;
+            }
+            let (first, second) = match (genotype[0].index(), genotype[1].index()) {
+                (Some(first), Some(second)) => (first, second),
+                _ => continue,
+            };
+            // METHOD: only phased genotypes can be assigned to the haplotypes (htslib marks
+            // the phasing at the second allele). Homozygous genotypes are on both anyway.
+            if first != second && !matches!(genotype[1], GenotypeAllele::Phased(_)) {
+                continue;
+            }
+
+            let contig = str::from_utf8(record.header().rid2name(record.rid().unwrap())?)?;
+            let alleles = record.alleles();
+            for (haplotype, allele) in [first, second].iter().enumerate() {
+                let alt = alleles[*allele as usize];
+                // METHOD: only substitutions are applied, because they keep the reference
+                // coordinates intact, such that all candidate loci remain valid on the haplotypes.
+                if *allele == 0
+                    || alt.len() != alleles[0].len()
+                    || !alt.iter().all(|c| b"ACGTNacgtn".contains(c))
+                {
+                    continue;
+                }
+                max_len = max_len.max(alt.len() as u64);
+                substitutions
+                    .entry(contig.to_owned())
+                    .or_default()
+                    .push(GermlineSubstitution {
+                        pos: record.pos() as u64,
+                        ref_allele: alleles[0].to_ascii_uppercase(),
+                        alt: alt.to_ascii_uppercase(),
+                        haplotype,
+                    });
+            }
+        }
+        for contig_substitutions in substitutions.values_mut() {
+            contig_substitutions.sort_by_key(|substitution| substitution.pos);
+        }
+
+        Ok(GermlineHaplotypes {
+            substitutions,
+            max_len,
+        })
+    }
+
+    /// Iterate over all germline substitutions that overlap the given reference interval.
+    fn overlapping<'a>(
+        &'a self,
+        contig: &str,
+        interval: &'a Range<u64>,
+    ) -> impl Iterator<Item = &'a GermlineSubstitution> {
+        let contig_substitutions = self
+            .substitutions
+            .get(contig)
+            .map_or(&[][..], |contig_substitutions| contig_substitutions);
+        let start = interval.start.saturating_sub(self.max_len);
+        let i = contig_substitutions.partition_point(|substitution| substitution.pos < start);
+        contig_substitutions[i..]
+            .iter()
+            .take_while(move |substitution| substitution.pos < interval.end)
+            .filter(move |substitution| substitution.overlaps(interval))
+    }
+
+    /// Return true if any germline substitution that does not overlap the given mask
+    /// overlaps the given reference interval.
+    pub(crate) fn contains_variants(
+        &self,
+        contig: &str,
+        interval: &Range<u64>,
+        mask: &[genome::Interval],
+    ) -> bool {
+        self.overlapping(contig, interval)
+            .any(|substitution| !substitution.is_masked(contig, mask))
+    }
+
+    /// Return true if any germline substitution overlaps one of the given loci.
+    pub(crate) fn overlaps(&self, loci: &[genome::Interval]) -> bool {
+        loci.iter().any(|locus| {
+            self.overlapping(locus.contig(), &locus.range())
+                .next()
+                .is_some()
+        })
+    }
+
+    /// Apply the substitutions of the given haplotype to the given sequence, omitting any
+    /// that overlap the mask.
+    fn apply(&self, contig: &str, haplotype: usize, mask: &[genome::Interval], seq: &mut [u8]) {
+        if let Some(contig_substitutions) = self.substitutions.get(contig) {
+            for substitution in contig_substitutions.iter().filter(|substitution| {
+                substitution.haplotype == haplotype && !substitution.is_masked(contig, mask)
+            }) {
+                let range =
+                    substitution.pos as usize..substitution.pos as usize + substitution.alt.len();
+                if range.end <= seq.len() {
+                    seq[range].copy_from_slice(&substitution.alt);
+                }
+            }
+        }
+    }
+
+    /// Revert the substitutions of the given haplotype that overlap the mask to the reference
+    /// in the given (already patched) sequence.
+    fn revert(&self, contig: &str, haplotype: usize, mask: &[genome::Interval], seq: &mut [u8]) {
+        for locus in mask.iter().filter(|locus| locus.contig() == contig) {
+            for substitution in self
+                .overlapping(contig, &locus.range())
+                .filter(|substitution| substitution.haplotype == haplotype)
+            {
+                let range = substitution.pos as usize
+                    ..substitution.pos as usize + substitution.ref_allele.len();
+                if range.end <= seq.len() {
+                    seq[range].copy_from_slice(&substitution.ref_allele);
+                }
+            }
+        }
+    }
+}
 
 /// A lazy buffer for reference sequences.
 pub(crate) struct Buffer {
-    reader: RwLock<fasta::IndexedReader<fs::File>>,
+    reader: Arc<RwLock<fasta::IndexedReader<fs::File>>>,
     sequences: Mutex<LruCache<String, Arc<Vec<u8>>>>,
     reference_path: Option<PathBuf>,
+    capacity: usize,
+    germline_haplotype: Option<(Arc<GermlineHaplotypes>, usize)>,
+    germline_mask: Vec<genome::Interval>,
 }
 
 impl Buffer {
@@ -22,12 +223,111 @@ impl Buffer {
     ) -> Result<Self> {
         let fasta: fasta::IndexedReader<fs::File> = fasta::IndexedReader::from_file(&path)?;
         Ok(Buffer {
-            reader: RwLock::new(fasta),
+            reader: Arc::new(RwLock::new(fasta)),
             sequences: Mutex::new(LruCache::with_capacity(capacity)),
             reference_path: Some(path.as_ref().to_path_buf()),
+            capacity,
+            germline_haplotype: None,
+            germline_mask: Vec::new(),
         })
     }
 
+    /// Create a buffer that yields the given germline haplotype (0 or 1) instead of the
+    /// plain reference sequences.
+    pub(crate) fn germline_haplotype<P: AsRef<Path> + std::fmt::Debug>(
+        path: P,
+        capacity: usize,
+        germline_haplotypes: Arc<GermlineHaplotypes>,
+        haplotype: usize,
+    ) -> Result<Self> {
+        let mut buffer = Self::from_path(path, capacity)?;
+        buffer.germline_haplotype = Some((germline_haplotypes, haplotype));
+        Ok(buffer)
+    }
+
+    /// Create a buffer that yields the sequences of this germline haplotype buffer without
+    /// any germline substitution overlapping the given loci. Of the given contig, only the
+    /// given windows are retained, all other positions are undefined. Other contigs are
+    /// loaded on demand.
+    pub(crate) fn masked(
+        &self,
+        contig: &str,
+        windows: &[Range<usize>],
+        mask: &[genome::Interval],
+    ) -> Result<Self> {
+        let (germline_haplotypes, haplotype) = self
+            .germline_haplotype
+            .as_ref()
+            .expect("bug: only germline haplotype buffers can be masked");
+        // METHOD: the masked substitutions are reverted in a copy of the windows of the
+        // already loaded haplotype sequence, instead of reading and patching the entire
+        // contig again.
+        let mut masked = self.windowed(contig, windows)?;
+        germline_haplotypes.revert(contig, *haplotype, mask, &mut masked);
+
+        Ok(self.with_contig(contig, masked, mask.to_vec()))
+    }
+
+    /// Create a buffer that yields the sequences of this buffer, with the given patches applied
+    /// to the given contig. Each patch consists of a target position and a source interval of the
+    /// contig that is copied to it. Of the patched contig, only the given windows (and the patches)
+    /// are retained, all other positions are undefined.
+    pub(crate) fn patched(
+        &self,
+        contig: &str,
+        windows: &[Range<usize>],
+        patches: &[(usize, Range<usize>)],
+    ) -> Result<Self> {
+        let seq = self.seq(contig)?;
+        let mut patched = self.windowed(contig, windows)?;
+        for (target, source) in patches {
+            patched[*target..*target + source.len()].copy_from_slice(&seq[source.clone()]);
+        }
+
+        Ok(self.with_contig(contig, patched, self.germline_mask.clone()))
+    }
+
+    /// Copy the given windows of the given contig into an otherwise undefined sequence of the
+    /// same length.
+    fn windowed(&self, contig: &str, windows: &[Range<usize>]) -> Result<Vec<u8>> {
+        let seq = self.seq(contig)?;
+        // METHOD: a zero-initialized allocation is lazily mapped by the operating system.
+        // Hence, only the windows are materialized, regardless of the length of the contig.
+        let mut windowed = vec![0; seq.len()];
+        for window in windows {
+            let window = window.start.min(seq.len())..window.end.min(seq.len());
+            windowed[window.clone()].copy_from_slice(&seq[window]);
+        }
+        Ok(windowed)
+    }
+
+    /// Create a buffer that shares the reader of this buffer and yields the given sequence
+    /// for the given contig.
+    fn with_contig(
+        &self,
+        contig: &str,
+        seq: Vec<u8>,
+        germline_mask: Vec<genome::Interval>,
+    ) -> Self {
+        let mut sequences = LruCache::with_capacity(self.capacity);
+        sequences.insert(contig.to_owned(), Arc::new(seq));
+
+        Buffer {
+            reader: Arc::clone(&self.reader),
+            sequences: Mutex::new(sequences),
+            reference_path: self.reference_path.clone(),
+            capacity: self.capacity,
+            germline_haplotype: self.germline_haplotype.clone(),
+            germline_mask,
+        }
+    }
+
+    pub(crate) fn germline_haplotypes(&self) -> Option<&GermlineHaplotypes> {
+        self.germline_haplotype
+            .as_ref()
+            .map(|(germline_haplotypes, _)| germline_haplotypes.as_ref())
+    }
+
     pub(crate) fn reference_path(&self) -> Option<&PathBuf> {
         self.reference_path.as_ref()
     }
@@ -47,6 +347,14 @@ impl Buffer {
                 reader.fetch_all(chrom)?;
                 reader.read(Arc::get_mut(&mut sequence).unwrap())?;
             }
+            if let Some((germline_haplotypes, haplotype)) = &self.germline_haplotype {
+                germline_haplotypes.apply(
+                    chrom,
+                    *haplotype,
+                    &self.germline_mask,
+                    Arc::get_mut(&mut sequence).unwrap(),
+                );
+            }
 
             sequences.insert(chrom.to_owned(), Arc::clone(&sequence));
             Ok(sequence)
@@ -55,3 +363,87 @@ impl Buffer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mobile_element_library() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("mobile_elements.fa");
+        fs::write(&path, ">ALU\nggccgggcgc\n>L1\nGGGGGAGGAG\n").unwrap();
+        let library = MobileElementLibrary::from_path(&path).unwrap();
+        assert_eq!(library.consensus(b"alu"), Some(b"GGCCGGGCGC".as_slice()));
+        assert_eq!(library.consensus(b"L1:HS"), Some(b"GGGGGAGGAG".as_slice()));
+        assert_eq!(library.consensus(b"SVA"), None);
+    }
+
+    #[test]
+    fn test_germline_haplotypes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let reference = tmp.path().join("ref.fa");
+        fs::write(&reference, ">chr1\nACGTACGTAC\n").unwrap();
+        fs::write(tmp.path().join("ref.fa.fai"), "chr1\t10\t6\t10\t11\n").unwrap();
+        let vcf = tmp.path().join("germline.vcf");
+        fs::write(
+            &vcf,
+            "##fileformat=VCFv4.2\n\
+             ##contig=<ID=chr1,length=10>\n\
+             ##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n\
+             #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tsample\n\
+             chr1\t2\t.\tC\tT\t.\t.\t.\tGT\t0|1\n\
+             chr1\t4\t.\tT\tG\t.\t.\t.\tGT\t0/1\n\
+             chr1\t6\t.\tCG\tAA\t.\t.\t.\tGT\t1|0\n\
+             chr1\t9\t.\tA\tAT\t.\t.\t.\tGT\t1|1\n",
+        )
+        .unwrap();
+        let germline_haplotypes = Arc::new(GermlineHaplotypes::from_path(&vcf).unwrap());
+
+        let mask = [genome::Interval::new("chr1".to_owned(), 1..2)];
+        assert!(germline_haplotypes.contains_variants("chr1", &(6..7), &[]));
+        assert!(!germline_haplotypes.contains_variants("chr1", &(2..5), &[]));
+        assert!(!germline_haplotypes.contains_variants("chr2", &(0..10), &[]));
+        assert!(germline_haplotypes.contains_variants("chr1", &(0..3), &[]));
+        assert!(!germline_haplotypes.contains_variants("chr1", &(0..3), &mask));
+        assert!(germline_haplotypes.overlaps(&mask));
+        assert!(!germline_haplotypes.overlaps(&[genome::Interval::new("chr1".to_owned(), 2..5)]));
+
+        let buffers = (0..2)
+            .map(|haplotype| {
+                Buffer::germline_haplotype(
+                    &reference,
+                    1,
+                    Arc::clone(&germline_haplotypes),
+                    haplotype,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(buffers[0].seq("chr1").unwrap().as_slice(), b"ACGTAAATAC");
+        assert_eq!(buffers[1].seq("chr1").unwrap().as_slice(), b"ATGTACGTAC");
+
+        let masked = buffers[1].masked("chr1", &[0..2, 3..4], &mask).unwrap();
+        assert_eq!(masked.seq("chr1").unwrap().as_slice(), b"AC\0T\0\0\0\0\0\0");
+        let masked = buffers[0].masked("chr1", &[0..5, 5..10], &mask).unwrap();
+        assert_eq!(masked.seq("chr1").unwrap().as_slice(), b"ACGTAAATAC");
+        // the original haplotype is unaffected by masking
+        assert_eq!(buffers[1].seq("chr1").unwrap().as_slice(), b"ATGTACGTAC");
+    }
+
+    #[test]
+    fn test_patched() {
+        let tmp = tempfile::tempdir().unwrap();
+        let reference = tmp.path().join("ref.fa");
+        fs::write(&reference, ">chr1\nACGTACGTAC\n").unwrap();
+        fs::write(tmp.path().join("ref.fa.fai"), "chr1\t10\t6\t10\t11\n").unwrap();
+        let buffer = Buffer::from_path(&reference, 1).unwrap();
+
+        let patched = buffer
+            .patched("chr1", &[4..8, 9..20], &[(2, 8..10)])
+            .unwrap();
+        assert_eq!(patched.seq("chr1").unwrap().as_slice(), b"\0\0ACACGT\0C");
+        // the original buffer is unaffected by patching
+        assert_eq!(buffer.seq("chr1").unwrap().as_slice(), b"ACGTACGTAC");
+    }
+}
@@ -13,11 +13,13 @@ use bio_types::genome::AbstractLocus;
 use derive_builder::Builder;
 use itertools::Itertools;
 use regex::Regex;
+use rust_htslib::bam::record::Aux;
 use rust_htslib::bam::Read as BamRead;
 use rust_htslib::{bam, bcf, bcf::Read};
 
 use crate::calling::variants::preprocessing::haplotype_feature_index::HaplotypeFeatureIndex;
 use crate::errors;
+use crate::estimation::alignment_properties::{AlignmentProperties, ReadType};
 use crate::utils;
 use crate::utils::anonymize::Anonymizer;
 use crate::utils::collect_variants::VariantInfo;
@@ -73,7 +75,7 @@ pub struct Testcase {
     reference_buffer: reference::Buffer,
     candidates: PathBuf,
     #[builder(private)]
-    bams: HashMap<String, PathBuf>,
+    bams: HashMap<String, Vec<PathBuf>>,
     scenario: Option<PathBuf>,
     #[builder(private)]
     options: HashMap<String, String>,
@@ -138,7 +140,7 @@ impl TestcaseBuilder {
     pub(crate) fn register_sample(
         mut self,
         name: &str,
-        bam: impl AsRef<Path>,
+        bams: &[PathBuf],
         mut options: cli::Varlociraptor,
     ) -> Result<Self> {
         if self.bams.is_none() {
@@ -148,7 +150,7 @@ impl TestcaseBuilder {
         self.bams
             .as_mut()
             .unwrap()
-            .insert(name.to_owned(), bam.as_ref().to_owned());
+            .insert(name.to_owned(), bams.to_owned());
 
         if self.options.is_none() {
             self = self.options(HashMap::new());
@@ -167,7 +169,7 @@ impl TestcaseBuilder {
         {
             *reference = "?".into();
             *candidates = "?".into();
-            *bam = "?".into();
+            *bam = vec!["?".into()];
             *output = Some("?".into());
         } else {
             unreachable!();
@@ -226,7 +228,7 @@ impl Testcase {
                             // METHOD: for breakend events, collect all the other breakends.
                             if breakend_index.is_none() {
                                 breakend_index =
-                                    Some(HaplotypeFeatureIndex::new(&self.candidates)?);
+                                    Some(HaplotypeFeatureIndex::new(&self.candidates, None)?);
                             }
                             let breakend_index = breakend_index.as_ref().unwrap();
                             let last_idx = breakend_index.last_record_index(&event).unwrap();
@@ -273,6 +275,9 @@ impl Testcase {
             Variant::Insertion(ref seq) => {
                 (pos.saturating_sub(1000), pos + seq.len() as u64 + 1000)
             }
+            Variant::MobileElementInsertion { len, .. } => {
+                (pos.saturating_sub(1000), pos + { *len } + 1000)
+            }
             Variant::Snv(_) => (pos.saturating_sub(100), pos + 1 + 100),
             Variant::Mnv(ref bases) => (pos.saturating_sub(100), pos + bases.len() as u64 + 100),
             Variant::Breakend { .. } => {
@@ -284,6 +289,9 @@ impl Testcase {
                 pos.saturating_sub(1000),
                 pos + ref_allele.len() as u64 + 1000,
             ),
+            Variant::ShortTandemRepeat { ref_len, .. } => {
+                (pos.saturating_sub(1000), pos + 1 + { *ref_len } + 1000)
+            }
             Variant::None => (pos.saturating_sub(100), pos + 1 + 100),
         };
         Ok((start, end))
@@ -293,8 +301,8 @@ impl Testcase {
         &self,
         chromosomal_regions: &HashMap<Vec<u8>, (u64, u64)>,
     ) -> Result<HashMap<Vec<u8>, (u64, u64)>> {
-        let mut extended_chromosomal_regions = HashMap::new();
-        for path in self.bams.values() {
+        let mut extended_chromosomal_regions: HashMap<Vec<u8>, (u64, u64)> = HashMap::new();
+        for path in self.bams.values().flatten() {
             let mut bam_reader = bam::IndexedReader::from_path(path)?;
 
             for (chrom_name, (start, end)) in chromosomal_regions.clone() {
@@ -309,7 +317,13 @@ impl Testcase {
                     ref_start = cmp::min((rec.pos() as u64).saturating_sub(seq_len), ref_start);
                     ref_end = cmp::max(rec.cigar().end_pos() as u64 + seq_len, ref_end);
                 }
-                extended_chromosomal_regions.insert(chrom_name.clone(), (ref_start, ref_end));
+                extended_chromosomal_regions
+                    .entry(chrom_name.clone())
+                    .and_modify(|e| {
+                        e.0 = e.0.min(ref_start);
+                        e.1 = e.1.max(ref_end);
+                    })
+                    .or_insert((ref_start, ref_end));
             }
         }
         Ok(extended_chromosomal_regions)
@@ -357,17 +371,37 @@ impl Testcase {
 
         // write bam records
         let mut samples = HashMap::new();
-        for (name, path) in &self.bams {
-            let properties = sample::estimate_alignment_properties(
-                path,
-                false,
-                &mut self.reference_buffer,
-                Some(crate::estimation::alignment_properties::NUM_FRAGMENTS),
-            )?;
+        for (name, paths) in &self.bams {
+            // METHOD: the records of all BAM files of the sample are merged into a single
+            // testcase BAM. If there is more than one file, read names are prefixed with the
+            // index of their file, such that fragments of different files remain distinct,
+            // and records are assigned to a read group per file, such that they are evaluated
+            // with the alignment properties of their file.
+            let mut properties = Vec::with_capacity(paths.len());
+            for (file, path) in paths.iter().enumerate() {
+                properties.push((
+                    file.to_string(),
+                    sample::estimate_alignment_properties(
+                        path,
+                        false,
+                        ReadType::Short,
+                        &mut self.reference_buffer,
+                        Some(crate::estimation::alignment_properties::NUM_FRAGMENTS),
+                    )?,
+                ));
+            }
+            let properties = if properties.len() > 1 {
+                AlignmentProperties::merge_files(properties)
+            } else {
+                properties.remove(0).1
+            };
 
             let filename = Path::new(name).with_extension("bam");
 
-            let mut bam_reader = bam::IndexedReader::from_path(path)?;
+            let mut bam_readers = paths
+                .iter()
+                .map(bam::IndexedReader::from_path)
+                .collect::<std::result::Result<Vec<_>, _>>()?;
 
             // TODO: create header with just the modified sequence
             // let mut header = bam::header::Header::new();
@@ -377,32 +411,53 @@ impl Testcase {
             //         .push_tag(b"LN", &format!("{}", ref_end - ref_start)),
             // );
 
-            let header = bam::header::Header::from_template(bam_reader.header());
+            let mut header = bam::header::Header::from_template(bam_readers[0].header());
+            if paths.len() > 1 {
+                for file in 0..paths.len() {
+                    let mut read_group = bam::header::HeaderRecord::new(b"RG");
+                    read_group.push_tag(b"ID", file);
+                    header.push_record(&read_group);
+                }
+            }
 
             let mut bam_writer =
                 bam::Writer::from_path(self.prefix.join(&filename), &header, bam::Format::Bam)?;
             for (chrom, (start, end)) in chromosomal_regions.clone() {
-                let tid: u32 = bam_reader.header().tid(&chrom).unwrap();
-                bam_reader.fetch((tid, start, end))?;
                 let (ref_start, _) = extended_chromosomal_regions.get(&chrom).unwrap().to_owned();
-                for res in bam_reader.records() {
-                    let mut rec = res?;
-                    // update mapping position to interval
-                    rec.set_pos(rec.pos() - ref_start as i64);
-                    let mtid = bam_writer.header().tid2name(rec.mtid() as u32);
-                    let ref_start_mate = if mtid == b"=" {
-                        ref_start
-                    } else if let Some(chrom_region) = extended_chromosomal_regions.get(mtid) {
-                        chrom_region.0
-                    } else {
-                        //TODO mate records not being on a candidate chromosome are being ignored by setting offset to 0
-                        0
-                    };
-                    rec.set_mpos(rec.mpos() - ref_start_mate as i64);
-                    rec.set_tid(bam_writer.header().tid(&chrom).unwrap() as i32);
-                    if rec.remove_aux(b"RG").is_err() {
-                        debug!("No RG tag to remove in BAM record.");
+                let mut records = Vec::new();
+                for (file, bam_reader) in bam_readers.iter_mut().enumerate() {
+                    let reader_header = bam_reader.header().clone();
+                    let tid: u32 = reader_header.tid(&chrom).unwrap();
+                    bam_reader.fetch((tid, start, end))?;
+                    for res in bam_reader.records() {
+                        let mut rec = res?;
+                        // update mapping position to interval
+                        rec.set_pos(rec.pos() - ref_start as i64);
+                        let mtid = reader_header.tid2name(rec.mtid() as u32);
+                        let ref_start_mate = if mtid == b"=" {
+                            ref_start
+                        } else if let Some(chrom_region) = extended_chromosomal_regions.get(mtid) {
+                            chrom_region.0
+                        } else {
+                            //TODO mate records not being on a candidate chromosome are being ignored by setting offset to 0
+                            0
+                        };
+                        rec.set_mpos(rec.mpos() - ref_start_mate as i64);
+                        rec.set_tid(bam_writer.header().tid(&chrom).unwrap() as i32);
+                        if rec.remove_aux(b"RG").is_err() {
+                            debug!("No RG tag to remove in BAM record.");
+                        }
+                        if paths.len() > 1 {
+                            let qname = [format!("{}:", file).as_bytes(), rec.qname()].concat();
+                            rec.set_qname(&qname);
+                            rec.push_aux(b"RG", Aux::String(&file.to_string()))?;
+                        }
+                        records.push(rec);
                     }
+                }
+                // keep the merged records sorted by position
+                records.sort_by_key(|rec| rec.pos());
+                for mut rec in records {
                     if self.anonymize {
                         anonymizer.anonymize_bam_record(&mut rec);
                     }
@@ -145,6 +145,14 @@ pub trait Testcase {
         }
     }
 
+    fn omit_damage_bias(&self) -> bool {
+        if self.yaml()["omit_damage_bias"].is_badvalue() {
+            false
+        } else {
+            self.yaml()["omit_damage_bias"].as_bool().unwrap()
+        }
+    }
+
     fn yaml(&self) -> &Yaml {
         &self.inner()[0]
     }
@@ -241,11 +249,11 @@ pub trait Testcase {
                         self.alignment_properties(&self.sample_alignment_properties(sample_name))?;
 
                     // replace options
-                    *bam = test_bam;
+                    *bam = vec![test_bam];
                     *reference = PathBuf::from((*temp_ref).as_ref());
                     *candidates = self.candidates();
                     *output = Some(self.sample_preprocessed_path(sample_name, &temp_preprocess));
-                    *alignment_properties = Some(props.path().to_owned());
+                    *alignment_properties = vec![props.path().to_owned()];
                     *output_raw_observations = Some(self.sample_observations_path(sample_name));
                     *pairhmm_mode = pairhmm_mode_override.into();
                     run(options)?;
@@ -269,6 +277,10 @@ pub trait Testcase {
                         omit_homopolymer_artifact_detection: self
                             .omit_homopolymer_artifact_detection(),
                         omit_alt_locus_bias: self.omit_alt_locus_bias(),
+                        omit_damage_bias: self.omit_damage_bias(),
+                        fragment_length_models: Vec::new(),
+                        phase: false,
+                        phasing_max_prob_absent: 0.05,
                         output: Some(self.output()),
                         propagate_info_fields: Vec::new(),
                         mode: VariantCallMode::Generic {
@@ -292,6 +304,9 @@ pub trait Testcase {
                         },
                         log_mode: "default".to_owned(),
                         full_prior: false,
+                        threads: 1,
+                        regions: Vec::new(),
+                        regions_bed: None,
                     },
                 };
 
@@ -310,6 +325,10 @@ pub trait Testcase {
                         omit_homopolymer_artifact_detection: self
                             .omit_homopolymer_artifact_detection(),
                         omit_alt_locus_bias: self.omit_alt_locus_bias(),
+                        omit_damage_bias: self.omit_damage_bias(),
+                        fragment_length_models: Vec::new(),
+                        phase: false,
+                        phasing_max_prob_absent: 0.05,
                         output: Some(self.output()),
                         propagate_info_fields: Vec::new(),
                         mode: VariantCallMode::TumorNormal {
@@ -321,6 +340,9 @@ pub trait Testcase {
                         },
                         log_mode: "default".to_owned(),
                         full_prior: false,
+                        threads: 1,
+                        regions: Vec::new(),
+                        regions_bed: None,
                     },
                 };
 
@@ -89,20 +89,30 @@ impl Testcase for TestcaseVersion0 {
                         realignment_window: indel_window as u64,
                         max_depth,
                         // The rest will be overwritten.
-                        alignment_properties: None,
-                        bam: PathBuf::from("dummy"),
+                        alignment_properties: Vec::new(),
+                        bam: vec![PathBuf::from("dummy")],
                         report_fragment_ids: true,
+                        report_fragment_lengths: false,
                         omit_mapq_adjustment: false,
+                        umi_families: false,
+                        alt_hit_format: None,
+                        mobile_element_library: None,
+                        germline_haplotypes: None,
                         candidates: self.candidates(),
                         output: None,
                         propagate_info_fields: Vec::new(),
                         omit_insert_size: false,
+                        rna: false,
+                        read_type: None,
                         reference_buffer_size: 10,
                         min_bam_refetch_distance: 1,
                         log_mode: "default".to_owned(),
                         pairhmm_mode: "exact".to_owned(),
                         output_raw_observations: None,
                         atomic_candidate_variants: false,
+                        threads: 1,
+                        regions: Vec::new(),
+                        regions_bed: None,
                     },
                 };
 
@@ -2,7 +2,6 @@ use std::collections::{HashMap, HashSet};
 
 use anyhow::Result;
 use linear_map::LinearMap;
-use rust_htslib::bcf::Read;
 use rust_htslib::bcf::{self, header::TagType};
 
 #[derive(Debug, Clone, Default)]
@@ -55,9 +54,7 @@ pub(crate) struct AuxInfoCollector {
 }
 
 impl AuxInfoCollector {
-    pub(crate) fn new(fields: &[Vec<u8>], bcf_reader: &bcf::Reader) -> Result<Self> {
-        let header = bcf_reader.header();
-
+    pub(crate) fn new(fields: &[Vec<u8>], header: &bcf::header::HeaderView) -> Result<Self> {
         let fields = fields
             .iter()
             .map(|field| Ok((field.to_owned(), header.info_type(field)?.0)))
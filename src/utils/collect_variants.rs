@@ -2,6 +2,7 @@ use std::convert::TryFrom;
 
 use anyhow::Result;
 use bio_types::genome::AbstractLocus;
+use bio_types::strand::Strand;
 use itertools::Itertools;
 use rust_htslib::{bcf, bcf::record::Numeric};
 
@@ -13,7 +14,9 @@ use crate::variants::model::{self, HaplotypeIdentifier, VariantPrecision};
     Hash, PartialEq, Eq, EnumString, EnumIter, IntoStaticStr, EnumVariantNames, Display, Debug,
 )]
 pub enum SkipReason {
-    #[strum(serialize = "imprecise variants (will be supported in a future release)")]
+    #[strum(
+        serialize = "imprecise variants other than deletions, inversions, duplications and breakends"
+    )]
     Imprecise,
     #[strum(serialize = "inversions with missing END tag")]
     InversionMissingEndTag,
@@ -23,8 +26,12 @@ pub enum SkipReason {
     InversionInvalidAlt,
     #[strum(serialize = "duplication with more than a single <DUP> allele")]
     DuplicationInvalidAlt,
-    #[strum(serialize = "breakend without EVENT tag (will be supported in a future release)")]
-    BreakendNoEvent,
+    #[strum(serialize = "breakend joined to another locus but without EVENT or MATEID tag")]
+    BreakendNoMate,
+    #[strum(serialize = "short tandem repeat alleles with missing RU or END tag")]
+    ShortTandemRepeatMissingUnit,
+    #[strum(serialize = "mobile element insertions with missing SVLEN and MEINFO tag")]
+    MobileElementInsertionMissingLength,
 }
 
 #[derive(Debug, Getters, Clone)]
@@ -32,13 +39,15 @@ pub enum SkipReason {
 pub struct VariantInfo {
     pub(crate) variant: model::Variant,
     pub(crate) haplotype: Option<HaplotypeIdentifier>,
+    /// Confidence intervals of the breakpoints of imprecise structural variants.
+    pub(crate) precision: VariantPrecision,
 }
 
 /// Collect variants from a given ´bcf::Record`.
 pub fn collect_variants(
     record: &mut bcf::Record,
     skip_imprecise: bool,
-    skips: Option<&mut SimpleCounter<SkipReason>>,
+    mut skips: Option<&mut SimpleCounter<SkipReason>>,
 ) -> Result<Vec<VariantInfo>> {
     // TODO ignore imprecise variants for now?
     // let nonzero_bounds = |tag| {
@@ -53,8 +62,8 @@ pub fn collect_variants(
 
     let imprecise = record.info(b"IMPRECISE").flag().ok().unwrap_or(false);
 
-    let skip_incr = |reason| {
-        if let Some(skips) = skips {
+    let mut skip_incr = |reason| {
+        if let Some(skips) = skips.as_mut() {
             skips.incr(reason);
         }
     };
@@ -87,6 +96,26 @@ pub fn collect_variants(
         Ok(Some(svtype)) => Some(svtype[0].to_owned()),
         _ => None,
     };
+    let repeat_unit = match record.info(b"RU").string() {
+        Ok(Some(unit)) if !unit[0].is_empty() => Some(unit[0].to_ascii_uppercase()),
+        _ => None,
+    };
+    let (mobile_element_len, mobile_element_strand) = match record.info(b"MEINFO").string() {
+        Ok(Some(info)) => parse_mobile_element_info(&info),
+        _ => (None, Strand::Unknown),
+    };
+    // METHOD: the inserted length of a mobile element is given by SVLEN or, if missing, by the
+    // part of the consensus sequence that is reported in MEINFO (e.g. for 5' truncated
+    // insertions).
+    let mobile_element_insertion = |element, svlen: Option<u64>| {
+        svlen
+            .or(mobile_element_len)
+            .map(|len| model::Variant::MobileElementInsertion {
+                element,
+                len,
+                strand: mobile_element_strand,
+            })
+    };
 
     let is_valid_insertion_alleles = |ref_allele: &[u8], alt_allele: &[u8]| {
         alt_allele == b"<INS>"
@@ -104,20 +133,32 @@ pub fn collect_variants(
 
     let haplotype = HaplotypeIdentifier::from(record)?;
 
+    // imprecise variants are supported for these SV types, other ones are skipped
+    let is_supported_imprecise = svtype.as_ref().is_some_and(|svtype| {
+        svtype == b"BND" || svtype == b"DEL" || svtype == b"INV" || svtype == b"DUP"
+    });
+
+    if skip_imprecise && imprecise && svtype.is_some() && !is_supported_imprecise {
+        skip_incr(SkipReason::Imprecise);
+        return Ok(Vec::with_capacity(1));
+    }
+
+    let precision = if imprecise && is_supported_imprecise {
+        VariantPrecision::try_from(&*record)?
+    } else {
+        VariantPrecision::Precise
+    };
+
     let mut variants = Vec::new();
 
     let mut push_variant = |variant| {
         variants.push(VariantInfo {
             variant,
             haplotype: haplotype.clone(),
+            precision: precision.clone(),
         })
     };
 
-    if skip_imprecise && imprecise && svtype.as_ref().map_or(false, |svtype| svtype != b"BND") {
-        skip_incr(SkipReason::Imprecise);
-        return Ok(Vec::with_capacity(1));
-    }
-
     if let Some(svtype) = svtype {
         if svtype == b"INV" {
             let alleles = record.alleles();
@@ -141,7 +182,13 @@ pub fn collect_variants(
             }
         } else if svtype == b"BND" {
             let alleles = record.alleles();
-            if haplotype.is_some() {
+            // single breakends (e.g. .A or A.) are not joined to any other locus
+            let is_single_breakend =
+                |spec: &[u8]| spec.first() == Some(&b'.') || spec.last() == Some(&b'.');
+            // METHOD: breakends are grouped via their EVENT tag or, if missing, via
+            // their MATEID (see HaplotypeIdentifier). Without both, only single
+            // breakends can be evaluated, since they do not depend on any other record.
+            if haplotype.is_some() || alleles[1..].iter().all(|spec| is_single_breakend(spec)) {
                 for spec in &alleles[1..] {
                     let rec: &bcf::Record = &*record;
                     push_variant(model::Variant::Breakend {
@@ -151,7 +198,7 @@ pub fn collect_variants(
                     })
                 }
             } else {
-                skip_incr(SkipReason::BreakendNoEvent);
+                skip_incr(SkipReason::BreakendNoMate);
             }
         } else if svtype == b"INS" {
             // get sequence
@@ -167,7 +214,14 @@ pub fn collect_variants(
             let ref_allele = alleles[0];
             let alt_allele = alleles[1];
 
-            if alt_allele != b"<INS>" {
+            if let Some(element) = parse_mobile_element_allele(alt_allele) {
+                let svlen = svlens.as_ref().and_then(|svlens| svlens[0]);
+                if let Some(variant) = mobile_element_insertion(element, svlen) {
+                    push_variant(variant);
+                } else {
+                    skip_incr(SkipReason::MobileElementInsertionMissingLength);
+                }
+            } else if alt_allele != b"<INS>" {
                 // don't support insertions without exact sequence
                 if is_valid_insertion_alleles(ref_allele, alt_allele) {
                     push_variant(model::Variant::Insertion(
@@ -213,6 +267,8 @@ pub fn collect_variants(
     } else {
         let alleles = record.alleles();
         let ref_allele = alleles[0];
+        let mut missing_repeat_unit = false;
+        let mut missing_mobile_element_len = false;
 
         for (i, alt_allele) in alleles.iter().skip(1).enumerate() {
             if alt_allele == b"<*>" {
@@ -225,6 +281,30 @@ pub fn collect_variants(
                     }
                     // TODO fail with an error in else case
                 }
+            } else if let Some(element) = parse_mobile_element_allele(alt_allele) {
+                let svlen = svlens.as_ref().and_then(|svlens| svlens[i]);
+                if let Some(variant) = mobile_element_insertion(element, svlen) {
+                    push_variant(variant);
+                } else {
+                    missing_mobile_element_len = true;
+                }
+            } else if let Some(count) = parse_short_tandem_repeat_allele(alt_allele) {
+                // ExpansionHunter style repeat allele: POS is pointing to the base before
+                // the repeat, END to the last base of the repeat.
+                match (&repeat_unit, end) {
+                    (Some(unit), Some(end)) if end > pos => {
+                        let ref_len = end - pos;
+                        // alleles of reference length do not alter the repeat
+                        if count as u64 * unit.len() as u64 != ref_len {
+                            push_variant(model::Variant::ShortTandemRepeat {
+                                unit: unit.to_owned(),
+                                ref_len,
+                                count,
+                            });
+                        }
+                    }
+                    _ => missing_repeat_unit = true,
+                }
             } else if alt_allele[0] == b'<' {
                 // skip any other special alleles
             } else if alt_allele.len() == 1 && ref_allele.len() == 1 {
@@ -249,7 +329,216 @@ pub fn collect_variants(
                 });
             }
         }
+        if missing_repeat_unit {
+            skip_incr(SkipReason::ShortTandemRepeatMissingUnit);
+        }
+        if missing_mobile_element_len {
+            skip_incr(SkipReason::MobileElementInsertionMissingLength);
+        }
     }
 
     Ok(variants)
 }
+
+/// Parse the mobile element name of a symbolic insertion allele like `<INS:ME:ALU>`.
+fn parse_mobile_element_allele(alt_allele: &[u8]) -> Option<Vec<u8>> {
+    alt_allele
+        .strip_prefix(b"<INS:ME:")
+        .and_then(|element| element.strip_suffix(b">"))
+        .filter(|element| !element.is_empty())
+        .map(|element| element.to_owned())
+}
+
+/// Parse the inserted length and the strand of a mobile element from the items of a MEINFO
+/// tag (NAME,START,END,POLARITY), with START and END being 1-based inclusive positions in
+/// the consensus of the element.
+fn parse_mobile_element_info(info: &[&[u8]]) -> (Option<u64>, Strand) {
+    if info.len() != 4 {
+        return (None, Strand::Unknown);
+    }
+    let coord = |item: &[u8]| {
+        std::str::from_utf8(item)
+            .ok()
+            .and_then(|coord| coord.parse::<u64>().ok())
+    };
+    let len = match (coord(info[1]), coord(info[2])) {
+        (Some(start), Some(end)) if end >= start => Some(end - start + 1),
+        _ => None,
+    };
+    let strand = match info[3] {
+        b"+" => Strand::Forward,
+        b"-" => Strand::Reverse,
+        _ => Strand::Unknown,
+    };
+    (len, strand)
+}
+
+/// Parse the repeat count of a symbolic short tandem repeat allele like `<STR12>`.
+fn parse_short_tandem_repeat_allele(alt_allele: &[u8]) -> Option<u32> {
+    alt_allele
+        .strip_prefix(b"<STR")
+        .and_then(|count| count.strip_suffix(b">"))
+        .and_then(|count| std::str::from_utf8(count).ok())
+        .and_then(|count| count.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bcf::{self, Read};
+
+    #[test]
+    fn test_collect_breakends_without_event() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("breakends.bcf");
+        let mut header = bcf::Header::new();
+        header.push_record(b"##contig=<ID=chr1,length=1000>");
+        header.push_record(b"##INFO=<ID=SVTYPE,Number=1,Type=String,Description=\"\">");
+        header.push_record(b"##INFO=<ID=MATEID,Number=.,Type=String,Description=\"\">");
+        {
+            let mut writer =
+                bcf::Writer::from_path(&path, &header, true, bcf::Format::Bcf).unwrap();
+            for (pos, id, alt, mateid) in [
+                (
+                    10,
+                    b"bnd1".as_slice(),
+                    b"A[chr1:500[".as_slice(),
+                    Some(b"bnd2"),
+                ),
+                (499, b"bnd2", b"]chr1:11]C", Some(b"bnd1")),
+                (600, b"bnd3", b"G.", None),
+                (700, b"bnd4", b"T[chr1:800[", None),
+            ] {
+                let mut record = writer.empty_record();
+                record.set_rid(Some(0));
+                record.set_pos(pos);
+                record.set_id(id).unwrap();
+                record.set_alleles(&[&alt[..1], alt]).unwrap();
+                record.push_info_string(b"SVTYPE", &[b"BND"]).unwrap();
+                if let Some(mateid) = mateid {
+                    record.push_info_string(b"MATEID", &[mateid]).unwrap();
+                }
+                writer.write(&record).unwrap();
+            }
+        }
+
+        let mut reader = bcf::Reader::from_path(&path).unwrap();
+        let mut skips = SimpleCounter::default();
+        let variants = reader
+            .records()
+            .map(|record| collect_variants(&mut record.unwrap(), false, Some(&mut skips)).unwrap())
+            .collect_vec();
+
+        // mates are grouped via their MATEID
+        let event = Some(HaplotypeIdentifier::Event(b"bnd1-bnd2".to_vec()));
+        assert_eq!(variants[0][0].haplotype, event);
+        assert_eq!(variants[1][0].haplotype, event);
+        // single breakends do not need to be grouped
+        assert_eq!(variants[2].len(), 1);
+        assert_eq!(variants[2][0].haplotype, None);
+        // joined breakends without mate cannot be evaluated
+        assert!(variants[3].is_empty());
+        assert_eq!(skips.get(&SkipReason::BreakendNoMate), Some(&1));
+    }
+
+    #[test]
+    fn test_collect_short_tandem_repeats() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("strs.bcf");
+        let mut header = bcf::Header::new();
+        header.push_record(b"##contig=<ID=chr1,length=1000>");
+        header.push_record(b"##INFO=<ID=RU,Number=1,Type=String,Description=\"\">");
+        header.push_record(b"##INFO=<ID=END,Number=1,Type=Integer,Description=\"\">");
+        {
+            let mut writer =
+                bcf::Writer::from_path(&path, &header, true, bcf::Format::Bcf).unwrap();
+            for (pos, alleles, unit) in [
+                (
+                    99,
+                    [b"A".as_slice(), b"<STR5>".as_slice(), b"<STR12>".as_slice()],
+                    Some(b"cag"),
+                ),
+                (199, [b"T", b"<STR3>", b"<STR7>"], None),
+            ] {
+                let mut record = writer.empty_record();
+                record.set_rid(Some(0));
+                record.set_pos(pos);
+                record.set_alleles(&alleles).unwrap();
+                // repeat tract of 7 units (1-based inclusive end)
+                record
+                    .push_info_integer(b"END", &[pos as i32 + 21])
+                    .unwrap();
+                if let Some(unit) = unit {
+                    record.push_info_string(b"RU", &[unit]).unwrap();
+                }
+                writer.write(&record).unwrap();
+            }
+        }
+
+        let mut reader = bcf::Reader::from_path(&path).unwrap();
+        let mut skips = SimpleCounter::default();
+        let variants = reader
+            .records()
+            .map(|record| collect_variants(&mut record.unwrap(), false, Some(&mut skips)).unwrap())
+            .collect_vec();
+
+        assert_eq!(variants[0].len(), 2);
+        for (variant, expected_count) in variants[0].iter().zip([5, 12]) {
+            match variant.variant() {
+                model::Variant::ShortTandemRepeat {
+                    unit,
+                    ref_len,
+                    count,
+                } => {
+                    assert_eq!(unit, b"CAG");
+                    assert_eq!(*ref_len, 21);
+                    assert_eq!(*count, expected_count);
+                }
+                _ => panic!("expected short tandem repeat"),
+            }
+        }
+        // repeats without unit cannot be evaluated
+        assert!(variants[1].is_empty());
+        assert_eq!(
+            skips.get(&SkipReason::ShortTandemRepeatMissingUnit),
+            Some(&1)
+        );
+        assert_eq!(parse_short_tandem_repeat_allele(b"<STR0>"), Some(0));
+        assert_eq!(parse_short_tandem_repeat_allele(b"<STR>"), None);
+    }
+
+    #[test]
+    fn test_parse_mobile_element_allele() {
+        assert_eq!(
+            parse_mobile_element_allele(b"<INS:ME:ALU>"),
+            Some(b"ALU".to_vec())
+        );
+        assert_eq!(
+            parse_mobile_element_allele(b"<INS:ME:L1:HS>"),
+            Some(b"L1:HS".to_vec())
+        );
+        assert_eq!(parse_mobile_element_allele(b"<INS:ME:>"), None);
+        assert_eq!(parse_mobile_element_allele(b"<INS>"), None);
+    }
+
+    #[test]
+    fn test_parse_mobile_element_info() {
+        assert_eq!(
+            parse_mobile_element_info(&[b"AluYa5", b"1", b"281", b"+"]),
+            (Some(281), Strand::Forward)
+        );
+        // 5' truncated L1
+        assert_eq!(
+            parse_mobile_element_info(&[b"L1HS", b"5200", b"6019", b"-"]),
+            (Some(820), Strand::Reverse)
+        );
+        assert_eq!(
+            parse_mobile_element_info(&[b"SVA", b"NULL", b"NULL", b"NULL"]),
+            (None, Strand::Unknown)
+        );
+        assert_eq!(
+            parse_mobile_element_info(&[b"ALU"]),
+            (None, Strand::Unknown)
+        );
+    }
+}
@@ -34,6 +34,7 @@ pub mod collect_variants;
 pub(crate) mod comparison;
 pub(crate) mod homopolymers;
 pub(crate) mod log2_fold_change;
+pub(crate) mod regions;
 pub(crate) mod variant_buffer;
 
 pub(crate) use collect_variants::collect_variants;
@@ -73,8 +74,8 @@ pub(crate) fn aux_tag_is_entire_fragment(record: &bam::Record) -> bool {
 /// Checks whether the given BCF contains fields required for evaluating haplotypes.
 /// Currently, this means that the EVENT or the MATEID field has to be defined in the
 /// header.
-pub(crate) fn is_haplotype_bcf(reader: &bcf::Reader) -> bool {
-    for rec in reader.header().header_records() {
+pub(crate) fn is_haplotype_bcf(header: &bcf::header::HeaderView) -> bool {
+    for rec in header.header_records() {
         if let bcf::header::HeaderRecord::Info { values, .. } = rec {
             if values
                 .get("ID")
@@ -102,6 +103,24 @@ pub(crate) fn info_tag_event(record: &mut bcf::Record) -> Result<Option<Vec<u8>>
     Ok(record.info(b"EVENT").string()?.map(|v| v[0].to_owned()))
 }
 
+/// Repeat unit of a short tandem repeat (RU). The tag is not defined in observations
+/// written by older versions.
+pub(crate) fn info_tag_repeat_unit(record: &mut bcf::Record) -> Result<Option<Vec<u8>>> {
+    if record.header().info_type(b"RU").is_err() {
+        return Ok(None);
+    }
+    Ok(record.info(b"RU").string()?.map(|v| v[0].to_owned()))
+}
+
+/// Number of ALT alleles at the site of the record (SITEALTS). The tag is not defined in
+/// observations written by older versions.
+pub(crate) fn info_tag_site_alts(record: &mut bcf::Record) -> Result<Option<u32>> {
+    if record.header().info_type(b"SITEALTS").is_err() {
+        return Ok(None);
+    }
+    Ok(record.info(b"SITEALTS").integer()?.map(|v| v[0] as u32))
+}
+
 pub(crate) fn info_tag_mateid(record: &mut bcf::Record) -> Result<Option<Vec<u8>>> {
     // TODO support multiple mateids (in case of uncertainty, see spec)
     Ok(record.info(b"MATEID").string()?.map(|v| v[0].to_owned()))
@@ -0,0 +1,364 @@
+// Copyright 2024 Johannes Köster.
+// Licensed under the GNU GPLv3 license (https://opensource.org/licenses/GPL-3.0)
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Restriction of candidate and observation BCFs to genomic regions.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bio::io::bed;
+use bio_types::genome::AbstractLocus;
+use itertools::Itertools;
+use rust_htslib::bcf::{self, header::HeaderView, Read};
+
+use crate::errors;
+use crate::utils;
+use crate::variants::model::HaplotypeIdentifier;
+
+/// A set of genomic regions, given as 0-based half-open intervals per contig.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Regions {
+    intervals: BTreeMap<String, Vec<Range<u64>>>,
+}
+
+impl Regions {
+    /// Build regions from the given region specifications (`chr`, `chr:start` or
+    /// `chr:start-end`, 1-based and inclusive) and BED file. Returns `None` if neither
+    /// is given.
+    pub(crate) fn from_args(
+        regions: &[String],
+        regions_bed: Option<&Path>,
+    ) -> Result<Option<Self>> {
+        if regions.is_empty() && regions_bed.is_none() {
+            return Ok(None);
+        }
+
+        let mut intervals: BTreeMap<String, Vec<Range<u64>>> = BTreeMap::new();
+        for region in regions {
+            let (contig, range) = Self::parse_region(region)?;
+            intervals.entry(contig).or_default().push(range);
+        }
+        if let Some(path) = regions_bed {
+            let mut reader = bed::Reader::from_file(path)?;
+            for record in reader.records() {
+                let record = record?;
+                if record.start() >= record.end() {
+                    return Err(errors::Error::InvalidRegion {
+                        region: format!("{}:{}-{}", record.chrom(), record.start(), record.end()),
+                    }
+                    .into());
+                }
+                intervals
+                    .entry(record.chrom().to_owned())
+                    .or_default()
+                    .push(record.start()..record.end());
+            }
+        }
+
+        // merge overlapping and adjacent intervals such that each record is visited only once
+        for ranges in intervals.values_mut() {
+            ranges.sort_unstable_by_key(|range| range.start);
+            let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+            for range in ranges.drain(..) {
+                match merged.last_mut() {
+                    Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                    _ => merged.push(range),
+                }
+            }
+            *ranges = merged;
+        }
+
+        Ok(Some(Regions { intervals }))
+    }
+
+    fn parse_region(region: &str) -> Result<(String, Range<u64>)> {
+        let invalid = || errors::Error::InvalidRegion {
+            region: region.to_owned(),
+        };
+
+        let (contig, range) = match region.rsplit_once(':') {
+            Some((contig, range)) => (contig, Some(range)),
+            None => (region, None),
+        };
+        if contig.is_empty() {
+            return Err(invalid().into());
+        }
+
+        let range = if let Some(range) = range {
+            let parse_pos = |pos: &str| pos.replace(',', "").parse::<u64>().map_err(|_| invalid());
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (parse_pos(start)?, parse_pos(end)?),
+                None => {
+                    let start = parse_pos(range)?;
+                    (start, u64::MAX)
+                }
+            };
+            if start == 0 || end < start {
+                return Err(invalid().into());
+            }
+            start - 1..end
+        } else {
+            0..u64::MAX
+        };
+
+        Ok((contig.to_owned(), range))
+    }
+
+    /// Check whether the given 0-based position on the given contig is contained in the regions.
+    pub(crate) fn contains(&self, contig: &str, pos: u64) -> bool {
+        self.intervals.get(contig).is_some_and(|ranges| {
+            let idx = ranges.partition_point(|range| range.end <= pos);
+            idx < ranges.len() && ranges[idx].start <= pos
+        })
+    }
+
+    /// Intervals as (rid, range) pairs in the contig order of the given header.
+    /// Contigs that are not contained in the header are omitted.
+    fn header_intervals(&self, header: &HeaderView) -> VecDeque<(u32, Range<u64>)> {
+        self.intervals
+            .iter()
+            .filter_map(|(contig, ranges)| {
+                header
+                    .name2rid(contig.as_bytes())
+                    .ok()
+                    .map(|rid| (rid, ranges))
+            })
+            .sorted_unstable_by_key(|(rid, _)| *rid)
+            .flat_map(|(rid, ranges)| ranges.iter().map(move |range| (rid, range.clone())))
+            .collect()
+    }
+}
+
+/// Selection of the records of a BCF file that shall be processed in a run restricted to regions.
+///
+/// Records of haplotypes (breakend events or haplotype blocks) are owned by the run whose
+/// regions contain the first record of the haplotype. Such a run processes all records of
+/// the haplotype, also those outside of its regions, while all other runs skip them entirely.
+/// This way, no haplotype is processed twice or dropped when scattering over disjoint regions.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordSelection {
+    regions: Regions,
+    owned_haplotypes: Option<HashMap<HaplotypeIdentifier, bool>>,
+}
+
+impl RecordSelection {
+    /// Create a selection for the given BCF. For BCFs that contain haplotypes, this scans the
+    /// whole file in order to determine which haplotypes are owned by the given regions.
+    pub(crate) fn new<P: AsRef<Path>>(inbcf: P, regions: Regions) -> Result<Self> {
+        let mut bcf_reader = bcf::Reader::from_path(inbcf)?;
+        if !utils::is_haplotype_bcf(bcf_reader.header()) {
+            return Ok(RecordSelection {
+                regions,
+                owned_haplotypes: None,
+            });
+        }
+
+        let mut owned_haplotypes = HashMap::new();
+        let mut record = bcf_reader.empty_record();
+        while let Some(res) = bcf_reader.read(&mut record) {
+            res?;
+            if let Some(identifier) = HaplotypeIdentifier::from(&mut record)? {
+                owned_haplotypes
+                    .entry(identifier)
+                    .or_insert_with(|| regions.contains(record.contig(), record.pos() as u64));
+            }
+        }
+
+        Ok(RecordSelection {
+            regions,
+            owned_haplotypes: Some(owned_haplotypes),
+        })
+    }
+
+    /// Check whether the given record shall be processed.
+    pub(crate) fn is_selected(&self, record: &mut bcf::Record) -> Result<bool> {
+        if let Some(ref owned_haplotypes) = self.owned_haplotypes {
+            if let Some(identifier) = HaplotypeIdentifier::from(record)? {
+                return Ok(owned_haplotypes.get(&identifier).cloned().unwrap_or(false));
+            }
+        }
+        Ok(self.regions.contains(record.contig(), record.pos() as u64))
+    }
+}
+
+/// Reader over the records of a BCF file, optionally restricted to a record selection.
+///
+/// If the BCF is indexed and does not contain haplotypes, selected regions are fetched
+/// via the index. Otherwise, the whole file is streamed and unselected records are skipped.
+/// Alternatively, the reader can be restricted to explicit intervals of an indexed BCF
+/// (see [`RecordReader::from_path_intervals`]).
+pub(crate) enum RecordReader {
+    Full(bcf::Reader),
+    Filtered {
+        reader: bcf::Reader,
+        selection: Arc<RecordSelection>,
+    },
+    Indexed {
+        reader: bcf::IndexedReader,
+        intervals: VecDeque<(u32, Range<u64>)>,
+        current_start: Option<u64>,
+        selection: Option<Arc<RecordSelection>>,
+    },
+}
+
+impl RecordReader {
+    pub(crate) fn from_path<P: AsRef<Path>>(
+        path: P,
+        selection: Option<&Arc<RecordSelection>>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        Ok(match selection {
+            None => RecordReader::Full(bcf::Reader::from_path(path)?),
+            Some(selection) => {
+                let indexed_reader = if selection.owned_haplotypes.is_none() {
+                    bcf::IndexedReader::from_path(path).ok()
+                } else {
+                    None
+                };
+                if let Some(reader) = indexed_reader {
+                    let intervals = selection.regions.header_intervals(reader.header());
+                    RecordReader::Indexed {
+                        reader,
+                        intervals,
+                        current_start: None,
+                        selection: None,
+                    }
+                } else {
+                    RecordReader::Filtered {
+                        reader: bcf::Reader::from_path(path)?,
+                        selection: Arc::clone(selection),
+                    }
+                }
+            }
+        })
+    }
+
+    /// Read the records starting in the given intervals (visited in the given order) via the
+    /// index of the given BCF, skipping all records that are not part of the selection, if any.
+    pub(crate) fn from_path_intervals<P: AsRef<Path>>(
+        path: P,
+        selection: Option<&Arc<RecordSelection>>,
+        intervals: Vec<(u32, Range<u64>)>,
+    ) -> Result<Self> {
+        Ok(RecordReader::Indexed {
+            reader: bcf::IndexedReader::from_path(path)?,
+            intervals: intervals.into(),
+            current_start: None,
+            selection: selection.map(Arc::clone),
+        })
+    }
+
+    pub(crate) fn header(&self) -> &HeaderView {
+        match self {
+            RecordReader::Full(reader) => reader.header(),
+            RecordReader::Filtered { reader, .. } => reader.header(),
+            RecordReader::Indexed { reader, .. } => reader.header(),
+        }
+    }
+
+    pub(crate) fn empty_record(&self) -> bcf::Record {
+        match self {
+            RecordReader::Full(reader) => reader.empty_record(),
+            RecordReader::Filtered { reader, .. } => reader.empty_record(),
+            RecordReader::Indexed { reader, .. } => reader.empty_record(),
+        }
+    }
+
+    pub(crate) fn set_threads(&mut self, n_threads: usize) -> Result<()> {
+        match self {
+            RecordReader::Full(reader) => reader.set_threads(n_threads)?,
+            RecordReader::Filtered { reader, .. } => reader.set_threads(n_threads)?,
+            RecordReader::Indexed { reader, .. } => reader.set_threads(n_threads)?,
+        }
+        Ok(())
+    }
+
+    /// Read the next selected record. Returns None at the end of the file or the last region.
+    pub(crate) fn read(&mut self, record: &mut bcf::Record) -> Option<Result<()>> {
+        match self {
+            RecordReader::Full(reader) => reader.read(record).map(|res| Ok(res?)),
+            RecordReader::Filtered { reader, selection } => loop {
+                match reader.read(record)? {
+                    Ok(()) => match selection.is_selected(record) {
+                        Ok(true) => return Some(Ok(())),
+                        Ok(false) => continue,
+                        Err(e) => return Some(Err(e)),
+                    },
+                    Err(e) => return Some(Err(e.into())),
+                }
+            },
+            RecordReader::Indexed {
+                reader,
+                intervals,
+                current_start,
+                selection,
+            } => loop {
+                if let Some(start) = *current_start {
+                    match reader.read(record) {
+                        Some(Ok(())) => {
+                            // Skip records that start before the region. They overlap it but
+                            // belong to a previous region or to none at all.
+                            if (record.pos() as u64) >= start {
+                                match selection.as_ref().map(|s| s.is_selected(record)) {
+                                    None | Some(Ok(true)) => return Some(Ok(())),
+                                    Some(Ok(false)) => continue,
+                                    Some(Err(e)) => return Some(Err(e)),
+                                }
+                            }
+                        }
+                        Some(Err(e)) => return Some(Err(e.into())),
+                        None => *current_start = None,
+                    }
+                } else {
+                    let (rid, range) = intervals.pop_front()?;
+                    let end = if range.end == u64::MAX {
+                        None
+                    } else {
+                        Some(range.end - 1)
+                    };
+                    if let Err(e) = reader.fetch(rid, range.start, end) {
+                        return Some(Err(e.into()));
+                    }
+                    *current_start = Some(range.start);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regions(specs: &[&str]) -> Regions {
+        let specs = specs.iter().map(|spec| spec.to_string()).collect_vec();
+        Regions::from_args(&specs, None).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_parse_regions() {
+        let regions = regions(&["chr1:11-20", "chr1:15-30", "chr2", "chr3:1,001"]);
+        assert!(!regions.contains("chr1", 9));
+        assert!(regions.contains("chr1", 10));
+        assert!(regions.contains("chr1", 29));
+        assert!(!regions.contains("chr1", 30));
+        assert!(regions.contains("chr2", 123456789));
+        assert!(!regions.contains("chr3", 999));
+        assert!(regions.contains("chr3", 1000));
+        assert!(!regions.contains("chr4", 0));
+        assert_eq!(regions.intervals["chr1"], vec![10..30]);
+    }
+
+    #[test]
+    fn test_parse_invalid_regions() {
+        for spec in &["chr1:0-10", "chr1:20-10", "chr1:a-b", ":1-10"] {
+            assert!(Regions::from_args(&[spec.to_string()], None).is_err());
+        }
+        assert_eq!(Regions::from_args(&[], None).unwrap(), None);
+    }
+}
@@ -1,7 +1,9 @@
+use std::ops::Range;
+
 use anyhow::Result;
 use bio_types::genome::{AbstractLocus, Locus};
 use progress_logger::ProgressLogger;
-use rust_htslib::bcf::{self, Read};
+use rust_htslib::bcf;
 use vec_map::VecMap;
 
 use crate::{errors, utils};
@@ -9,10 +11,11 @@ use crate::{errors, utils};
 use super::{
     aux_info::{AuxInfo, AuxInfoCollector},
     collect_variants::VariantInfo,
+    regions::RecordReader,
 };
 
 pub(crate) struct VariantBuffer {
-    reader: bcf::Reader,
+    reader: RecordReader,
     variants: Vec<VariantInfo>,
     variant_index: usize,
     record_infos: VecMap<RecordInfo>,
@@ -24,11 +27,12 @@ pub(crate) struct VariantBuffer {
     record_index: isize,
     state: State,
     aux_info_collector: AuxInfoCollector,
+    record_range: Option<Range<usize>>,
 }
 
 impl VariantBuffer {
     pub(crate) fn new(
-        reader: bcf::Reader,
+        reader: RecordReader,
         progress_logger: ProgressLogger,
         log_each_record: bool,
         aux_info_collector: AuxInfoCollector,
@@ -46,9 +50,20 @@ impl VariantBuffer {
             record_index: -1,
             state: State::Init,
             aux_info_collector,
+            record_range: None,
         }
     }
 
+    /// Only yield variants from records with an index in the given range. The reader has
+    /// to start at the first record of the range (e.g. by fetching it via the index), such
+    /// that preceding records need not be read. Record indices remain relative to the start
+    /// of the file.
+    pub(crate) fn with_record_range(mut self, record_range: Range<usize>) -> Self {
+        self.record_index = record_range.start as isize - 1;
+        self.record_range = Some(record_range);
+        self
+    }
+
     pub(crate) fn next(&mut self) -> Result<Option<Variants>> {
         if self.skips.total_count() > 0 && self.skips.total_count() % 100 == 0 {
             self.display_skips();
@@ -132,6 +147,13 @@ impl VariantBuffer {
     }
 
     fn next_record(&mut self) -> Result<()> {
+        if let Some(ref range) = self.record_range {
+            if self.record_index + 1 >= range.end as isize {
+                // end of the requested record range
+                self.current_record = None;
+                return Ok(());
+            }
+        }
         let mut record = self.reader.empty_record();
         match self.reader.read(&mut record) {
             None => {
@@ -0,0 +1,296 @@
+// Copyright 2016-2019 Johannes Köster, David Lähnemann.
+// Licensed under the GNU GPLv3 license (https://opensource.org/licenses/GPL-3.0)
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Parsers for the alternative hits that read mappers report for a record.
+//! They feed the alt locus bias, via the reported alternative loci, and the
+//! decision whether a record is a maximum MAPQ hit.
+
+use bio_types::genome;
+use rust_htslib::bam::{self, record::Aux};
+
+const INVALID_XA_FORMAT_MSG: &str = "XA tag of bam records in unexpected format. Expecting string (type Z) in bwa format (chr,pos,CIGAR,NM;).";
+const INVALID_DRAGEN_XA_FORMAT_MSG: &str = "XA tag of bam records in unexpected format. Expecting string (type Z) in dragen format (chr,pos,CIGAR,NM[,...];).";
+
+/// Minimum number of minimizers on the chain of a minimap2 alignment to consider its
+/// placement unambiguous.
+const MIN_MINIMAP2_CHAIN_MINIMIZERS: i64 = 10;
+
+/// Maximum ratio between the chaining score of the best secondary (s2) and the primary (s1)
+/// minimap2 chain to consider the placement of a record unambiguous.
+const MAX_MINIMAP2_SECONDARY_CHAIN_SCORE_RATIO: f64 = 0.9;
+
+/// Minimum difference between the best (AS) and second best (XS) bowtie2 alignment score
+/// to consider the placement of a record unambiguous. This corresponds to the default
+/// penalty of a single high quality mismatch.
+const MIN_BOWTIE2_SCORE_GAP: i64 = 6;
+
+/// Format of the alternative hits reported by the read mapper.
+#[derive(
+    Display,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Default,
+    Serialize,
+    Deserialize,
+    EnumString,
+    EnumIter,
+    IntoStaticStr,
+)]
+#[strum(serialize_all = "kebab_case")]
+#[serde(rename_all = "kebab-case")]
+pub enum AltHitFormat {
+    /// bwa XA tag.
+    #[default]
+    Bwa,
+    /// minimap2 tp, cm, s1 and s2 tags.
+    Minimap2,
+    /// bowtie2 AS and XS tags.
+    Bowtie2,
+    /// dragen XA tag.
+    Dragen,
+}
+
+impl AltHitFormat {
+    /// Infer the format from the @PG lines of the given BAM header.
+    /// Returns `None` if no known read mapper is found.
+    pub(crate) fn from_header(header: &bam::HeaderView) -> Option<Self> {
+        let header = String::from_utf8_lossy(header.as_bytes()).to_lowercase();
+        header
+            .lines()
+            .filter(|line| line.starts_with("@pg"))
+            .flat_map(|line| line.split('\t').skip(1))
+            .filter_map(|field| {
+                field
+                    .strip_prefix("pn:")
+                    .or_else(|| field.strip_prefix("id:"))
+            })
+            .find_map(|program| {
+                if program.starts_with("bwa") {
+                    Some(AltHitFormat::Bwa)
+                } else if program.starts_with("minimap2") {
+                    Some(AltHitFormat::Minimap2)
+                } else if program.starts_with("bowtie2") {
+                    Some(AltHitFormat::Bowtie2)
+                } else if program.starts_with("dragen") {
+                    Some(AltHitFormat::Dragen)
+                } else {
+                    None
+                }
+            })
+    }
+
+    pub(crate) fn parser(&self) -> &'static dyn AltHitParser {
+        match self {
+            AltHitFormat::Bwa => &BwaAltHitParser,
+            AltHitFormat::Minimap2 => &Minimap2AltHitParser,
+            AltHitFormat::Bowtie2 => &Bowtie2AltHitParser,
+            AltHitFormat::Dragen => &DragenAltHitParser,
+        }
+    }
+}
+
+pub(crate) trait AltHitParser {
+    /// Alternative loci the given record could stem from as well.
+    fn alt_loci(&self, record: &bam::Record) -> Vec<genome::Locus>;
+
+    /// Whether the mapper reports an (almost) equally good alternative hit for the record.
+    /// Then, the record is not considered a maximum MAPQ hit, regardless of its MAPQ.
+    fn has_ambiguous_hit(&self, _record: &bam::Record) -> bool {
+        false
+    }
+}
+
+/// Parse the position of an alternative hit, which may be prefixed with the strand.
+fn parse_pos(pos: &str) -> Option<u64> {
+    pos.trim_start_matches(['+', '-']).parse().ok()
+}
+
+fn aux_integer(record: &bam::Record, tag: &[u8]) -> Option<i64> {
+    match record.aux(tag) {
+        Ok(Aux::I8(value)) => Some(value as i64),
+        Ok(Aux::U8(value)) => Some(value as i64),
+        Ok(Aux::I16(value)) => Some(value as i64),
+        Ok(Aux::U16(value)) => Some(value as i64),
+        Ok(Aux::I32(value)) => Some(value as i64),
+        Ok(Aux::U32(value)) => Some(value as i64),
+        _ => None,
+    }
+}
+
+/// Parse a string tag of semicolon separated hits, each consisting of comma separated
+/// items. The given closure decides whether a hit has the expected number of items.
+fn parse_hits(
+    record: &bam::Record,
+    tag: &[u8],
+    invalid_format_msg: &str,
+    is_valid_len: impl Fn(usize) -> bool,
+) -> Vec<genome::Locus> {
+    match record.aux(tag) {
+        Ok(Aux::String(hits)) => hits
+            .split(';')
+            .filter_map(|hit| {
+                if hit.is_empty() {
+                    // last semicolon passed
+                    None
+                } else {
+                    let items: Vec<_> = hit.split(',').collect();
+                    if is_valid_len(items.len()) {
+                        parse_pos(items[1]).map(|pos| genome::Locus::new(items[0].to_owned(), pos))
+                    } else {
+                        warn!("{}", invalid_format_msg);
+                        None
+                    }
+                }
+            })
+            .collect(),
+        Ok(_tag) => {
+            warn!("{}", invalid_format_msg);
+            Vec::new()
+        }
+        Err(_e) => {
+            // no tag found, return empty.
+            Vec::new()
+        }
+    }
+}
+
+/// Parser for the bwa XA tag (chr,pos,CIGAR,NM;).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BwaAltHitParser;
+
+impl AltHitParser for BwaAltHitParser {
+    fn alt_loci(&self, record: &bam::Record) -> Vec<genome::Locus> {
+        parse_hits(record, b"XA", INVALID_XA_FORMAT_MSG, |len| len == 4)
+    }
+}
+
+/// Parser for the dragen XA tag, which follows the bwa format but may contain
+/// additional items per hit (chr,pos,CIGAR,NM[,...];).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DragenAltHitParser;
+
+impl AltHitParser for DragenAltHitParser {
+    fn alt_loci(&self, record: &bam::Record) -> Vec<genome::Locus> {
+        parse_hits(record, b"XA", INVALID_DRAGEN_XA_FORMAT_MSG, |len| len >= 4)
+    }
+}
+
+/// Parser for minimap2 records. Minimap2 does not report the loci of alternative hits.
+/// The SA tag lists the other parts of a chimeric (split) alignment, which are no
+/// alternatives to the record itself. Hence, only ambiguity is derived, from the tp, cm,
+/// s1 and s2 tags.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Minimap2AltHitParser;
+
+impl AltHitParser for Minimap2AltHitParser {
+    fn alt_loci(&self, _record: &bam::Record) -> Vec<genome::Locus> {
+        Vec::new()
+    }
+
+    fn has_ambiguous_hit(&self, record: &bam::Record) -> bool {
+        // METHOD: minimap2 marks secondary hits with tp:A:S. Further, alignments with only
+        // few minimizers on their chain (cm tag) cannot be placed reliably, even if minimap2
+        // reports a high MAPQ. Finally, if the best secondary chain (s2) scores almost as
+        // good as the primary one (s1), the placement of the read is ambiguous.
+        let is_secondary = matches!(record.aux(b"tp"), Ok(Aux::Char(b'S')));
+        let is_weak_chain =
+            aux_integer(record, b"cm").is_some_and(|cm| cm < MIN_MINIMAP2_CHAIN_MINIMIZERS);
+        let has_competing_chain = match (aux_integer(record, b"s1"), aux_integer(record, b"s2")) {
+            (Some(s1), Some(s2)) => {
+                s1 > 0 && s2 as f64 >= s1 as f64 * MAX_MINIMAP2_SECONDARY_CHAIN_SCORE_RATIO
+            }
+            _ => false,
+        };
+        is_secondary || is_weak_chain || has_competing_chain
+    }
+}
+
+/// Parser for bowtie2 records. Bowtie2 does not report the loci of alternative hits,
+/// only the score of the second best one (XS tag).
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Bowtie2AltHitParser;
+
+impl AltHitParser for Bowtie2AltHitParser {
+    fn alt_loci(&self, _record: &bam::Record) -> Vec<genome::Locus> {
+        Vec::new()
+    }
+
+    fn has_ambiguous_hit(&self, record: &bam::Record) -> bool {
+        // METHOD: if the second best alignment scores almost as good as the best one,
+        // the placement of the read is ambiguous.
+        match (aux_integer(record, b"AS"), aux_integer(record, b"XS")) {
+            (Some(best), Some(second_best)) => best - second_best < MIN_BOWTIE2_SCORE_GAP,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio_types::genome::AbstractLocus;
+
+    #[test]
+    fn test_alt_loci() {
+        let mut record = bam::Record::new();
+        record
+            .push_aux(b"XA", Aux::String("chr1,+100,50M,1;chr2,-200,50M,0;"))
+            .unwrap();
+        let loci = BwaAltHitParser.alt_loci(&record);
+        assert_eq!(loci.len(), 2);
+        assert_eq!(loci[1].contig(), "chr2");
+        assert_eq!(loci[1].pos(), 200);
+
+        let mut record = bam::Record::new();
+        record
+            .push_aux(b"XA", Aux::String("chr1,+100,50M,1,60;"))
+            .unwrap();
+        assert!(BwaAltHitParser.alt_loci(&record).is_empty());
+        assert_eq!(DragenAltHitParser.alt_loci(&record)[0].pos(), 100);
+
+        let mut record = bam::Record::new();
+        record
+            .push_aux(b"SA", Aux::String("chr3,300,+,20S30M,60,0;"))
+            .unwrap();
+        record.push_aux(b"cm", Aux::I32(40)).unwrap();
+        // supplementary parts of a chimeric alignment are no alternative loci
+        assert!(Minimap2AltHitParser.alt_loci(&record).is_empty());
+        assert!(!Minimap2AltHitParser.has_ambiguous_hit(&record));
+    }
+
+    #[test]
+    fn test_has_ambiguous_hit() {
+        let mut record = bam::Record::new();
+        record.push_aux(b"AS", Aux::I32(-3)).unwrap();
+        record.push_aux(b"XS", Aux::I32(-6)).unwrap();
+        assert!(Bowtie2AltHitParser.has_ambiguous_hit(&record));
+
+        let mut record = bam::Record::new();
+        record.push_aux(b"AS", Aux::I32(0)).unwrap();
+        record.push_aux(b"XS", Aux::I32(-12)).unwrap();
+        assert!(!Bowtie2AltHitParser.has_ambiguous_hit(&record));
+
+        let mut record = bam::Record::new();
+        record.push_aux(b"tp", Aux::Char(b'S')).unwrap();
+        assert!(Minimap2AltHitParser.has_ambiguous_hit(&record));
+
+        let mut record = bam::Record::new();
+        record.push_aux(b"tp", Aux::Char(b'P')).unwrap();
+        record.push_aux(b"cm", Aux::I32(40)).unwrap();
+        record.push_aux(b"s1", Aux::I32(200)).unwrap();
+        record.push_aux(b"s2", Aux::I32(190)).unwrap();
+        assert!(Minimap2AltHitParser.has_ambiguous_hit(&record));
+
+        let mut record = bam::Record::new();
+        record.push_aux(b"tp", Aux::Char(b'P')).unwrap();
+        record.push_aux(b"cm", Aux::I32(40)).unwrap();
+        record.push_aux(b"s1", Aux::I32(200)).unwrap();
+        record.push_aux(b"s2", Aux::I32(50)).unwrap();
+        assert!(!Minimap2AltHitParser.has_ambiguous_hit(&record));
+    }
+}
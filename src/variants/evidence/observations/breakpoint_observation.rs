@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use bio::stats::LogProb;
+use ordered_float::OrderedFloat;
+
+/// Likelihood of the evidence of a sample given a candidate placement of the breakpoints
+/// of an imprecise structural variant.
+#[derive(Debug, new, Clone, Copy, PartialEq, Serialize, Deserialize, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct BreakpointObservation {
+    /// Position of the variant (0-based, as POS in BCF records).
+    pos: u64,
+    /// End of the variant (1-based, as the END tag in VCF records).
+    end: u64,
+    /// Likelihood of the evidence given the variant at this placement.
+    likelihood: LogProb,
+}
+
+/// Obtain the most likely placement of the breakpoints (pos and end, see
+/// [`BreakpointObservation`]), given the breakpoint observations of all samples.
+pub(crate) fn refined_breakpoints<'a>(
+    observations: impl Iterator<Item = &'a [BreakpointObservation]>,
+) -> Option<(u64, u64)> {
+    // METHOD: samples are independent given the placement of the breakpoints.
+    // Hence, their likelihoods multiply.
+    let mut likelihoods: HashMap<(u64, u64), LogProb> = HashMap::new();
+    for obs in observations.flatten() {
+        *likelihoods
+            .entry((obs.pos, obs.end))
+            .or_insert_with(LogProb::ln_one) += obs.likelihood;
+    }
+    likelihoods
+        .into_iter()
+        .max_by_key(|(breakpoints, likelihood)| {
+            // break ties deterministically
+            (OrderedFloat(**likelihood), std::cmp::Reverse(*breakpoints))
+        })
+        .map(|(breakpoints, _)| breakpoints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refined_breakpoints() {
+        let sample_a = [
+            BreakpointObservation::new(100, 500, LogProb(-10.0)),
+            BreakpointObservation::new(110, 510, LogProb(-5.0)),
+        ];
+        let sample_b = [
+            BreakpointObservation::new(100, 500, LogProb(-1.0)),
+            BreakpointObservation::new(110, 510, LogProb(-7.0)),
+        ];
+        assert_eq!(
+            refined_breakpoints(vec![&sample_a[..], &sample_b[..]].into_iter()),
+            Some((100, 500))
+        );
+        assert_eq!(
+            refined_breakpoints(vec![&sample_a[..]].into_iter()),
+            Some((110, 510))
+        );
+        assert_eq!(refined_breakpoints(std::iter::empty()), None);
+    }
+}
@@ -1,4 +1,204 @@
-#[derive(Debug, new, Clone)]
+use std::ops::Range;
+
+use anyhow::Result;
+use bio::stats::LogProb;
+use bio_types::genome::{self, AbstractInterval};
+use rust_htslib::bam::{self, Read};
+use statrs::function::{beta::ln_beta, factorial::ln_binomial};
+
+use crate::estimation::alignment_properties::AlignmentProperties;
+
+/// Minimum MAPQ of reads considered for depth observations. Reads from repetitive regions
+/// would otherwise blur the depth of both the event and the flanking regions.
+const MIN_MAPQ: u8 = 20;
+/// Minimum length of the flanking regions (left and right, each) used as depth reference.
+const MIN_FLANK_LEN: u64 = 1000;
+/// Maximum length of the flanking regions (left and right, each) used as depth reference.
+const MAX_FLANK_LEN: u64 = 10000;
+/// Maximum length of the window within the event in which reads are counted.
+const MAX_EVENT_WINDOW_LEN: u64 = 100000;
+/// Concentration of the beta-binomial distribution of read counts. This models the
+/// overdispersion of real sequencing depth (e.g. due to GC bias) and thereby limits the
+/// weight of depth observations compared to read observations.
+const DEPTH_CONCENTRATION: f64 = 100.0;
+/// Minimum expected fraction of reads stemming from the event window (and from the flanking
+/// windows), accounting for mismapped reads (e.g. in case of homozygous deletions).
+const MIN_EVENT_FRACTION: f64 = 0.01;
+
+/// An event that changes the copy number of a genomic interval on the alt allele,
+/// like a deletion (no copy) or a tandem duplication (two copies).
+#[derive(Debug, Clone, new, Getters, CopyGetters)]
+pub(crate) struct DepthEvent {
+    #[getset(get = "pub(crate)")]
+    interval: genome::Interval,
+    #[getset(get_copy = "pub(crate)")]
+    alt_copies: u8,
+}
+
+/// Read depth over an event, relative to its flanking regions.
+#[derive(Debug, new, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, CopyGetters)]
+#[getset(get_copy = "pub")]
 pub struct DepthObservation {
-    // TODO implement
+    /// Number of reads starting in the event window.
+    event_count: u32,
+    /// Effective length of the event window.
+    event_len: u32,
+    /// Number of reads starting in the flanking windows.
+    flank_count: u32,
+    /// Effective length of the flanking windows.
+    flank_len: u32,
+    /// Number of copies of the event interval on the alt allele.
+    alt_copies: u8,
+}
+
+impl DepthObservation {
+    /// Likelihood of the observed read counts given the allele frequency of the event.
+    ///
+    /// Conditioning on the total number of reads, the reads in the event window follow
+    /// a beta-binomial distribution, with the expected fraction being determined by the
+    /// relative depth of the event (e.g. 0.5 for a heterozygous deletion) and the window lengths.
+    pub(crate) fn likelihood(&self, allele_freq: f64) -> LogProb {
+        let relative_depth = (1.0 - allele_freq) + allele_freq * self.alt_copies as f64;
+        let event_weight = self.event_len as f64 * relative_depth;
+        let p = (event_weight / (event_weight + self.flank_len as f64))
+            .clamp(MIN_EVENT_FRACTION, 1.0 - MIN_EVENT_FRACTION);
+
+        let k = self.event_count as u64;
+        let n = (self.event_count + self.flank_count) as u64;
+        let alpha = p * DEPTH_CONCENTRATION;
+        let beta = (1.0 - p) * DEPTH_CONCENTRATION;
+
+        LogProb(
+            ln_binomial(n, k) + ln_beta(k as f64 + alpha, (n - k) as f64 + beta)
+                - ln_beta(alpha, beta),
+        )
+    }
+}
+
+/// Counts reads over events and their flanking regions, pooled over all BAM files of a sample.
+#[derive(Debug)]
+pub(crate) struct DepthCounter {
+    readers: Vec<bam::IndexedReader>,
+}
+
+impl DepthCounter {
+    pub(crate) fn new(readers: Vec<bam::IndexedReader>) -> Self {
+        DepthCounter { readers }
+    }
+
+    /// Obtain a depth observation for the given event. Returns `None` if the event is too short
+    /// to contain reads that are entirely located inside of it.
+    ///
+    /// # Arguments
+    /// * `event` - the event to observe
+    /// * `alignment_properties` - the alignment properties of each BAM file
+    pub(crate) fn observe(
+        &mut self,
+        event: &DepthEvent,
+        alignment_properties: &[AlignmentProperties],
+    ) -> Result<Option<DepthObservation>> {
+        let contig = event.interval().contig();
+        let Some(contig_len) = self.readers.iter().find_map(|reader| {
+            let header = reader.header();
+            header
+                .tid(contig.as_bytes())
+                .map(|tid| header.target_len(tid).unwrap_or(0))
+        }) else {
+            return Ok(None);
+        };
+        // METHOD: the windows are determined by the longest reads of all BAM files, such
+        // that reads of all files can be counted over the same windows.
+        let read_len = alignment_properties
+            .iter()
+            .map(|properties| properties.max_read_len as u64)
+            .max()
+            .unwrap_or(0);
+        let range = event.interval().range();
+
+        // METHOD: we count read starts such that each read is counted at most once.
+        // Only reads that are entirely located inside of the event are considered, because
+        // reads overlapping a breakpoint will be soft clipped or mapped elsewhere.
+        if range.end < range.start + 2 * read_len {
+            return Ok(None);
+        }
+        let mut event_window = range.start..range.end - read_len;
+        if event_window.end - event_window.start > MAX_EVENT_WINDOW_LEN {
+            let center = event_window.start + (event_window.end - event_window.start) / 2;
+            event_window = center - MAX_EVENT_WINDOW_LEN / 2..center + MAX_EVENT_WINDOW_LEN / 2;
+        }
+        let flank_len = (event_window.end - event_window.start).clamp(MIN_FLANK_LEN, MAX_FLANK_LEN);
+        let left_flank =
+            range.start.saturating_sub(read_len + flank_len)..range.start.saturating_sub(read_len);
+        let right_flank = range.end.min(contig_len)..(range.end + flank_len).min(contig_len);
+
+        let event_count = self.count(contig, &event_window)?;
+        let flank_count = self.count(contig, &left_flank)? + self.count(contig, &right_flank)?;
+        let flank_len = (left_flank.end - left_flank.start) + (right_flank.end - right_flank.start);
+        if flank_len == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(DepthObservation::new(
+            event_count,
+            (event_window.end - event_window.start) as u32,
+            flank_count,
+            flank_len as u32,
+            event.alt_copies(),
+        )))
+    }
+
+    /// Count the reads starting in the given window, summed over all BAM files.
+    fn count(&mut self, contig: &str, window: &Range<u64>) -> Result<u32> {
+        if window.start >= window.end {
+            return Ok(0);
+        }
+        let mut count = 0;
+        let mut record = bam::Record::new();
+        for reader in &mut self.readers {
+            let Some(tid) = reader.header().tid(contig.as_bytes()) else {
+                continue;
+            };
+            reader.fetch((tid, window.start as i64, window.end as i64))?;
+            while let Some(res) = reader.read(&mut record) {
+                res?;
+                if record.is_secondary()
+                    || record.is_supplementary()
+                    || record.is_duplicate()
+                    || record.is_unmapped()
+                    || record.is_quality_check_failed()
+                    || record.mapq() < MIN_MAPQ
+                {
+                    continue;
+                }
+                let pos = record.pos() as u64;
+                if pos >= window.start && pos < window.end {
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depth_likelihood_heterozygous_deletion() {
+        // half the depth over the event
+        let obs = DepthObservation::new(500, 10000, 2000, 20000, 0);
+        let lh_absent = obs.likelihood(0.0);
+        let lh_het = obs.likelihood(0.5);
+        let lh_hom = obs.likelihood(1.0);
+        assert!(lh_het > lh_absent);
+        assert!(lh_het > lh_hom);
+    }
+
+    #[test]
+    fn test_depth_likelihood_no_change() {
+        let obs = DepthObservation::new(1000, 10000, 2000, 20000, 2);
+        assert!(obs.likelihood(0.0) > obs.likelihood(0.5));
+        assert!(obs.likelihood(0.0) > obs.likelihood(1.0));
+    }
 }
@@ -4,9 +4,10 @@ use super::read_observation::{Evidence, EvidenceIdentifier};
 
 #[derive(Default, Debug)]
 pub(crate) struct FragmentIdFactory {
-    ids: HashMap<EvidenceIdentifier, u64>,
+    ids: HashMap<(usize, EvidenceIdentifier), u64>,
     next_id: u64,
     current_contig: String,
+    current_file: usize,
 }
 
 impl FragmentIdFactory {
@@ -17,12 +18,19 @@ impl FragmentIdFactory {
             self.current_contig = contig.to_owned();
         }
     }
+    /// Set the BAM file subsequently registered evidence stems from. Fragments with the
+    /// same name in different BAM files are considered to be different fragments.
+    pub(crate) fn register_file(&mut self, file: usize) {
+        self.current_file = file;
+    }
+
     pub(crate) fn register(&mut self, evidence: &Evidence) -> u64 {
-        if self.ids.contains_key(&evidence.id()) {
-            *self.ids.get(&evidence.id()).unwrap()
+        let key = (self.current_file, evidence.id());
+        if let Some(id) = self.ids.get(&key) {
+            *id
         } else {
             let id = self.next_id;
-            self.ids.insert(evidence.id(), id);
+            self.ids.insert(key, id);
             self.next_id += 1;
             id
         }
@@ -1,3 +1,5 @@
+pub(crate) mod alt_hits;
+pub(crate) mod breakpoint_observation;
 pub(crate) mod depth_observation;
 pub(crate) mod fragment_id_factory;
 pub mod pileup;
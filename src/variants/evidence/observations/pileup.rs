@@ -1,13 +1,22 @@
 use bio_types::sequence::SequenceReadPairOrientation;
 
-use super::{depth_observation::DepthObservation, read_observation::ProcessedReadObservation};
+use super::{
+    breakpoint_observation::BreakpointObservation, depth_observation::DepthObservation,
+    read_observation::ProcessedReadObservation,
+};
 
 #[derive(Debug, Getters, MutGetters, Default)]
 #[getset(get = "pub", get_mut = "pub(crate)")]
 pub struct Pileup {
     read_observations: Vec<ProcessedReadObservation>,
     depth_observations: Vec<DepthObservation>,
+    /// Likelihoods of candidate breakpoints (only for imprecise structural variants).
+    breakpoint_observations: Vec<BreakpointObservation>,
     n_filtered_out_observations: usize,
+    /// Probability for substitutions of the variant's damage type to occur in the read
+    /// orientation in which the damage manifests, as estimated for the sample.
+    #[getset(skip)]
+    prob_damage_orientation: Option<f64>,
 }
 
 impl Pileup {
@@ -18,7 +27,9 @@ impl Pileup {
         Self {
             read_observations,
             depth_observations,
+            breakpoint_observations: Vec::new(),
             n_filtered_out_observations: 0,
+            prob_damage_orientation: None,
         }
     }
 
@@ -37,6 +48,24 @@ impl Pileup {
         self.n_filtered_out_observations += n_orig - self.read_observations.len();
     }
 
+    /// Number of competing ALT alleles at the site, for which the read observations carry
+    /// probabilities (zero if there are none).
+    pub(crate) fn n_competing_alleles(&self) -> usize {
+        self.read_observations
+            .iter()
+            .map(|obs| obs.prob_competing_alleles.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn prob_damage_orientation(&self) -> Option<f64> {
+        self.prob_damage_orientation
+    }
+
+    pub(crate) fn set_prob_damage_orientation(&mut self, prob_damage_orientation: Option<f64>) {
+        self.prob_damage_orientation = prob_damage_orientation;
+    }
+
     pub(crate) fn is_empty(&self) -> bool {
         self.read_observations.is_empty() && self.depth_observations.is_empty()
     }
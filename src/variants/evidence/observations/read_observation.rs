@@ -4,6 +4,7 @@
 // except according to those terms.
 
 use std::char;
+use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 use std::ops;
 use std::rc::Rc;
@@ -17,6 +18,7 @@ use bio_types::sequence::SequenceReadPairOrientation;
 use counter::Counter;
 
 use rust_htslib::bam;
+use vec_map::VecMap;
 
 use serde::Serialize;
 // use bio::stats::bayesian::bayes_factors::evidence::KassRaftery;
@@ -31,12 +33,12 @@ use crate::utils::{bayes_factor_to_letter, PROB_095};
 use crate::variants::sample;
 use crate::variants::types::Variant;
 
+use crate::variants::evidence::insert_size::estimate_insert_size;
 use crate::variants::evidence::realignment::Realignable;
 
+use super::alt_hits::AltHitParser;
 use super::fragment_id_factory::FragmentIdFactory;
 
-const INVALID_XA_FORMAT_MSG: &str = "XA tag of bam records in unexpected format. Expecting string (type Z) in bwa format (chr,pos,CIGAR,NM;).";
-
 /// Calculate expected value of sequencing depth, considering mapping quality.
 pub(crate) fn expected_depth(obs: &[ProcessedReadObservation]) -> u32 {
     LogProb::ln_sum_exp(&obs.iter().map(|o| o.prob_mapping).collect_vec())
@@ -162,47 +164,11 @@ pub struct ExactAltLoci {
     inner: Vec<genome::Locus>,
 }
 
-impl<'a> From<&'a bam::Record> for ExactAltLoci {
-    fn from(record: &'a bam::Record) -> Self {
-        match record.aux(b"XA") {
-            Ok(bam::record::Aux::String(xa)) => {
-                ExactAltLoci {
-                    inner: xa
-                        .split(';')
-                        .filter_map(|xa| {
-                            if xa.is_empty() {
-                                // last semicolon passed
-                                None
-                            } else {
-                                let items: Vec<_> = xa.split(',').collect();
-                                if items.len() == 4 {
-                                    let contig = items[0];
-                                    let mut pos = items[1];
-                                    if pos.starts_with('-') || pos.starts_with('-') {
-                                        pos = &pos[1..];
-                                    }
-                                    if let Ok(pos) = pos.parse() {
-                                        Some(genome::Locus::new(contig.to_owned(), pos))
-                                    } else {
-                                        None
-                                    }
-                                } else {
-                                    warn!("{}", INVALID_XA_FORMAT_MSG);
-                                    None
-                                }
-                            }
-                        })
-                        .collect(),
-                }
-            }
-            Ok(_tag) => {
-                warn!("{}", INVALID_XA_FORMAT_MSG);
-                ExactAltLoci::default()
-            }
-            Err(_e) => {
-                // no XA tag found, return empty.
-                ExactAltLoci::default()
-            }
+impl ExactAltLoci {
+    /// Collect the alternative loci of the given record, as reported by the read mapper.
+    pub(crate) fn from_record(record: &bam::Record, alt_hit_parser: &dyn AltHitParser) -> Self {
+        ExactAltLoci {
+            inner: alt_hit_parser.alt_loci(record),
         }
     }
 }
@@ -237,6 +203,11 @@ where
     pub prob_alt: LogProb,
     /// Probability that the read/read-pair comes from the reference allele.
     pub prob_ref: LogProb,
+    /// Probabilities that the read/read-pair comes from each of the competing ALT alleles at
+    /// the same site (empty if there are none, or if they have not been evaluated, in which
+    /// case they are assumed to be the same as prob_ref).
+    #[builder(default)]
+    pub prob_competing_alleles: Vec<LogProb>,
     /// Probability that the read/read-pair comes from an unknown allele at an unknown true
     /// locus (in case it is mismapped). This should usually be set as the product of the maxima
     /// of prob_ref and prob_alt per read.
@@ -269,6 +240,14 @@ where
     /// Edit distance of the read against the alt allele. Only recorded if it is higher than
     /// the expected number of sequencing errors of each type.
     pub third_allele_evidence: Option<u32>,
+    /// Number of reads of the UMI family this observation has been combined from.
+    /// None if reads have not been grouped into UMI families.
+    #[builder(default)]
+    pub family_size: Option<u32>,
+    /// Length of the sequenced fragment (e.g. the insert size of a read pair).
+    /// None if unknown or not reported.
+    #[builder(default)]
+    pub fragment_len: Option<u32>,
 }
 
 pub type ProcessedReadObservation = ReadObservation<ReadPosition, AltLocus>;
@@ -290,8 +269,14 @@ impl ReadObservation<Option<u32>, ExactAltLoci> {
         &self,
         major_read_position: Option<u32>,
         major_alt_locus: &Option<genome::Locus>,
+        max_read_len: u32,
         alignment_properties: &AlignmentProperties,
     ) -> ReadObservation<ReadPosition, AltLocus> {
+        // METHOD: single long reads cover a variant at widely varying read positions, and
+        // softclips mostly stem from split alignments of structural variants. Hence, read
+        // position and softclip bias are meaningless there, and we neutralize the
+        // corresponding information such that the biases can never be inferred.
+        let is_long_read = alignment_properties.read_type.is_long();
         ReadObservation {
             name: self.name.clone(),
             fragment_id: self.fragment_id,
@@ -301,6 +286,7 @@ impl ReadObservation<Option<u32>, ExactAltLoci> {
             prob_mismapping_adj: self.prob_mismapping_adj,
             prob_alt: self.prob_alt,
             prob_ref: self.prob_ref,
+            prob_competing_alleles: self.prob_competing_alleles.clone(),
             prob_missed_allele: self.prob_missed_allele,
             prob_sample_alt: self.prob_sample_alt,
             prob_double_overlap: self.prob_double_overlap,
@@ -308,10 +294,12 @@ impl ReadObservation<Option<u32>, ExactAltLoci> {
             prob_hit_base: self.prob_hit_base,
             strand: self.strand,
             read_orientation: self.read_orientation,
-            softclipped: self.softclipped,
+            softclipped: self.softclipped && !is_long_read,
             paired: self.paired,
             read_position: self.read_position.map_or(ReadPosition::Some, |pos| {
-                if let Some(major_pos) = major_read_position {
+                if is_long_read {
+                    ReadPosition::Some
+                } else if let Some(major_pos) = major_read_position {
                     if pos == major_pos {
                         ReadPosition::Major
                     } else {
@@ -326,9 +314,11 @@ impl ReadObservation<Option<u32>, ExactAltLoci> {
             homopolymer_indel_len: self.homopolymer_indel_len,
             is_max_mapq: self.is_max_mapq,
             alt_locus: if let Some(major_alt_locus) = major_alt_locus {
-                if self.alt_locus.iter().any(|alt_locus| {
-                    locus_to_bucket(alt_locus, alignment_properties) == *major_alt_locus
-                }) {
+                if self
+                    .alt_locus
+                    .iter()
+                    .any(|alt_locus| locus_to_bucket(alt_locus, max_read_len) == *major_alt_locus)
+                {
                     AltLocus::Major
                 } else if self.alt_locus.is_empty() {
                     AltLocus::None
@@ -339,6 +329,81 @@ impl ReadObservation<Option<u32>, ExactAltLoci> {
                 AltLocus::None
             },
             third_allele_evidence: self.third_allele_evidence,
+            family_size: self.family_size,
+            fragment_len: self.fragment_len,
+        }
+    }
+
+    /// Combine the observations of all reads of a UMI family into a single consensus
+    /// observation.
+    pub(crate) fn umi_family_consensus(family: Vec<Self>) -> Self {
+        let family_size = family.len() as u32;
+        let mut family = family.into_iter();
+        let mut consensus = family.next().expect("bug: empty UMI family");
+        for obs in family {
+            // METHOD: all reads of a family stem from the same molecule. Hence, given the
+            // allele of the molecule, the probabilities of the reads multiply.
+            consensus.prob_alt += obs.prob_alt;
+            combine_prob_competing_alleles(
+                &mut consensus.prob_competing_alleles,
+                consensus.prob_ref,
+                &obs.prob_competing_alleles,
+                obs.prob_ref,
+            );
+            consensus.prob_ref += obs.prob_ref;
+            consensus.prob_missed_allele += obs.prob_missed_allele;
+            // METHOD: like for read pairs, we conservatively take the minimum mapping
+            // probability of all reads in the family.
+            if obs.prob_mapping < consensus.prob_mapping {
+                consensus.prob_mapping = obs.prob_mapping;
+                consensus.prob_mismapping = obs.prob_mismapping;
+            }
+            if obs.prob_double_overlap > consensus.prob_double_overlap {
+                consensus.prob_double_overlap = obs.prob_double_overlap;
+                consensus.prob_single_overlap = obs.prob_single_overlap;
+            }
+            consensus.strand |= obs.strand;
+            consensus.softclipped |= obs.softclipped;
+            consensus.is_max_mapq &= obs.is_max_mapq;
+            consensus.third_allele_evidence = consensus
+                .third_allele_evidence
+                .max(obs.third_allele_evidence);
+        }
+        consensus.family_size = Some(family_size);
+        consensus
+    }
+}
+
+/// Combine the probabilities of two independent pieces of evidence (e.g. two reads of a pair
+/// or of a UMI family) for the competing ALT alleles of a site. Missing probabilities (i.e. an
+/// empty vector) are assumed to be the same as the probability for the reference allele.
+/// The given reference probabilities are those before combining.
+pub(crate) fn combine_prob_competing_alleles(
+    prob_competing_alleles: &mut Vec<LogProb>,
+    prob_ref: LogProb,
+    other_prob_competing_alleles: &[LogProb],
+    other_prob_ref: LogProb,
+) {
+    if prob_competing_alleles.is_empty() {
+        *prob_competing_alleles = other_prob_competing_alleles
+            .iter()
+            .map(|prob| *prob + prob_ref)
+            .collect();
+    } else if other_prob_competing_alleles.is_empty() {
+        for prob in prob_competing_alleles.iter_mut() {
+            *prob += other_prob_ref;
+        }
+    } else {
+        assert_eq!(
+            prob_competing_alleles.len(),
+            other_prob_competing_alleles.len(),
+            "bug: differing number of competing alleles"
+        );
+        for (prob, other_prob) in prob_competing_alleles
+            .iter_mut()
+            .zip(other_prob_competing_alleles)
+        {
+            *prob += *other_prob;
         }
     }
 }
@@ -522,7 +587,7 @@ pub(crate) fn major_read_position(
 
 pub(crate) fn major_alt_locus(
     pileup: &[ReadObservation<Option<u32>, ExactAltLoci>],
-    alignment_properties: &AlignmentProperties,
+    max_read_len: u32,
 ) -> Option<genome::Locus> {
     calc_major_feature(
         pileup
@@ -532,19 +597,16 @@ pub(crate) fn major_alt_locus(
             .flat_map(|obs| {
                 obs.alt_locus
                     .iter()
-                    .map(|locus| locus_to_bucket(locus, alignment_properties))
+                    .map(|locus| locus_to_bucket(locus, max_read_len))
             }),
     )
 }
 
-pub(crate) fn locus_to_bucket(
-    locus: &genome::Locus,
-    alignment_properties: &AlignmentProperties,
-) -> genome::Locus {
+pub(crate) fn locus_to_bucket(locus: &genome::Locus, max_read_len: u32) -> genome::Locus {
     // METHOD: map each locus to the nearest multiple of the read len from the left.
     // This way, varying reads become comparable
 
-    let coeff = alignment_properties.max_read_len as u64 * 10;
+    let coeff = max_read_len as u64 * 10;
 
     let bucket = genome::Locus::new(locus.contig().to_owned(), (locus.pos() / coeff) * coeff);
 
@@ -553,12 +615,22 @@ pub(crate) fn locus_to_bucket(
 
 /// Something that can be converted into observations.
 pub(crate) trait Observable: Variant {
-    fn extract_observations(
+    /// Collect the candidate evidence for the variant from the given record buffer.
+    fn collect_evidence(
         &self,
         buffer: &mut sample::RecordBuffer,
         alignment_properties: &mut AlignmentProperties,
-        max_depth: usize,
-        alt_variants: &[Box<dyn Realignable>],
+    ) -> Result<CandidateEvidence>;
+
+    /// Convert the given candidate evidence (collected from the given record buffer) into
+    /// observations. Evidence that is not kept by the subsampler is skipped.
+    fn extract_observations(
+        &self,
+        candidates: &CandidateEvidence,
+        buffer: &sample::RecordBuffer,
+        alignment_properties: &mut AlignmentProperties,
+        subsampler: &mut sample::SubsampleCandidates,
+        alt_variants: &[Box<dyn Realignable + Send>],
         observation_id_factory: &mut Option<&mut FragmentIdFactory>,
     ) -> Result<Vec<ReadObservation>>;
 
@@ -575,12 +647,16 @@ pub(crate) trait Observable: Variant {
         evidence: &Evidence,
         alignment_properties: &mut AlignmentProperties,
         homopolymer_error_model: &Option<HomopolymerErrorModel>,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
+        alt_hit_parser: &dyn AltHitParser,
         observation_id_factory: &mut Option<&mut FragmentIdFactory>,
     ) -> Result<Option<ReadObservation>> {
         let id = observation_id_factory
             .as_mut()
             .map(|factory| factory.register(evidence));
+        // METHOD: use the properties (e.g. the insert size distribution) of the library
+        // the evidence stems from.
+        let alignment_properties = alignment_properties.read_group_properties(evidence.record());
 
         Ok(
             match self.allele_support(evidence, alignment_properties, alt_variants)? {
@@ -599,6 +675,7 @@ pub(crate) trait Observable: Variant {
                         .prob_mapping_mismapping(self.prob_mapping(evidence))
                         .prob_alt(allele_support.prob_alt_allele())
                         .prob_ref(allele_support.prob_ref_allele())
+                        .prob_competing_alleles(allele_support.prob_competing_alleles().clone())
                         .prob_sample_alt(self.prob_sample_alt(evidence, alignment_properties))
                         .prob_missed_allele(allele_support.prob_missed_allele())
                         .prob_overlap(if allele_support.strand() == Strand::Both {
@@ -612,9 +689,13 @@ pub(crate) trait Observable: Variant {
                         .read_position(allele_support.read_position())
                         .paired(evidence.is_paired())
                         .prob_hit_base(LogProb::ln_one() - LogProb((evidence.len() as f64).ln()))
-                        .is_max_mapq(self.min_mapq(evidence) == alignment_properties.max_mapq)
-                        .alt_locus(evidence.alt_loci())
-                        .third_allele_evidence(allele_support.third_allele_evidence().map(|d| *d));
+                        .is_max_mapq(
+                            self.min_mapq(evidence) == alignment_properties.max_mapq
+                                && !evidence.has_ambiguous_alt_hit(alt_hit_parser),
+                        )
+                        .alt_locus(evidence.alt_loci(alt_hit_parser))
+                        .third_allele_evidence(allele_support.third_allele_evidence().map(|d| *d))
+                        .fragment_len(evidence.fragment_len());
 
                     if let Some(homopolymer_error_model) = homopolymer_error_model {
                         let ref_indel_len =
@@ -648,6 +729,29 @@ pub(crate) trait Observable: Variant {
     }
 }
 
+/// Candidate evidence for a variant, collected from a single BAM file.
+#[derive(Debug, Default)]
+pub(crate) struct CandidateEvidence {
+    /// Evidence grouped into UMI families (singletons if reads are not grouped by UMIs),
+    /// in the (deterministic) order of their first member.
+    pub(crate) families: Vec<Vec<Evidence>>,
+    /// Amount of evidence per locus of the variant.
+    pub(crate) locus_depth: VecMap<usize>,
+    /// Splice junctions close to the variant that are observed in any of the reads.
+    pub(crate) splice_junctions: HashSet<u64>,
+}
+
+impl CandidateEvidence {
+    /// Iterate over the families that are kept by the given subsampler.
+    /// UMI families are always kept or discarded as a whole.
+    pub(crate) fn subsample_families<'a>(
+        &'a self,
+        subsampler: &'a mut sample::SubsampleCandidates,
+    ) -> impl Iterator<Item = &'a Vec<Evidence>> + 'a {
+        self.families.iter().filter(move |_| subsampler.keep())
+    }
+}
+
 #[derive(Clone, Eq, Debug)]
 pub(crate) enum Evidence {
     SingleEndSequencingRead(Rc<bam::Record>),
@@ -682,6 +786,52 @@ impl Evidence {
         }
     }
 
+    /// All records of this evidence.
+    pub(crate) fn records(&self) -> Vec<&bam::Record> {
+        match self {
+            Evidence::SingleEndSequencingRead(read) => vec![read.as_ref()],
+            Evidence::PairedEndSequencingRead { left, right } => {
+                vec![left.as_ref(), right.as_ref()]
+            }
+        }
+    }
+
+    /// The record that determines the read group of this evidence.
+    pub(crate) fn record(&self) -> &bam::Record {
+        match self {
+            Evidence::SingleEndSequencingRead(read) => read.as_ref(),
+            Evidence::PairedEndSequencingRead { left, .. } => left.as_ref(),
+        }
+    }
+
+    /// UMI family of this evidence, given by the MI tag, or, if missing, by the RX tag.
+    /// Since RX only contains the UMI sequence, it is combined with the start of the fragment.
+    pub(crate) fn umi_family(&self) -> Option<Vec<u8>> {
+        use bam::record::Aux;
+        let record = self.record();
+        let aux_value = |tag: &[u8]| match record.aux(tag) {
+            Ok(Aux::String(value)) => Some(value.as_bytes().to_owned()),
+            Ok(Aux::I8(value)) => Some(value.to_string().into_bytes()),
+            Ok(Aux::U8(value)) => Some(value.to_string().into_bytes()),
+            Ok(Aux::I16(value)) => Some(value.to_string().into_bytes()),
+            Ok(Aux::U16(value)) => Some(value.to_string().into_bytes()),
+            Ok(Aux::I32(value)) => Some(value.to_string().into_bytes()),
+            Ok(Aux::U32(value)) => Some(value.to_string().into_bytes()),
+            _ => None,
+        };
+        aux_value(b"MI").or_else(|| {
+            aux_value(b"RX").map(|mut umi| {
+                let start = if record.is_paired() && !record.is_mate_unmapped() {
+                    record.pos().min(record.mpos())
+                } else {
+                    record.pos()
+                };
+                umi.extend(format!(":{}", start).bytes());
+                umi
+            })
+        })
+    }
+
     fn is_paired(&self) -> bool {
         match self {
             Evidence::SingleEndSequencingRead(read) => read.is_paired(),
@@ -706,6 +856,23 @@ impl Evidence {
         }
     }
 
+    /// Length of the sequenced fragment. For single reads of a pair (e.g. because the mate
+    /// does not overlap the variant), it is taken from the TLEN field.
+    fn fragment_len(&self) -> Option<u32> {
+        match self {
+            Evidence::SingleEndSequencingRead(rec) => {
+                if rec.is_paired() && !rec.is_mate_unmapped() && rec.tid() == rec.mtid() {
+                    Some(rec.insert_size().unsigned_abs() as u32).filter(|len| *len > 0)
+                } else {
+                    None
+                }
+            }
+            Evidence::PairedEndSequencingRead { left, right } => {
+                estimate_insert_size(left, right).ok().map(|len| len as u32)
+            }
+        }
+    }
+
     fn len(&self) -> usize {
         match self {
             Evidence::SingleEndSequencingRead(rec) => rec.seq_len(),
@@ -724,16 +891,30 @@ impl Evidence {
         }
     }
 
-    fn alt_loci(&self) -> ExactAltLoci {
+    fn alt_loci(&self, alt_hit_parser: &dyn AltHitParser) -> ExactAltLoci {
         match self {
-            Evidence::SingleEndSequencingRead(rec) => ExactAltLoci::from(rec.as_ref()),
+            Evidence::SingleEndSequencingRead(rec) => {
+                ExactAltLoci::from_record(rec.as_ref(), alt_hit_parser)
+            }
             Evidence::PairedEndSequencingRead { left, right } => {
-                let mut left = ExactAltLoci::from(left.as_ref());
-                left.inner.extend(ExactAltLoci::from(right.as_ref()).inner);
+                let mut left = ExactAltLoci::from_record(left.as_ref(), alt_hit_parser);
+                left.inner
+                    .extend(ExactAltLoci::from_record(right.as_ref(), alt_hit_parser).inner);
                 left
             }
         }
     }
+
+    /// Whether the read mapper reports an (almost) equally good alternative hit for any
+    /// of the records.
+    fn has_ambiguous_alt_hit(&self, alt_hit_parser: &dyn AltHitParser) -> bool {
+        match self {
+            Evidence::SingleEndSequencingRead(rec) => alt_hit_parser.has_ambiguous_hit(rec),
+            Evidence::PairedEndSequencingRead { left, right } => {
+                alt_hit_parser.has_ambiguous_hit(left) || alt_hit_parser.has_ambiguous_hit(right)
+            }
+        }
+    }
 }
 
 impl PartialEq for Evidence {
@@ -783,3 +964,137 @@ impl Hash for EvidenceIdentifier {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bam::record::Aux;
+
+    fn observation(
+        prob_alt: f64,
+        prob_ref: f64,
+        prob_mapping: f64,
+        strand: Strand,
+        prob_competing_alleles: &[f64],
+    ) -> ReadObservation {
+        ReadObservation {
+            prob_mapping: LogProb(prob_mapping.ln()),
+            prob_mismapping: LogProb((1.0 - prob_mapping).ln()),
+            prob_alt: LogProb(prob_alt.ln()),
+            prob_ref: LogProb(prob_ref.ln()),
+            prob_competing_alleles: prob_competing_alleles
+                .iter()
+                .map(|prob| LogProb(prob.ln()))
+                .collect(),
+            prob_missed_allele: LogProb(0.5_f64.ln()),
+            strand,
+            is_max_mapq: true,
+            ..Default::default()
+        }
+    }
+
+    fn evidence(pos: i64, mpos: Option<i64>, aux: Vec<(&[u8], Aux<'_>)>) -> Evidence {
+        let mut record = bam::Record::new();
+        record.set(b"read", None, b"ACGT", &[40; 4]);
+        record.set_pos(pos);
+        if let Some(mpos) = mpos {
+            record.set_paired();
+            record.set_mpos(mpos);
+        }
+        for (tag, value) in aux {
+            record.push_aux(tag, value).unwrap();
+        }
+        Evidence::SingleEndSequencingRead(Rc::new(record))
+    }
+
+    #[test]
+    fn test_umi_family_consensus() {
+        let mut second = observation(0.8, 0.1, 0.9, Strand::Reverse, &[]);
+        second.is_max_mapq = false;
+        second.third_allele_evidence = Some(2);
+        let family = vec![
+            observation(0.9, 0.2, 0.99, Strand::Forward, &[0.3]),
+            second,
+            observation(0.7, 0.1, 0.95, Strand::Forward, &[0.2]),
+        ];
+
+        let consensus = ReadObservation::umi_family_consensus(family);
+        assert_eq!(consensus.family_size, Some(3));
+        // probabilities of the reads multiply, missing competing alleles are taken from REF
+        assert_relative_eq!(consensus.prob_alt.exp(), 0.9 * 0.8 * 0.7, epsilon = 1e-9);
+        assert_relative_eq!(consensus.prob_ref.exp(), 0.2 * 0.1 * 0.1, epsilon = 1e-9);
+        assert_eq!(consensus.prob_competing_alleles.len(), 1);
+        assert_relative_eq!(
+            consensus.prob_competing_alleles[0].exp(),
+            0.3 * 0.1 * 0.2,
+            epsilon = 1e-9
+        );
+        assert_relative_eq!(consensus.prob_missed_allele.exp(), 0.125, epsilon = 1e-9);
+        // the worst mapping quality of the family is taken
+        assert_relative_eq!(consensus.prob_mapping.exp(), 0.9, epsilon = 1e-9);
+        assert_relative_eq!(consensus.prob_mismapping.exp(), 0.1, epsilon = 1e-9);
+        assert!(!consensus.is_max_mapq);
+        assert_eq!(consensus.strand, Strand::Both);
+        assert_eq!(consensus.third_allele_evidence, Some(2));
+    }
+
+    #[test]
+    fn test_umi_family_consensus_singleton() {
+        let consensus = ReadObservation::umi_family_consensus(vec![observation(
+            0.9,
+            0.2,
+            0.99,
+            Strand::Forward,
+            &[],
+        )]);
+        assert_eq!(consensus.family_size, Some(1));
+        assert_relative_eq!(consensus.prob_alt.exp(), 0.9, epsilon = 1e-9);
+        assert_relative_eq!(consensus.prob_ref.exp(), 0.2, epsilon = 1e-9);
+        assert!(consensus.prob_competing_alleles.is_empty());
+    }
+
+    #[test]
+    fn test_umi_family_mi() {
+        // MI takes precedence over RX and the position of the fragment
+        let first = evidence(
+            100,
+            None,
+            vec![(b"MI", Aux::String("7")), (b"RX", Aux::String("ACGT"))],
+        );
+        let second = evidence(
+            500,
+            None,
+            vec![(b"MI", Aux::String("7")), (b"RX", Aux::String("TTTT"))],
+        );
+        assert_eq!(first.umi_family(), Some(b"7".to_vec()));
+        assert_eq!(first.umi_family(), second.umi_family());
+
+        // integer MI values are supported as well
+        let integer = evidence(100, None, vec![(b"MI", Aux::I32(7))]);
+        assert_eq!(integer.umi_family(), first.umi_family());
+
+        assert_eq!(evidence(100, None, vec![]).umi_family(), None);
+    }
+
+    #[test]
+    fn test_umi_family_rx() {
+        let rx = || vec![(&b"RX"[..], Aux::String("ACGT"))];
+        // both mates of a pair belong to the same family
+        let left = evidence(100, Some(300), rx());
+        let right = evidence(300, Some(100), rx());
+        assert_eq!(left.umi_family(), Some(b"ACGT:100".to_vec()));
+        assert_eq!(left.umi_family(), right.umi_family());
+
+        // same UMI sequence, but a different fragment start
+        let other = evidence(150, Some(300), rx());
+        assert_ne!(left.umi_family(), other.umi_family());
+
+        // different UMI sequence at the same fragment start
+        let other = evidence(100, Some(300), vec![(b"RX", Aux::String("TTTT"))]);
+        assert_ne!(left.umi_family(), other.umi_family());
+
+        // single end reads are grouped by their own start
+        let single = evidence(120, None, rx());
+        assert_eq!(single.umi_family(), Some(b"ACGT:120".to_vec()));
+    }
+}
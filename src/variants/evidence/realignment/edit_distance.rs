@@ -15,7 +15,7 @@ use bio::stats::LogProb;
 use crate::default_ref_base_emission;
 use crate::estimation::alignment_properties::AlignmentProperties;
 use crate::utils::homopolymers::HomopolymerIndelOperation;
-use crate::variants::evidence::realignment::pairhmm::{RefBaseEmission, EDIT_BAND};
+use crate::variants::evidence::realignment::pairhmm::{RefBaseEmission, EDIT_BAND, UNKNOWN_BASE};
 
 use super::pairhmm::{ReadVsAlleleEmission, VariantEmission};
 
@@ -150,10 +150,13 @@ impl EditDistanceCalculation {
         let l = read_seq.len();
         let read_seq = read_seq.collect();
         let num_bits = 64; // TODO consider using 128 here in comb with u128 for Myers
+                           // METHOD: unknown allele bases (e.g. beyond a single breakend) match any read base.
+        let mut builder = myers::MyersBuilder::new();
+        builder.text_wildcard(UNKNOWN_BASE);
         let myers = if l <= num_bits {
-            Myers::Short(myers::Myers::new(&read_seq))
+            Myers::Short(builder.build_64(&read_seq))
         } else {
-            Myers::Long(long::Myers::new(&read_seq))
+            Myers::Long(builder.build_long_64(&read_seq))
         };
 
         EditDistanceCalculation { myers, read_seq }
@@ -5,6 +5,7 @@
 
 use std::cmp;
 use std::cmp::Ordering;
+use std::iter;
 use std::ops::Range;
 use std::str;
 use std::sync::Arc;
@@ -18,6 +19,7 @@ use bio_types::genome;
 use bio_types::genome::AbstractInterval;
 use itertools::Itertools;
 use rust_htslib::bam;
+use rust_htslib::bam::record::Cigar;
 
 use crate::errors::Error;
 use crate::estimation::alignment_properties::AlignmentProperties;
@@ -26,7 +28,7 @@ use crate::utils;
 use crate::variants::evidence::observations::read_observation::Strand;
 use crate::variants::evidence::realignment::edit_distance::EditDistanceCalculation;
 use crate::variants::evidence::realignment::pairhmm::{ReadEmission, ReferenceEmissionParams};
-use crate::variants::types::{AlleleSupport, AlleleSupportBuilder, SingleLocus};
+use crate::variants::types::{AlleleSupport, AlleleSupportBuilder, MultiLocus, SingleLocus};
 
 pub(crate) mod edit_distance;
 pub(crate) mod pairhmm;
@@ -45,6 +47,77 @@ pub(crate) struct CandidateRegion {
     ref_interval: Range<usize>,
 }
 
+/// Return the reference intervals of the aligned blocks of the given record that are separated
+/// by splice junctions (N CIGAR operations).
+fn exon_blocks(record: &bam::Record) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut start = record.pos() as usize;
+    let mut pos = start;
+    for c in record.cigar_cached().unwrap().iter() {
+        match c {
+            Cigar::RefSkip(l) => {
+                blocks.push(start..pos);
+                pos += *l as usize;
+                start = pos;
+            }
+            Cigar::Match(l) | Cigar::Equal(l) | Cigar::Diff(l) | Cigar::Del(l) => {
+                pos += *l as usize
+            }
+            Cigar::Ins(_) | Cigar::SoftClip(_) | Cigar::HardClip(_) | Cigar::Pad(_) => (),
+        }
+    }
+    blocks.push(start..pos);
+    blocks
+}
+
+/// Return sequence patches (target position and source interval) that move the exon blocks of
+/// a spliced read next to the block containing the given reference position, thereby
+/// concatenating them along the spliced path of the read. The coordinates of the anchoring
+/// block stay unchanged. The outermost blocks are extended by the given margin.
+fn splice_patches(
+    blocks: &[Range<usize>],
+    pos: usize,
+    margin: usize,
+    contig_len: usize,
+) -> Vec<(usize, Range<usize>)> {
+    let mut patches = Vec::new();
+    if blocks.len() < 2 {
+        return patches;
+    }
+    let anchor = blocks
+        .iter()
+        .rposition(|block| block.start <= pos)
+        .unwrap_or(0);
+
+    // upstream blocks are placed right before the anchor, from the inside out
+    let mut target_end = blocks[anchor].start;
+    for (i, block) in blocks[..anchor].iter().enumerate().rev() {
+        let start = if i == 0 {
+            block.start.saturating_sub(margin)
+        } else {
+            block.start
+        };
+        let len = cmp::min(block.end - start, target_end);
+        patches.push((target_end - len, block.end - len..block.end));
+        target_end -= len;
+    }
+
+    // downstream blocks are placed right after the anchor, from the inside out
+    let mut target_start = blocks[anchor].end;
+    for (i, block) in blocks.iter().enumerate().skip(anchor + 1) {
+        let end = if i == blocks.len() - 1 {
+            cmp::min(block.end + margin, contig_len)
+        } else {
+            block.end
+        };
+        let len = cmp::min(end - block.start, contig_len - target_start);
+        patches.push((target_start, block.start..block.start + len));
+        target_start += len;
+    }
+
+    patches
+}
+
 pub(crate) trait Realignable {
     fn alt_emission_params(
         &self,
@@ -52,15 +125,71 @@ pub(crate) trait Realignable {
         ref_interval: &genome::Interval,
         ref_window: usize,
     ) -> Result<Vec<Box<dyn RefBaseVariantEmission>>>;
+
+    /// Loci of the variant. When realigning against germline haplotypes, germline
+    /// substitutions overlapping them are not applied.
+    fn realignment_loci(&self) -> &MultiLocus;
+}
+
+/// Reference buffers of the phased germline haplotypes of a sample.
+#[derive(Clone)]
+pub(crate) struct GermlineHaplotypeBuffers {
+    buffers: Vec<Arc<reference::Buffer>>,
+}
+
+impl GermlineHaplotypeBuffers {
+    pub(crate) fn new(buffers: Vec<Arc<reference::Buffer>>) -> Self {
+        GermlineHaplotypeBuffers { buffers }
+    }
+
+    /// Return the haplotype buffers to realign against in the given reference interval, or
+    /// None if the haplotypes do not differ from the reference there. Germline substitutions
+    /// overlapping the mask are not applied. In that case, only the given windows of the
+    /// contig of the interval are retained.
+    fn for_interval(
+        &self,
+        interval: &genome::Interval,
+        mask: &[genome::Interval],
+        windows: &[Range<usize>],
+    ) -> Result<Option<Vec<Arc<reference::Buffer>>>> {
+        let germline_haplotypes = match self
+            .buffers
+            .first()
+            .and_then(|buffer| buffer.germline_haplotypes())
+        {
+            Some(germline_haplotypes) => germline_haplotypes,
+            None => return Ok(None),
+        };
+        if !germline_haplotypes.contains_variants(interval.contig(), &interval.range(), mask) {
+            return Ok(None);
+        }
+        if !germline_haplotypes.overlaps(mask) {
+            return Ok(Some(self.buffers.clone()));
+        }
+        Ok(Some(
+            self.buffers
+                .iter()
+                .map(|buffer| {
+                    buffer
+                        .masked(interval.contig(), windows, mask)
+                        .map(Arc::new)
+                })
+                .collect::<Result<Vec<_>>>()?,
+        ))
+    }
 }
 
 pub(crate) trait Realigner {
+    /// Determine the region of the read and the reference that shall be realigned.
+    /// For long reads, the read region is always anchored at a breakpoint of the variant.
     fn candidate_region(
         &self,
         record: &bam::Record,
         locus: &genome::Interval,
+        alignment_properties: &AlignmentProperties,
     ) -> Result<CandidateRegion> {
         let cigar = record.cigar_cached().unwrap();
+        let long_read = alignment_properties.read_type.is_long();
 
         let locus_start = locus.range().start;
         let locus_end = locus.range().end;
@@ -72,84 +201,114 @@ pub(crate) trait Realigner {
                 ..cmp::min(breakpoint + self.ref_window(), ref_seq.len())
         };
 
-        Ok(
-            match (
-                cigar.read_pos(locus_start as u32, true, true)?,
-                cigar.read_pos(locus_end as u32, true, true)?,
-            ) {
-                // read encloses variant
-                (Some(qstart), Some(qend)) => {
-                    let qstart = qstart as usize;
-                    // exclusive end of variant
-                    let qend = qend as usize;
-                    // ensure that distance between qstart and qend does not make the window too
-                    // large
-                    let max_window =
-                        (self.max_window() as usize).saturating_sub((qend - qstart) / 2);
-                    let mut read_offset = qstart.saturating_sub(max_window);
-                    let mut read_end = cmp::min(qend + max_window, record.seq_len());
-
-                    // correct for reads that enclose the entire variant while that exceeds the maximum pattern len
-                    let exceed = (read_end - read_offset)
-                        .saturating_sub(EditDistanceCalculation::max_pattern_len());
-                    if exceed > 0 {
-                        read_offset += exceed / 2;
-                        read_end -= (exceed as f64 / 2.0).ceil() as usize;
-                    }
+        let region = match (
+            cigar.read_pos(locus_start as u32, true, true)?,
+            cigar.read_pos(locus_end as u32, true, true)?,
+        ) {
+            // long read encloses variant
+            (Some(qstart), Some(_)) if long_read => {
+                // METHOD: long reads may enclose large variants (e.g. insertions of
+                // several kb). Centering the window on the variant would then place it
+                // far away from both breakpoints. Instead, we bound it around the left
+                // breakpoint.
+                let qstart = qstart as usize;
+                let read_offset = qstart.saturating_sub(self.max_window() as usize);
+                let read_end = cmp::min(qstart + self.max_window() as usize, record.seq_len());
+
+                CandidateRegion {
+                    overlap: true,
+                    read_interval: read_offset..read_end,
+                    ref_interval: ref_interval(locus_start as usize),
+                }
+            }
 
-                    CandidateRegion {
-                        overlap: true,
-                        read_interval: read_offset..read_end,
-                        ref_interval: ref_interval(locus_start as usize),
-                    }
+            // read encloses variant
+            (Some(qstart), Some(qend)) => {
+                let qstart = qstart as usize;
+                // exclusive end of variant
+                let qend = qend as usize;
+                // ensure that distance between qstart and qend does not make the window too
+                // large
+                let max_window = (self.max_window() as usize).saturating_sub((qend - qstart) / 2);
+                let mut read_offset = qstart.saturating_sub(max_window);
+                let mut read_end = cmp::min(qend + max_window, record.seq_len());
+
+                // correct for reads that enclose the entire variant while that exceeds the maximum pattern len
+                let exceed = (read_end - read_offset)
+                    .saturating_sub(EditDistanceCalculation::max_pattern_len());
+                if exceed > 0 {
+                    read_offset += exceed / 2;
+                    read_end -= (exceed as f64 / 2.0).ceil() as usize;
                 }
 
-                // read overlaps from right
-                (Some(qstart), None) => {
-                    let qstart = qstart as usize;
-                    let read_offset = qstart.saturating_sub(self.max_window() as usize);
-                    let read_end = cmp::min(qstart + self.max_window() as usize, record.seq_len());
+                CandidateRegion {
+                    overlap: true,
+                    read_interval: read_offset..read_end,
+                    ref_interval: ref_interval(locus_start as usize),
+                }
+            }
 
-                    CandidateRegion {
-                        overlap: true,
-                        read_interval: read_offset..read_end,
-                        ref_interval: ref_interval(locus_start as usize),
-                    }
+            // read overlaps from right
+            (Some(qstart), None) => {
+                let qstart = qstart as usize;
+                let read_offset = qstart.saturating_sub(self.max_window() as usize);
+                let read_end = cmp::min(qstart + self.max_window() as usize, record.seq_len());
+
+                CandidateRegion {
+                    overlap: true,
+                    read_interval: read_offset..read_end,
+                    ref_interval: ref_interval(locus_start as usize),
                 }
+            }
 
-                // read overlaps from left
-                (None, Some(qend)) => {
-                    let qend = qend as usize;
-                    let read_offset = qend.saturating_sub(self.max_window() as usize);
-                    let read_end = cmp::min(qend + self.max_window() as usize, record.seq_len());
+            // read overlaps from left
+            (None, Some(qend)) => {
+                let qend = qend as usize;
+                let read_offset = qend.saturating_sub(self.max_window() as usize);
+                let read_end = cmp::min(qend + self.max_window() as usize, record.seq_len());
 
-                    CandidateRegion {
-                        overlap: true,
-                        read_interval: read_offset..read_end,
-                        ref_interval: ref_interval(locus_end as usize),
-                    }
+                CandidateRegion {
+                    overlap: true,
+                    read_interval: read_offset..read_end,
+                    ref_interval: ref_interval(locus_end as usize),
                 }
+            }
 
-                // no overlap
-                (None, None) => {
-                    let m = record.seq_len() / 2;
-                    let read_offset = m.saturating_sub(self.max_window() as usize);
-                    let read_end = cmp::min(m + self.max_window() as usize - 1, record.seq_len());
-                    let breakpoint = record.pos() as usize + m;
-                    // The following should only happen with deletions.
-                    // It occurs if the read comes from ref allele and is mapped within start
-                    // and end of deletion. Usually, such reads strongly support the ref allele.
-                    let read_enclosed_by_variant =
-                        record.pos() >= locus_start as i64 && cigar.end_pos() <= locus_end as i64;
-
-                    CandidateRegion {
-                        overlap: read_enclosed_by_variant,
-                        read_interval: read_offset..read_end,
-                        ref_interval: ref_interval(breakpoint),
+            // no overlap
+            (None, None) => {
+                // METHOD: for long reads, the middle of the read can be far away from the
+                // variant. Hence, we take the end of the read that is closest to the variant:
+                // the end of reads left of the variant and the beginning of all others.
+                let m = if long_read {
+                    if cigar.end_pos() <= locus_start as i64 {
+                        cmp::max(
+                            record.seq_len().saturating_sub(self.max_window() as usize),
+                            record.seq_len() / 2,
+                        )
+                    } else {
+                        cmp::min(self.max_window() as usize, record.seq_len() / 2)
                     }
+                } else {
+                    record.seq_len() / 2
+                };
+                let read_offset = m.saturating_sub(self.max_window() as usize);
+                let read_end = cmp::min(m + self.max_window() as usize - 1, record.seq_len());
+                let breakpoint = record.pos() as usize + m;
+                // The following should only happen with deletions.
+                // It occurs if the read comes from ref allele and is mapped within start
+                // and end of deletion. Usually, such reads strongly support the ref allele.
+                let read_enclosed_by_variant =
+                    record.pos() >= locus_start as i64 && cigar.end_pos() <= locus_end as i64;
+
+                CandidateRegion {
+                    overlap: read_enclosed_by_variant,
+                    read_interval: read_offset..read_end,
+                    ref_interval: ref_interval(breakpoint),
                 }
-            },
-        )
+            }
+        };
+
+        Ok(region)
     }
 
     fn ref_window(&self) -> usize {
@@ -163,7 +322,7 @@ pub(crate) trait Realigner {
         record: &'a bam::Record,
         loci: L,
         variant: &V,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
         alignment_properties: &AlignmentProperties,
     ) -> Result<AlleleSupport>
     where
@@ -176,7 +335,7 @@ pub(crate) trait Realigner {
             .into_iter()
             .filter_map(|locus| {
                 if locus.as_ref().contig() == record.contig() {
-                    Some(self.candidate_region(record, locus.as_ref()))
+                    Some(self.candidate_region(record, locus.as_ref(), alignment_properties))
                 } else {
                     None
                 }
@@ -192,6 +351,7 @@ pub(crate) trait Realigner {
             return Ok(AlleleSupportBuilder::default()
                 .prob_ref_allele(p)
                 .prob_alt_allele(p)
+                .prob_competing_alleles(vec![p; alt_variants.len()])
                 .strand(Strand::None)
                 .third_allele_evidence(None)
                 .build()
@@ -225,10 +385,10 @@ pub(crate) trait Realigner {
         // Calculate independent probabilities over all merged regions.
         let mut prob_ref_all = LogProb::ln_one();
         let mut prob_alt_all = LogProb::ln_one();
+        let mut prob_competing_all = vec![LogProb::ln_one(); alt_variants.len()];
         let mut alt_edit_dist: Option<EditDistance> = None;
         let mut is_third_allele = false;
 
-        let ref_seq = self.ref_buffer().seq(record.contig())?;
         let read_seq: bam::record::Seq<'a> = record.seq();
         let read_qual = record.qual();
 
@@ -236,6 +396,22 @@ pub(crate) trait Realigner {
         let mut strand = Strand::None;
         let mut homopolymer_indel_len = None;
 
+        let mask = variant
+            .realignment_loci()
+            .iter()
+            .chain(
+                alt_variants
+                    .iter()
+                    .flat_map(|alt_variant| alt_variant.realignment_loci().iter()),
+            )
+            .map(|locus| (**locus).clone())
+            .collect_vec();
+        let exon_blocks = if alignment_properties.spliced {
+            exon_blocks(record)
+        } else {
+            Vec::new()
+        };
+
         for region in merged_regions {
             // read emission
             let read_emission = ReadEmission::new(
@@ -247,105 +423,128 @@ pub(crate) trait Realigner {
             let mut edit_dist_calc =
                 EditDistanceCalculation::new(region.read_interval.clone().map(|i| read_seq[i]));
 
-            // Prepare reference alleles (the actual reference and any alt variants).
-            // METHOD: here, we consider all alternative variants, together with the reference allele.
-            // The method prob_allele below then chooses the most likely allele of origin via edit distance.
-            // On ties, it computes the full likelihoods via pair HMMs and chooses the maximum.
-            // This is an approximation of calculating full VAFs for all possible variants at a locus,
-            // under the assumption that in reality usually there will be just one of them with a high
-            // probability, and if not, the highest is still a good representative for comparison
-            // with the variant allele.
-            let mut ref_emissions = vec![ReadVsAlleleEmission::new(
-                &read_emission,
-                Box::new(ReferenceEmissionParams {
-                    ref_seq: Arc::clone(&ref_seq),
-                    ref_offset: region.ref_interval.start,
-                    ref_end: region.ref_interval.end,
-                    ref_offset_override: None,
-                    ref_end_override: None,
-                }),
-            )];
-            for variant in alt_variants {
-                ref_emissions.extend(
-                    variant
-                        .alt_emission_params(
-                            Arc::clone(self.ref_buffer()),
-                            &genome::Interval::new(
-                                record.contig().to_owned(),
-                                region.ref_interval.start as u64..region.ref_interval.end as u64,
-                            ),
-                            self.ref_window(),
-                        )?
-                        .into_iter()
-                        .map(|allele_emission| {
-                            ReadVsAlleleEmission::new(&read_emission, allele_emission)
-                        }),
-                );
-            }
-
-            // ref allele (or one of the alt variants)
-            let (mut prob_ref, _, _) = self.prob_allele(
-                &mut ref_emissions,
-                &mut edit_dist_calc,
-                alignment_properties,
-                false,
+            let ref_interval = genome::Interval::new(
+                record.contig().to_owned(),
+                region.ref_interval.start as u64..region.ref_interval.end as u64,
             );
-
-            let mut alt_emission_params = variant
-                .alt_emission_params(
-                    Arc::clone(self.ref_buffer()),
-                    &genome::Interval::new(
-                        record.contig().to_owned(),
-                        region.ref_interval.start as u64..region.ref_interval.end as u64,
-                    ),
-                    self.ref_window(),
-                )?
-                .into_iter()
-                .map(|allele_emission| ReadVsAlleleEmission::new(&read_emission, allele_emission))
+            // METHOD: for spliced reads, the parts of the read behind a splice junction stem from
+            // distant exons. Hence, the exons of the read are concatenated along its spliced
+            // path, such that the entire read region can be realigned against the reference
+            // window. Since the exon containing the region stays in place, the coordinates of
+            // the variant remain valid.
+            let crosses_splice_junction = exon_blocks.windows(2).any(|blocks| {
+                blocks[0].end < region.ref_interval.end
+                    && blocks[1].start > region.ref_interval.start
+            });
+            let splice_patches = if crosses_splice_junction {
+                splice_patches(
+                    &exon_blocks,
+                    (region.ref_interval.start + region.ref_interval.end) / 2,
+                    record.seq_len(),
+                    self.ref_buffer().seq(record.contig())?.len(),
+                )
+            } else {
+                Vec::new()
+            };
+            // Windows of the contig that are needed for realigning the region: the reference
+            // interval and the loci of the variant (and its competing alleles), with a margin
+            // for emission parameters that reach beyond them.
+            let margin = self.ref_window() + record.seq_len();
+            let windows = mask
+                .iter()
+                .filter(|locus| locus.contig() == record.contig())
+                .map(|locus| locus.range())
+                .chain(iter::once(
+                    region.ref_interval.start as u64..region.ref_interval.end as u64,
+                ))
+                .map(|range| {
+                    (range.start as usize).saturating_sub(margin)..range.end as usize + margin
+                })
                 .collect_vec();
 
-            let (mut prob_alt, alt_hit, alt_params_idx) = self.prob_allele(
-                &mut alt_emission_params,
-                &mut edit_dist_calc,
-                alignment_properties,
-                false,
-            );
-
-            assert!(!prob_ref.is_nan());
-            assert!(!prob_alt.is_nan());
-
-            if prob_alt > prob_ref {
-                // METHOD: If the read has so many edits that it is not plausible that it comes from the
-                // alt allele (more edits than expected by the sequencing error rates) and also not plausible that
-                // it comes from the ref allele, calculate the probability
-                // that it comes from an allele that is inferred from the read sequence itself, and use that as a
-                // contrast to the alt allele instead of prob_ref.
-
-                // Take emission params and undo any shrinkage that has been applied before, because
-                // our alignments are relative to the not shrunken parameters.
-                // TODO: think about a way to ensure this in a typing based approach.
-                let alt_params = &mut alt_emission_params[alt_params_idx];
-                alt_params.clear_ref_offset_override();
-                alt_params.clear_ref_end_override();
-
-                if let Some(mut read_inferred_allele) =
-                    edit_dist_calc.derive_allele_from_read(alt_params, &alt_hit)
+            let ref_buffers = if let Some(germline_haplotype_buffers) =
+                self.germline_haplotype_buffers()
+            {
+                // METHOD: germline substitutions overlapping the loci of the variant are
+                // only reverted within the windows needed for realignment (including the
+                // exons the read is patched together from).
+                let masked_windows = windows
+                    .iter()
+                    .cloned()
+                    .chain(splice_patches.iter().map(|(_, source)| source.clone()))
+                    .collect_vec();
+                germline_haplotype_buffers.for_interval(&ref_interval, &mask, &masked_windows)?
+            } else {
+                None
+            }
+            .unwrap_or_else(|| vec![Arc::clone(self.ref_buffer())]);
+            let ref_buffers = if splice_patches.is_empty() {
+                ref_buffers
+            } else {
+                ref_buffers
+                    .iter()
+                    .map(|ref_buffer| {
+                        ref_buffer
+                            .patched(record.contig(), &windows, &splice_patches)
+                            .map(Arc::new)
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            };
+
+            // METHOD: if there are phased germline variants in the region, both alleles are
+            // built on top of each germline haplotype, and the allele probabilities are
+            // marginalized over the haplotypes (assuming that both are equally likely
+            // to be the origin of the read). Otherwise, reads carrying the germline variants
+            // would be penalized on both alleles. Germline substitutions at the loci of the
+            // variant and its competing alleles are left out, because otherwise the reference
+            // allele of a haplotype could already carry the variant itself (e.g. if the
+            // candidate is a germline variant). Hit information (e.g. for third allele
+            // evidence) is taken from the haplotype that explains the read best.
+            let mut probs_ref = Vec::with_capacity(ref_buffers.len());
+            let mut probs_alt = Vec::with_capacity(ref_buffers.len());
+            let mut probs_competing =
+                vec![Vec::with_capacity(ref_buffers.len()); alt_variants.len()];
+            let mut best = None;
+            for ref_buffer in &ref_buffers {
+                let (prob_ref, prob_alt, prob_competing, alt_hit, is_third) = self
+                    .region_allele_probs(
+                        &ref_interval,
+                        ref_buffer,
+                        &read_emission,
+                        &mut edit_dist_calc,
+                        variant,
+                        alt_variants,
+                        alignment_properties,
+                    )?;
+                let prob_total = prob_ref.ln_add_exp(prob_alt);
+                if best
+                    .as_ref()
+                    .is_none_or(|(best_prob_total, _, _)| prob_total > *best_prob_total)
                 {
-                    let third_allele_hit = edit_dist_calc
-                        .calc_best_hit(&read_inferred_allele, None, alignment_properties, true)
-                        .expect(
-                            "bug: no hit obtained when aligning against third allele. \
-                        This is impossible because the third allele is derived from \
-                        the read.",
-                        );
-                    let prob_read_inferred =
-                        self.calculate_prob_allele(&third_allele_hit, &mut read_inferred_allele);
-                    if prob_read_inferred > prob_ref {
-                        prob_ref = prob_read_inferred;
-                        is_third_allele = true;
-                    }
+                    best = Some((prob_total, alt_hit, is_third));
+                }
+                probs_ref.push(prob_ref);
+                probs_alt.push(prob_alt);
+                for (probs, prob) in probs_competing.iter_mut().zip(prob_competing) {
+                    probs.push(prob);
                 }
             }
+            let (_, alt_hit, is_third) = best.unwrap();
+            is_third_allele |= is_third;
+            let prob_haplotype = LogProb((ref_buffers.len() as f64).recip().ln());
+            let marginalize = |probs: &[LogProb]| {
+                if probs.len() == 1 {
+                    probs[0]
+                } else {
+                    LogProb::ln_sum_exp(probs) + prob_haplotype
+                }
+            };
+            let mut prob_ref = marginalize(&probs_ref);
+            let mut prob_alt = marginalize(&probs_alt);
+            let mut prob_competing = probs_competing
+                .iter()
+                .map(|probs| marginalize(probs))
+                .collect_vec();
 
             // METHOD: Normalize probabilities. By this, we avoid biases due to proximal variants that are in
             // cis with the considered one. They are normalized away since they affect both ref and alt.
@@ -354,6 +553,8 @@ pub(crate) trait Realigner {
             // equally bad, and the normalized one will not prefer any of them.
             // This is ok, because for the likelihood function only the ratio between the two
             // probabilities is relevant!
+            // Competing ALT alleles at the same site are normalized by the same constant, such
+            // that the ratios between all alleles are retained.
 
             if prob_ref != LogProb::ln_zero() && prob_alt != LogProb::ln_zero() {
                 // METHOD: Only perform normalization if both probs are non-zero
@@ -362,6 +563,9 @@ pub(crate) trait Realigner {
                 let prob_total = prob_alt.ln_add_exp(prob_ref);
                 prob_ref -= prob_total;
                 prob_alt -= prob_total;
+                for prob in &mut prob_competing {
+                    *prob -= prob_total;
+                }
             }
 
             if prob_ref == LogProb::ln_zero() && prob_alt == LogProb::ln_zero() {
@@ -404,6 +608,9 @@ pub(crate) trait Realigner {
             // METHOD: probabilities of independent regions are combined here.
             prob_ref_all += prob_ref;
             prob_alt_all += prob_alt;
+            for (prob_all, prob) in prob_competing_all.iter_mut().zip(prob_competing) {
+                *prob_all += prob;
+            }
         }
 
         if aux_strand_info.is_none() && prob_ref_all != prob_alt_all {
@@ -417,11 +624,131 @@ pub(crate) trait Realigner {
             .homopolymer_indel_len(homopolymer_indel_len)
             .prob_ref_allele(prob_ref_all)
             .prob_alt_allele(prob_alt_all)
+            .prob_competing_alleles(prob_competing_all)
             .third_allele_evidence(if is_third_allele { alt_edit_dist } else { None })
             .build()
             .unwrap())
     }
 
+    /// Calculate the probabilities of the read to stem from the reference allele, from the alt
+    /// allele, and from each of the given competing alt variants within the given reference
+    /// interval, with all alleles built from the given reference buffer. Returns the
+    /// probabilities, the best alt hit, and whether the read provides third allele evidence.
+    #[allow(clippy::too_many_arguments, clippy::type_complexity)]
+    fn region_allele_probs<V>(
+        &mut self,
+        ref_interval: &genome::Interval,
+        ref_buffer: &Arc<reference::Buffer>,
+        read_emission: &ReadEmission,
+        edit_dist_calc: &mut EditDistanceCalculation,
+        variant: &V,
+        alt_variants: &[Box<dyn Realignable + Send>],
+        alignment_properties: &AlignmentProperties,
+    ) -> Result<(LogProb, LogProb, Vec<LogProb>, EditDistanceHit, bool)>
+    where
+        V: Realignable,
+    {
+        let mut is_third_allele = false;
+
+        // ref allele
+        let mut ref_emissions = vec![ReadVsAlleleEmission::new(
+            read_emission,
+            Box::new(ReferenceEmissionParams {
+                ref_seq: ref_buffer.seq(ref_interval.contig())?,
+                ref_offset: ref_interval.range().start as usize,
+                ref_end: ref_interval.range().end as usize,
+                ref_offset_override: None,
+                ref_end_override: None,
+            }),
+        )];
+        let (mut prob_ref, _, _) = self.prob_allele(
+            &mut ref_emissions,
+            edit_dist_calc,
+            alignment_properties,
+            false,
+        );
+
+        // METHOD: each alternative variant at the same site (i.e. each competing ALT allele)
+        // obtains its own probability, such that the likelihood function can assign the read
+        // to the allele it stems from, with the allele frequencies of all ALT alleles jointly
+        // located on the simplex.
+        let mut prob_competing = Vec::with_capacity(alt_variants.len());
+        for alt_variant in alt_variants {
+            let mut competing_emissions = alt_variant
+                .alt_emission_params(Arc::clone(ref_buffer), ref_interval, self.ref_window())?
+                .into_iter()
+                .map(|allele_emission| ReadVsAlleleEmission::new(read_emission, allele_emission))
+                .collect_vec();
+            let (prob, _, _) = self.prob_allele(
+                &mut competing_emissions,
+                edit_dist_calc,
+                alignment_properties,
+                false,
+            );
+            prob_competing.push(prob);
+        }
+
+        let mut alt_emission_params = variant
+            .alt_emission_params(Arc::clone(ref_buffer), ref_interval, self.ref_window())?
+            .into_iter()
+            .map(|allele_emission| ReadVsAlleleEmission::new(read_emission, allele_emission))
+            .collect_vec();
+
+        let (prob_alt, alt_hit, alt_params_idx) = self.prob_allele(
+            &mut alt_emission_params,
+            edit_dist_calc,
+            alignment_properties,
+            false,
+        );
+
+        assert!(!prob_ref.is_nan());
+        assert!(!prob_alt.is_nan());
+
+        // the best explanation of the read apart from the alt allele
+        let prob_other = prob_competing.iter().fold(prob_ref, |prob_other, prob| {
+            if *prob > prob_other {
+                *prob
+            } else {
+                prob_other
+            }
+        });
+
+        if prob_alt > prob_other {
+            // METHOD: If the read has so many edits that it is not plausible that it comes from the
+            // alt allele (more edits than expected by the sequencing error rates) and also not plausible that
+            // it comes from the ref allele (or one of the competing alleles), calculate the probability
+            // that it comes from an allele that is inferred from the read sequence itself, and use that as a
+            // contrast to the alt allele instead of prob_ref.
+
+            // Take emission params and undo any shrinkage that has been applied before, because
+            // our alignments are relative to the not shrunken parameters.
+            // TODO: think about a way to ensure this in a typing based approach.
+            let alt_params = &mut alt_emission_params[alt_params_idx];
+            alt_params.clear_ref_offset_override();
+            alt_params.clear_ref_end_override();
+
+            if let Some(mut read_inferred_allele) =
+                edit_dist_calc.derive_allele_from_read(alt_params, &alt_hit)
+            {
+                let third_allele_hit = edit_dist_calc
+                    .calc_best_hit(&read_inferred_allele, None, alignment_properties, true)
+                    .expect(
+                        "bug: no hit obtained when aligning against third allele. \
+                    This is impossible because the third allele is derived from \
+                    the read.",
+                    );
+                let prob_read_inferred =
+                    self.calculate_prob_allele(&third_allele_hit, &mut read_inferred_allele);
+                if prob_read_inferred > prob_other {
+                    prob_ref = prob_read_inferred;
+                    is_third_allele = true;
+                }
+            }
+        }
+
+        Ok((prob_ref, prob_alt, prob_competing, alt_hit, is_third_allele))
+    }
+
     /// Calculate probability of a certain allele.
     fn prob_allele(
         &mut self,
@@ -486,6 +813,11 @@ pub(crate) trait Realigner {
 
     fn ref_buffer(&self) -> &Arc<reference::Buffer>;
 
+    /// Reference buffers of the phased germline haplotypes of the sample (if known).
+    fn germline_haplotype_buffers(&self) -> Option<&GermlineHaplotypeBuffers> {
+        None
+    }
+
     fn max_window(&self) -> u64;
 }
 
@@ -495,12 +827,14 @@ pub(crate) struct PairHMMRealigner {
     pairhmm: PairHMM,
     max_window: u64,
     ref_buffer: Arc<reference::Buffer>,
+    germline_haplotype_buffers: GermlineHaplotypeBuffers,
 }
 
 impl PairHMMRealigner {
     /// Create a new instance.
     pub(crate) fn new(
         ref_buffer: Arc<reference::Buffer>,
+        germline_haplotype_buffers: Vec<Arc<reference::Buffer>>,
         gap_params: pairhmm::GapParams,
         max_window: u64,
     ) -> Self {
@@ -510,6 +844,7 @@ impl PairHMMRealigner {
             pairhmm,
             max_window,
             ref_buffer,
+            germline_haplotype_buffers: GermlineHaplotypeBuffers::new(germline_haplotype_buffers),
         }
     }
 }
@@ -519,6 +854,10 @@ impl Realigner for PairHMMRealigner {
         &self.ref_buffer
     }
 
+    fn germline_haplotype_buffers(&self) -> Option<&GermlineHaplotypeBuffers> {
+        Some(&self.germline_haplotype_buffers)
+    }
+
     fn max_window(&self) -> u64 {
         self.max_window
     }
@@ -548,6 +887,7 @@ pub(crate) struct PathHMMRealigner {
     gap_params: pairhmm::GapParams,
     max_window: u64,
     ref_buffer: Arc<reference::Buffer>,
+    germline_haplotype_buffers: GermlineHaplotypeBuffers,
     prob_no_gap: LogProb,
     prob_close_gap_x: LogProb,
     prob_close_gap_y: LogProb,
@@ -560,6 +900,7 @@ impl PathHMMRealigner {
         gap_params: pairhmm::GapParams,
         max_window: u64,
         ref_buffer: Arc<reference::Buffer>,
+        germline_haplotype_buffers: Vec<Arc<reference::Buffer>>,
     ) -> Self {
         let prob_no_gap = gap_params
             .prob_gap_x()
@@ -577,6 +918,7 @@ impl PathHMMRealigner {
             gap_params,
             max_window,
             ref_buffer,
+            germline_haplotype_buffers: GermlineHaplotypeBuffers::new(germline_haplotype_buffers),
             prob_no_gap,
             prob_close_gap_x,
             prob_close_gap_y,
@@ -591,6 +933,10 @@ impl Realigner for PathHMMRealigner {
         &self.ref_buffer
     }
 
+    fn germline_haplotype_buffers(&self) -> Option<&GermlineHaplotypeBuffers> {
+        Some(&self.germline_haplotype_buffers)
+    }
+
     fn max_window(&self) -> u64 {
         self.max_window
     }
@@ -682,12 +1028,14 @@ pub(crate) struct HomopolyPairHMMRealigner {
     pairhmm: HomopolyPairHMM,
     max_window: u64,
     ref_buffer: Arc<reference::Buffer>,
+    germline_haplotype_buffers: GermlineHaplotypeBuffers,
 }
 
 impl HomopolyPairHMMRealigner {
     /// Create a new instance.
     pub(crate) fn new(
         ref_buffer: Arc<reference::Buffer>,
+        germline_haplotype_buffers: Vec<Arc<reference::Buffer>>,
         gap_params: pairhmm::GapParams,
         hop_params: pairhmm::HopParams,
         max_window: u64,
@@ -698,6 +1046,7 @@ impl HomopolyPairHMMRealigner {
             pairhmm,
             max_window,
             ref_buffer,
+            germline_haplotype_buffers: GermlineHaplotypeBuffers::new(germline_haplotype_buffers),
         }
     }
 }
@@ -707,6 +1056,10 @@ impl Realigner for HomopolyPairHMMRealigner {
         &self.ref_buffer
     }
 
+    fn germline_haplotype_buffers(&self) -> Option<&GermlineHaplotypeBuffers> {
+        Some(&self.germline_haplotype_buffers)
+    }
+
     fn max_window(&self) -> u64 {
         self.max_window
     }
@@ -728,3 +1081,289 @@ impl Realigner for HomopolyPairHMMRealigner {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    use rust_htslib::bam::record::CigarString;
+
+    use super::*;
+    use crate::utils::aux_info::AuxInfo;
+    use crate::variants::evidence::observations::read_observation::Evidence;
+    use crate::variants::model::VariantPrecision;
+    use crate::variants::types::breakends::{Breakend, BreakendGroupBuilder};
+    use crate::variants::types::{Snv, Variant};
+
+    const REFERENCE: &[u8] = b"GATTACAGGTCCATGCAAGTCCGATCGTTAGCCATGACGTAGCTTGACCA";
+
+    fn write_reference(tmp: &tempfile::TempDir) -> PathBuf {
+        let reference = tmp.path().join("ref.fa");
+        fs::write(
+            &reference,
+            format!(">chr1\n{}\n", str::from_utf8(REFERENCE).unwrap()),
+        )
+        .unwrap();
+        fs::write(tmp.path().join("ref.fa.fai"), "chr1\t50\t6\t50\t51\n").unwrap();
+        reference
+    }
+
+    fn read(pos: i64, cigar: Vec<Cigar>, seq: &[u8]) -> bam::Record {
+        let mut header = bam::Header::new();
+        header.push_record(
+            bam::header::HeaderRecord::new(b"SQ")
+                .push_tag(b"SN", "chr1")
+                .push_tag(b"LN", 50),
+        );
+        let mut record = bam::Record::new();
+        record.set(
+            b"read",
+            Some(&CigarString(cigar)),
+            seq,
+            &vec![40; seq.len()],
+        );
+        record.set_tid(0);
+        record.set_pos(pos);
+        record.set_header(Rc::new(bam::HeaderView::from_header(&header)));
+        record.cache_cigar();
+        record
+    }
+
+    /// Realign a read stemming from the second germline haplotype, which carries both the
+    /// candidate SNV at position 20 and a germline SNV at position 25.
+    fn realign(germline_records: &str) -> AlleleSupport {
+        let tmp = tempfile::tempdir().unwrap();
+        let reference = write_reference(&tmp);
+        let vcf = tmp.path().join("germline.vcf");
+        fs::write(
+            &vcf,
+            format!(
+                "##fileformat=VCFv4.2\n\
+                 ##contig=<ID=chr1,length=50>\n\
+                 ##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n\
+                 #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tsample\n\
+                 {}",
+                germline_records
+            ),
+        )
+        .unwrap();
+
+        let germline_haplotypes = Arc::new(reference::GermlineHaplotypes::from_path(&vcf).unwrap());
+        let germline_haplotype_buffers = (0..2)
+            .map(|haplotype| {
+                Arc::new(
+                    reference::Buffer::germline_haplotype(
+                        &reference,
+                        1,
+                        Arc::clone(&germline_haplotypes),
+                        haplotype,
+                    )
+                    .unwrap(),
+                )
+            })
+            .collect();
+        let ref_buffer = Arc::new(reference::Buffer::from_path(&reference, 1).unwrap());
+        let mut realigner = PathHMMRealigner::new(
+            pairhmm::GapParams::default(),
+            100,
+            Arc::clone(&ref_buffer),
+            germline_haplotype_buffers,
+        );
+
+        let snv = Snv::new(
+            genome::Locus::new("chr1".to_owned(), 20),
+            b'C',
+            b'G',
+            realigner.clone(),
+            true,
+        );
+
+        let mut read_seq = REFERENCE[5..35].to_vec();
+        read_seq[15] = b'G';
+        read_seq[20] = b'A';
+        let record = read(5, vec![Cigar::Match(30)], &read_seq);
+
+        let alignment_properties: AlignmentProperties =
+            serde_json::from_str(r#"{"max_read_len": 30}"#).unwrap();
+
+        realigner
+            .allele_support(&record, snv.loci().iter(), &snv, &[], &alignment_properties)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_allele_support_candidate_is_germline_variant() {
+        assert_eq!(REFERENCE[20], b'C');
+        assert_eq!(REFERENCE[25], b'C');
+        let nearby = "chr1\t26\t.\tC\tA\t.\t.\t.\tGT\t0|1\n";
+        let support = realign(nearby);
+        // The candidate itself is a phased germline variant on the same haplotype.
+        let support_germline_candidate =
+            realign(&format!("chr1\t21\t.\tC\tG\t.\t.\t.\tGT\t0|1\n{}", nearby));
+
+        // The read clearly supports the ALT allele.
+        assert!(support.prob_alt_allele() > support.prob_ref_allele() + LogProb(10.0f64.ln()));
+        // The germline record of the candidate must not be applied to the reference allele,
+        // otherwise the support for the ALT allele would be diluted.
+        assert_relative_eq!(
+            *support_germline_candidate.prob_alt_allele(),
+            *support.prob_alt_allele(),
+            epsilon = 1e-6
+        );
+        assert_relative_eq!(
+            *support_germline_candidate.prob_ref_allele(),
+            *support.prob_ref_allele(),
+            epsilon = 1e-6
+        );
+    }
+
+    #[test]
+    fn test_exon_blocks() {
+        let record = read(
+            5,
+            vec![
+                Cigar::SoftClip(2),
+                Cigar::Match(5),
+                Cigar::RefSkip(10),
+                Cigar::Match(3),
+                Cigar::Del(1),
+                Cigar::Ins(2),
+                Cigar::Match(4),
+                Cigar::RefSkip(5),
+                Cigar::Match(6),
+            ],
+            &REFERENCE[..22],
+        );
+        assert_eq!(exon_blocks(&record), vec![5..10, 20..28, 33..39]);
+
+        let record = read(5, vec![Cigar::Match(30)], &REFERENCE[5..35]);
+        assert_eq!(exon_blocks(&record), vec![5..35]);
+    }
+
+    #[test]
+    fn test_splice_patches() {
+        let blocks = [10..20, 30..40, 60..70];
+        // Unspliced reads need no patches.
+        assert!(splice_patches(&blocks[..1], 15, 5, 100).is_empty());
+        // Anchored in the middle exon, the flanking exons are moved next to it and the
+        // outermost ones are extended by the margin.
+        assert_eq!(
+            splice_patches(&blocks, 35, 5, 100),
+            vec![(15, 5..20), (40, 60..75)]
+        );
+        // Anchored in the last exon, the upstream exons are stacked in front of it.
+        assert_eq!(
+            splice_patches(&blocks, 65, 5, 100),
+            vec![(50, 30..40), (35, 5..20)]
+        );
+        // Patches are truncated at the contig boundaries.
+        assert_eq!(
+            splice_patches(&blocks, 15, 5, 75),
+            vec![(20, 30..40), (30, 60..75)]
+        );
+        assert_eq!(splice_patches(&[2..5, 10..20], 15, 5, 100), vec![(5, 0..5)]);
+    }
+
+    #[test]
+    fn test_allele_support_spliced_read() {
+        let tmp = tempfile::tempdir().unwrap();
+        let reference = write_reference(&tmp);
+        let ref_buffer = Arc::new(reference::Buffer::from_path(&reference, 1).unwrap());
+        let mut realigner = PathHMMRealigner::new(
+            pairhmm::GapParams::default(),
+            100,
+            Arc::clone(&ref_buffer),
+            Vec::new(),
+        );
+        assert_eq!(REFERENCE[40], b'A');
+        let snv = Snv::new(
+            genome::Locus::new("chr1".to_owned(), 40),
+            b'A',
+            b'C',
+            realigner.clone(),
+            true,
+        );
+
+        // The read spans an intron from position 20 to 35, and carries the ALT allele.
+        let mut read_seq = REFERENCE[5..20].to_vec();
+        read_seq.extend(&REFERENCE[35..50]);
+        read_seq[20] = b'C';
+        let record = read(
+            5,
+            vec![Cigar::Match(15), Cigar::RefSkip(15), Cigar::Match(15)],
+            &read_seq,
+        );
+
+        let mut alignment_properties: AlignmentProperties =
+            serde_json::from_str(r#"{"max_read_len": 30}"#).unwrap();
+        alignment_properties.set_spliced();
+
+        let support = realigner
+            .allele_support(&record, snv.loci().iter(), &snv, &[], &alignment_properties)
+            .unwrap();
+        assert!(support.prob_alt_allele() > support.prob_ref_allele() + LogProb(10.0f64.ln()));
+    }
+
+    #[test]
+    fn test_allele_support_single_breakend() {
+        let tmp = tempfile::tempdir().unwrap();
+        let reference = write_reference(&tmp);
+        let ref_buffer = Arc::new(reference::Buffer::from_path(&reference, 1).unwrap());
+        let realigner = PathHMMRealigner::new(
+            pairhmm::GapParams::default(),
+            100,
+            Arc::clone(&ref_buffer),
+            Vec::new(),
+        );
+        assert_eq!(REFERENCE[30], b'G');
+        // Single breakend G. at position 30: the sequence following it is unknown.
+        let breakend = Breakend::new(
+            genome::Locus::new("chr1".to_owned(), 30),
+            b"G",
+            b"G.",
+            b"bnd0",
+            None,
+            VariantPrecision::Precise,
+            AuxInfo::default(),
+        )
+        .unwrap()
+        .unwrap();
+        let breakend_group = BreakendGroupBuilder::new()
+            .realigner(realigner)
+            .push_breakend(breakend)
+            .build()
+            .unwrap();
+
+        let alignment_properties: AlignmentProperties =
+            serde_json::from_str(r#"{"max_read_len": 41}"#).unwrap();
+        let support = |record| {
+            breakend_group
+                .allele_support(
+                    &Evidence::SingleEndSequencingRead(Rc::new(record)),
+                    &alignment_properties,
+                    &[],
+                )
+                .unwrap()
+                .unwrap()
+        };
+
+        // The read follows the reference up to the breakend and is softclipped afterwards,
+        // with a sequence that differs from the reference.
+        let mut read_seq = REFERENCE[5..31].to_vec();
+        read_seq.extend(b"GGCATCGTACTGAAT");
+        let softclipped = support(read(
+            5,
+            vec![Cigar::Match(26), Cigar::SoftClip(15)],
+            &read_seq,
+        ));
+        assert!(
+            softclipped.prob_alt_allele() > softclipped.prob_ref_allele() + LogProb(10.0f64.ln())
+        );
+
+        // The read continues along the reference beyond the breakend.
+        let spanning = support(read(5, vec![Cigar::Match(41)], &REFERENCE[5..46]));
+        assert!(spanning.prob_ref_allele() > spanning.prob_alt_allele() + LogProb(10.0f64.ln()));
+    }
+}
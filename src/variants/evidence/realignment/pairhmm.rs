@@ -14,14 +14,19 @@ use bio::stats::{LogProb, Prob};
 use num_traits::Zero;
 use rust_htslib::bam;
 
+use crate::estimation::alignment_properties::ReadType;
 use crate::variants::evidence::bases::prob_read_base_miscall;
 use crate::variants::evidence::realignment::edit_distance::EditDistanceHit;
 
 /// Width of band around alignment with optimal edit distance.
 pub(crate) const EDIT_BAND: usize = 4;
 
+/// Placeholder for allele bases of unknown sequence (e.g. beyond a single breakend).
+pub(crate) const UNKNOWN_BASE: u8 = b'*';
+
 lazy_static! {
     static ref PROB_CONFUSION: LogProb = LogProb::from(Prob(0.3333));
+    static ref PROB_UNKNOWN_BASE: LogProb = LogProb::from(Prob(0.25));
 }
 
 pub(crate) trait RefBaseEmission {
@@ -141,6 +146,31 @@ impl Default for GapParams {
     }
 }
 
+impl GapParams {
+    /// Default gap parameters for the given read type, used if they cannot be estimated
+    /// from the data.
+    pub(crate) fn default_for(read_type: ReadType) -> Self {
+        // METHOD: for long reads, we fall back to rough indel error rates as reported for
+        // the respective technologies. Indel errors of long reads frequently extend over
+        // multiple bases, hence extension is not neglected there.
+        match read_type {
+            ReadType::Short => Self::default(),
+            ReadType::Ont => Self {
+                prob_insertion_artifact: LogProb::from(Prob(1e-2)),
+                prob_deletion_artifact: LogProb::from(Prob(2e-2)),
+                prob_insertion_extend_artifact: LogProb::from(Prob(0.2)),
+                prob_deletion_extend_artifact: LogProb::from(Prob(0.2)),
+            },
+            ReadType::Hifi => Self {
+                prob_insertion_artifact: LogProb::from(Prob(5e-4)),
+                prob_deletion_artifact: LogProb::from(Prob(5e-4)),
+                prob_insertion_extend_artifact: LogProb::from(Prob(0.1)),
+                prob_deletion_extend_artifact: LogProb::from(Prob(0.1)),
+            },
+        }
+    }
+}
+
 fn parse_float_or_null<'de, D>(d: D) -> Result<LogProb, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -247,6 +277,32 @@ impl Default for HopParams {
     }
 }
 
+impl HopParams {
+    /// Default hop parameters for the given read type, used if they cannot be estimated
+    /// from the data.
+    pub(crate) fn default_for(read_type: ReadType) -> Self {
+        // METHOD: homopolymer length errors are negligible for short reads, but they are the
+        // dominant error mode of long reads (in particular ONT). Without estimates, we fall
+        // back to rough rates of homopolymer indels as reported for the respective
+        // technologies, equally for all bases.
+        let params = |prob_hop: f64, prob_extend: f64| {
+            let prob_hop = vec![LogProb::from(Prob(prob_hop)); 4];
+            let prob_extend = vec![LogProb::from(Prob(prob_extend)); 4];
+            Self {
+                prob_seq_homopolymer: prob_hop.clone(),
+                prob_ref_homopolymer: prob_hop,
+                prob_seq_extend_homopolymer: prob_extend.clone(),
+                prob_ref_extend_homopolymer: prob_extend,
+            }
+        };
+        match read_type {
+            ReadType::Short => Self::default(),
+            ReadType::Ont => params(2e-2, 0.3),
+            ReadType::Hifi => params(2e-3, 0.1),
+        }
+    }
+}
+
 impl pairhmm::BaseSpecificHopParameters for HopParams {
     #[inline]
     fn prob_hop_x_with_base(&self, base: u8) -> LogProb {
@@ -378,6 +434,13 @@ impl<'a> pairhmm::EmissionParameters for ReadVsAlleleEmission<'a> {
     #[inline]
     fn prob_emit_xy(&self, i: usize, j: usize) -> bio::stats::pairhmm::XYEmission {
         let r = self.allele_emission.ref_base(i);
+        if r == UNKNOWN_BASE {
+            // METHOD: a base of unknown sequence can be emitted as any read base. We use the
+            // probability of a uniformly chosen base, such that reads carrying sequence that
+            // differs from the reference there (e.g. softclips) are favored over reads that
+            // continue along the reference, and vice versa.
+            return pairhmm::XYEmission::Match(*PROB_UNKNOWN_BASE);
+        }
         self.read_emission.prob_match_mismatch(j, r)
     }
 
@@ -0,0 +1,270 @@
+use bio::stats::probs::LogProb;
+use bio_types::sequence::SequenceReadPairOrientation;
+use ordered_float::NotNan;
+
+use crate::utils::PROB_05;
+use crate::variants::evidence::observations::pileup::Pileup;
+use crate::variants::evidence::observations::read_observation::ProcessedReadObservation;
+use crate::variants::model::bias::Bias;
+
+/// Minimum probability for substitutions of a damage type to occur in the read orientation in
+/// which the damage manifests, such that a sample is considered to be affected by the damage.
+const MIN_PROB_DAMAGE_ORIENTATION: f64 = 0.6;
+
+/// Chemical DNA damage that causes substitutions on the damaged strand of a fragment.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub(crate) enum Damage {
+    /// Cytosine deamination, typical for FFPE samples (C>T, G>A if the reverse strand is damaged).
+    Deamination { reverse_strand: bool },
+    /// Guanine oxidation to 8-oxoG (G>T, C>A if the reverse strand is damaged).
+    Oxidation { reverse_strand: bool },
+}
+
+impl Damage {
+    /// Return the damage that can cause the given substitution (if any).
+    /// This is only applied to single-base SNVs, not to the substitutions inside MNVs.
+    pub(crate) fn from_substitution(refbase: u8, altbase: u8) -> Option<Self> {
+        match (refbase.to_ascii_uppercase(), altbase.to_ascii_uppercase()) {
+            (b'C', b'T') => Some(Damage::Deamination {
+                reverse_strand: false,
+            }),
+            (b'G', b'A') => Some(Damage::Deamination {
+                reverse_strand: true,
+            }),
+            (b'G', b'T') => Some(Damage::Oxidation {
+                reverse_strand: false,
+            }),
+            (b'C', b'A') => Some(Damage::Oxidation {
+                reverse_strand: true,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Reference and read base of the substitution caused by this damage.
+    pub(crate) fn substitution(&self) -> (u8, u8) {
+        match self {
+            Damage::Deamination {
+                reverse_strand: false,
+            } => (b'C', b'T'),
+            Damage::Deamination {
+                reverse_strand: true,
+            } => (b'G', b'A'),
+            Damage::Oxidation {
+                reverse_strand: false,
+            } => (b'G', b'T'),
+            Damage::Oxidation {
+                reverse_strand: true,
+            } => (b'C', b'A'),
+        }
+    }
+
+    /// Read pair orientation in which the damage manifests.
+    pub(crate) fn read_orientation(&self) -> SequenceReadPairOrientation {
+        // METHOD: the damage occurs on the original strand of the fragment, which is sequenced
+        // by the first read. Hence, damage on the forward strand is seen in F1R2 pairs, and
+        // damage on the reverse strand in F2R1 pairs.
+        match self {
+            Damage::Deamination { reverse_strand } | Damage::Oxidation { reverse_strand } => {
+                if *reverse_strand {
+                    SequenceReadPairOrientation::F2R1
+                } else {
+                    SequenceReadPairOrientation::F1R2
+                }
+            }
+        }
+    }
+
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            Damage::Deamination { .. } => "FFPE",
+            Damage::Oxidation { .. } => "OXOG",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, Default)]
+pub(crate) enum DamageBias {
+    #[default]
+    None,
+    Some {
+        damage: Damage,
+        prob_damage_orientation: NotNan<f64>,
+    },
+}
+
+impl DamageBias {
+    /// Possible damage biases for a variant that can be caused by the given damage.
+    pub(crate) fn values(damage: Option<Damage>) -> Vec<Self> {
+        let mut values = vec![DamageBias::None];
+        if let Some(damage) = damage {
+            values.push(DamageBias::Some {
+                damage,
+                prob_damage_orientation: NotNan::new(MIN_PROB_DAMAGE_ORIENTATION).unwrap(),
+            });
+        }
+        values
+    }
+
+    pub(crate) fn damage(&self) -> Option<Damage> {
+        match self {
+            DamageBias::None => None,
+            DamageBias::Some { damage, .. } => Some(*damage),
+        }
+    }
+
+    fn estimate_prob_damage_orientation(pileups: &[Pileup]) -> Option<NotNan<f64>> {
+        // METHOD: the damage rates are estimated per sample during preprocessing. Since the bias
+        // is shared between samples, we take the strongest damage signature among them.
+        pileups
+            .iter()
+            .filter_map(|pileup| pileup.prob_damage_orientation())
+            .filter(|prob| *prob >= MIN_PROB_DAMAGE_ORIENTATION)
+            .filter_map(|prob| NotNan::new(prob).ok())
+            .max()
+    }
+}
+
+impl Bias for DamageBias {
+    fn prob_alt(&self, observation: &ProcessedReadObservation) -> LogProb {
+        match self {
+            DamageBias::None => *PROB_05, // normal
+            DamageBias::Some {
+                damage,
+                prob_damage_orientation,
+            } => {
+                // METHOD: damage induced ALT reads occur in the damaged read orientation with the
+                // probability that the substitution type shows in that orientation in the sample.
+                if observation.read_orientation == damage.read_orientation() {
+                    LogProb(prob_damage_orientation.ln())
+                } else if observation.read_orientation == SequenceReadPairOrientation::F1R2
+                    || observation.read_orientation == SequenceReadPairOrientation::F2R1
+                {
+                    LogProb((1.0 - **prob_damage_orientation).ln())
+                } else {
+                    // For nonstandard orientations, the true one can be either F1R2 or F2R1, hence 0.5.
+                    *PROB_05
+                }
+            }
+        }
+    }
+
+    fn prob_any(&self, _observation: &ProcessedReadObservation) -> LogProb {
+        *PROB_05
+    }
+
+    fn is_artifact(&self) -> bool {
+        *self != DamageBias::None
+    }
+
+    fn is_informative(&self, pileups: &[Pileup]) -> bool {
+        // METHOD: damage is only considered if at least one sample shows a signature of it,
+        // i.e. substitutions of the damage type are enriched in the damaged read orientation.
+        !self.is_artifact() || Self::estimate_prob_damage_orientation(pileups).is_some()
+    }
+
+    fn is_bias_evidence(&self, observation: &ProcessedReadObservation) -> bool {
+        self.damage()
+            .is_some_and(|damage| observation.read_orientation == damage.read_orientation())
+    }
+
+    fn learn_parameters(&mut self, pileups: &[Pileup]) {
+        if let DamageBias::Some {
+            ref mut prob_damage_orientation,
+            ..
+        } = self
+        {
+            // METHOD: either we can estimate the probability, or the bias is discarded by
+            // is_informative().
+            if let Some(prob) = Self::estimate_prob_damage_orientation(pileups) {
+                *prob_damage_orientation = prob;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variants::model::tests::observation;
+
+    #[test]
+    fn test_damage_from_substitution() {
+        for (refbase, altbase) in [(b'C', b'T'), (b'G', b'A'), (b'G', b'T'), (b'C', b'A')] {
+            let damage = Damage::from_substitution(refbase, altbase).unwrap();
+            assert_eq!(damage.substitution(), (refbase, altbase));
+        }
+        assert_eq!(
+            Damage::from_substitution(b'g', b'a')
+                .unwrap()
+                .read_orientation(),
+            SequenceReadPairOrientation::F2R1
+        );
+        assert_eq!(
+            Damage::from_substitution(b'G', b'T')
+                .unwrap()
+                .read_orientation(),
+            SequenceReadPairOrientation::F1R2
+        );
+        assert!(Damage::from_substitution(b'A', b'G').is_none());
+    }
+
+    #[test]
+    fn test_damage_bias_prob_alt() {
+        let bias = DamageBias::Some {
+            damage: Damage::Deamination {
+                reverse_strand: false,
+            },
+            prob_damage_orientation: NotNan::new(0.8).unwrap(),
+        };
+        let mut obs = observation(LogProb::ln_one(), LogProb::ln_one(), LogProb::ln_zero());
+
+        obs.read_orientation = SequenceReadPairOrientation::F1R2;
+        assert_relative_eq!(bias.prob_alt(&obs).exp(), 0.8, epsilon = 1e-9);
+        assert!(bias.is_bias_evidence(&obs));
+
+        obs.read_orientation = SequenceReadPairOrientation::F2R1;
+        assert_relative_eq!(bias.prob_alt(&obs).exp(), 0.2, epsilon = 1e-9);
+        assert!(!bias.is_bias_evidence(&obs));
+
+        obs.read_orientation = SequenceReadPairOrientation::None;
+        assert_relative_eq!(bias.prob_alt(&obs).exp(), 0.5, epsilon = 1e-9);
+        assert_relative_eq!(DamageBias::None.prob_alt(&obs).exp(), 0.5, epsilon = 1e-9);
+        assert!(!DamageBias::None.is_bias_evidence(&obs));
+    }
+
+    #[test]
+    fn test_damage_bias_is_informative() {
+        let damage = Damage::Oxidation {
+            reverse_strand: true,
+        };
+        let values = DamageBias::values(Some(damage));
+        assert_eq!(values.len(), 2);
+        assert_eq!(DamageBias::values(None), vec![DamageBias::None]);
+        let bias = values[1];
+
+        let pileup = |prob_damage_orientation| {
+            let mut pileup = Pileup::new(Vec::new(), Vec::new());
+            pileup.set_prob_damage_orientation(prob_damage_orientation);
+            pileup
+        };
+
+        // no damage signature in any sample
+        let pileups = [pileup(None), pileup(Some(0.55))];
+        assert!(DamageBias::None.is_informative(&pileups));
+        assert!(!bias.is_informative(&pileups));
+
+        // damage signature in one of the samples
+        let pileups = [pileup(Some(0.55)), pileup(Some(0.9))];
+        assert!(bias.is_informative(&pileups));
+        let mut learned = bias;
+        learned.learn_parameters(&pileups);
+        assert_eq!(
+            learned,
+            DamageBias::Some {
+                damage,
+                prob_damage_orientation: NotNan::new(0.9).unwrap(),
+            }
+        );
+    }
+}
@@ -10,6 +10,7 @@ use crate::variants::evidence::observations::{
 };
 
 pub(crate) mod alt_locus_bias;
+pub(crate) mod damage_bias;
 pub(crate) mod homopolymer_error;
 pub(crate) mod read_orientation_bias;
 pub(crate) mod read_position_bias;
@@ -17,6 +18,7 @@ pub(crate) mod softclip_bias;
 pub(crate) mod strand_bias;
 
 pub(crate) use alt_locus_bias::AltLocusBias;
+pub(crate) use damage_bias::{Damage, DamageBias};
 pub(crate) use homopolymer_error::HomopolymerError;
 pub(crate) use read_orientation_bias::ReadOrientationBias;
 pub(crate) use read_position_bias::ReadPositionBias;
@@ -125,6 +127,8 @@ pub struct Artifacts {
     homopolymer_error: HomopolymerError,
     #[getset(get = "pub(crate)")]
     alt_locus_bias: AltLocusBias,
+    #[getset(get = "pub(crate)")]
+    damage_bias: DamageBias,
 }
 
 impl Artifacts {
@@ -135,6 +139,7 @@ impl Artifacts {
         consider_softclip_bias: bool,
         consider_homopolymer_error: bool,
         consider_alt_locus_bias: bool,
+        consider_damage: Option<Damage>,
     ) -> Box<dyn Iterator<Item = Self>> {
         if !consider_strand_bias
             && !consider_read_orientation_bias
@@ -142,6 +147,7 @@ impl Artifacts {
             && !consider_softclip_bias
             && !consider_homopolymer_error
             && !consider_alt_locus_bias
+            && consider_damage.is_none()
         {
             return Box::new(std::iter::empty());
         }
@@ -176,6 +182,7 @@ impl Artifacts {
         } else {
             vec![AltLocusBias::default()]
         };
+        let damage_biases = DamageBias::values(consider_damage);
 
         Box::new(
             strand_biases
@@ -185,7 +192,8 @@ impl Artifacts {
                 .cartesian_product(softclip_biases)
                 .cartesian_product(homopolymer_error)
                 .cartesian_product(alt_locus_bias)
-                .filter_map(|(((((sb, rob), rpb), scb), dib), alb)| {
+                .cartesian_product(damage_biases)
+                .filter_map(|((((((sb, rob), rpb), scb), dib), alb), db)| {
                     if [
                         sb.is_artifact(),
                         rob.is_artifact(),
@@ -193,6 +201,7 @@ impl Artifacts {
                         scb.is_artifact(),
                         dib.is_artifact(),
                         alb.is_artifact(),
+                        db.is_artifact(),
                     ]
                     .iter()
                     .map(|artifact| if *artifact { 1 } else { 0 })
@@ -207,6 +216,7 @@ impl Artifacts {
                                 .softclip_bias(scb)
                                 .homopolymer_error(dib)
                                 .alt_locus_bias(alb)
+                                .damage_bias(db)
                                 .build()
                                 .unwrap(),
                         )
@@ -225,6 +235,7 @@ impl Artifacts {
             .softclip_bias(SoftclipBias::None)
             .homopolymer_error(HomopolymerError::default())
             .alt_locus_bias(AltLocusBias::None)
+            .damage_bias(DamageBias::None)
             .build()
             .unwrap()
     }
@@ -236,6 +247,7 @@ impl Artifacts {
             && self.softclip_bias.is_possible(pileups)
             && self.homopolymer_error.is_possible(pileups)
             && self.alt_locus_bias.is_possible(pileups)
+            && self.damage_bias.is_possible(pileups)
     }
 
     pub(crate) fn is_informative(&self, pileups: &[Pileup]) -> bool {
@@ -245,6 +257,7 @@ impl Artifacts {
             && self.softclip_bias.is_informative(pileups)
             && self.homopolymer_error.is_informative(pileups)
             && self.alt_locus_bias.is_informative(pileups)
+            && self.damage_bias.is_informative(pileups)
     }
 
     pub(crate) fn is_likely(&self, pileups: &[Pileup]) -> bool {
@@ -254,6 +267,7 @@ impl Artifacts {
             && self.softclip_bias.is_likely(pileups)
             && self.homopolymer_error.is_likely(pileups)
             && self.alt_locus_bias.is_likely(pileups)
+            && self.damage_bias.is_likely(pileups)
     }
 
     pub(crate) fn prob_alt(&self, observation: &ProcessedReadObservation) -> LogProb {
@@ -263,6 +277,7 @@ impl Artifacts {
             + self.softclip_bias.prob_alt(observation)
             + self.homopolymer_error.prob_alt(observation)
             + self.alt_locus_bias.prob_alt(observation)
+            + self.damage_bias.prob_alt(observation)
     }
 
     pub(crate) fn prob_ref(&self, observation: &ProcessedReadObservation) -> LogProb {
@@ -272,6 +287,7 @@ impl Artifacts {
             + self.softclip_bias.prob_ref(observation)
             + self.homopolymer_error.prob_ref(observation)
             + self.alt_locus_bias.prob_ref(observation)
+            + self.damage_bias.prob_ref(observation)
     }
 
     pub(crate) fn prob_any(&self, observation: &ProcessedReadObservation) -> LogProb {
@@ -281,6 +297,7 @@ impl Artifacts {
             + self.softclip_bias.prob_any(observation)
             + self.homopolymer_error.prob_any(observation)
             + self.alt_locus_bias.prob_any(observation)
+            + self.damage_bias.prob_any(observation)
     }
 
     pub(crate) fn is_artifact(&self) -> bool {
@@ -290,11 +307,13 @@ impl Artifacts {
             || self.softclip_bias.is_artifact()
             || self.homopolymer_error.is_artifact()
             || self.alt_locus_bias.is_artifact()
+            || self.damage_bias.is_artifact()
     }
 
     pub(crate) fn learn_parameters(&mut self, pileups: &[Pileup]) {
         self.homopolymer_error.learn_parameters(pileups);
         self.strand_bias.learn_parameters(pileups);
         self.read_position_bias.learn_parameters(pileups);
+        self.damage_bias.learn_parameters(pileups);
     }
 }
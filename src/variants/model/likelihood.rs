@@ -40,6 +40,128 @@ impl Event {
     }
 }
 
+/// Weight of observations stemming from UMI families consisting of a single read.
+const SINGLE_READ_FAMILY_WEIGHT: f64 = 0.5;
+
+/// Weight of the likelihood of the given observation.
+///
+/// METHOD: errors in reads of a UMI family can be recognized by the other family members,
+/// which is impossible for families consisting of a single read. Hence, the likelihood of an
+/// observation is tempered by a weight that starts at SINGLE_READ_FAMILY_WEIGHT for single
+/// read families and approaches 1 with increasing family size. The agreement of the reads of
+/// a family is already reflected by their combined allele probabilities.
+fn family_weight(observation: &ProcessedReadObservation) -> f64 {
+    match observation.family_size {
+        Some(family_size) => 1.0 - (1.0 - SINGLE_READ_FAMILY_WEIGHT).powi(family_size as i32),
+        None => 1.0,
+    }
+}
+
+/// Number of steps into which the allele frequency mass that remains besides the ALT allele of
+/// interest is divided among the reference and the competing ALT alleles.
+const COMPETING_ALLELE_FREQ_STEPS: usize = 10;
+/// Maximum number of considered divisions of the remaining allele frequency mass among the
+/// reference and the competing ALT alleles. With many competing alleles, the number of steps
+/// is reduced accordingly.
+const MAX_COMPETING_ALLELE_FREQ_DIVISIONS: usize = 100;
+
+/// Division of the allele frequency mass that remains besides the ALT allele of interest
+/// among the reference allele and the competing ALT alleles at the same site.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CompetingAlleleFractions {
+    /// Fraction of the reference allele (log space).
+    prob_ref: LogProb,
+    /// Fractions of the competing ALT alleles (log space).
+    probs_competing: Vec<LogProb>,
+}
+
+impl CompetingAlleleFractions {
+    /// Enumerate all divisions of the remaining allele frequency mass among the reference
+    /// allele and the given number of competing ALT alleles, on an equidistant grid.
+    pub(crate) fn grid(n_competing: usize) -> Vec<Self> {
+        let n_divisions = |steps: usize| {
+            // number of ways to put the given steps into n_competing + 1 alleles
+            (1..=n_competing).fold(1.0, |n, k| n * (steps + k) as f64 / k as f64)
+        };
+        let steps = (0..=COMPETING_ALLELE_FREQ_STEPS)
+            .rev()
+            .find(|steps| n_divisions(*steps) <= MAX_COMPETING_ALLELE_FREQ_DIVISIONS as f64)
+            .unwrap();
+
+        let mut divisions = Vec::new();
+        let mut current = Vec::with_capacity(n_competing);
+        fn enumerate(
+            current: &mut Vec<usize>,
+            remaining: usize,
+            n_competing: usize,
+            steps: usize,
+            divisions: &mut Vec<CompetingAlleleFractions>,
+        ) {
+            if current.len() == n_competing {
+                let fraction = |k: usize| {
+                    if steps > 0 {
+                        LogProb((k as f64 / steps as f64).ln())
+                    } else {
+                        LogProb::ln_one()
+                    }
+                };
+                divisions.push(CompetingAlleleFractions {
+                    prob_ref: fraction(remaining),
+                    probs_competing: current.iter().map(|k| fraction(*k)).collect(),
+                });
+                return;
+            }
+            for k in 0..=remaining {
+                current.push(k);
+                enumerate(current, remaining - k, n_competing, steps, divisions);
+                current.pop();
+            }
+        }
+        enumerate(&mut current, steps, n_competing, steps, &mut divisions);
+
+        divisions
+    }
+}
+
+/// Calculate the likelihood of the given pileup for the given per-observation likelihood
+/// (depending on the division of the remaining allele frequency mass among the competing
+/// ALT alleles at the site, if any).
+///
+/// METHOD: at sites with multiple ALT alleles, each read can stem from the reference, the ALT
+/// allele of interest, or one of the competing ALT alleles, each with its own probability.
+/// The allele frequencies of all ALT alleles are jointly located on the simplex: the mass
+/// that remains besides the ALT allele of interest is divided among the reference and the
+/// competing alleles, and we marginalize over all such divisions (with a uniform prior).
+/// This way, the likelihood of the allele frequency of interest is the marginal of the joint
+/// likelihood over the allele frequency vector of the site.
+fn likelihood_pileup<F>(pileup: &Pileup, likelihood_observation: F) -> LogProb
+where
+    F: Fn(Option<&CompetingAlleleFractions>, &ProcessedReadObservation) -> LogProb,
+{
+    let likelihood_reads = |fractions: Option<&CompetingAlleleFractions>| {
+        pileup
+            .read_observations()
+            .iter()
+            .fold(LogProb::ln_one(), |prob, obs| {
+                prob + likelihood_observation(fractions, obs)
+            })
+    };
+
+    let n_competing = pileup.n_competing_alleles();
+    if n_competing == 0 {
+        likelihood_reads(None)
+    } else {
+        let grid = CompetingAlleleFractions::grid(n_competing);
+        let prob_division = LogProb((grid.len() as f64).recip().ln());
+        LogProb::ln_sum_exp(
+            &grid
+                .iter()
+                .map(|fractions| likelihood_reads(Some(fractions)) + prob_division)
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
 fn prob_sample_alt(observation: &ProcessedReadObservation, allele_freq: LogProb) -> LogProb {
     if allele_freq != LogProb::ln_one() {
         // The effective sample probability for the alt allele is the allele frequency times
@@ -87,17 +209,28 @@ impl ContaminatedSampleLikelihoodModel {
         &self,
         allele_freq_primary: LogProb,
         allele_freq_secondary: LogProb,
+        competing_allele_fractions: Option<&CompetingAlleleFractions>,
         biases_primary: &Artifacts,
         biases_secondary: &Artifacts,
         observation: &ProcessedReadObservation,
     ) -> LogProb {
         // Step 1: likelihoods for the mapping case.
         // Case 1: read comes from primary sample and is correctly mapped
-        let prob_primary =
-            self.purity + likelihood_mapping(allele_freq_primary, biases_primary, observation);
+        let prob_primary = self.purity
+            + likelihood_mapping(
+                allele_freq_primary,
+                competing_allele_fractions,
+                biases_primary,
+                observation,
+            );
         // Case 2: read comes from secondary sample and is correctly mapped
         let prob_secondary = self.impurity
-            + likelihood_mapping(allele_freq_secondary, biases_secondary, observation);
+            + likelihood_mapping(
+                allele_freq_secondary,
+                competing_allele_fractions,
+                biases_secondary,
+                observation,
+            );
 
         // Step 4: total probability
         // Important note: we need to multiply a probability for a hypothetical missed allele
@@ -110,6 +243,7 @@ impl ContaminatedSampleLikelihoodModel {
                     + observation.prob_missed_allele
                     + biases_primary.prob_any(observation),
             );
+        let total = LogProb(*total * family_weight(observation));
         assert!(!total.is_nan());
         total
     }
@@ -132,20 +266,24 @@ impl Likelihood<ContaminatedSampleCache> for ContaminatedSampleLikelihoodModel {
             let ln_af_secondary = LogProb(events.secondary.allele_freq.ln());
 
             // calculate product of per-observation likelihoods in log space
-            let likelihood =
-                pileup
-                    .read_observations()
-                    .iter()
-                    .fold(LogProb::ln_one(), |prob, obs| {
-                        let lh = self.likelihood_observation(
-                            ln_af_primary,
-                            ln_af_secondary,
-                            &events.primary.artifacts,
-                            &events.secondary.artifacts,
-                            obs,
-                        );
-                        prob + lh
-                    });
+            // METHOD: the division of the remaining allele frequency mass among the competing
+            // ALT alleles (if any) is shared between both samples.
+            let likelihood = likelihood_pileup(pileup, |competing_allele_fractions, obs| {
+                self.likelihood_observation(
+                    ln_af_primary,
+                    ln_af_secondary,
+                    competing_allele_fractions,
+                    &events.primary.artifacts,
+                    &events.secondary.artifacts,
+                    obs,
+                )
+            });
+
+            // METHOD: the depth stems from both samples, weighted by purity.
+            let mixed_allele_freq = (self.purity + ln_af_primary)
+                .ln_add_exp(self.impurity + ln_af_secondary)
+                .exp();
+            let likelihood = likelihood + likelihood_depth(mixed_allele_freq, pileup);
 
             assert!(!likelihood.is_nan());
 
@@ -171,11 +309,12 @@ impl SampleLikelihoodModel {
     fn likelihood_observation(
         &self,
         allele_freq: LogProb,
+        competing_allele_fractions: Option<&CompetingAlleleFractions>,
         biases: &Artifacts,
         observation: &ProcessedReadObservation,
     ) -> LogProb {
         // Step 1: likelihood for the mapping case.
-        let prob = likelihood_mapping(allele_freq, biases, observation);
+        let prob = likelihood_mapping(allele_freq, competing_allele_fractions, biases, observation);
 
         // Step 2: total probability
         // Important note: we need to multiply a probability for a hypothetical missed allele
@@ -187,6 +326,7 @@ impl SampleLikelihoodModel {
                 + observation.prob_missed_allele
                 + biases.prob_any(observation),
         );
+        let total = LogProb(*total * family_weight(observation));
 
         assert!(!total.is_nan());
         total
@@ -197,6 +337,7 @@ impl SampleLikelihoodModel {
 /// underlying fragment/read is mapped correctly.
 fn likelihood_mapping(
     allele_freq: LogProb,
+    competing_allele_fractions: Option<&CompetingAlleleFractions>,
     biases: &Artifacts,
     observation: &ProcessedReadObservation,
 ) -> LogProb {
@@ -208,17 +349,47 @@ fn likelihood_mapping(
     let prob_bias_ref = biases.prob_ref(observation);
 
     // Step 2: read comes from case sample and is correctly mapped
-    let prob = LogProb::ln_sum_exp(&[
-        // alt allele
-        prob_sample_alt + prob_bias_alt + observation.prob_alt,
-        // ref allele
-        prob_sample_ref + observation.prob_ref + prob_bias_ref,
-    ]);
+    let prob = if let Some(fractions) = competing_allele_fractions {
+        // METHOD: the remaining allele frequency mass is divided among the reference and the
+        // competing ALT alleles. Artifacts are modeled for the ALT allele of interest only,
+        // hence all other alleles are treated like the reference in that regard.
+        // Observations that do not carry probabilities for the competing alleles (e.g. because
+        // they are not informative about them) are assumed to fit them like the reference.
+        fractions.probs_competing.iter().enumerate().fold(
+            (prob_sample_alt + prob_bias_alt + observation.prob_alt).ln_add_exp(
+                prob_sample_ref + fractions.prob_ref + observation.prob_ref + prob_bias_ref,
+            ),
+            |prob, (k, fraction)| {
+                let prob_competing = observation
+                    .prob_competing_alleles
+                    .get(k)
+                    .copied()
+                    .unwrap_or(observation.prob_ref);
+                prob.ln_add_exp(prob_sample_ref + *fraction + prob_competing + prob_bias_ref)
+            },
+        )
+    } else {
+        LogProb::ln_sum_exp(&[
+            // alt allele
+            prob_sample_alt + prob_bias_alt + observation.prob_alt,
+            // ref allele
+            prob_sample_ref + observation.prob_ref + prob_bias_ref,
+        ])
+    };
     assert!(!prob.is_nan());
 
     prob
 }
 
+/// Calculate likelihood of allele freq given the depth observations of the pileup.
+fn likelihood_depth(allele_freq: f64, pileup: &Pileup) -> LogProb {
+    pileup
+        .depth_observations()
+        .iter()
+        .map(|obs| obs.likelihood(allele_freq))
+        .sum()
+}
+
 impl Likelihood<SingleSampleCache> for SampleLikelihoodModel {
     type Event = Event;
     type Data = Pileup;
@@ -231,14 +402,15 @@ impl Likelihood<SingleSampleCache> for SampleLikelihoodModel {
             let ln_af = LogProb(event.allele_freq.ln());
 
             // calculate product of per-read likelihoods in log space
-            let likelihood =
-                pileup
-                    .read_observations()
-                    .iter()
-                    .fold(LogProb::ln_one(), |prob, obs| {
-                        let lh = self.likelihood_observation(ln_af, &event.artifacts, obs);
-                        prob + lh
-                    });
+            let likelihood = likelihood_pileup(pileup, |competing_allele_fractions, obs| {
+                self.likelihood_observation(
+                    ln_af,
+                    competing_allele_fractions,
+                    &event.artifacts,
+                    obs,
+                )
+            });
+            let likelihood = likelihood + likelihood_depth(*event.allele_freq, pileup);
 
             assert!(!likelihood.is_nan());
 
@@ -252,10 +424,11 @@ impl Likelihood<SingleSampleCache> for SampleLikelihoodModel {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::variants::model::bias::Artifacts;
+    use crate::variants::model::bias::{Artifacts, Bias, Damage};
     use crate::variants::model::likelihood;
     use crate::variants::model::tests::observation;
     use bio::stats::LogProb;
+    use bio_types::sequence::SequenceReadPairOrientation;
     use itertools_num::linspace;
 
     fn biases() -> Artifacts {
@@ -276,8 +449,12 @@ mod tests {
 
         let model = SampleLikelihoodModel::new();
 
-        let lh =
-            model.likelihood_observation(LogProb(AlleleFreq(0.0).ln()), &biases(), &observation);
+        let lh = model.likelihood_observation(
+            LogProb(AlleleFreq(0.0).ln()),
+            None,
+            &biases(),
+            &observation,
+        );
         assert_relative_eq!(*lh, *biases().prob_ref(&observation));
     }
 
@@ -289,6 +466,7 @@ mod tests {
         let lh = model.likelihood_observation(
             LogProb(AlleleFreq(0.0).ln()),
             LogProb(AlleleFreq(0.0).ln()),
+            None,
             &biases(),
             &biases(),
             &observation,
@@ -392,4 +570,153 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_likelihood_observation_family_size() {
+        let model = SampleLikelihoodModel::new();
+        let mut observation =
+            observation(LogProb::ln_one(), LogProb::ln_one(), LogProb(0.1f64.ln()));
+        let lh = |observation: &ProcessedReadObservation| {
+            *model.likelihood_observation(
+                LogProb(AlleleFreq(0.0).ln()),
+                None,
+                &biases(),
+                observation,
+            )
+        };
+
+        let lh_unknown = lh(&observation);
+        observation.family_size = Some(1);
+        let lh_single = lh(&observation);
+        observation.family_size = Some(5);
+        let lh_large = lh(&observation);
+
+        // an alt observation contradicts an absent variant less if it stems from a single read
+        assert_relative_eq!(lh_single, lh_unknown * SINGLE_READ_FAMILY_WEIGHT);
+        assert!(lh_single > lh_large);
+        assert!(lh_large > lh_unknown);
+    }
+
+    #[test]
+    fn test_likelihood_pileup_damage() {
+        let model = SampleLikelihoodModel::new();
+        let pileup = |alt_orientations: &[SequenceReadPairOrientation]| {
+            let mut pileup = Pileup::default();
+            for read_orientation in alt_orientations {
+                let mut obs = observation(LogProb::ln_one(), LogProb::ln_one(), LogProb::ln_zero());
+                obs.read_orientation = *read_orientation;
+                pileup.read_observations_mut().push(obs);
+            }
+            for _ in 0..alt_orientations.len() {
+                let mut obs = observation(LogProb::ln_one(), LogProb::ln_zero(), LogProb::ln_one());
+                obs.read_orientation = SequenceReadPairOrientation::F2R1;
+                pileup.read_observations_mut().push(obs);
+            }
+            // the sample shows a C>T deamination signature
+            pileup.set_prob_damage_orientation(Some(0.9));
+            pileup
+        };
+        let lh = |pileup: &Pileup, artifacts: &Artifacts| {
+            let mut cache = likelihood::SingleSampleCache::new(100);
+            model.compute(
+                &Event {
+                    allele_freq: AlleleFreq(0.5),
+                    artifacts: artifacts.clone(),
+                    is_discrete: true,
+                },
+                pileup,
+                &mut cache,
+            )
+        };
+        let damage = |pileup: &Pileup| {
+            let mut artifacts = Artifacts::all_artifact_combinations(
+                false,
+                false,
+                false,
+                false,
+                false,
+                false,
+                Some(Damage::Deamination {
+                    reverse_strand: false,
+                }),
+            )
+            .find(|artifacts| artifacts.damage_bias().is_artifact())
+            .unwrap();
+            artifacts.learn_parameters(std::slice::from_ref(pileup));
+            artifacts
+        };
+
+        // C>T ALT reads only occur in the damaged read orientation
+        let skewed = pileup(&[SequenceReadPairOrientation::F1R2; 10]);
+        assert!(lh(&skewed, &damage(&skewed)) > lh(&skewed, &biases()));
+
+        // C>T ALT reads occur in both read orientations
+        let balanced = pileup(
+            &[
+                SequenceReadPairOrientation::F1R2,
+                SequenceReadPairOrientation::F2R1,
+            ]
+            .repeat(5),
+        );
+        assert!(lh(&balanced, &damage(&balanced)) < lh(&balanced, &biases()));
+    }
+
+    #[test]
+    fn test_competing_allele_fractions_grid() {
+        for (n_competing, n_divisions) in [(1, 11), (2, 66), (3, 84)] {
+            let grid = CompetingAlleleFractions::grid(n_competing);
+            assert_eq!(grid.len(), n_divisions);
+            for fractions in &grid {
+                assert_eq!(fractions.probs_competing.len(), n_competing);
+                let total = fractions
+                    .probs_competing
+                    .iter()
+                    .fold(fractions.prob_ref, |total, prob| total.ln_add_exp(*prob));
+                assert_relative_eq!(total.exp(), 1.0, epsilon = 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_likelihood_pileup_competing_allele() {
+        let model = SampleLikelihoodModel::new();
+        let mut observations = Pileup::default();
+        let prob_low = LogProb(0.01f64.ln());
+        for _ in 0..5 {
+            // reads supporting the ALT allele of interest
+            let mut obs = observation(LogProb::ln_one(), LogProb::ln_one(), prob_low);
+            obs.prob_competing_alleles = vec![prob_low];
+            observations.read_observations_mut().push(obs);
+        }
+        for _ in 0..5 {
+            // reads supporting the competing ALT allele
+            let mut obs = observation(LogProb::ln_one(), prob_low, prob_low);
+            obs.prob_competing_alleles = vec![LogProb::ln_one()];
+            observations.read_observations_mut().push(obs);
+        }
+        assert_eq!(observations.n_competing_alleles(), 1);
+
+        let mut cache = likelihood::SingleSampleCache::new(100);
+        let lh = model.compute(&event(0.5), &observations, &mut cache);
+        // The reads of the competing allele are not counted as reference, but take the
+        // remaining allele frequency mass, such that a VAF of 0.5 is most likely.
+        for af in linspace(0.0, 1.0, 10) {
+            let l = model.compute(&event(af), &observations, &mut cache);
+            assert!(lh > l);
+        }
+
+        // Without probabilities for the competing allele, its reads would contradict any
+        // allele frequency.
+        let mut observations_without_competing = Pileup::default();
+        for obs in observations.read_observations() {
+            let mut obs = obs.clone();
+            obs.prob_competing_alleles.clear();
+            observations_without_competing
+                .read_observations_mut()
+                .push(obs);
+        }
+        let lh_without_competing =
+            model.compute(&event(0.5), &observations_without_competing, &mut cache);
+        assert!(lh > lh_without_competing);
+    }
 }
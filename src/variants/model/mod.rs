@@ -11,6 +11,7 @@ use std::str;
 
 use anyhow::{bail, Result};
 use bio_types::genome::AbstractLocus;
+use bio_types::strand::Strand;
 use ordered_float::NotNan;
 use rust_htslib::bcf;
 use strum_macros::{EnumIter, EnumString, IntoStaticStr};
@@ -221,6 +222,8 @@ pub enum VariantType {
     Duplication,
     #[strum(serialize = "REP")]
     Replacement,
+    #[strum(serialize = "STR")]
+    ShortTandemRepeat,
     #[strum(serialize = "REF")]
     None, // site with no suggested alternative allele
 }
@@ -229,12 +232,23 @@ pub enum VariantType {
 pub(crate) enum VariantPrecision {
     #[default]
     Precise,
+    /// Confidence intervals are given as signed offsets relative to the reported position
+    /// (e.g. -50..=50 for CIPOS=-50,50).
     Imprecise {
-        cistart: RangeInclusive<u64>,
-        ciend: Option<RangeInclusive<u64>>,
+        cistart: RangeInclusive<i64>,
+        ciend: Option<RangeInclusive<i64>>,
     },
 }
 
+/// Positions covered by applying the given confidence interval offsets to the given
+/// position. Positions are bounded by the contig start.
+pub(crate) fn confidence_interval_positions(
+    pos: u64,
+    ci: &RangeInclusive<i64>,
+) -> RangeInclusive<u64> {
+    pos.saturating_add_signed(*ci.start())..=pos.saturating_add_signed(*ci.end())
+}
+
 impl fmt::Display for VariantPrecision {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -260,8 +274,12 @@ impl<'a> TryFrom<&'a bcf::Record> for VariantPrecision {
                 Err(err(
                     "CIPOS or CIEND field does not contain two integers".to_owned()
                 ))
+            } else if cifield[0] > cifield[1] {
+                Err(err(
+                    "lower bound of CIPOS or CIEND exceeds the upper bound".to_owned()
+                ))
             } else {
-                Ok(cifield[0] as u64..=cifield[1] as u64)
+                Ok(cifield[0] as i64..=cifield[1] as i64)
             }
         };
 
@@ -321,6 +339,22 @@ pub(crate) enum Variant {
         ref_allele: Vec<u8>,
         alt_allele: Vec<u8>,
     },
+    /// Insertion of the mobile element with the given name (e.g. ALU). The inserted
+    /// sequence is given by the last `len` bases of the consensus of the element, such that
+    /// 5' truncated insertions are represented as well. If the strand is unknown, both
+    /// orientations of the element are considered.
+    MobileElementInsertion {
+        element: Vec<u8>,
+        len: u64,
+        strand: Strand,
+    },
+    /// Short tandem repeat with the given repeat unit, length of the reference repeat tract
+    /// and candidate number of repeat units.
+    ShortTandemRepeat {
+        unit: Vec<u8>,
+        ref_len: u64,
+        count: u32,
+    },
     None,
 }
 
@@ -336,6 +370,9 @@ impl fmt::Display for Variant {
             Variant::Snv(alt) => write!(f, "snv_{}", fmt_allele(&[*alt])),
             Variant::Deletion(len) => write!(f, "del_{}", len),
             Variant::Insertion(seq) => write!(f, "ins_{}", fmt_allele(seq)),
+            Variant::MobileElementInsertion { element, .. } => {
+                write!(f, "mei_{}", String::from_utf8_lossy(element))
+            }
             Variant::Mnv(seq) => write!(f, "mnv_{}", fmt_allele(seq)),
             Variant::Inversion(len) => write!(f, "inv_{}", len),
             Variant::Duplication(len) => write!(f, "dup_{}", len),
@@ -348,6 +385,9 @@ impl fmt::Display for Variant {
                 fmt_allele(ref_allele),
                 fmt_allele(alt_allele)
             ),
+            Variant::ShortTandemRepeat { unit, count, .. } => {
+                write!(f, "str_{}_{}", fmt_allele(unit), count)
+            }
             Variant::Breakend {
                 ref_allele,
                 spec,
@@ -378,11 +418,13 @@ impl Variant {
             (&Variant::Deletion(l), &VariantType::Deletion(Some(ref range))) => {
                 l >= range.start && l < range.end
             }
-            (&Variant::Insertion(_), &VariantType::Insertion(Some(ref range))) => {
-                self.len() >= range.start && self.len() < range.end
-            }
+            (
+                &Variant::Insertion(_) | &Variant::MobileElementInsertion { .. },
+                &VariantType::Insertion(Some(ref range)),
+            ) => self.len() >= range.start && self.len() < range.end,
             (&Variant::Deletion(_), &VariantType::Deletion(None)) => true,
             (&Variant::Insertion(_), &VariantType::Insertion(None)) => true,
+            (&Variant::MobileElementInsertion { .. }, &VariantType::Insertion(None)) => true,
             (&Variant::Snv(_), &VariantType::Snv) => true,
             (&Variant::Mnv(_), &VariantType::Mnv) => true,
             (&Variant::None, &VariantType::None) => true,
@@ -390,6 +432,7 @@ impl Variant {
             (&Variant::Inversion { .. }, &VariantType::Inversion) => true,
             (&Variant::Duplication { .. }, &VariantType::Duplication) => true,
             (&Variant::Replacement { .. }, &VariantType::Replacement) => true,
+            (&Variant::ShortTandemRepeat { .. }, &VariantType::ShortTandemRepeat) => true,
             _ => false,
         }
     }
@@ -398,12 +441,14 @@ impl Variant {
         match self {
             Variant::Deletion(_) => VariantType::Deletion(None),
             Variant::Insertion(_) => VariantType::Insertion(None),
+            Variant::MobileElementInsertion { .. } => VariantType::Insertion(None),
             Variant::Snv(_) => VariantType::Snv,
             Variant::Mnv(_) => VariantType::Mnv,
             Variant::Breakend { .. } => VariantType::Breakend,
             Variant::Inversion(_) => VariantType::Inversion,
             Variant::Duplication(_) => VariantType::Duplication,
             Variant::Replacement { .. } => VariantType::Replacement,
+            Variant::ShortTandemRepeat { .. } => VariantType::ShortTandemRepeat,
             Variant::None => VariantType::None,
         }
     }
@@ -412,19 +457,23 @@ impl Variant {
         match *self {
             Variant::Deletion(l) => l,
             Variant::Insertion(ref s) => s.len() as u64,
+            Variant::MobileElementInsertion { len, .. } => len,
             Variant::Snv(_) => 1,
             Variant::Mnv(ref alt) => alt.len() as u64,
             Variant::Breakend { .. } => 1,
             Variant::Inversion(l) => l,
             Variant::Duplication(l) => l,
             Variant::Replacement { ref alt_allele, .. } => alt_allele.len() as u64,
+            Variant::ShortTandemRepeat {
+                ref unit, count, ..
+            } => unit.len() as u64 * count as u64,
             Variant::None => 1,
         }
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use crate::variants::evidence::observations::read_observation::{
         AltLocus, ProcessedReadObservation, ReadObservationBuilder, ReadPosition, Strand,
     };
@@ -242,8 +242,11 @@ impl GenericPosterior {
                         (pileup.depth_observations().len(), false)
                     } else {
                         // normal variants, only consider read observations for these heuristic markers
+                        // (depth observations may contradict read observations for large events,
+                        // hence there is no clear ref support if there are any).
                         let n_obs = pileup.read_observations().len();
                         let is_clear_ref = n_obs > 10
+                            && pileup.depth_observations().is_empty()
                             && pileup
                                 .read_observations()
                                 .iter()
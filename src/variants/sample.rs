@@ -14,12 +14,15 @@ use derive_builder::Builder;
 use rand::distributions;
 use rand::distributions::Distribution;
 use rand::{rngs::StdRng, SeedableRng};
-use rust_htslib::bam;
+use rust_htslib::bam::{self, Read};
+use vec_map::VecMap;
 
 use crate::estimation::alignment_properties;
 use crate::reference;
+use crate::variants::evidence::observations::alt_hits::AltHitFormat;
+use crate::variants::evidence::observations::depth_observation::DepthCounter;
 use crate::variants::evidence::observations::read_observation::{
-    major_read_position, Observable, ReadObservation,
+    major_read_position, CandidateEvidence, Observable, ReadObservation,
 };
 use crate::variants::types::Variant;
 
@@ -29,13 +32,19 @@ use super::evidence::realignment::Realignable;
 use super::types::Loci;
 use crate::variants::evidence::observations::pileup::Pileup;
 
-#[derive(new, Getters, Debug)]
+#[derive(new, Getters, CopyGetters, Debug)]
 pub(crate) struct RecordBuffer {
     inner: bam::RecordBuffer,
     #[getset(get = "pub")]
     single_read_window: u64,
     #[getset(get = "pub")]
     read_pair_window: u64,
+    /// Whether reads of the same UMI family shall be combined into one observation.
+    #[getset(get_copy = "pub")]
+    umi_families: bool,
+    /// Format of the alternative hits reported by the read mapper.
+    #[getset(get_copy = "pub")]
+    alt_hit_format: AltHitFormat,
 }
 
 impl RecordBuffer {
@@ -74,9 +83,10 @@ impl RecordBuffer {
     }
 
     pub(crate) fn iter(&self) -> impl Iterator<Item = Rc<bam::Record>> + '_ {
+        let umi_families = self.umi_families;
         self.inner
             .iter()
-            .filter(|record| is_valid_record(record.as_ref()))
+            .filter(move |record| is_valid_record(record.as_ref(), umi_families))
             .map(Rc::clone)
     }
 }
@@ -127,6 +137,30 @@ impl SubsampleCandidates {
         }
     }
 
+    /// Subsample the given candidate evidence of all BAM files of a sample, such that the
+    /// sample does not exceed the given maximum depth.
+    pub(crate) fn for_evidence(candidates: &[CandidateEvidence], max_depth: usize) -> Self {
+        // METHOD: if all loci exceed the maximum depth, we subsample the evidence.
+        // We cannot decide this per locus, because we risk adding more biases if loci have
+        // different alt allele sampling biases. The depth is pooled over all BAM files of the
+        // sample, such that the maximum depth applies to the sample as a whole.
+        let mut locus_depth: VecMap<usize> = VecMap::new();
+        for candidates in candidates {
+            for (locus, depth) in candidates.locus_depth.iter() {
+                *locus_depth.entry(locus).or_insert(0) += depth;
+            }
+        }
+        if locus_depth.values().all(|depth| *depth > max_depth) {
+            let families = candidates
+                .iter()
+                .map(|candidates| candidates.families.len())
+                .sum();
+            SubsampleCandidates::new(max_depth, families)
+        } else {
+            SubsampleCandidates::None
+        }
+    }
+
     pub(crate) fn keep(&mut self) -> bool {
         match self {
             SubsampleCandidates::Necessary {
@@ -142,12 +176,14 @@ impl SubsampleCandidates {
 pub(crate) fn estimate_alignment_properties<P: AsRef<Path>>(
     path: P,
     omit_insert_size: bool,
+    read_type: alignment_properties::ReadType,
     reference_buffer: &mut reference::Buffer,
     num_records: Option<usize>,
 ) -> Result<alignment_properties::AlignmentProperties> {
     alignment_properties::AlignmentProperties::estimate(
         path,
         omit_insert_size,
+        read_type,
         reference_buffer,
         num_records,
     )
@@ -157,66 +193,107 @@ pub(crate) fn estimate_alignment_properties<P: AsRef<Path>>(
 #[derive(Builder, Debug)]
 #[builder(pattern = "owned")]
 pub(crate) struct Sample {
+    /// One record buffer per BAM file of the sample.
     #[builder(private)]
-    record_buffer: RecordBuffer,
+    record_buffers: Vec<RecordBuffer>,
+    /// Alignment properties of each BAM file, in the same order as the record buffers.
     #[builder(private)]
-    alignment_properties: alignment_properties::AlignmentProperties,
+    alignment_properties: Vec<alignment_properties::AlignmentProperties>,
+    /// Maximum number of observations of the sample, over all its BAM files. If exceeded,
+    /// downsampling is performed.
     #[builder(default = "200")]
     max_depth: usize,
     #[builder(default)]
     fragment_id_factory: FragmentIdFactory,
     report_fragment_ids: bool,
+    /// Whether to report the lengths of the sequenced fragments (e.g. for cfDNA).
+    #[builder(default)]
+    report_fragment_lengths: bool,
     adjust_prob_mapping: bool,
+    /// Counter for depth observations, pooled over all BAM files of the sample.
+    /// If omitted, no depth observations are obtained.
+    #[builder(default)]
+    depth_counter: Option<DepthCounter>,
 }
 
 impl SampleBuilder {
     /// Register alignment information.
     ///
     /// # Arguments
-    /// * `bam` - BAM file with the aligned and deduplicated sequence reads.
+    /// * `bams` - BAM files with the aligned and deduplicated sequence reads, each together
+    ///   with its alignment properties.
+    /// * `umi_families` - whether to combine reads of the same UMI family (MI or RX tag) into
+    ///   one observation. Then, duplicates do not have to be removed from the BAM files.
+    /// * `alt_hit_format` - format of the alternative hits reported by the read mapper.
+    ///   If `None`, it is inferred from the header of each BAM file, falling back to bwa.
     pub(crate) fn alignments(
         self,
-        bam: bam::IndexedReader,
-        alignment_properties: alignment_properties::AlignmentProperties,
+        bams: Vec<(
+            bam::IndexedReader,
+            alignment_properties::AlignmentProperties,
+        )>,
         min_refetch_distance: u64,
+        umi_families: bool,
+        alt_hit_format: Option<AltHitFormat>,
     ) -> Self {
-        // METHOD: add maximum deletion len as this can make the footprint of the read on the reference
-        // effectively larger. Additionally add some 10 bases further to account for uncertainty in the
-        // estimated maximum deletion len.
-        let single_read_window = alignment_properties.max_read_len as u64
-            + alignment_properties
-                .max_del_cigar_len
-                .map_or(0, |l| l as u64)
-            + 10;
-
-        let read_pair_window = match alignment_properties.insert_size {
-            Some(isize) => (isize.mean + isize.sd * 6.0) as u64,
-            None => single_read_window,
-        };
-        let mut record_buffer = bam::RecordBuffer::new(bam, true);
-        record_buffer.set_min_refetch_distance(min_refetch_distance);
+        let (record_buffers, alignment_properties): (Vec<_>, Vec<_>) = bams
+            .into_iter()
+            .map(|(bam, alignment_properties)| {
+                // METHOD: add maximum deletion len as this can make the footprint of the read on the reference
+                // effectively larger. Additionally add some 10 bases further to account for uncertainty in the
+                // estimated maximum deletion len.
+                let single_read_window = alignment_properties.max_read_len as u64
+                    + alignment_properties
+                        .max_del_cigar_len
+                        .map_or(0, |l| l as u64)
+                    + 10;
+
+                // METHOD: the window has to cover the fragments of the read group with the largest
+                // insert sizes.
+                let read_pair_window = alignment_properties
+                    .iter_read_groups()
+                    .filter_map(|properties| properties.insert_size)
+                    .map(|isize| (isize.mean + isize.sd * 6.0) as u64)
+                    .max()
+                    .unwrap_or(single_read_window);
+                let alt_hit_format = alt_hit_format
+                    .or_else(|| AltHitFormat::from_header(bam.header()))
+                    .unwrap_or_default();
+                let mut record_buffer = bam::RecordBuffer::new(bam, true);
+                record_buffer.set_min_refetch_distance(min_refetch_distance);
+                (
+                    RecordBuffer::new(
+                        record_buffer,
+                        single_read_window,
+                        read_pair_window,
+                        umi_families,
+                        alt_hit_format,
+                    ),
+                    alignment_properties,
+                )
+            })
+            .unzip();
         self.alignment_properties(alignment_properties)
-            .record_buffer(RecordBuffer::new(
-                record_buffer,
-                single_read_window,
-                read_pair_window,
-            ))
+            .record_buffers(record_buffers)
     }
 }
 
-fn is_valid_record(record: &bam::Record) -> bool {
+/// Check whether the given record shall be considered.
+/// If reads are grouped into UMI families, duplicates are kept, since they are members
+/// of the family of the read they duplicate.
+pub(crate) fn is_valid_record(record: &bam::Record, umi_families: bool) -> bool {
     !(record.is_secondary()
-        || record.is_duplicate()
+        || (record.is_duplicate() && !umi_families)
         || record.is_unmapped()
         || record.is_quality_check_failed())
 }
 
 impl Sample {
-    /// Extract observations for the given variant.
+    /// Extract observations for the given variant from all BAM files of the sample.
     pub(crate) fn extract_observations<V>(
         &mut self,
         variant: &V,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Pileup>
     where
         V: Variant + Observable,
@@ -237,23 +314,248 @@ impl Sample {
             None
         };
 
-        let observations = variant.extract_observations(
-            &mut self.record_buffer,
-            &mut self.alignment_properties,
-            self.max_depth,
-            alt_variants,
-            &mut observation_id_factory,
-        )?;
+        let candidates = self
+            .record_buffers
+            .iter_mut()
+            .zip(self.alignment_properties.iter_mut())
+            .map(|(record_buffer, alignment_properties)| {
+                variant.collect_evidence(record_buffer, alignment_properties)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut subsampler = SubsampleCandidates::for_evidence(&candidates, self.max_depth);
+        let mut observations = Vec::with_capacity(self.record_buffers.len());
+        for (file, ((candidates, record_buffer), alignment_properties)) in candidates
+            .iter()
+            .zip(self.record_buffers.iter())
+            .zip(self.alignment_properties.iter_mut())
+            .enumerate()
+        {
+            if let Some(observation_id_factory) = observation_id_factory.as_mut() {
+                observation_id_factory.register_file(file);
+            }
+            observations.push(variant.extract_observations(
+                candidates,
+                record_buffer,
+                alignment_properties,
+                &mut subsampler,
+                alt_variants,
+                &mut observation_id_factory,
+            )?);
+        }
+
         // Process for each observation whether it is from the major read position or not.
-        let major_pos = major_read_position(&observations);
-        let major_alt_locus = major_alt_locus(&observations, &self.alignment_properties);
-        let mut observations: Vec<_> = observations
+        let all_observations = observations.concat();
+        let major_pos = major_read_position(&all_observations);
+        // METHOD: alternative loci are mapped to buckets of the same size for all BAM files,
+        // such that they remain comparable across files with different read lengths.
+        let max_read_len = self
+            .alignment_properties
             .iter()
-            .map(|obs| obs.process(major_pos, &major_alt_locus, &self.alignment_properties))
-            .collect();
-        if self.adjust_prob_mapping {
-            ReadObservation::adjust_prob_mapping(&mut observations, &self.alignment_properties);
+            .map(|alignment_properties| alignment_properties.max_read_len)
+            .max()
+            .unwrap();
+        let major_alt_locus = major_alt_locus(&all_observations, max_read_len);
+        let mut processed_observations = Vec::with_capacity(all_observations.len());
+        for (observations, alignment_properties) in
+            observations.iter().zip(self.alignment_properties.iter())
+        {
+            let mut observations: Vec<_> = observations
+                .iter()
+                .map(|obs| {
+                    obs.process(
+                        major_pos,
+                        &major_alt_locus,
+                        max_read_len,
+                        alignment_properties,
+                    )
+                })
+                .collect();
+            // METHOD: MAPQs are adjusted per BAM file, since the files may stem from
+            // different read mappers with different maximum MAPQs.
+            if self.adjust_prob_mapping {
+                ReadObservation::adjust_prob_mapping(&mut observations, alignment_properties);
+            }
+            if !self.report_fragment_lengths {
+                // do not blow up the observation BCF if fragment lengths are not needed
+                for obs in &mut observations {
+                    obs.fragment_len = None;
+                }
+            }
+            processed_observations.extend(observations);
+        }
+
+        let mut depth_observations = Vec::new();
+        if let (Some(depth_event), Some(depth_counter)) =
+            (variant.depth_event(), self.depth_counter.as_mut())
+        {
+            depth_observations
+                .extend(depth_counter.observe(&depth_event, &self.alignment_properties)?);
         }
-        Ok(Pileup::new(observations, Vec::new())) // TODO add depth observations!
+
+        Ok(Pileup::new(processed_observations, depth_observations))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::estimation::alignment_properties::{AlignmentProperties, ReadType, NUM_FRAGMENTS};
+    use crate::variants::evidence::realignment::PathHMMRealigner;
+    use crate::variants::types::Deletion;
+
+    const BAM: &str = "tests/resources/tumor-first30000.bam";
+
+    /// Split the records of the test BAM into two files, keeping the reads of each fragment
+    /// together, such that the two files together are equivalent to the test BAM.
+    fn split_bam(dir: &Path) -> Vec<PathBuf> {
+        let mut reader = bam::Reader::from_path(BAM).unwrap();
+        let header = bam::Header::from_template(reader.header());
+        let paths = vec![dir.join("a.bam"), dir.join("b.bam")];
+        {
+            let mut writers = paths
+                .iter()
+                .map(|path| bam::Writer::from_path(path, &header, bam::Format::Bam).unwrap())
+                .collect::<Vec<_>>();
+            for record in reader.records() {
+                let record = record.unwrap();
+                let file = record.qname().iter().map(|c| *c as usize).sum::<usize>() % 2;
+                writers[file].write(&record).unwrap();
+            }
+        }
+        for path in &paths {
+            bam::index::build(path, None, bam::index::Type::Bai, 1).unwrap();
+        }
+        paths
+    }
+
+    fn sample(
+        bams: &[PathBuf],
+        alignment_properties: &AlignmentProperties,
+        report_fragment_ids: bool,
+        max_depth: usize,
+    ) -> Sample {
+        let bam_reader = |path: &PathBuf| bam::IndexedReader::from_path(path).unwrap();
+        SampleBuilder::default()
+            .max_depth(max_depth)
+            .report_fragment_ids(report_fragment_ids)
+            .adjust_prob_mapping(false)
+            .alignments(
+                bams.iter()
+                    .map(|path| (bam_reader(path), alignment_properties.clone()))
+                    .collect(),
+                1,
+                false,
+                None,
+            )
+            .depth_counter(Some(DepthCounter::new(
+                bams.iter().map(bam_reader).collect(),
+            )))
+            .build()
+            .unwrap()
+    }
+
+    fn setup() -> (AlignmentProperties, Deletion<PathHMMRealigner>) {
+        let mut reference_buffer =
+            reference::Buffer::from_path("tests/resources/chr10.fa", 1).unwrap();
+        let alignment_properties = estimate_alignment_properties(
+            BAM,
+            false,
+            ReadType::Short,
+            &mut reference_buffer,
+            Some(NUM_FRAGMENTS),
+        )
+        .unwrap();
+        let realigner = PathHMMRealigner::new(
+            alignment_properties.gap_params.clone(),
+            64,
+            Arc::new(reference_buffer),
+            Vec::new(),
+        );
+        let deletion = Deletion::new(
+            genome::Interval::new("chr10".to_owned(), 90000..91000),
+            realigner,
+        )
+        .unwrap();
+        (alignment_properties, deletion)
+    }
+
+    #[test]
+    fn test_extract_observations_multiple_bams() {
+        let tmp = tempfile::tempdir().unwrap();
+        let split = split_bam(tmp.path());
+        let (alignment_properties, deletion) = setup();
+
+        let observations = |bams: &[PathBuf]| {
+            let pileup = sample(bams, &alignment_properties, false, usize::MAX)
+                .extract_observations(&deletion, &[])
+                .unwrap();
+            let mut probs = pileup
+                .read_observations()
+                .iter()
+                .map(|obs| (obs.prob_alt.0.to_bits(), obs.prob_ref.0.to_bits()))
+                .collect::<Vec<_>>();
+            probs.sort_unstable();
+            (probs, pileup.depth_observations().to_vec())
+        };
+
+        let (merged, merged_depth) = observations(&[PathBuf::from(BAM)]);
+        let (separate, separate_depth) = observations(&split);
+        assert!(!merged.is_empty());
+        // two BAMs yield the same pileup as their merge
+        assert_eq!(merged, separate);
+        // depth is pooled over the BAM files of the sample
+        assert_eq!(merged_depth.len(), 1);
+        assert_eq!(merged_depth, separate_depth);
+    }
+
+    #[test]
+    fn test_max_depth_multiple_bams() {
+        let tmp = tempfile::tempdir().unwrap();
+        let split = split_bam(tmp.path());
+        let (alignment_properties, deletion) = setup();
+
+        let depth = |bams: &[PathBuf], max_depth| {
+            sample(bams, &alignment_properties, false, max_depth)
+                .extract_observations(&deletion, &[])
+                .unwrap()
+                .read_observations()
+                .len()
+        };
+
+        let full_depth = depth(&split, usize::MAX);
+        let max_depth = full_depth / 4;
+        assert!(max_depth > 0);
+        // the maximum depth applies to the sample, not to each of its BAM files, which
+        // would otherwise retain about half of the observations here
+        let subsampled_depth = depth(&split, max_depth);
+        assert!(subsampled_depth > 0);
+        assert!(subsampled_depth < 3 * full_depth / 8);
+    }
+
+    #[test]
+    fn test_fragment_ids_multiple_bams() {
+        let (alignment_properties, deletion) = setup();
+        // the same fragments in two different BAM files
+        let bams = [PathBuf::from(BAM), PathBuf::from(BAM)];
+        let pileup = sample(&bams, &alignment_properties, true, usize::MAX)
+            .extract_observations(&deletion, &[])
+            .unwrap();
+
+        let fragment_ids = pileup
+            .read_observations()
+            .iter()
+            .map(|obs| obs.fragment_id.unwrap())
+            .collect::<Vec<_>>();
+        assert!(!fragment_ids.is_empty());
+        let (first, second) = fragment_ids.split_at(fragment_ids.len() / 2);
+        let first = first.iter().collect::<HashSet<_>>();
+        let second = second.iter().collect::<HashSet<_>>();
+        assert_eq!(first.len(), second.len());
+        assert!(first.is_disjoint(&second));
     }
 }
@@ -27,10 +27,10 @@ use crate::reference;
 use crate::utils::aux_info::AuxInfo;
 use crate::variants::evidence::observations::read_observation::Strand;
 use crate::variants::evidence::realignment::pairhmm::{
-    RefBaseEmission, RefBaseVariantEmission, VariantEmission,
+    RefBaseEmission, RefBaseVariantEmission, VariantEmission, UNKNOWN_BASE,
 };
 use crate::variants::evidence::realignment::{Realignable, Realigner};
-use crate::variants::model::{self, VariantPrecision};
+use crate::variants::model::{self, confidence_interval_positions, VariantPrecision};
 use crate::variants::sampling_bias::{FragmentSamplingBias, ReadSamplingBias, SamplingBias};
 use crate::variants::types::{
     AlleleSupport, AlleleSupportBuilder, Evidence, MultiLocus, SingleLocus, SingleLocusBuilder,
@@ -219,6 +219,12 @@ impl<R: Realigner> BreakendGroup<R> {
         }
     }
 
+    /// Whether the group consists of single breakends, i.e. breakends that are not joined
+    /// to another locus (e.g. `.A` or `A.`).
+    fn is_single(&self) -> bool {
+        self.breakends.values().all(|bnd| bnd.join().is_none())
+    }
+
     fn is_insertion(&self) -> bool {
         if let Some((left, right)) = self.breakend_pair() {
             if left.locus.pos() + 1 == right.locus.pos()
@@ -396,7 +402,7 @@ impl<R: Realigner> Variant for BreakendGroup<R> {
         &self,
         evidence: &Evidence,
         alignment_properties: &AlignmentProperties,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
         if self.imprecise {
             if let Some(classification) = self.classify_imprecise_evidence(evidence) {
@@ -494,6 +500,35 @@ impl<R: Realigner> Variant for BreakendGroup<R> {
                         alignment_properties,
                     )?))
                 }
+                Evidence::PairedEndSequencingRead { left, right } if self.is_single() => {
+                    // METHOD: the sequence beyond a single breakend is unknown. Hence, only
+                    // reads overlapping the breakend (i.e. split or softclipped reads for the
+                    // alt allele, spanning reads for the reference allele) can be evaluated.
+                    // Their mates do not carry any information.
+                    let mut support: Option<AlleleSupport> = None;
+                    for read in [left, right] {
+                        if !self
+                            .loci
+                            .iter()
+                            .any(|locus| !locus.overlap(read, true).is_none())
+                        {
+                            continue;
+                        }
+                        let read_support = self.realigner.borrow_mut().allele_support(
+                            read,
+                            self.loci.iter(),
+                            self,
+                            alt_variants,
+                            alignment_properties,
+                        )?;
+                        if let Some(ref mut support) = support {
+                            support.merge(&read_support);
+                        } else {
+                            support = Some(read_support);
+                        }
+                    }
+                    Ok(support)
+                }
                 Evidence::PairedEndSequencingRead { left, right } => {
                     let left_support = self.realigner.borrow_mut().allele_support(
                         left,
@@ -591,15 +626,7 @@ impl<R: Realigner> SamplingBias for BreakendGroup<R> {
             if let Some((left, right)) = self.breakend_pair() {
                 // METHOD: if the deletion is imprecise, we take the minimum len derived from the
                 // confidence intervals.
-                let left_add = match left.precision() {
-                    VariantPrecision::Imprecise { cistart, .. } => *cistart.end(),
-                    _ => 0,
-                };
-                let right_sub = match right.precision() {
-                    VariantPrecision::Imprecise { cistart, .. } => *cistart.start(),
-                    _ => 0,
-                };
-                return Some((right.locus.pos() - right_sub) - (left.locus.pos() + left_add));
+                return Some(right.min_pos().saturating_sub(left.max_pos()));
             }
         } else if self.is_insertion() {
             return Some(self.breakends.values().next().unwrap().replacement.len() as u64 - 1);
@@ -810,7 +837,13 @@ impl<R: Realigner> Realignable for BreakendGroup<R> {
                         );
                     } else {
                         // Single breakend, assembly stops here.
-                        // Nothing else to do, the replacement sequence has already been added in the step before.
+                        // METHOD: the sequence beyond the replacement is unknown. Instead of
+                        // continuing along the reference (which would render the alt allele
+                        // identical to the reference), we append a free end of unknown bases,
+                        // that can be emitted by any read base (e.g. from softclips).
+                        let unknown = vec![UNKNOWN_BASE; ref_window];
+                        alt_allele.push_seq(unknown.iter(), !current.is_left_to_right(), false);
+                        next_bnd = None;
                     }
                 }
 
@@ -838,6 +871,10 @@ impl<R: Realigner> Realignable for BreakendGroup<R> {
 
         Ok(emission_params)
     }
+
+    fn realignment_loci(&self) -> &MultiLocus {
+        self.loci()
+    }
 }
 
 #[derive(Derefable, Debug, Default)]
@@ -1161,7 +1198,7 @@ impl Breakend {
     fn max_pos(&self) -> u64 {
         let pos = self.locus.pos();
         if let VariantPrecision::Imprecise { ref cistart, .. } = self.precision {
-            pos + cistart.end()
+            *confidence_interval_positions(pos, cistart).end()
         } else {
             pos
         }
@@ -1171,7 +1208,7 @@ impl Breakend {
     fn min_pos(&self) -> u64 {
         let pos = self.locus.pos();
         if let VariantPrecision::Imprecise { ref cistart, .. } = self.precision {
-            pos.saturating_sub(*cistart.start())
+            *confidence_interval_positions(pos, cistart).start()
         } else {
             pos
         }
@@ -18,6 +18,7 @@ use crate::default_ref_base_emission;
 use crate::estimation::alignment_properties::AlignmentProperties;
 use crate::reference;
 use crate::utils::homopolymers::{extend_homopolymer_stretch, is_homopolymer_seq};
+use crate::variants::evidence::observations::depth_observation::DepthEvent;
 use crate::variants::evidence::realignment::pairhmm::{
     RefBaseEmission, RefBaseVariantEmission, VariantEmission,
 };
@@ -139,6 +140,10 @@ impl<R: Realigner> Realignable for Deletion<R> {
             ref_end_override: None,
         })])
     }
+
+    fn realignment_loci(&self) -> &MultiLocus {
+        self.loci()
+    }
 }
 
 impl<R: Realigner> Variant for Deletion<R> {
@@ -155,6 +160,18 @@ impl<R: Realigner> Variant for Deletion<R> {
         }
     }
 
+    fn depth_event(&self) -> Option<DepthEvent> {
+        // The deleted bases start after the (anchor) position of the locus.
+        let range = self.locus.range();
+        Some(DepthEvent::new(
+            genome::Interval::new(
+                self.locus.contig().to_owned(),
+                range.start + 1..range.end + 1,
+            ),
+            0,
+        ))
+    }
+
     fn is_valid_evidence(
         &self,
         evidence: &Evidence,
@@ -205,7 +222,7 @@ impl<R: Realigner> Variant for Deletion<R> {
         &self,
         evidence: &Evidence,
         alignment_properties: &AlignmentProperties,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
         match evidence {
             Evidence::SingleEndSequencingRead(record) => {
@@ -3,6 +3,7 @@ use bio::stats::LogProb;
 use bio_types::genome::{self, AbstractInterval};
 
 use crate::estimation::alignment_properties::AlignmentProperties;
+use crate::variants::evidence::observations::depth_observation::DepthEvent;
 use crate::variants::evidence::realignment::{Realignable, Realigner};
 use crate::variants::model;
 use crate::variants::types::breakends::{
@@ -15,6 +16,7 @@ use super::ToVariantRepresentation;
 #[derive(Debug)]
 pub(crate) struct Duplication<R: Realigner> {
     breakends: BreakendGroup<R>,
+    interval: genome::Interval,
     len: u64,
 }
 
@@ -94,6 +96,7 @@ impl<R: Realigner> Duplication<R> {
         Duplication {
             breakends: breakend_group_builder.build().unwrap(),
             len: interval.range().end - interval.range().start,
+            interval,
         }
     }
 }
@@ -103,6 +106,10 @@ impl<R: Realigner> Variant for Duplication<R> {
         false
     }
 
+    fn depth_event(&self) -> Option<DepthEvent> {
+        Some(DepthEvent::new(self.interval.clone(), 2))
+    }
+
     fn is_valid_evidence(
         &self,
         evidence: &Evidence,
@@ -120,7 +127,7 @@ impl<R: Realigner> Variant for Duplication<R> {
         &self,
         evidence: &Evidence,
         alignment_properties: &AlignmentProperties,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
         let support =
             self.breakends
@@ -156,4 +163,8 @@ impl<R: Realigner> Realignable for Duplication<R> {
         self.breakends
             .alt_emission_params(ref_buffer, ref_interval, ref_window)
     }
+
+    fn realignment_loci(&self) -> &MultiLocus {
+        self.loci()
+    }
 }
@@ -13,7 +13,8 @@ use itertools::Itertools;
 use crate::estimation::alignment_properties::AlignmentProperties;
 use crate::variants::evidence::observations::read_observation::Observable;
 use crate::variants::evidence::realignment::edit_distance::EditDistance;
-use crate::variants::evidence::realignment::Realignable;
+use crate::variants::evidence::realignment::{Realignable, Realigner};
+use crate::variants::types::breakends::{Breakend, BreakendGroup};
 use crate::variants::types::{AlleleSupport, AlleleSupportBuilder, Evidence, MultiLocus, Variant};
 
 use super::ToVariantRepresentation;
@@ -25,7 +26,13 @@ impl<V> HaplotypeVariant for V where V: Variant + Observable + ToVariantRepresen
 #[derive(Default, Getters)]
 pub(crate) struct HaplotypeBlock {
     #[getset(get = "pub(crate)")]
-    variants: Vec<Box<dyn HaplotypeVariant>>,
+    variants: Vec<Box<dyn HaplotypeVariant + Send>>,
+    /// Breakend groups that are part of the haplotype (e.g. small indels at the junction
+    /// of a rearrangement).
+    breakend_groups: Vec<Box<dyn Variant + Send>>,
+    /// Breakends of all breakend groups, in order to report them.
+    #[getset(get = "pub(crate)")]
+    breakends: Vec<Breakend>,
     loci: MultiLocus,
 }
 
@@ -33,14 +40,25 @@ impl HaplotypeBlock {
     pub(crate) fn new() -> Self {
         HaplotypeBlock {
             variants: Vec::new(),
+            breakend_groups: Vec::new(),
+            breakends: Vec::new(),
             loci: MultiLocus::new(Vec::new()),
         }
     }
 
-    pub(crate) fn push_variant(&mut self, variant: Box<dyn HaplotypeVariant>) {
+    pub(crate) fn push_variant(&mut self, variant: Box<dyn HaplotypeVariant + Send>) {
         self.loci.extend(variant.loci().iter().cloned());
         self.variants.push(variant);
     }
+
+    pub(crate) fn push_breakend_group<R>(&mut self, group: BreakendGroup<R>)
+    where
+        R: Realigner + Send + 'static,
+    {
+        self.loci.extend(group.loci().iter().cloned());
+        self.breakends.extend(group.breakends().cloned());
+        self.breakend_groups.push(Box::new(group));
+    }
 }
 
 impl Variant for HaplotypeBlock {
@@ -57,17 +75,26 @@ impl Variant for HaplotypeBlock {
         let valid_indices: Vec<usize> = self
             .variants
             .iter()
+            .map(|variant| {
+                (
+                    variant.is_valid_evidence(evidence, alignment_properties),
+                    variant.loci().len(),
+                )
+            })
+            .chain(self.breakend_groups.iter().map(|group| {
+                (
+                    group.is_valid_evidence(evidence, alignment_properties),
+                    group.loci().len(),
+                )
+            }))
             .enumerate()
-            .filter_map(|(i, variant)| {
-                let ret = if variant
-                    .is_valid_evidence(evidence, alignment_properties)
-                    .is_some()
-                {
+            .filter_map(|(i, (valid, n_loci))| {
+                let ret = if valid.is_some() {
                     Some(i + locus_offset)
                 } else {
                     None
                 };
-                locus_offset += variant.loci().len();
+                locus_offset += n_loci;
                 ret
             })
             .collect();
@@ -87,12 +114,25 @@ impl Variant for HaplotypeBlock {
         &self,
         evidence: &Evidence,
         alignment_properties: &AlignmentProperties,
-        _alt_variants: &[Box<dyn Realignable>],
+        _alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
+        // METHOD: breakend groups are combined with the small variants of the haplotype
+        // in the same way as small variants among each other.
         let support: Vec<Option<_>> = self
             .variants
             .iter()
             .map(|variant| variant.allele_support(evidence, alignment_properties, &[]))
+            .chain(self.breakend_groups.iter().map(|group| {
+                // breakend groups cannot evaluate evidence that does not reach their breakends
+                if group
+                    .is_valid_evidence(evidence, alignment_properties)
+                    .is_some()
+                {
+                    group.allele_support(evidence, alignment_properties, &[])
+                } else {
+                    Ok(None)
+                }
+            }))
             .collect::<Result<_>>()?;
         let support = support.into_iter().flatten().collect_vec();
         if support.is_empty() {
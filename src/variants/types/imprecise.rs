@@ -0,0 +1,305 @@
+// Copyright 2024 Johannes Köster.
+// Licensed under the GNU GPLv3 license (https://opensource.org/licenses/GPL-3.0)
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Imprecise structural variants, i.e. variants whose breakpoints are only known up to
+//! confidence intervals (CIPOS and CIEND).
+
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+
+use anyhow::Result;
+use bio::stats::{LogProb, Prob};
+use itertools::Itertools;
+
+use crate::estimation::alignment_properties::AlignmentProperties;
+use crate::variants::evidence::observations::breakpoint_observation::BreakpointObservation;
+use crate::variants::evidence::observations::depth_observation::DepthEvent;
+use crate::variants::evidence::realignment::Realignable;
+use crate::variants::model::confidence_interval_positions;
+use crate::variants::types::{AlleleSupport, Evidence, MultiLocus, SingleLocus, Variant};
+
+/// Maximum number of candidate positions considered per breakpoint.
+const MAX_BREAKPOINT_CANDIDATES: u64 = 5;
+
+/// Candidate positions of a breakpoint, given its reported position and confidence
+/// interval. The reported position is always a candidate.
+fn breakpoint_candidates(pos: u64, ci: &RangeInclusive<i64>) -> Vec<u64> {
+    let (min, max) = confidence_interval_positions(pos, ci).into_inner();
+    // METHOD: for wide confidence intervals, we evaluate a grid of positions instead of
+    // every single one, in order to keep the runtime feasible. The grid is fine enough
+    // for insert size evidence, which carries the bulk of the information for imprecise
+    // variants.
+    let step = ((max - min) / (MAX_BREAKPOINT_CANDIDATES - 1)).max(1);
+    let mut candidates = (min..=max).step_by(step as usize).collect_vec();
+    candidates.push(pos);
+    candidates.sort_unstable();
+    candidates.dedup();
+    candidates
+}
+
+#[derive(Debug)]
+struct Candidate<V> {
+    pos: u64,
+    end: u64,
+    variant: V,
+    /// Indices of the loci of the variant in the loci of the imprecise variant.
+    loci: Vec<usize>,
+}
+
+/// An imprecise variant, evaluated by marginalizing over candidate placements of its
+/// breakpoints within the confidence intervals.
+#[derive(Debug)]
+pub(crate) struct Imprecise<V: Variant> {
+    candidates: Vec<Candidate<V>>,
+    loci: MultiLocus,
+    /// Log likelihood ratio of the evidence given the variant at the candidate placement
+    /// (assuming heterozygosity) vs. the reference, accumulated over all evidence.
+    likelihoods: RefCell<Vec<LogProb>>,
+}
+
+impl<V: Variant> Imprecise<V> {
+    /// Create a new imprecise variant.
+    ///
+    /// # Arguments
+    /// * `pos` - reported position of the variant (0-based, as POS in BCF records)
+    /// * `end` - reported end of the variant (1-based, as the END tag in VCF records)
+    /// * `cistart` - confidence interval of the position (CIPOS)
+    /// * `ciend` - confidence interval of the end (CIEND), if any
+    /// * `build` - builds the precise variant for given pos and end, `None` if infeasible
+    pub(crate) fn new<F>(
+        pos: u64,
+        end: u64,
+        cistart: &RangeInclusive<i64>,
+        ciend: Option<&RangeInclusive<i64>>,
+        build: F,
+    ) -> Result<Self>
+    where
+        F: Fn(u64, u64) -> Result<Option<V>>,
+    {
+        let ends = breakpoint_candidates(end, ciend.unwrap_or(&(0..=0)));
+        // the reported placement comes first, such that it determines the primary locus
+        let placements = std::iter::once((pos, end)).chain(
+            breakpoint_candidates(pos, cistart)
+                .into_iter()
+                .cartesian_product(ends)
+                .filter(|placement| *placement != (pos, end)),
+        );
+
+        let mut loci: Vec<SingleLocus> = Vec::new();
+        let mut candidates = Vec::new();
+        for (pos, end) in placements {
+            if let Some(variant) = build(pos, end)? {
+                let variant_loci = variant
+                    .loci()
+                    .iter()
+                    .map(|locus| {
+                        loci.iter()
+                            .position(|other| **other == **locus)
+                            .unwrap_or_else(|| {
+                                loci.push(locus.clone());
+                                loci.len() - 1
+                            })
+                    })
+                    .collect();
+                candidates.push(Candidate {
+                    pos,
+                    end,
+                    variant,
+                    loci: variant_loci,
+                });
+            }
+        }
+        let n = candidates.len();
+
+        Ok(Imprecise {
+            candidates,
+            loci: MultiLocus::new(loci),
+            likelihoods: RefCell::new(vec![LogProb::ln_one(); n]),
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// Likelihoods of all candidate placements of the breakpoints, given the evidence seen so far.
+    pub(crate) fn breakpoint_observations(&self) -> Vec<BreakpointObservation> {
+        self.candidates
+            .iter()
+            .zip(self.likelihoods.borrow().iter())
+            .map(|(candidate, likelihood)| {
+                BreakpointObservation::new(candidate.pos, candidate.end, *likelihood)
+            })
+            .collect()
+    }
+
+    fn prior(&self) -> LogProb {
+        LogProb::from(Prob(1.0 / self.candidates.len() as f64))
+    }
+}
+
+impl<V: Variant> Variant for Imprecise<V> {
+    fn is_imprecise(&self) -> bool {
+        true
+    }
+
+    fn is_valid_evidence(
+        &self,
+        evidence: &Evidence,
+        alignment_properties: &AlignmentProperties,
+    ) -> Option<Vec<usize>> {
+        let loci = self
+            .candidates
+            .iter()
+            .filter_map(|candidate| {
+                candidate
+                    .variant
+                    .is_valid_evidence(evidence, alignment_properties)
+                    .map(|idx| idx.into_iter().map(|i| candidate.loci[i]).collect_vec())
+            })
+            .flatten()
+            .unique()
+            .collect_vec();
+        if loci.is_empty() {
+            None
+        } else {
+            Some(loci)
+        }
+    }
+
+    fn loci(&self) -> &MultiLocus {
+        &self.loci
+    }
+
+    fn allele_support(
+        &self,
+        evidence: &Evidence,
+        alignment_properties: &AlignmentProperties,
+        alt_variants: &[Box<dyn Realignable + Send>],
+    ) -> Result<Option<AlleleSupport>> {
+        let mut supports = Vec::with_capacity(self.candidates.len());
+        for candidate in &self.candidates {
+            supports.push(
+                if candidate
+                    .variant
+                    .is_valid_evidence(evidence, alignment_properties)
+                    .is_some()
+                {
+                    candidate.variant.allele_support(
+                        evidence,
+                        alignment_properties,
+                        alt_variants,
+                    )?
+                } else {
+                    None
+                },
+            );
+        }
+
+        let best = supports
+            .iter()
+            .enumerate()
+            .filter_map(|(i, support)| support.as_ref().map(|support| (i, support)))
+            .max_by(|(_, a), (_, b)| {
+                a.prob_alt_allele()
+                    .partial_cmp(&b.prob_alt_allele())
+                    .unwrap()
+            })
+            .map(|(i, _)| i);
+
+        if let Some(best) = best {
+            let prob_ref = supports[best].as_ref().unwrap().prob_ref_allele();
+            let prior = self.prior();
+            // METHOD: we use a uniform prior over the candidate placements of the breakpoints
+            // and sum up the joint probabilities for the alt allele. If the evidence is not
+            // informative for a placement (e.g. because it does not overlap it), it is equally
+            // likely under both alleles.
+            let prob_alt = LogProb::ln_sum_exp(
+                &supports
+                    .iter()
+                    .map(|support| {
+                        support
+                            .as_ref()
+                            .map_or(prob_ref, |support| support.prob_alt_allele())
+                            + prior
+                    })
+                    .collect_vec(),
+            );
+
+            // METHOD: record how well each placement explains the evidence, in terms of the
+            // likelihood ratio against the reference, assuming a heterozygous variant.
+            // This is later used to report refined breakpoints.
+            let half = LogProb::from(Prob(0.5));
+            for (likelihood, support) in self
+                .likelihoods
+                .borrow_mut()
+                .iter_mut()
+                .zip(supports.iter())
+            {
+                if let Some(support) = support {
+                    *likelihood += (support.prob_alt_allele() - support.prob_ref_allele() + half)
+                        .ln_add_exp(half);
+                }
+            }
+
+            let mut support = supports.swap_remove(best).unwrap();
+            support.prob_alt_allele = prob_alt;
+            support.prob_ref_allele = prob_ref;
+            Ok(Some(support))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn prob_sample_alt(
+        &self,
+        evidence: &Evidence,
+        alignment_properties: &AlignmentProperties,
+    ) -> LogProb {
+        let prior = self.prior();
+        LogProb::ln_sum_exp(
+            &self
+                .candidates
+                .iter()
+                .map(|candidate| {
+                    candidate
+                        .variant
+                        .prob_sample_alt(evidence, alignment_properties)
+                        + prior
+                })
+                .collect_vec(),
+        )
+    }
+
+    fn depth_event(&self) -> Option<DepthEvent> {
+        // METHOD: depth is counted over the reported placement of the variant.
+        self.candidates
+            .first()
+            .and_then(|candidate| candidate.variant.depth_event())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breakpoint_candidates() {
+        assert_eq!(breakpoint_candidates(100, &(0..=0)), vec![100]);
+        assert_eq!(
+            breakpoint_candidates(100, &(-2..=2)),
+            vec![98, 99, 100, 101, 102]
+        );
+        let candidates = breakpoint_candidates(1000, &(-500..=300));
+        assert_eq!(candidates.first(), Some(&500));
+        assert_eq!(candidates.last(), Some(&1300));
+        assert!(candidates.contains(&1000));
+        assert!(candidates.len() <= MAX_BREAKPOINT_CANDIDATES as usize + 1);
+        // asymmetric intervals that do not contain the reported position
+        assert_eq!(breakpoint_candidates(100, &(1..=2)), vec![100, 101, 102]);
+        // confidence intervals must not exceed the contig start
+        assert_eq!(breakpoint_candidates(1, &(-5..=0))[0], 0);
+    }
+}
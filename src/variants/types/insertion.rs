@@ -6,34 +6,57 @@
 use std::cell::RefCell;
 use std::cmp;
 use std::ops::Range;
-use std::rc::Rc;
 use std::sync::Arc;
 
 use anyhow::Result;
 
+use bio::alphabets::dna;
 use bio::stats::LogProb;
 use bio_types::genome::{self, AbstractInterval, AbstractLocus};
+use bio_types::strand::Strand as ElementStrand;
+use rust_htslib::bam;
 
 use crate::default_ref_base_emission;
 use crate::estimation::alignment_properties::AlignmentProperties;
 use crate::reference;
 use crate::utils::homopolymers::{extend_homopolymer_stretch, is_homopolymer_seq};
+use crate::variants::evidence::observations::read_observation::Strand;
 use crate::variants::evidence::realignment::pairhmm::{
     RefBaseEmission, RefBaseVariantEmission, VariantEmission,
 };
 use crate::variants::evidence::realignment::{Realignable, Realigner};
 use crate::variants::model;
 use crate::variants::sampling_bias::{ReadSamplingBias, SamplingBias};
-use crate::variants::types::{AlleleSupport, Evidence, MultiLocus, SingleLocus, Variant};
+use crate::variants::types::{
+    AlleleSupport, AlleleSupportBuilder, Evidence, MultiLocus, SingleLocus, Variant,
+};
 
 use super::ToVariantRepresentation;
 
+/// Probability to observe a read with a discordant mate pointing towards the insertion site
+/// if there is no insertion, e.g. because of chimeric fragments or mapping artifacts.
+const PROB_DISCORDANT_MATE_ARTIFACT: f64 = 0.01;
+/// Number of standard deviations above the mean insert size from which a mate is
+/// considered to be discordant.
+const DISCORDANT_INSERT_SIZE_SDS: f64 = 6.0;
+
 #[derive(Debug)]
 pub(crate) struct Insertion<R: Realigner> {
     locus: MultiLocus,
-    ins_seq: Rc<Vec<u8>>,
+    ins_seq: Arc<Vec<u8>>,
     realigner: RefCell<R>,
     homopolymer: Option<Range<u64>>,
+    /// The inserted mobile element, if any.
+    mobile_element: Option<MobileElement>,
+}
+
+#[derive(Debug)]
+struct MobileElement {
+    name: Vec<u8>,
+    strand: ElementStrand,
+    /// Inserted sequence in the opposite orientation, which is considered as well if the
+    /// strand of the element is unknown.
+    opposite_ins_seq: Option<Arc<Vec<u8>>>,
 }
 
 impl<R: Realigner> Insertion<R> {
@@ -59,15 +82,94 @@ impl<R: Realigner> Insertion<R> {
                 locus.contig().to_owned(),
                 locus.pos()..locus.pos() + 1,
             ))]),
-            ins_seq: Rc::new(ins_seq),
+            ins_seq: Arc::new(ins_seq),
             realigner: RefCell::new(realigner),
             homopolymer,
+            mobile_element: None,
         })
     }
 
+    /// Insertion of a mobile element, with the inserted sequence given by the last `len`
+    /// bases of the consensus sequence of the element, in the orientation of the given strand.
+    /// If the strand is unknown, both orientations are considered.
+    pub(crate) fn mobile_element(
+        locus: genome::Locus,
+        element: Vec<u8>,
+        consensus: &[u8],
+        len: u64,
+        strand: ElementStrand,
+        realigner: R,
+    ) -> Result<Self> {
+        // METHOD: mobile element insertions are frequently 5' truncated, because reverse
+        // transcription starts at the 3' end of the element and terminates prematurely.
+        // Hence, the inserted sequence is the 3' end of the consensus.
+        let seq = &consensus[consensus.len().saturating_sub(len as usize)..];
+        let (ins_seq, opposite_ins_seq) = match strand {
+            ElementStrand::Forward => (seq.to_owned(), None),
+            ElementStrand::Reverse => (dna::revcomp(seq), None),
+            ElementStrand::Unknown => (seq.to_owned(), Some(Arc::new(dna::revcomp(seq)))),
+        };
+        let mut insertion = Self::new(locus, ins_seq, realigner)?;
+        insertion.mobile_element = Some(MobileElement {
+            name: element,
+            strand,
+            opposite_ins_seq,
+        });
+        Ok(insertion)
+    }
+
     pub(crate) fn locus(&self) -> &SingleLocus {
         &self.locus[0]
     }
+
+    /// Determine whether the given read does not overlap the insertion site but points
+    /// towards it while its mate is unmapped or mapped far away. For mobile element insertions,
+    /// this happens if the mate stems from the inserted element and is hence mapped to
+    /// another copy of the element in the genome (or not at all).
+    fn is_discordant_mate(
+        &self,
+        read: &bam::Record,
+        alignment_properties: &AlignmentProperties,
+    ) -> bool {
+        if self.mobile_element.is_none() || !read.is_paired() || read.is_unmapped() {
+            return false;
+        }
+        let max_dist = if let Some(insert_size) = alignment_properties
+            .read_group_properties(read)
+            .insert_size
+            .as_ref()
+        {
+            (insert_size.mean + DISCORDANT_INSERT_SIZE_SDS * insert_size.sd) as i64
+        } else {
+            return false;
+        };
+        let is_discordant = read.is_mate_unmapped()
+            || read.tid() != read.mtid()
+            || read.insert_size().abs() > max_dist;
+
+        let pos = self.locus().range().start as i64;
+        let is_pointing_towards_insertion = if read.is_reverse() {
+            read.pos() > pos && read.pos() - pos <= max_dist
+        } else {
+            let end = read.cigar_cached().unwrap().end_pos();
+            end <= pos + 1 && pos - read.pos() <= max_dist
+        };
+
+        is_discordant && is_pointing_towards_insertion
+    }
+
+    fn discordant_mate_support(&self, read: &bam::Record) -> AlleleSupport {
+        // METHOD: without the insertion, a discordant mate pointing towards the insertion site
+        // can only be explained by artifacts. With the insertion, it is expected for all
+        // fragments that reach into the inserted element.
+        AlleleSupportBuilder::default()
+            .prob_ref_allele(LogProb(PROB_DISCORDANT_MATE_ARTIFACT.ln()))
+            .prob_alt_allele(LogProb::ln_one())
+            .strand(Strand::from_record(read))
+            .third_allele_evidence(None)
+            .build()
+            .unwrap()
+    }
 }
 
 impl<R: Realigner> Realignable for Insertion<R> {
@@ -83,18 +185,35 @@ impl<R: Realigner> Realignable for Insertion<R> {
         let ref_seq = ref_buffer.seq(self.locus().contig())?;
 
         let ref_seq_len = ref_seq.len();
-        Ok(vec![Box::new(InsertionEmissionParams {
-            ref_seq,
-            ref_offset: start.saturating_sub(ref_window),
-            ref_end: cmp::min(start + l + ref_window, ref_seq_len),
-            ins_start: start,
-            ins_len: l,
-            ins_end: start + l,
-            ins_seq: Rc::clone(&self.ins_seq),
-            homopolymer: self.homopolymer.clone(),
-            ref_offset_override: None,
-            ref_end_override: None,
-        })])
+        let emission_params =
+            |ins_seq: &Arc<Vec<u8>>, homopolymer| -> Box<dyn RefBaseVariantEmission> {
+                Box::new(InsertionEmissionParams {
+                    ref_seq: Arc::clone(&ref_seq),
+                    ref_offset: start.saturating_sub(ref_window),
+                    ref_end: cmp::min(start + l + ref_window, ref_seq_len),
+                    ins_start: start,
+                    ins_len: l,
+                    ins_end: start + l,
+                    ins_seq: Arc::clone(ins_seq),
+                    homopolymer,
+                    ref_offset_override: None,
+                    ref_end_override: None,
+                })
+            };
+
+        let mut params = vec![emission_params(&self.ins_seq, self.homopolymer.clone())];
+        if let Some(opposite_ins_seq) = self
+            .mobile_element
+            .as_ref()
+            .and_then(|element| element.opposite_ins_seq.as_ref())
+        {
+            params.push(emission_params(opposite_ins_seq, None));
+        }
+        Ok(params)
+    }
+
+    fn realignment_loci(&self) -> &MultiLocus {
+        self.loci()
     }
 }
 
@@ -139,10 +258,13 @@ impl<R: Realigner> Variant for Insertion<R> {
     fn is_valid_evidence(
         &self,
         evidence: &Evidence,
-        _: &AlignmentProperties,
+        alignment_properties: &AlignmentProperties,
     ) -> Option<Vec<usize>> {
         if match evidence {
-            Evidence::SingleEndSequencingRead(read) => !self.locus().overlap(read, true).is_none(),
+            Evidence::SingleEndSequencingRead(read) => {
+                !self.locus().overlap(read, true).is_none()
+                    || self.is_discordant_mate(read, alignment_properties)
+            }
             Evidence::PairedEndSequencingRead { left, right } => {
                 !self.locus().overlap(left, true).is_none()
                     || !self.locus().overlap(right, true).is_none()
@@ -164,9 +286,16 @@ impl<R: Realigner> Variant for Insertion<R> {
         &self,
         evidence: &Evidence,
         alignment_properties: &AlignmentProperties,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
         match evidence {
+            Evidence::SingleEndSequencingRead(record)
+                if self.locus().overlap(record, true).is_none() =>
+            {
+                // split reads are covered by the realignment below, reads that do not
+                // overlap the insertion site can only be valid because of a discordant mate
+                Ok(Some(self.discordant_mate_support(record)))
+            }
             Evidence::SingleEndSequencingRead(record) => {
                 Ok(Some(self.realigner.borrow_mut().allele_support(
                     record,
@@ -227,7 +356,15 @@ impl<R: Realigner> Variant for Insertion<R> {
 
 impl<R: Realigner> ToVariantRepresentation for Insertion<R> {
     fn to_variant_representation(&self) -> model::Variant {
-        model::Variant::Insertion(self.ins_seq.to_vec())
+        if let Some(ref element) = self.mobile_element {
+            model::Variant::MobileElementInsertion {
+                element: element.name.to_owned(),
+                len: self.ins_seq.len() as u64,
+                strand: element.strand,
+            }
+        } else {
+            model::Variant::Insertion(self.ins_seq.to_vec())
+        }
     }
 }
 
@@ -239,7 +376,7 @@ pub(crate) struct InsertionEmissionParams {
     ins_start: usize,
     ins_end: usize,
     ins_len: usize,
-    ins_seq: Rc<Vec<u8>>,
+    ins_seq: Arc<Vec<u8>>,
     homopolymer: Option<Range<u64>>,
     ref_offset_override: Option<usize>,
     ref_end_override: Option<usize>,
@@ -116,7 +116,7 @@ impl<R: Realigner> Variant for Inversion<R> {
         &self,
         evidence: &Evidence,
         alignment_properties: &AlignmentProperties,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
         let support =
             self.breakends
@@ -152,4 +152,8 @@ impl<R: Realigner> Realignable for Inversion<R> {
         self.breakends
             .alt_emission_params(ref_buffer, ref_interval, ref_window)
     }
+
+    fn realignment_loci(&self) -> &MultiLocus {
+        self.loci()
+    }
 }
@@ -6,7 +6,6 @@
 use std::cell::RefCell;
 use std::cmp;
 use std::ops::Range;
-use std::rc::Rc;
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -41,7 +40,7 @@ use super::ToVariantRepresentation;
 pub(crate) struct Mnv<R: Realigner> {
     loci: MultiLocus,
     ref_bases: Vec<u8>,
-    alt_bases: Rc<Vec<u8>>,
+    alt_bases: Arc<Vec<u8>>,
     realigner: RefCell<R>,
     realign_indel_reads: bool,
 }
@@ -60,7 +59,7 @@ impl<R: Realigner> Mnv<R> {
                 locus.pos()..locus.pos() + alt_bases.len() as u64,
             ))),
             ref_bases: ref_bases.to_ascii_uppercase(),
-            alt_bases: Rc::new(alt_bases.to_ascii_uppercase()),
+            alt_bases: Arc::new(alt_bases.to_ascii_uppercase()),
             realigner: RefCell::new(realigner),
             realign_indel_reads,
         }
@@ -74,7 +73,7 @@ impl<R: Realigner> Mnv<R> {
         &self,
         read: &bam::Record,
         alignment_properties: &AlignmentProperties,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
         if self.locus().overlap(read, false) != Overlap::Enclosing {
             return Ok(None);
@@ -85,9 +84,8 @@ impl<R: Realigner> Mnv<R> {
             // METHOD: reads containing indel operations should always be realigned,
             // as their support or non-support of the MNV might be an artifact
             // of the aligner. Also, if we have alt alignments here, we need to
-            // realign as well since we need the multi-allelic case handling in the
-            // realigner. TODO the latter is probably not needed anymore with third allele
-            // handling. Check this.
+            // realign as well, since the realigner provides the probabilities for each
+            // of the competing ALT alleles at the site.
             Ok(Some(self.realigner.borrow_mut().allele_support(
                 read,
                 self.loci().iter(),
@@ -223,11 +221,15 @@ impl<R: Realigner> Realignable for Mnv<R> {
             ref_end: cmp::min(start + self.alt_bases.len() + ref_window, ref_seq_len),
             alt_start: start,
             alt_end: self.locus().range().end as usize,
-            alt_seq: Rc::clone(&self.alt_bases),
+            alt_seq: Arc::clone(&self.alt_bases),
             ref_offset_override: None,
             ref_end_override: None,
         })])
     }
+
+    fn realignment_loci(&self) -> &MultiLocus {
+        self.loci()
+    }
 }
 
 impl<R: Realigner> Variant for Mnv<R> {
@@ -268,7 +270,7 @@ impl<R: Realigner> Variant for Mnv<R> {
         &self,
         evidence: &Evidence,
         alignment_properties: &AlignmentProperties,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
         match evidence {
             Evidence::SingleEndSequencingRead(read) => {
@@ -311,7 +313,7 @@ pub(crate) struct MnvEmissionParams {
     ref_end: usize,
     alt_start: usize,
     alt_end: usize, // exclusive end
-    alt_seq: Rc<Vec<u8>>,
+    alt_seq: Arc<Vec<u8>>,
     ref_offset_override: Option<usize>,
     ref_end_override: Option<usize>,
 }
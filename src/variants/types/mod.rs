@@ -4,21 +4,24 @@
 // except according to those terms.
 
 use std::cmp;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::rc::Rc;
 
 use anyhow::Result;
 use bio::stats::{LogProb, PHREDProb};
 use bio_types::genome::{self, AbstractInterval};
 use rust_htslib::bam;
+use rust_htslib::bam::record::Cigar;
 use vec_map::VecMap;
 
 use crate::errors::Error;
 use crate::estimation::alignment_properties::AlignmentProperties;
 use crate::utils::homopolymers::HomopolymerErrorModel;
 use crate::utils::PROB_05;
+use crate::variants::evidence::observations::depth_observation::DepthEvent;
 use crate::variants::evidence::observations::read_observation::{
-    Evidence, Observable, ReadObservation, Strand,
+    combine_prob_competing_alleles, CandidateEvidence, Evidence, Observable, ReadObservation,
+    Strand,
 };
 use crate::variants::sample;
 
@@ -26,20 +29,24 @@ pub(crate) mod breakends;
 pub(crate) mod deletion;
 pub(crate) mod duplication;
 pub(crate) mod haplotype_block;
+pub(crate) mod imprecise;
 pub(crate) mod insertion;
 pub(crate) mod inversion;
 pub(crate) mod mnv;
 pub(crate) mod none;
 pub(crate) mod replacement;
+pub(crate) mod short_tandem_repeat;
 pub(crate) mod snv;
 
 pub(crate) use deletion::Deletion;
 pub(crate) use duplication::Duplication;
+pub(crate) use imprecise::Imprecise;
 pub(crate) use insertion::Insertion;
 pub(crate) use inversion::Inversion;
 pub(crate) use mnv::Mnv;
 pub(crate) use none::None;
 pub(crate) use replacement::Replacement;
+pub(crate) use short_tandem_repeat::ShortTandemRepeat;
 pub(crate) use snv::Snv;
 
 use super::evidence::insert_size::estimate_insert_size;
@@ -53,6 +60,11 @@ use super::sampling_bias::FragmentSamplingBias;
 pub(crate) struct AlleleSupport {
     prob_ref_allele: LogProb,
     prob_alt_allele: LogProb,
+    /// Probabilities for each of the competing ALT alleles at the site (empty if there are
+    /// none or they have not been evaluated).
+    #[builder(default)]
+    #[getset(get = "pub")]
+    prob_competing_alleles: Vec<LogProb>,
     #[getset(get_copy = "pub")]
     strand: Strand,
     #[builder(default)]
@@ -97,6 +109,12 @@ impl AlleleSupport {
 
     pub(crate) fn merge(&mut self, other: &AlleleSupport) -> &mut Self {
         // TODO set read position to None if both allele supports have one
+        combine_prob_competing_alleles(
+            &mut self.prob_competing_alleles,
+            self.prob_ref_allele,
+            &other.prob_competing_alleles,
+            other.prob_ref_allele,
+        );
         self.prob_ref_allele += other.prob_ref_allele;
         self.prob_alt_allele += other.prob_alt_allele;
 
@@ -153,7 +171,7 @@ pub(crate) trait Variant {
         &self,
         evidence: &Evidence,
         alignment_properties: &AlignmentProperties,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>>;
 
     /// Calculate probability to sample a record length like the given one from the alt allele.
@@ -167,6 +185,12 @@ pub(crate) trait Variant {
     fn homopolymer_indel_len(&self) -> Option<i8> {
         None
     }
+
+    /// Return the copy number changing event of the variant, if any, such that depth
+    /// observations can be obtained for it.
+    fn depth_event(&self) -> Option<DepthEvent> {
+        None
+    }
 }
 
 pub(crate) trait IsizeObservable: Variant + FragmentSamplingBias {
@@ -177,7 +201,11 @@ pub(crate) trait IsizeObservable: Variant + FragmentSamplingBias {
         alignment_properties: &AlignmentProperties,
         alt_del_len: u64,
     ) -> Result<AlleleSupport> {
-        if alignment_properties.insert_size.unwrap().sd == 0.0 {
+        let alignment_properties = alignment_properties.read_group_properties(left_record);
+        // METHOD: a standard deviation of zero (or NaN, e.g. if estimated from a single
+        // fragment) would yield a degenerate insert size distribution.
+        let sd = alignment_properties.insert_size.unwrap().sd;
+        if sd.is_nan() || sd <= 0.0 {
             return Err(Error::UnrealisticIsizeSd.into());
         }
 
@@ -255,21 +283,16 @@ where
         }
     }
 
-    fn extract_observations(
+    fn collect_evidence(
         &self,
         buffer: &mut sample::RecordBuffer,
         alignment_properties: &mut AlignmentProperties,
-        max_depth: usize,
-        alt_variants: &[Box<dyn Realignable>],
-        observation_id_factory: &mut Option<&mut FragmentIdFactory>,
-    ) -> Result<Vec<ReadObservation>> {
+    ) -> Result<CandidateEvidence> {
         // We cannot use a hash function here because candidates have to be considered
         // in a deterministic order. Otherwise, subsampling high-depth regions will result
         // in slightly different probabilities each time.
         let mut candidate_records = BTreeMap::new();
 
-        let homopolymer_error_model = HomopolymerErrorModel::new(self, alignment_properties);
-
         let mut fetches = buffer.build_fetches(true);
         for locus in self.loci().iter() {
             fetches.push(locus);
@@ -314,6 +337,14 @@ where
             }
         }
 
+        // METHOD: evidence is validated with the properties (e.g. the insert size distribution)
+        // of its read group, the same ones that are used for calculating its allele support.
+        let is_valid_evidence = |evidence: &Evidence| {
+            self.is_valid_evidence(
+                evidence,
+                alignment_properties.read_group_properties(evidence.record()),
+            )
+        };
         let mut candidates = Vec::new();
         let mut locus_depth = VecMap::new();
         let mut push_evidence = |evidence: Evidence, idx| {
@@ -335,36 +366,96 @@ where
                     left: Rc::clone(&candidate.left),
                     right: Rc::clone(right),
                 };
-                if let Some(idx) = self.is_valid_evidence(&evidence, alignment_properties) {
+                if let Some(idx) = is_valid_evidence(&evidence) {
                     push_evidence(evidence, idx);
                 }
             } else {
                 // this is a single alignment with unmapped mate or mate outside of the
                 // region of interest
                 let evidence = Evidence::SingleEndSequencingRead(Rc::clone(&candidate.left));
-                if let Some(idx) = self.is_valid_evidence(&evidence, alignment_properties) {
+                if let Some(idx) = is_valid_evidence(&evidence) {
                     push_evidence(evidence, idx);
                 }
             }
         }
 
-        // METHOD: if all loci exceed the maximum depth, we subsample the evidence.
-        // We cannot decide this per locus, because we risk adding more biases if loci have different alt allele sampling biases.
-        let subsample = locus_depth.values().all(|depth| *depth > max_depth);
-        let mut subsampler = sample::SubsampleCandidates::new(max_depth, candidates.len());
+        // METHOD: in RNA data, softclips at splice junctions usually stem from exon overhangs
+        // that the read mapper could not align across the junction. Hence, softclips of a read
+        // are not considered if they start at a splice junction close to the variant that is
+        // observed in any of the reads.
+        let splice_junctions: HashSet<u64> = if alignment_properties.spliced {
+            let max_read_len = alignment_properties.max_read_len as u64;
+            candidates
+                .iter()
+                .flat_map(|evidence| evidence.records())
+                .flat_map(|record| {
+                    self.loci()
+                        .iter()
+                        .flat_map(move |locus| locus.splice_junctions(record, max_read_len))
+                })
+                .collect()
+        } else {
+            HashSet::new()
+        };
+
+        // METHOD: reads of the same UMI family stem from the same molecule. They are combined
+        // into a single observation, such that they are not considered as independent evidence.
+        // Families are kept in the (deterministic) order of their first member.
+        let families = if buffer.umi_families() {
+            group_umi_families(candidates)
+        } else {
+            candidates
+                .into_iter()
+                .map(|evidence| vec![evidence])
+                .collect()
+        };
+
+        Ok(CandidateEvidence {
+            families,
+            locus_depth,
+            splice_junctions,
+        })
+    }
+
+    fn extract_observations(
+        &self,
+        candidates: &CandidateEvidence,
+        buffer: &sample::RecordBuffer,
+        alignment_properties: &mut AlignmentProperties,
+        subsampler: &mut sample::SubsampleCandidates,
+        alt_variants: &[Box<dyn Realignable + Send>],
+        observation_id_factory: &mut Option<&mut FragmentIdFactory>,
+    ) -> Result<Vec<ReadObservation>> {
+        let homopolymer_error_model = HomopolymerErrorModel::new(self, alignment_properties);
 
         let mut observations = Vec::new();
-        for evidence in &candidates {
-            if !subsample || subsampler.keep() {
-                if let Some(obs) = self.evidence_to_observation(
+        for family in candidates.subsample_families(subsampler) {
+            let mut family_observations = Vec::with_capacity(family.len());
+            for evidence in family {
+                if let Some(mut obs) = self.evidence_to_observation(
                     evidence,
                     alignment_properties,
                     &homopolymer_error_model,
                     alt_variants,
+                    buffer.alt_hit_format().parser(),
                     observation_id_factory,
                 )? {
-                    observations.push(obs);
+                    if !candidates.splice_junctions.is_empty()
+                        && evidence.records().into_iter().any(|record| {
+                            is_softclipped_at_splice_junction(record, &candidates.splice_junctions)
+                        })
+                    {
+                        obs.softclipped = false;
+                    }
+                    family_observations.push(obs);
+                }
+            }
+            if buffer.umi_families() {
+                if !family_observations.is_empty() {
+                    observations.push(ReadObservation::umi_family_consensus(family_observations));
                 }
+            } else {
+                observations.extend(family_observations);
             }
         }
 
@@ -372,6 +463,25 @@ where
     }
 }
 
+/// Group the given evidence into UMI families. Evidence without UMI forms a singleton family.
+/// Families are kept in the (deterministic) order of their first member.
+fn group_umi_families(candidates: Vec<Evidence>) -> Vec<Vec<Evidence>> {
+    let mut family_indices = HashMap::new();
+    let mut families: Vec<Vec<Evidence>> = Vec::new();
+    for evidence in candidates {
+        if let Some(family) = evidence.umi_family() {
+            let idx = *family_indices.entry(family).or_insert_with(|| {
+                families.push(Vec::new());
+                families.len() - 1
+            });
+            families[idx].push(evidence);
+        } else {
+            families.push(vec![evidence]);
+        }
+    }
+    families
+}
+
 pub(crate) trait Loci {
     fn contig(&self) -> Option<&str>;
     fn first_pos(&self) -> u64;
@@ -415,6 +525,11 @@ impl SingleLocus {
 
         if pos <= self.range().start {
             if end_pos >= self.range().end {
+                if self.is_in_intron(record) {
+                    // METHOD: the read is spliced and the locus lies in between two of its
+                    // exons. Hence, the read does not cover the locus.
+                    return Overlap::None;
+                }
                 return Overlap::Enclosing;
             } else if end_pos > self.range().start {
                 return Overlap::Left;
@@ -427,6 +542,82 @@ impl SingleLocus {
 
         Overlap::None
     }
+
+    /// Return the boundaries of the splice junctions (N CIGAR operations) of the given record
+    /// that are closer than `distance` to the locus.
+    fn splice_junctions(&self, record: &bam::Record, distance: u64) -> Vec<u64> {
+        let start = self.range().start.saturating_sub(distance);
+        let end = self.range().end + distance;
+        let mut junctions = Vec::new();
+        let mut pos = record.pos() as u64;
+        for c in record.cigar_cached().unwrap().iter() {
+            match c {
+                Cigar::RefSkip(l) => {
+                    let junction_end = pos + *l as u64;
+                    junctions.extend(
+                        [pos, junction_end]
+                            .iter()
+                            .filter(|boundary| (start..end).contains(*boundary)),
+                    );
+                    pos = junction_end;
+                }
+                Cigar::Match(l) | Cigar::Equal(l) | Cigar::Diff(l) | Cigar::Del(l) => {
+                    pos += *l as u64
+                }
+                _ => (),
+            }
+            if pos >= end {
+                break;
+            }
+        }
+        junctions
+    }
+
+    /// Check whether the locus lies entirely within a reference skip (N CIGAR operation,
+    /// e.g. an intron) of the given record.
+    fn is_in_intron(&self, record: &bam::Record) -> bool {
+        let mut pos = record.pos() as u64;
+        for c in record.cigar_cached().unwrap().iter() {
+            match c {
+                Cigar::RefSkip(l) => {
+                    let end = pos + *l as u64;
+                    if pos <= self.range().start && self.range().end <= end {
+                        return true;
+                    }
+                    pos = end;
+                }
+                Cigar::Match(l) | Cigar::Equal(l) | Cigar::Diff(l) | Cigar::Del(l) => {
+                    pos += *l as u64
+                }
+                _ => (),
+            }
+            if pos > self.range().start {
+                break;
+            }
+        }
+        false
+    }
+}
+
+/// Maximum distance between the start of a softclip and a splice junction, such that the
+/// softclip is considered to stem from an exon overhang. Read mappers may align a few bases
+/// of the overhang into the intron if they match by chance.
+const MAX_SPLICE_OVERHANG_DISTANCE: u64 = 5;
+
+/// Check whether the given record has a softclip that starts at (or close to) one of the given
+/// splice junction boundaries.
+fn is_softclipped_at_splice_junction(
+    record: &bam::Record,
+    splice_junctions: &HashSet<u64>,
+) -> bool {
+    let cigar = record.cigar_cached().unwrap();
+    let is_close = |pos: u64| {
+        splice_junctions
+            .iter()
+            .any(|junction| junction.abs_diff(pos) <= MAX_SPLICE_OVERHANG_DISTANCE)
+    };
+    (cigar.leading_softclips() > 0 && is_close(record.pos() as u64))
+        || (cigar.trailing_softclips() > 0 && is_close(cigar.end_pos() as u64))
 }
 
 impl Loci for SingleLocus {
@@ -498,3 +689,149 @@ impl Overlap {
         matches!(self, Overlap::None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rust_htslib::bam::record::{Aux, CigarString};
+
+    use super::*;
+
+    fn record(pos: i64, cigar: Vec<Cigar>) -> bam::Record {
+        let len = CigarString(cigar.clone())
+            .into_view(0)
+            .iter()
+            .map(|c| match c {
+                Cigar::Match(l) | Cigar::Ins(l) | Cigar::SoftClip(l) => *l as usize,
+                _ => 0,
+            })
+            .sum();
+        let mut record = bam::Record::new();
+        record.set(
+            b"read",
+            Some(&CigarString(cigar)),
+            &vec![b'A'; len],
+            &vec![40; len],
+        );
+        record.set_pos(pos);
+        record.cache_cigar();
+        record
+    }
+
+    fn umi_evidence(mi: Option<u32>) -> Evidence {
+        let mut record = record(100, vec![Cigar::Match(10)]);
+        if let Some(mi) = mi {
+            record.push_aux(b"MI", Aux::U32(mi)).unwrap();
+        }
+        Evidence::SingleEndSequencingRead(Rc::new(record))
+    }
+
+    #[test]
+    fn test_group_umi_families() {
+        let families = group_umi_families(vec![
+            umi_evidence(Some(1)),
+            umi_evidence(None),
+            umi_evidence(Some(2)),
+            umi_evidence(Some(1)),
+            umi_evidence(None),
+        ]);
+        assert_eq!(
+            families
+                .iter()
+                .map(|family| (family.len(), family[0].umi_family()))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, Some(b"1".to_vec())),
+                (1, None),
+                (1, Some(b"2".to_vec())),
+                (1, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_subsample_families() {
+        let family_size = 3;
+        let num_families = 100;
+        let candidates = CandidateEvidence {
+            families: group_umi_families(
+                (0..num_families * family_size)
+                    .map(|i| umi_evidence(Some(i % num_families)))
+                    .collect(),
+            ),
+            locus_depth: vec![(0, (num_families * family_size) as usize)]
+                .into_iter()
+                .collect(),
+            splice_junctions: HashSet::new(),
+        };
+        assert_eq!(candidates.families.len(), num_families as usize);
+
+        let max_depth = 60;
+        let mut subsampler =
+            sample::SubsampleCandidates::for_evidence(std::slice::from_ref(&candidates), max_depth);
+        let kept = candidates
+            .subsample_families(&mut subsampler)
+            .collect::<Vec<_>>();
+        // families are kept or discarded as a whole, and the maximum depth refers to families
+        assert!(kept
+            .iter()
+            .all(|family| family.len() == family_size as usize));
+        assert!(!kept.is_empty());
+        assert!(kept.len() < 2 * max_depth);
+        assert!(kept.len() < num_families as usize);
+
+        // no subsampling below the maximum depth
+        let mut subsampler = sample::SubsampleCandidates::for_evidence(
+            std::slice::from_ref(&candidates),
+            (num_families * family_size) as usize,
+        );
+        assert_eq!(
+            candidates.subsample_families(&mut subsampler).count(),
+            num_families as usize
+        );
+    }
+
+    #[test]
+    fn test_splice_junctions() {
+        let locus = SingleLocus::new(genome::Interval::new("chr1".to_owned(), 100..101));
+        let spliced = record(
+            50,
+            vec![
+                Cigar::Match(40),
+                Cigar::RefSkip(20),
+                Cigar::Match(20),
+                Cigar::RefSkip(1000),
+                Cigar::Match(10),
+            ],
+        );
+        assert_eq!(locus.splice_junctions(&spliced, 20), vec![90, 110]);
+        assert_eq!(locus.splice_junctions(&spliced, 5), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_is_softclipped_at_splice_junction() {
+        let splice_junctions = vec![140].into_iter().collect();
+        // trailing softclip at the junction, i.e. the read overhangs into the intron
+        let overhang = record(100, vec![Cigar::Match(38), Cigar::SoftClip(10)]);
+        assert!(is_softclipped_at_splice_junction(
+            &overhang,
+            &splice_junctions
+        ));
+        // leading softclip right behind the junction
+        let overhang = record(140, vec![Cigar::SoftClip(10), Cigar::Match(38)]);
+        assert!(is_softclipped_at_splice_junction(
+            &overhang,
+            &splice_junctions
+        ));
+        // softclip elsewhere, e.g. caused by a nearby variant
+        let softclipped = record(100, vec![Cigar::Match(20), Cigar::SoftClip(10)]);
+        assert!(!is_softclipped_at_splice_junction(
+            &softclipped,
+            &splice_junctions
+        ));
+        let unclipped = record(100, vec![Cigar::Match(40)]);
+        assert!(!is_softclipped_at_splice_junction(
+            &unclipped,
+            &splice_junctions
+        ));
+    }
+}
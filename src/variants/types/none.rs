@@ -124,7 +124,7 @@ impl Variant for None {
         &self,
         evidence: &Evidence,
         _: &AlignmentProperties,
-        _: &[Box<dyn Realignable>],
+        _: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
         match evidence {
             Evidence::SingleEndSequencingRead(read) => Ok(self.allele_support_per_read(read)?),
@@ -6,7 +6,6 @@
 use std::cell::RefCell;
 use std::cmp;
 use std::ops::Range;
-use std::rc::Rc;
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -31,7 +30,7 @@ use crate::variants::types::{AlleleSupport, Evidence, MultiLocus, SingleLocus, V
 pub(crate) struct Replacement<R: Realigner> {
     loci: MultiLocus,
     ref_seq: Vec<u8>,
-    replacement: Rc<Vec<u8>>,
+    replacement: Arc<Vec<u8>>,
     realigner: RefCell<R>,
     homopolymer_indel_len: Option<i8>,
 }
@@ -47,7 +46,7 @@ impl<R: Realigner> Replacement<R> {
         Ok(Replacement {
             loci: MultiLocus::from_single_locus(SingleLocus::new(locus)),
             ref_seq,
-            replacement: Rc::new(replacement),
+            replacement: Arc::new(replacement),
             realigner: RefCell::new(realigner),
             homopolymer_indel_len,
         })
@@ -94,12 +93,16 @@ impl<R: Realigner> Realignable for Replacement<R> {
             repl_alt_end: start + repl_alt_len,
             repl_alt_len,
             repl_ref_len,
-            repl_seq: Rc::clone(&self.replacement),
+            repl_seq: Arc::clone(&self.replacement),
             is_homopolymer_indel: self.homopolymer_indel_len.is_some(),
             ref_offset_override: None,
             ref_end_override: None,
         })])
     }
+
+    fn realignment_loci(&self) -> &MultiLocus {
+        self.loci()
+    }
 }
 
 impl<R: Realigner> SamplingBias for Replacement<R> {
@@ -179,7 +182,7 @@ impl<R: Realigner> Variant for Replacement<R> {
         &self,
         evidence: &Evidence,
         alignment_properties: &AlignmentProperties,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
         match evidence {
             Evidence::SingleEndSequencingRead(record) => {
@@ -258,7 +261,7 @@ pub(crate) struct ReplacementEmissionParams {
     repl_alt_end: usize,
     repl_alt_len: usize,
     repl_ref_len: usize,
-    repl_seq: Rc<Vec<u8>>,
+    repl_seq: Arc<Vec<u8>>,
     is_homopolymer_indel: bool,
     ref_offset_override: Option<usize>,
     ref_end_override: Option<usize>,
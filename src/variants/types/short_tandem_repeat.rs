@@ -0,0 +1,291 @@
+// Copyright 2021 Johannes Köster.
+// Licensed under the GNU GPLv3 license (https://opensource.org/licenses/GPL-3.0)
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use bio::stats::LogProb;
+use bio_types::genome::{self, AbstractInterval};
+
+use super::ToVariantRepresentation;
+use crate::estimation::alignment_properties::AlignmentProperties;
+use crate::reference;
+use crate::variants::evidence::realignment::pairhmm::RefBaseVariantEmission;
+use crate::variants::evidence::realignment::{Realignable, Realigner};
+use crate::variants::model;
+use crate::variants::types::{
+    AlleleSupport, AlleleSupportBuilder, Evidence, MultiLocus, Overlap, Replacement, Variant,
+};
+
+/// Probability that PCR stutter removes a repeat unit from a read.
+const PROB_STUTTER_CONTRACTION: f64 = 0.04;
+/// Probability that PCR stutter adds a repeat unit to a read.
+const PROB_STUTTER_EXPANSION: f64 = 0.01;
+
+/// Short tandem repeat allele with a given number of repeat units.
+/// The locus spans the base before the repeat tract and the tract itself.
+pub(crate) struct ShortTandemRepeat<R: Realigner> {
+    unit: Vec<u8>,
+    ref_len: u64,
+    count: u32,
+    allele: Replacement<R>,
+    /// Numbers of repeat units of the other ALT alleles at the same site, in the order
+    /// in which they are given as competing alleles (None if not a repeat allele of the
+    /// same repeat).
+    site_counts: Vec<Option<u32>>,
+    /// Numbers of repeat units of all further alleles that are needed to evaluate the
+    /// alleles of the site and their stutter artifacts, along with the alleles themselves.
+    other_counts: Vec<u32>,
+    other_alleles: Vec<Box<dyn Realignable + Send>>,
+}
+
+impl<R: Realigner + Clone + Send + 'static> ShortTandemRepeat<R> {
+    pub(crate) fn new(
+        locus: genome::Interval,
+        unit: Vec<u8>,
+        count: u32,
+        site_counts: Vec<Option<u32>>,
+        realigner: R,
+    ) -> Result<Self> {
+        let ref_len = locus.range().end - locus.range().start - 1;
+        let ref_count = ref_count(&unit, ref_len);
+        let anchor = realigner.ref_buffer().seq(locus.contig())?[locus.range().start as usize];
+        let allele = |c: u32| {
+            let mut seq = Vec::with_capacity(1 + unit.len() * c as usize);
+            seq.push(anchor);
+            for _ in 0..c {
+                seq.extend(&unit);
+            }
+            Replacement::new(locus.clone(), seq, realigner.clone())
+        };
+
+        // METHOD: besides the allele itself, we need the reference and the other ALT alleles
+        // of the site, and the alleles that PCR stutter can produce from any of them, i.e.,
+        // one unit less or more.
+        let mut other_counts = Vec::new();
+        for c in [ref_count, count]
+            .iter()
+            .chain(site_counts.iter().flatten())
+        {
+            for c in [Some(*c), c.checked_sub(1), Some(c + 1)].iter().flatten() {
+                if *c != ref_count && *c != count && !other_counts.contains(c) {
+                    other_counts.push(*c);
+                }
+            }
+        }
+        let other_alleles = other_counts
+            .iter()
+            .map(|c| Ok(Box::new(allele(*c)?) as Box<dyn Realignable + Send>))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ShortTandemRepeat {
+            allele: allele(count)?,
+            unit,
+            ref_len,
+            count,
+            site_counts,
+            other_counts,
+            other_alleles,
+        })
+    }
+}
+
+impl<R: Realigner> ShortTandemRepeat<R> {
+    fn allele(&self) -> &Replacement<R> {
+        &self.allele
+    }
+
+    fn ref_count(&self) -> u32 {
+        ref_count(&self.unit, self.ref_len)
+    }
+}
+
+/// Number of repeat units in the reference repeat tract.
+fn ref_count(unit: &[u8], ref_len: u64) -> u32 {
+    (ref_len / unit.len() as u64) as u32
+}
+
+/// Probability to observe a read given that it stems from the allele with the given
+/// number of repeat units, while allowing PCR stutter to add or remove a unit.
+/// `prob_allele` returns the probability to observe the read given the exact allele.
+fn prob_with_stutter<F>(count: u32, prob_allele: F) -> LogProb
+where
+    F: Fn(u32) -> Option<LogProb>,
+{
+    let mut probs = vec![
+        LogProb((1.0 - PROB_STUTTER_CONTRACTION - PROB_STUTTER_EXPANSION).ln())
+            + prob_allele(count).unwrap(),
+    ];
+    if let Some(prob) = count.checked_sub(1).and_then(&prob_allele) {
+        probs.push(LogProb(PROB_STUTTER_CONTRACTION.ln()) + prob);
+    }
+    if let Some(prob) = prob_allele(count + 1) {
+        probs.push(LogProb(PROB_STUTTER_EXPANSION.ln()) + prob);
+    }
+    LogProb::ln_sum_exp(&probs)
+}
+
+impl<R: Realigner> Realignable for ShortTandemRepeat<R> {
+    fn alt_emission_params(
+        &self,
+        ref_buffer: Arc<reference::Buffer>,
+        ref_interval: &genome::Interval,
+        ref_window: usize,
+    ) -> Result<Vec<Box<dyn RefBaseVariantEmission>>> {
+        self.allele()
+            .alt_emission_params(ref_buffer, ref_interval, ref_window)
+    }
+
+    fn realignment_loci(&self) -> &MultiLocus {
+        self.loci()
+    }
+}
+
+impl<R: Realigner> Variant for ShortTandemRepeat<R> {
+    fn is_imprecise(&self) -> bool {
+        false
+    }
+
+    fn is_valid_evidence(
+        &self,
+        evidence: &Evidence,
+        _: &AlignmentProperties,
+    ) -> Option<Vec<usize>> {
+        // METHOD: only reads spanning the entire repeat tract are informative about
+        // the number of repeat units.
+        let locus = self.allele().locus();
+        if match evidence {
+            Evidence::SingleEndSequencingRead(read) => {
+                locus.overlap(read, false) == Overlap::Enclosing
+            }
+            Evidence::PairedEndSequencingRead { left, right } => {
+                locus.overlap(left, false) == Overlap::Enclosing
+                    || locus.overlap(right, false) == Overlap::Enclosing
+            }
+        } {
+            Some(vec![0])
+        } else {
+            None
+        }
+    }
+
+    fn loci(&self) -> &MultiLocus {
+        self.allele().loci()
+    }
+
+    fn allele_support(
+        &self,
+        evidence: &Evidence,
+        alignment_properties: &AlignmentProperties,
+        _: &[Box<dyn Realignable + Send>],
+    ) -> Result<Option<AlleleSupport>> {
+        // METHOD: the read is realigned against all needed repeat alleles at once, such
+        // that their probabilities are normalized jointly and can hence be combined.
+        let support = if let Some(support) =
+            self.allele
+                .allele_support(evidence, alignment_properties, &self.other_alleles)?
+        {
+            support
+        } else {
+            return Ok(None);
+        };
+
+        let ref_count = self.ref_count();
+        let prob_allele = |count| {
+            if count == ref_count {
+                Some(support.prob_ref_allele)
+            } else if count == self.count {
+                Some(support.prob_alt_allele)
+            } else {
+                self.other_counts.iter().position(|c| *c == count).map(|i| {
+                    // empty if the read does not overlap any of the alleles
+                    support
+                        .prob_competing_alleles
+                        .get(i)
+                        .copied()
+                        .unwrap_or(support.prob_ref_allele)
+                })
+            }
+        };
+
+        // METHOD: reads may carry PCR stutter, i.e., have lost or gained a repeat unit
+        // during amplification. Hence, we model the probability of a read given an allele
+        // as a mixture over the allele itself and its stutter products.
+        // Homopolymer errors are covered by the stutter model as well, hence we do not
+        // report a homopolymer indel length here.
+        // The other ALT alleles of the site are reported as competing alleles, such that
+        // all repeat lengths of the site are evaluated jointly in the likelihood model.
+        // Competing alleles that are not repeat alleles of the same repeat are treated
+        // like the reference.
+        let prob_ref = prob_with_stutter(ref_count, prob_allele);
+        Ok(Some(
+            AlleleSupportBuilder::default()
+                .prob_ref_allele(prob_ref)
+                .prob_alt_allele(prob_with_stutter(self.count, prob_allele))
+                .prob_competing_alleles(
+                    self.site_counts
+                        .iter()
+                        .map(|count| count.map_or(prob_ref, |c| prob_with_stutter(c, prob_allele)))
+                        .collect(),
+                )
+                .strand(support.strand())
+                .read_position(support.read_position())
+                .third_allele_evidence(support.third_allele_evidence())
+                .build()
+                .unwrap(),
+        ))
+    }
+
+    fn prob_sample_alt(
+        &self,
+        evidence: &Evidence,
+        alignment_properties: &AlignmentProperties,
+    ) -> LogProb {
+        self.allele()
+            .prob_sample_alt(evidence, alignment_properties)
+    }
+}
+
+impl<R: Realigner> ToVariantRepresentation for ShortTandemRepeat<R> {
+    fn to_variant_representation(&self) -> model::Variant {
+        model::Variant::ShortTandemRepeat {
+            unit: self.unit.clone(),
+            ref_len: self.ref_len,
+            count: self.count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prob_with_stutter() {
+        // read supports 10 units, reference has 10 units, allele has 11 units
+        let prob_allele = |count| match count {
+            10 => Some(LogProb::ln_one()),
+            9 | 11 | 12 => Some(LogProb::ln_zero()),
+            _ => None,
+        };
+        let prob_ref = prob_with_stutter(10, prob_allele);
+        let prob_alt = prob_with_stutter(11, prob_allele);
+        assert_relative_eq!(prob_ref.exp(), 0.95, epsilon = 1e-9);
+        assert_relative_eq!(prob_alt.exp(), PROB_STUTTER_CONTRACTION, epsilon = 1e-9);
+
+        // without shorter allele, only expansion stutter is possible
+        let prob_allele = |count| match count {
+            0 => Some(LogProb::ln_zero()),
+            1 => Some(LogProb::ln_one()),
+            _ => None,
+        };
+        assert_relative_eq!(
+            prob_with_stutter(0, prob_allele).exp(),
+            PROB_STUTTER_EXPANSION,
+            epsilon = 1e-9
+        );
+        assert_eq!(ref_count(b"CAG", 22), 7);
+    }
+}
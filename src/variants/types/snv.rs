@@ -67,16 +67,18 @@ impl<R: Realigner> Snv<R> {
         &self,
         read: &bam::Record,
         alignment_properties: &AlignmentProperties,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
         if self.locus().overlap(read, false) != Overlap::Enclosing {
             return Ok(None);
         }
 
-        if self.realign_indel_reads && utils::contains_indel_op(read) {
+        if self.realign_indel_reads && (utils::contains_indel_op(read) || !alt_variants.is_empty())
+        {
             // METHOD: reads containing indel operations should always be realigned,
             // as their support or non-support of the SNV might be an artifact
-            // of the aligner.
+            // of the aligner. Also, at sites with multiple ALT alleles, we realign, since
+            // the realigner provides the probabilities for each of the competing ALT alleles.
             Ok(Some(self.realigner.borrow_mut().allele_support(
                 read,
                 self.loci.iter(),
@@ -98,12 +100,9 @@ impl<R: Realigner> Snv<R> {
 
             // METHOD: instead of considering the actual REF base, we assume that REF is whatever
             // base the read has at this position (if not the ALT base). This way, we avoid biased
-            // allele frequencies at sites with multiple alternative alleles.
-            // Note that this is an approximation. The real solution would be to have multiple allele
-            // frequency variables in the likelihood function, but that would be computationally
-            // more demanding (leading to a combinatorial explosion).
-            // However, the approximation is pretty accurate, because it will only matter for true
-            // multiallelic cases. Sequencing errors won't have a severe effect on the allele frequencies
+            // allele frequencies at sites with further alternative alleles that are not among
+            // the candidates (known competing ALT alleles are handled via realignment above).
+            // Sequencing errors won't have a severe effect on the allele frequencies
             // because they are too rare.
             // Here, N bases do not count as additional edits that would indicate a third allele.
             let non_alt_base = if read_base != b'N' && read_base != self.alt_base {
@@ -171,6 +170,10 @@ impl<R: Realigner> Realignable for Snv<R> {
             ref_end_override: None,
         })])
     }
+
+    fn realignment_loci(&self) -> &MultiLocus {
+        self.loci()
+    }
 }
 
 impl<R: Realigner> Variant for Snv<R> {
@@ -211,7 +214,7 @@ impl<R: Realigner> Variant for Snv<R> {
         &self,
         evidence: &Evidence,
         alignment_properties: &AlignmentProperties,
-        alt_variants: &[Box<dyn Realignable>],
+        alt_variants: &[Box<dyn Realignable + Send>],
     ) -> Result<Option<AlleleSupport>> {
         match evidence {
             Evidence::SingleEndSequencingRead(read) => {